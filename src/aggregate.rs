@@ -0,0 +1,157 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+use anyhow::Context;
+use serde_json::{json, Value};
+use tokio::{io::AsyncWriteExt, net::{TcpListener, TcpStream}, sync::broadcast::{self, error::RecvError}};
+use tracing::*;
+
+use crate::ws::{self, Message, Opcode};
+
+/// Aggregator side of `uplink`: instances stream into `/ingest`, viewers read the merged feed
+/// from `/feed` (WebSocket) or `/sources` (one JSON object, latest update per source).
+pub struct Aggregator {
+    /// Latest envelope per connection; two uplinks with the same name stay apart.
+    sources: Mutex<BTreeMap<u64, Value>>,
+    next_id: AtomicU64,
+    /// Feed messages, already serialized.
+    tx: broadcast::Sender<Arc<String>>,
+    token: Option<String>,
+}
+
+pub async fn serve(addr: SocketAddr, token: Option<String>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await.with_context(|| format!("binding aggregator on {addr}"))?;
+    info!(target: "aggregate", "aggregator listening on {addr} (/ingest, /feed, /sources)");
+    let (tx, _) = broadcast::channel(64);
+    let agg = Arc::new(Aggregator { sources: Mutex::new(BTreeMap::new()), next_id: AtomicU64::new(1), tx, token });
+    loop {
+        let (sock, peer) = match listener.accept().await {
+            Ok(c) => c,
+            Err(e) => { warn!(target: "aggregate", %e, "accept failed"); continue; }
+        };
+        let agg = agg.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(sock, &agg).await { debug!(target: "aggregate", %peer, "{e:#}"); }
+        });
+    }
+}
+
+struct Head { path: String, ws_key: Option<String>, bearer: Option<String> }
+
+async fn handle(mut sock: TcpStream, agg: &Aggregator) -> anyhow::Result<()> {
+    sock.set_nodelay(true).ok();
+    let raw = ws::read_head(&mut sock).await?;
+    let mut hdrs = [httparse::EMPTY_HEADER; 32];
+    let mut req = httparse::Request::new(&mut hdrs);
+    req.parse(&raw).context("malformed request")?;
+    let header = |name: &str| req.headers.iter().find(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| String::from_utf8_lossy(h.value).trim().to_string());
+    let head = Head {
+        path: req.path.unwrap_or("/").split('?').next().unwrap_or("/").to_string(),
+        ws_key: header("sec-websocket-key"),
+        bearer: header("authorization").and_then(|a| a.strip_prefix("Bearer ").map(str::to_string)),
+    };
+
+    match (head.path.as_str(), head.ws_key) {
+        ("/ingest", Some(key)) => {
+            if agg.token.is_some() && head.bearer != agg.token {
+                return plain(&mut sock, "401 Unauthorized", "text/plain", "bad or missing token").await;
+            }
+            ws::accept(&mut sock, &key).await?;
+            ingest(sock, agg).await
+        }
+        ("/feed", Some(key)) => {
+            ws::accept(&mut sock, &key).await?;
+            feed(sock, agg).await
+        }
+        ("/sources", _) => {
+            let body = serde_json::to_string(&*agg.sources.lock().unwrap_or_else(|p| p.into_inner()))?;
+            plain(&mut sock, "200 OK", "application/json", &body).await
+        }
+        _ => plain(&mut sock, "404 Not Found", "text/plain", "not found").await,
+    }
+}
+
+async fn plain(sock: &mut TcpStream, status: &str, content_type: &str, body: &str) -> anyhow::Result<()> {
+    let rsp = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    sock.write_all(rsp.as_bytes()).await?;
+    Ok(())
+}
+
+/// One uplink, under a connection ID of its own; its source name comes from the messages and
+/// is only a label. When it drops, the source is removed and viewers get
+/// `{"connection": id, "source": name, "gone": true}`.
+async fn ingest(mut sock: TcpStream, agg: &Aggregator) -> anyhow::Result<()> {
+    let (rd, mut sock) = sock.split();
+    let mut rd = ws::Reader::new(rd);
+    let id = agg.next_id.fetch_add(1, Ordering::Relaxed);
+    let mut name: Option<String> = None;
+    let result = loop {
+        let v = match rd.next().await {
+            Ok(Message::Text(t)) => serde_json::from_str::<Value>(&t).ok(),
+            Ok(Message::Binary(b)) => envelope(&b),
+            Ok(Message::Ping(p)) => { ws::write_frame(&mut sock, Opcode::Pong, &p, false).await?; continue; }
+            Ok(Message::Pong) => continue,
+            Ok(Message::Close) => { ws::write_frame(&mut sock, Opcode::Close, &[], false).await.ok(); break Ok(()); }
+            Err(e) => break Err(e.into()),
+        };
+        let Some(mut v) = v else { debug!(target: "aggregate", id, "unreadable uplink message"); continue };
+        let Some(source) = v.get("source").and_then(Value::as_str).filter(|s| !s.is_empty()).map(str::to_string) else { continue };
+        if v.get("result").is_none() { continue; }
+        if name.as_deref() != Some(&source) {
+            info!(target: "aggregate", id, "source {source:?} connected");
+            name = Some(source);
+        }
+        v["connection"] = id.into();
+        agg.sources.lock().unwrap_or_else(|p| p.into_inner()).insert(id, v.clone());
+        let _ = agg.tx.send(Arc::new(v.to_string()));
+    };
+    if let Some(n) = name {
+        info!(target: "aggregate", id, "source {n:?} disconnected");
+        agg.remove(id, &n);
+    }
+    result
+}
+
+/// A binary uplink message: MessagePack if it starts like a map, else a protobuf `Update`.
+/// A protobuf `Update` can't start with those bytes: its first tag is field 1-4, one byte under 0x80.
+fn envelope(b: &[u8]) -> Option<Value> {
+    match b.first()? {
+        0x80..=0x8f | 0xde | 0xdf => crate::msgpack::from_msgpack(b),
+        _ => crate::proto::to_value(crate::proto::UPDATE, b),
+    }
+}
+
+impl Aggregator {
+    fn remove(&self, id: u64, name: &str) {
+        self.sources.lock().unwrap_or_else(|p| p.into_inner()).remove(&id);
+        let _ = self.tx.send(Arc::new(json!({ "connection": id, "source": name, "gone": true }).to_string()));
+    }
+}
+
+/// One viewer: the latest update of every source, then everything as it arrives.
+async fn feed(sock: TcpStream, agg: &Aggregator) -> anyhow::Result<()> {
+    let (rd, mut wr) = tokio::io::split(sock);
+    // buffered, so an update going out mid-frame doesn't lose what's been read of it
+    let mut rd = ws::Reader::new(rd);
+    let (current, mut rx) = {
+        let sources = agg.sources.lock().unwrap_or_else(|p| p.into_inner());
+        (sources.values().map(Value::to_string).collect::<Vec<_>>(), agg.tx.subscribe())
+    };
+    for m in current { ws::write_frame(&mut wr, Opcode::Text, m.as_bytes(), false).await?; }
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(m) => ws::write_frame(&mut wr, Opcode::Text, m.as_bytes(), false).await?,
+                Err(RecvError::Lagged(n)) => debug!(target: "aggregate", n, "viewer lagged"),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            msg = rd.next() => match msg? {
+                Message::Ping(p) => ws::write_frame(&mut wr, Opcode::Pong, &p, false).await?,
+                Message::Close => { ws::write_frame(&mut wr, Opcode::Close, &[], false).await.ok(); return Ok(()); }
+                _ => {}
+            },
+        }
+    }
+}
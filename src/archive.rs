@@ -0,0 +1,134 @@
+use std::path::{Component, Path, PathBuf};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{beatmaps, goals, history::{self, History}, hub::unix_ms, library::Library, tags, zip};
+
+const EXPORT_USAGE: &str = "usage: minacalc-overlay export-data <archive.zip> [--no-cache]";
+const IMPORT_USAGE: &str = "usage: minacalc-overlay import-data <archive.zip>";
+/// Bumped when the archive's layout changes in a way older versions can't import.
+const VERSION: u32 = 1;
+
+/// `manifest.json`, first in the archive.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    exported_unix_ms: u64,
+    overlay_version: String,
+    /// MinaCalc version of the exporting build; the library's entries say their own.
+    calc_version: i32,
+}
+
+/// `minacalc-overlay export-data <archive.zip> [--no-cache]`: the play history, library index,
+/// goals, chart tags and (unless `--no-cache`) the downloaded `.osu` files in one zip, for
+/// `import-data` on another machine.
+pub fn export() -> anyhow::Result<()> {
+    let out = PathBuf::from(std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(EXPORT_USAGE)?);
+    let manifest = Manifest { version: VERSION, exported_unix_ms: unix_ms(), overlay_version: env!("CARGO_PKG_VERSION").into(), calc_version: minacalc_rs::Calc::version() };
+    let mut zip = zip::Writer::default();
+    zip.add("manifest.json", &serde_json::to_vec_pretty(&manifest)?);
+    let mut parts = Vec::new();
+    for (name, path) in [("history.jsonl", History::default_path()), ("library.json", Library::default_path()), ("goals.json", goals::default_path()), ("tags.json", tags::default_path())] {
+        match std::fs::read(&path) {
+            Ok(b) => { zip.add(name, &b); parts.push(name.to_string()); }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => debug!("no {} to export", path.display()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+    let mut maps = 0;
+    if !std::env::args().any(|a| a == "--no-cache") {
+        let dir = beatmaps::cache_dir();
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let p = entry.path();
+            if p.extension().is_none_or(|e| e != "osu") { continue; }
+            let Some(name) = p.file_name().and_then(|n| n.to_str()) else { continue };
+            // the writer's counts and offsets are 16 and 32 bits
+            anyhow::ensure!(maps < 60_000, "the beatmap cache has too many files for one archive; export with --no-cache");
+            zip.add(&format!("beatmaps/{name}"), &std::fs::read(&p).with_context(|| format!("reading {}", p.display()))?);
+            maps += 1;
+        }
+        if maps > 0 { parts.push(format!("{maps} cached beatmaps")); }
+    }
+    let bytes = zip.finish();
+    anyhow::ensure!(bytes.len() < u32::MAX as usize, "the archive would be over 4 GiB; export with --no-cache");
+    std::fs::write(&out, &bytes).with_context(|| format!("writing {}", out.display()))?;
+    if parts.is_empty() { info!("nothing recorded yet; {} only has the manifest", out.display()); }
+    else { info!("exported {} to {} ({} KiB)", parts.join(", "), out.display(), bytes.len() / 1024); }
+    Ok(())
+}
+
+/// `minacalc-overlay import-data <archive.zip>`: merges an `export-data` archive into this
+/// machine's data, never dropping anything already here. Plays are added unless the same one is
+/// recorded, library entries are merged per chart, goals keep the earlier completion and the
+/// better attempt, tags are combined, and cached beatmaps that aren't here are copied.
+pub fn import() -> anyhow::Result<()> {
+    let from = PathBuf::from(std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(IMPORT_USAGE)?);
+    let bytes = std::fs::read(&from).with_context(|| format!("reading {}", from.display()))?;
+    let entries = zip::read(&bytes, |_| true).with_context(|| format!("reading {}", from.display()))?;
+    let get = |name: &str| entries.iter().find(|(n, _)| n == name).map(|(_, b)| b.as_slice());
+    let manifest: Manifest = serde_json::from_slice(get("manifest.json").context("no manifest.json; not an export-data archive")?)
+        .context("reading manifest.json")?;
+    anyhow::ensure!(manifest.version <= VERSION, "the archive is from a newer minacalc-overlay ({}); update this one first", manifest.overlay_version);
+    if manifest.calc_version != minacalc_rs::Calc::version() {
+        info!("the archive was made with MinaCalc version {} (this is {}); its ratings are kept, and warm-cache rates its charts again", manifest.calc_version, minacalc_rs::Calc::version());
+    }
+
+    if let Some(b) = get("history.jsonl") {
+        let path = History::default_path();
+        let n = history::merge_file(&path, b)?;
+        info!("history: {n} plays added to {}", path.display());
+    }
+    if let Some(b) = get("library.json") {
+        let mut lib = Library::open(&Library::default_path())?;
+        let (added, updated) = lib.merge_file(b)?;
+        if added + updated > 0 { lib.save()?; }
+        info!("library: {added} charts added, {updated} updated in {}", lib.path().display());
+    }
+    if let Some(b) = get("goals.json") {
+        let path = goals::default_path();
+        let n = goals::merge_file(&path, b)?;
+        info!("goals: {n} added to {}", path.display());
+    }
+    if let Some(b) = get("tags.json") {
+        let path = tags::default_path();
+        let n = tags::merge_file(&path, b)?;
+        info!("tags: {n} charts tagged or noted in {}", path.display());
+    }
+    let dir = beatmaps::cache_dir();
+    let (mut copied, mut had) = (0, 0);
+    for (name, body) in &entries {
+        let Some(file) = name.strip_prefix("beatmaps/") else { continue };
+        if !cache_name(file) { continue; }
+        let dest = dir.join(file);
+        if dest.exists() { had += 1; continue; }
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(&dest, body).with_context(|| format!("writing {}", dest.display()))?;
+        copied += 1;
+    }
+    if copied + had > 0 { info!("beatmap cache: {copied} copied, {had} already there"); }
+    Ok(())
+}
+
+/// Whether an archived beatmap name is a plain `<id>.osu`: one path component, no separators,
+/// no drive, UNC prefix or stream (`:`), not hidden. Anything else could point outside the cache.
+fn cache_name(file: &str) -> bool {
+    let mut parts = Path::new(file).components();
+    matches!((parts.next(), parts.next()), (Some(Component::Normal(_)), None))
+        && !file.contains(['/', '\\', ':'])
+        && !file.starts_with('.')
+        && Path::new(file).extension().is_some_and(|e| e == "osu")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plain_osu_names_reach_the_cache() {
+        assert!(cache_name("2345678.osu"));
+        for bad in ["../2345678.osu", "/etc/x.osu", "a/b.osu", "a\\b.osu", "C:x.osu", "C:\\x.osu", "\\\\server\\share\\x.osu", "x.osu:stream", ".osu", ".hidden.osu", "x.txt", "", "."] {
+            assert!(!cache_name(bad), "{bad:?}");
+        }
+    }
+}
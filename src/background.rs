@@ -0,0 +1,101 @@
+use std::{path::Path, time::Duration};
+use reqwest::Client;
+use serde::Serialize;
+use tracing::*;
+
+use crate::{config::Config, image, output::{self, Format}};
+
+/// `theme.json`; also written from a skin by the `skin` command.
+#[derive(Serialize)]
+pub struct ThemeOut {
+    dominant: String,
+    accent: String,
+    /// Readable text colour on `dominant`.
+    text: String,
+    /// Readable text colour on `accent`.
+    accent_text: String,
+    palette: Vec<String>,
+}
+
+impl From<image::Palette> for ThemeOut {
+    fn from(p: image::Palette) -> Self {
+        ThemeOut {
+            dominant: image::hex(p.dominant),
+            accent: image::hex(p.accent),
+            text: image::hex(image::contrast(p.dominant)),
+            accent_text: image::hex(image::contrast(p.accent)),
+            palette: p.colors.into_iter().map(image::hex).collect(),
+        }
+    }
+}
+
+/// Fetches the beatmap background once per change and derives `theme.json` (its colours) and
+/// `cover.jpg` (resized / cropped / blurred copy) from it.
+#[derive(Default)]
+pub struct Background { key: Option<String> }
+
+impl Background {
+    /// `key` identifies the background (set folder + file name) so rate changes don't refetch it.
+    /// It's only remembered once the files are written, so a failed map is tried again.
+    pub async fn update(&mut self, static_root: &Path, http: &Client, url: &str, key: String, cfg: &Config) {
+        if self.key.as_ref() == Some(&key) { return; }
+        let dir = output::overlay_dir(static_root);
+        if render(&dir, http, url, cfg).await {
+            self.key = Some(key);
+        } else {
+            // the previous map's colours and cover would pass for this one's
+            remove(&dir).await;
+        }
+    }
+}
+
+async fn remove(dir: &Path) {
+    for f in ["theme.json", "cover.jpg"] { tokio::fs::remove_file(dir.join(f)).await.ok(); }
+}
+
+/// Fetch the background and write `theme.json` / `cover.jpg`; false (already logged) if any
+/// step failed. A map without a background succeeds with neither file.
+async fn render(dir: &Path, http: &Client, url: &str, cfg: &Config) -> bool {
+    let bytes = match http.get(url).timeout(Duration::from_millis(cfg.file_timeout_ms)).send().await {
+        Ok(r) if r.status().is_success() => match r.bytes().await { Ok(b) => b, Err(e) => { warn!(target: "tosu", %e, "read background"); return false; } },
+        Ok(r) => {
+            // no background for this map: don't leave the previous map's files up
+            debug!(target: "tosu", status = %r.status(), "no background");
+            remove(dir).await;
+            return true;
+        }
+        Err(e) => { warn!(target: "tosu", %e, "GET background"); return false; }
+    };
+
+    let (colors, cover, blur, quality) = (cfg.theme.then_some(cfg.theme_colors), cfg.cover, cfg.cover_blur, cfg.cover_quality);
+    let work = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+        let img = image::decode(&bytes)?;
+        let palette = colors.and_then(|n| image::palette(&img, n));
+        let cover = cover.map(|(w, h)| {
+            let mut c = img.cover(w, h);
+            c.blur(blur);
+            crate::jpeg::encode(&c, quality)
+        });
+        Ok((palette, cover))
+    }).await;
+    let (palette, cover) = match work {
+        Ok(Ok(r)) => r,
+        Ok(Err(e)) => { debug!(target: "output", "background not decoded: {e:#}"); return false; }
+        Err(e) => { warn!(target: "output", %e, "background task failed"); return false; }
+    };
+
+    let mut ok = true;
+    match palette {
+        Some(p) => match output::encode(&ThemeOut::from(p), Format::Json, cfg) {
+            Ok(b) => if let Err(e) = tokio::fs::write(dir.join("theme.json"), &b).await { warn!(target: "output", %e, "failed to write theme.json"); ok = false; },
+            Err(e) => { warn!(target: "output", %e, "encode theme.json"); ok = false; }
+        },
+        // nothing to take colours from (an empty image)
+        None if cfg.theme => { tokio::fs::remove_file(dir.join("theme.json")).await.ok(); }
+        None => {}
+    }
+    if let Some(jpg) = cover {
+        if let Err(e) = tokio::fs::write(dir.join("cover.jpg"), &jpg).await { warn!(target: "output", %e, "failed to write cover.jpg"); ok = false; }
+    }
+    ok
+}
@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use reqwest::Client;
+use tracing::*;
+
+use crate::retry::Policy;
+
+/// Where a chart comes from: an osu! beatmap (difficulty) ID or a local `.osu` file.
+#[derive(Clone, Debug)]
+pub enum MapRef { Id(u64), File(PathBuf) }
+
+impl MapRef {
+    /// `123`, `https://osu.ppy.sh/b/123`, `.../beatmaps/123`, `.../beatmapsets/1#mania/123`, or a path.
+    pub fn parse(s: &str) -> Option<MapRef> {
+        let s = s.trim();
+        if s.is_empty() { return None; }
+        if let Ok(id) = s.parse() { return Some(MapRef::Id(id)); }
+        if s.starts_with("http://") || s.starts_with("https://") {
+            let url = url::Url::parse(s).ok()?;
+            // the difficulty ID is the fragment's last segment on set links, else the path's
+            if let Some(id) = url.fragment().and_then(|f| f.rsplit('/').next()).and_then(|x| x.parse().ok()) {
+                return Some(MapRef::Id(id));
+            }
+            let mut seg = url.path_segments()?.filter(|p| !p.is_empty());
+            return match (seg.next(), seg.next()) {
+                (Some("b" | "beatmaps"), Some(id)) => id.parse().ok().map(MapRef::Id),
+                _ => None,
+            };
+        }
+        Some(MapRef::File(PathBuf::from(s)))
+    }
+
+    pub fn beatmap_id(&self) -> Option<u64> { match self { MapRef::Id(id) => Some(*id), MapRef::File(_) => None } }
+}
+
+/// Downloaded `.osu` files: `MSD_BEATMAP_CACHE`, else the OS cache dir.
+pub fn cache_dir() -> PathBuf {
+    if let Ok(p) = std::env::var("MSD_BEATMAP_CACHE") { return PathBuf::from(p); }
+    dirs::cache_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("beatmaps")
+}
+
+/// `.osu` contents for `r`; IDs come from the cache or osu.ppy.sh (and are cached), retried per `retry`.
+pub async fn load(http: &Client, r: &MapRef, retry: Policy) -> anyhow::Result<Vec<u8>> {
+    match r {
+        MapRef::File(p) => tokio::fs::read(p).await.with_context(|| format!("reading {}", p.display())),
+        MapRef::Id(id) => {
+            if let Ok(b) = tokio::fs::read(cache_dir().join(format!("{id}.osu"))).await { return Ok(b); }
+            let bytes = retry.run(&format!("beatmap {id}"), || download(http, *id)).await?;
+            store(*id, &bytes).await;
+            Ok(bytes)
+        }
+    }
+}
+
+async fn download(http: &Client, id: u64) -> anyhow::Result<Vec<u8>> {
+    info!(target: "calc", "downloading beatmap {id}");
+    let rsp = http.get(format!("https://osu.ppy.sh/osu/{id}")).send().await?.error_for_status()?;
+    let bytes = rsp.bytes().await?.to_vec();
+    // unknown IDs come back as an empty 200
+    anyhow::ensure!(!bytes.is_empty(), "beatmap {id} not found");
+    Ok(bytes)
+}
+
+/// Put a `.osu` in the cache (best effort).
+pub async fn store(id: u64, bytes: &[u8]) {
+    let path = cache_dir().join(format!("{id}.osu"));
+    if let Err(e) = save(&path, bytes).await { debug!(target: "calc", %e, "caching {}", path.display()); }
+}
+
+async fn save(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() { tokio::fs::create_dir_all(dir).await?; }
+    tokio::fs::write(path, bytes).await
+}
@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use minacalc_rs::Calc;
+
+use crate::{calc, config::{arg_value, Config}};
+
+const USAGE: &str = "usage: minacalc-overlay bench <file.osu> [--iterations N] [--rate 1.0]";
+
+/// `bench <file.osu>`: run the live path's work for one chart (parse, MSD, `MSD_CURVE`,
+/// section breakdown) over and over with one calculator, as a long session would, and report
+/// the time per step and how much resident memory grew (Linux only, from `/proc/self/statm`).
+/// Steady growth here is a leak or churn that a stream left running for hours would feel.
+pub fn run(cfg: &Config) -> anyhow::Result<()> {
+    let path = std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(USAGE)?;
+    let bytes = std::fs::read(&path).with_context(|| format!("reading {path}"))?;
+    // Shift-JIS and UTF-16 charts too, as the live path reads them
+    let (osu, _) = calc::decode(&bytes);
+    let iterations: usize = arg_value("--iterations").map(|n| n.parse().context("--iterations must be a number")).transpose()?.unwrap_or(200).max(1);
+    let rate: f32 = arg_value("--rate").map(|r| r.parse().context("--rate must be a number like 1.1")).transpose()?.unwrap_or(1.0);
+    let calc = Calc::new()?;
+
+    let mut times = [Duration::ZERO; 4];
+    let mut step = |i: usize, t: Instant| times[i] += t.elapsed();
+    // the first round allocates what later ones should reuse; measure growth after it
+    let mut baseline = None;
+    for n in 0..iterations {
+        let t = Instant::now();
+        let (map, notes) = calc::notes(&osu)?;
+        step(0, t);
+        let t = Instant::now();
+        calc::ssr(&calc, &notes, rate, cfg.score_goal)?;
+        step(1, t);
+        let t = Instant::now();
+        if !cfg.curve.is_empty() { calc::curve(&calc, &notes, rate, &cfg.curve)?; }
+        step(2, t);
+        let t = Instant::now();
+        calc::sections(&calc, &map, &notes, rate, cfg.score_goal, &Default::default())?;
+        step(3, t);
+        if n == 0 { baseline = resident_bytes(); }
+    }
+
+    println!("{path}: {iterations} iterations at {}x", calc::rate_label(rate));
+    for (name, t) in ["parse", "msd", "curve", "sections"].iter().zip(times) {
+        println!("  {name:<9} {:>8.2} ms/iteration", t.as_secs_f64() * 1000.0 / iterations as f64);
+    }
+    match (baseline, resident_bytes()) {
+        (Some(before), Some(after)) => println!("  resident  {:.1} MiB after the first iteration, {:+.1} KiB after the rest",
+            before as f64 / (1024.0 * 1024.0), (after as f64 - before as f64) / 1024.0),
+        _ => println!("  resident  only measured on Linux"),
+    }
+    Ok(())
+}
+
+/// Resident set size on Linux; `None` elsewhere.
+fn resident_bytes() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+        // SAFETY: sysconf has no preconditions
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        Some(pages * u64::try_from(page).ok()?)
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
@@ -0,0 +1,792 @@
+use std::{sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
+use minacalc_rs::{Calc, OsuCalcExt, SkillsetScores, ThreadSafeCalcPool};
+use serde::Serialize;
+
+/// 93.0 is the common Etterna score goal used for MSD (`MSD_SCORE_GOAL` can change it; the
+/// library is always rated at this one)
+pub const SCORE_GOAL: f32 = 93.0;
+/// MinaCalc treats higher goals as this (`ssr_goal_cap`).
+pub const GOAL_CAP: f32 = 96.5;
+
+/// The msd.json payload.
+#[derive(Serialize, Default, Clone)]
+pub struct MsdOut {
+    pub song: String,
+    pub diff: String,
+    pub overall: f32,
+    pub stamina: f32,
+    pub jumpstream: f32,
+    pub handstream: f32,
+    pub stream: f32,
+    pub chordjack:f32,
+    pub jacks: f32,
+    pub technical: f32,
+    pub rate: String, // "1.60"
+    /// lazer's speed slider while it's still moving (`MSD_RATE_SETTLE_MS`); `rate` and the MSD
+    /// stay those of the last calculation until it holds still.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub live_rate: Option<String>,
+    /// Wife% the skillsets are calculated for (`MSD_SCORE_GOAL` unless it follows the live score).
+    pub goal: f32,
+    /// MD5 of the `.osu`, the checksum osu! itself uses (osu!.db, scores.db, the API).
+    pub md5: String,
+    pub beatmap_id: Option<u64>,
+    pub set_id: Option<u64>,
+    /// `https://osu.ppy.sh/b/<beatmap_id>` (or the set page when only the set is known).
+    pub url: Option<String>,
+    /// The speed mod behind `rate` (`DT`, `NC`, `HT`, `DC`), if any.
+    pub speed_mod: Option<String>,
+    /// Whether audio pitch follows the rate (NC / DC, or DT / HT with `adjust_pitch`); MSD is the same either way.
+    pub pitched: bool,
+    /// Active mods (acronyms), minus `MSD_MODS_IGNORE`.
+    pub mods: Vec<String>,
+    /// Caveats, e.g. for `MSD_MODS_UNSUPPORTED` mods that are on.
+    pub notes: Vec<String>,
+    /// Hit windows for the map's OD with the active mods and rate; only set by the live poll loop.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub windows: Option<crate::wife::Windows>,
+    /// Overall MSD at a sweep of goals (`MSD_CURVE`), lowest goal first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub curve: Vec<CurvePoint>,
+    /// Every skillset at each `MSD_LADDER` goal, lowest goal first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ladder: Vec<LadderStep>,
+    /// MSD per section of the chart (`MSD_SECTIONS`), in chart order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<Section>,
+    /// Percent of the library this chart is harder than, per skillset; only set by the live poll loop.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentile: Option<crate::library::Percentile>,
+    /// How the notes spread over columns and hands; only set by the live poll loop.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load: Option<Load>,
+    /// The same chart through the `MSD_CALC_COMPARE` calculator, if one is loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compare: Option<crate::compare::Comparison>,
+    /// What the result before this one showed, so overlays can tween from it; only set by the live poll loop.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<Previous>,
+    /// Skillsets minus the previous chart's at the same rate and goal; only set by the live poll loop.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<Delta>,
+    /// MinaCalc's own version number; MSD shifts between calculator releases.
+    pub calc_version: i32,
+    /// This program's version.
+    pub overlay_version: &'static str,
+    /// Where the time went for this result; only set by the live poll loop.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
+    /// Set for maps MinaCalc can't rate (other modes, other key counts): the skillsets are all 0
+    /// and this says why, with tosu's star rating instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unrated: Option<Unrated>,
+    /// The player's tags on this chart (`tag`, gRPC `TagMap`).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The player's note on this chart; not to be confused with `notes`, the caveats.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_note: Option<String>,
+    /// Every skillset at each rate of `TABLE_RATES` (`MSD_RATE_TABLE`), lowest first; only set by
+    /// the live poll loop, once the background table is done.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rates: Vec<crate::library::Ratings>,
+}
+
+/// A map that isn't 4K mania.
+#[derive(Serialize, Clone, Debug)]
+pub struct Unrated {
+    /// `osu`, `taiko`, `catch` or `mania`.
+    pub mode: &'static str,
+    /// Key count, for mania.
+    pub keys: Option<u32>,
+    /// tosu's star rating for the map with the current mods (rosu-pp), when it sends one.
+    pub stars: Option<f32>,
+}
+
+impl Unrated {
+    pub fn of(map: &rosu_map::Beatmap) -> Self {
+        use rosu_map::section::general::GameMode;
+        let mode = match map.mode { GameMode::Osu => "osu", GameMode::Taiko => "taiko", GameMode::Catch => "catch", GameMode::Mania => "mania" };
+        Unrated { mode, keys: (map.mode == GameMode::Mania).then_some(map.circle_size.round() as u32), stars: None }
+    }
+}
+
+impl std::fmt::Display for Unrated {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.keys {
+            Some(k) => write!(f, "{k}K mania; MinaCalc only rates 4K"),
+            None => write!(f, "an osu!{} map; MinaCalc only rates 4K mania", if self.mode == "osu" { "standard" } else { self.mode }),
+        }
+    }
+}
+
+impl std::error::Error for Unrated {}
+
+#[derive(Serialize, Clone)]
+pub struct Previous {
+    pub song: String,
+    pub diff: String,
+    pub rate: String,
+    pub overall: f32,
+}
+
+/// Change in each skillset from one chart to the next; positive is harder.
+#[derive(Serialize, Clone)]
+pub struct Delta {
+    pub overall: f32,
+    pub stamina: f32,
+    pub jumpstream: f32,
+    pub handstream: f32,
+    pub stream: f32,
+    pub chordjack: f32,
+    pub jacks: f32,
+    pub technical: f32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CurvePoint {
+    pub goal: f32,
+    pub overall: f32,
+}
+
+/// The skillsets at one wife% goal.
+#[derive(Serialize, Clone)]
+pub struct LadderStep {
+    pub goal: f32,
+    pub overall: f32,
+    pub stamina: f32,
+    pub jumpstream: f32,
+    pub handstream: f32,
+    pub stream: f32,
+    pub chordjack: f32,
+    pub jacks: f32,
+    pub technical: f32,
+}
+
+/// A stretch of the chart between bookmarks, kiai toggles or BPM changes.
+#[derive(Serialize, Clone)]
+pub struct Section {
+    /// `Bookmark 2`, `Kiai 1`, `180 BPM`, ...
+    pub label: String,
+    /// Map time (ms, before rate), start inclusive.
+    pub start_ms: i32,
+    pub end_ms: i32,
+    /// Rows (chords count once).
+    pub notes: usize,
+    /// `None` when the section is too short to rate.
+    pub overall: Option<f32>,
+    /// The section's strongest skillset (`stream`, `chordjack`, ...), stamina aside.
+    pub skillset: Option<&'static str>,
+}
+
+/// `graph.json` (`MSD_GRAPH`): the chart's difficulty over time.
+#[derive(Serialize)]
+pub struct Graph {
+    pub md5: String,
+    pub rate: String,
+    pub goal: f32,
+    /// Length of each of `chunks`, real seconds.
+    pub chunk_secs: u32,
+    /// Notes in each real second (a chord counts once per column).
+    pub nps: Vec<u32>,
+    pub chunks: Vec<Chunk>,
+}
+
+/// `chunk_secs` of the chart.
+#[derive(Serialize)]
+pub struct Chunk {
+    pub start_secs: u32,
+    /// Rows (chords count once).
+    pub notes: usize,
+    /// `None` when there are too few notes to rate (a break).
+    pub overall: Option<f32>,
+    /// Its strongest skillset, stamina aside.
+    pub skillset: Option<&'static str>,
+}
+
+/// Note counts per column and per hand (columns 1-2 left, 3-4 right).
+#[derive(Serialize, Clone)]
+pub struct Load {
+    pub columns: Vec<Share>,
+    pub left: Share,
+    pub right: Share,
+    /// Most notes in any one second of real time (after the rate), all columns.
+    pub peak_nps: f32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Share {
+    pub notes: usize,
+    /// Fraction of all the chart's notes, 0-1, to three decimals.
+    pub share: f32,
+    /// Most of these notes in any one second of real time.
+    pub peak_nps: f32,
+}
+
+/// Pipeline timestamps (unix ms) and durations (ms), so overlays can compensate for delay.
+#[derive(Serialize, Default, Clone)]
+pub struct Timing {
+    /// When the tosu snapshot (`/json/v2`) this result is based on arrived.
+    pub snapshot_unix_ms: u64,
+    /// When the calculation finished.
+    pub calc_unix_ms: u64,
+    /// When the result was handed to the outputs.
+    pub emitted_unix_ms: u64,
+    /// Fetching `/json/v2` and the `.osu` from tosu.
+    pub fetch_ms: u64,
+    /// Parsing the map and running MinaCalc.
+    pub calc_ms: u64,
+    /// Requesting the snapshot to emit, end to end.
+    pub latency_ms: u64,
+}
+
+impl MsdOut {
+    pub fn new(song: String, diff: String, scores: &SkillsetScores, rate: String) -> Self {
+        MsdOut {
+            song,
+            diff,
+            overall: scores.overall,
+            stamina: scores.stamina,
+            jumpstream: scores.jumpstream,
+            handstream: scores.handstream,
+            stream: scores.stream,
+            chordjack: scores.chordjack,
+            jacks: scores.jackspeed,
+            technical: scores.technical,
+            rate,
+            live_rate: None,
+            goal: SCORE_GOAL,
+            md5: String::new(),
+            beatmap_id: None,
+            set_id: None,
+            url: None,
+            speed_mod: None,
+            pitched: false,
+            mods: Vec::new(),
+            notes: Vec::new(),
+            windows: None,
+            curve: Vec::new(),
+            ladder: Vec::new(),
+            sections: Vec::new(),
+            percentile: None,
+            load: None,
+            compare: None,
+            previous: None,
+            delta: None,
+            calc_version: Calc::version(),
+            overlay_version: env!("CARGO_PKG_VERSION"),
+            timing: None,
+            unrated: None,
+            tags: Vec::new(),
+            user_note: None,
+            rates: Vec::new(),
+        }
+    }
+
+    /// A map MinaCalc can't rate: every skillset 0, and `unrated` saying why.
+    pub fn unrated(song: String, diff: String, rate: String, unrated: Unrated) -> Self {
+        let zero = SkillsetScores { overall: 0.0, stream: 0.0, jumpstream: 0.0, handstream: 0.0, stamina: 0.0, jackspeed: 0.0, chordjack: 0.0, technical: 0.0 };
+        MsdOut { unrated: Some(unrated), ..MsdOut::new(song, diff, &zero, rate) }
+    }
+
+    pub fn with_ids(self, beatmap_id: Option<u64>, set_id: Option<u64>) -> Self {
+        let url = match (beatmap_id, set_id) {
+            (Some(b), _) => Some(format!("https://osu.ppy.sh/b/{b}")),
+            (None, Some(s)) => Some(format!("https://osu.ppy.sh/s/{s}")),
+            _ => None,
+        };
+        MsdOut { beatmap_id, set_id, url, ..self }
+    }
+
+    /// `None` unless `before` was rated at the same rate and goal; the difference would say more
+    /// about the rate than about the charts.
+    pub fn delta(&self, before: &MsdOut) -> Option<Delta> {
+        if before.rate != self.rate || before.goal != self.goal { return None; }
+        Some(Delta {
+            overall: self.overall - before.overall,
+            stamina: self.stamina - before.stamina,
+            jumpstream: self.jumpstream - before.jumpstream,
+            handstream: self.handstream - before.handstream,
+            stream: self.stream - before.stream,
+            chordjack: self.chordjack - before.chordjack,
+            jacks: self.jacks - before.jacks,
+            technical: self.technical - before.technical,
+        })
+    }
+
+    pub fn previous(&self) -> Previous {
+        Previous { song: self.song.clone(), diff: self.diff.clone(), rate: self.rate.clone(), overall: self.overall }
+    }
+}
+
+/// Score goal that follows the player's live wife% estimate, moving only in `step` increments
+/// so every judgement doesn't trigger a recalc.
+#[derive(Default)]
+pub struct GoalFollow { goal: Option<f32> }
+
+impl GoalFollow {
+    /// Fewer judgements than this and the estimate is too noisy to follow.
+    const MIN_JUDGED: u32 = 20;
+
+    /// The goal to calculate for; `base` until the estimate is good enough.
+    pub fn next(&mut self, hits: Option<&crate::wife::Hits>, windows: Option<&crate::wife::Windows>, step: f32, base: f32) -> f32 {
+        let estimate = hits.filter(|h| h.judged() >= Self::MIN_JUDGED)
+            .and_then(|h| crate::wife::from_hits(h, windows?))
+            .map(|w| w.clamp(1.0, GOAL_CAP));
+        match (estimate, self.goal) {
+            (Some(e), Some(g)) if (e - g).abs() < step => {}
+            (Some(e), _) => self.goal = Some(e),
+            (None, _) => {}
+        }
+        self.goal.unwrap_or(base)
+    }
+
+    /// Back to the base goal (the play ended).
+    pub fn reset(&mut self) { self.goal = None; }
+}
+
+/// `MSD_SECTIONS` and `MSD_GRAPH` of one chart.
+pub struct Breakdown { pub sections: Vec<Section>, pub graph: Option<Graph> }
+type BreakdownJob = tokio::task::JoinHandle<Option<Breakdown>>;
+
+/// Sections and the graph rate the chart again a few dozen times over, so they run on the
+/// blocking pool once the chart's own result is out and the poll loop adds them when they're
+/// done. One chart at a time: starting another, or cancelling, stops the running one.
+#[derive(Default)]
+pub struct Breakdowns {
+    running: Option<(CalcKey, Arc<AtomicBool>, BreakdownJob)>,
+}
+
+impl Breakdowns {
+    /// Start chart `key`'s breakdown, whichever of `MSD_SECTIONS` and `MSD_GRAPH` is on.
+    pub fn start(&mut self, key: CalcKey, map: rosu_map::Beatmap, notes: Vec<minacalc_rs::Note>, rate: f32, goal: f32, cfg: &crate::config::Config) {
+        self.cancel();
+        let (with_sections, graph_secs) = (cfg.sections, cfg.graph.then_some(cfg.graph_secs));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        let job = tokio::task::spawn_blocking(move || {
+            // a cancelled breakdown fails on purpose; that's not worth a warning
+            let warn = |code: &str, what: &str, e: anyhow::Error| if !flag.load(Ordering::Relaxed) {
+                tracing::warn!(target: "calc", code, %e, "{what} failed");
+            };
+            let sections = if !with_sections { Vec::new() } else {
+                pooled(|c| sections(c, &map, &notes, rate, goal, &flag)).unwrap_or_else(|e| { warn("calc.sections", "section breakdown", e); Vec::new() })
+            };
+            let graph = graph_secs.and_then(|secs| pooled(|c| graph(c, &notes, rate, goal, secs, &flag)).map_err(|e| warn("calc.graph", "graph.json", e)).ok());
+            (!flag.load(Ordering::Relaxed)).then_some(Breakdown { sections, graph })
+        });
+        self.running = Some((key, cancel, job));
+    }
+
+    /// Stop the running breakdown, if any; its threads finish the calculation they're on.
+    pub fn cancel(&mut self) {
+        if let Some((_, cancel, _)) = self.running.take() { cancel.store(true, Ordering::Relaxed); }
+    }
+
+    /// A finished breakdown and the chart it's for, the one time it completes.
+    pub async fn poll(&mut self) -> Option<(CalcKey, Breakdown)> {
+        if !self.running.as_ref()?.2.is_finished() { return None; }
+        let (key, _, job) = self.running.take()?;
+        match job.await {
+            Ok(b) => Some((key, b?)),
+            Err(e) => { tracing::warn!(target: "calc", code = "calc.sections", %e, "section worker panicked"); None }
+        }
+    }
+}
+
+/// What a poll's result depends on; a poll with the same key as the last calculation is skipped.
+/// Mods and pitch are in it because they change the output (DT -> NC), not the MSD; the goal and
+/// MinaCalc version because they change the MSD itself; and the LN / key-mode conversion
+/// (`mods::conversion`) because it changes the chart being played.
+#[derive(Clone, PartialEq)]
+pub struct CalcKey { sha1: String, rate: String, mods: String, conversion: String, pitched: bool, goal: f32, calc_version: i32 }
+
+impl CalcKey {
+    pub fn new(sha1: &str, rate: &str, mods: &[String], conversion: &str, pitched: bool, goal: f32) -> CalcKey {
+        CalcKey { sha1: sha1.into(), rate: rate.into(), mods: mods.concat(), conversion: conversion.into(), pitched, goal, calc_version: Calc::version() }
+    }
+}
+
+/// Holds the calc back while the rate on the same chart keeps moving: scrubbing lazer's speed
+/// slider passes a dozen rates in a second, and only the one it stops at is worth calculating.
+#[derive(Default)]
+pub struct RateSettle { seen: Option<(String, String)>, since: Option<Instant> }
+
+impl RateSettle {
+    /// Whether to wait before calculating chart `sha1` at `rate`: the chart was last calculated
+    /// at `calculated` (on this chart), and `rate` hasn't held for `settle` yet.
+    pub fn waiting(&mut self, sha1: &str, rate: &str, calculated: Option<&str>, settle: Duration) -> bool {
+        let now = (sha1.to_string(), rate.to_string());
+        if self.seen.as_ref() != Some(&now) {
+            self.seen = Some(now);
+            self.since = Some(Instant::now());
+        }
+        calculated.is_some_and(|c| c != rate) && self.since.is_some_and(|t| t.elapsed() < settle)
+    }
+}
+
+/// Etterna's rate list: 0.7x to 2.0x in 0.05 steps.
+pub const TABLE_RATES: std::ops::RangeInclusive<u32> = 14..=40;
+
+/// A finished rate table: the chart's SHA-1, the goal, and the ratings per rate.
+pub type Table = (String, f32, Vec<crate::library::Ratings>);
+type TableJob = std::thread::JoinHandle<anyhow::Result<Vec<crate::library::Ratings>>>;
+
+/// `MSD_RATE_TABLE`: the chart on screen at every rate of `TABLE_RATES`, calculated on a
+/// thread of its own after the chart loads, so the poll loop can answer rate changes from it.
+/// One table is calculated at a time, for the newest chart asked for.
+#[derive(Default)]
+pub struct RateTable {
+    done: Option<Table>,
+    wanted: Option<(String, f32, Vec<minacalc_rs::Note>)>,
+    running: Option<(String, f32, TableJob)>,
+}
+
+impl RateTable {
+    /// Ask for chart `sha1`'s table at `goal`, unless it's done or on its way.
+    pub fn want(&mut self, sha1: &str, goal: f32, notes: &[minacalc_rs::Note]) {
+        let is = |s: &str, g: f32| s == sha1 && g == goal;
+        if self.done.as_ref().is_some_and(|t| is(&t.0, t.1)) || self.running.as_ref().is_some_and(|r| is(&r.0, r.1)) { return; }
+        self.wanted = Some((sha1.to_string(), goal, notes.to_vec()));
+    }
+
+    /// Start the wanted table if nothing is running, and collect a finished one: returns it
+    /// the one time it completes.
+    pub fn poll(&mut self) -> Option<&Table> {
+        let mut finished = false;
+        if self.running.as_ref().is_some_and(|r| r.2.is_finished()) {
+            let (sha1, goal, handle) = self.running.take()?;
+            match handle.join().unwrap_or_else(|_| Err(anyhow::anyhow!("rate table thread panicked"))) {
+                Ok(rows) => { self.done = Some((sha1, goal, rows)); finished = true; }
+                Err(e) => tracing::warn!(target: "calc", code = "calc.rate_table", "rate table failed: {e:#}"),
+            }
+        }
+        if self.running.is_none() {
+            if let Some((sha1, goal, notes)) = self.wanted.take() {
+                let handle = std::thread::spawn(move || pooled(|c| {
+                    TABLE_RATES.map(|i| {
+                        let rate = i as f32 / 20.0;
+                        Ok((&MsdOut::new(String::new(), String::new(), &ssr(c, &notes, rate, goal)?, rate_label(rate))).into())
+                    }).collect()
+                }));
+                self.running = Some((sha1, goal, handle));
+            }
+        }
+        self.done.as_ref().filter(|_| finished)
+    }
+
+    /// The skillsets of `sha1` at `rate` and `goal`, if the table has them.
+    pub fn scores(&self, sha1: &str, rate: &str, goal: f32) -> Option<SkillsetScores> {
+        let (s, g, rows) = self.done.as_ref()?;
+        (s == sha1 && *g == goal).then(|| rows.iter().find(|r| r.rate == rate).map(SkillsetScores::from))?
+    }
+
+    /// The table for `sha1`, at whatever goal it was asked for; empty until it's done.
+    pub fn rows(&self, sha1: &str) -> Vec<crate::library::Ratings> {
+        self.done.as_ref().filter(|t| t.0 == sha1).map(|t| t.2.clone()).unwrap_or_default()
+    }
+}
+
+/// The map the poll loop last calculated, for outputs that refresh between calcs.
+pub struct Current {
+    pub out: MsdOut,
+    pub od: f32,
+    pub notes: usize,
+}
+
+/// `BeatmapID` / `BeatmapSetID` from the `.osu`; old or unsubmitted maps have none (0 or -1).
+pub fn map_ids(map: &rosu_map::Beatmap) -> (Option<u64>, Option<u64>) {
+    let id = |n: i32| u64::try_from(n).ok().filter(|&n| n > 0);
+    (id(map.beatmap_id), id(map.beatmap_set_id))
+}
+
+/// "Artist - Title", or "Unknown Song" when both are empty.
+pub fn song_label(artist: &str, title: &str) -> String {
+    if !artist.is_empty() || !title.is_empty() { format!("{artist} - {title}") } else { "Unknown Song".to_string() }
+}
+
+pub fn rate_label(rate: f32) -> String { format!("{:.2}", rate) }
+
+/// `1.0,1.1x,1.2`; `None` if any entry isn't a rate between 0 and 3.
+pub fn parse_rates(s: &str) -> Option<Vec<f32>> {
+    s.split(',').map(|r| r.trim().trim_end_matches('x').parse::<f32>().ok().filter(|r| *r > 0.0 && *r <= 3.0)).collect()
+}
+
+/// Build notes from the osu!mania 4K map and compute SSR *at the exact rate* for a wife% `goal`.
+/// OsuCalcExt::to_notes_merged converts Beatmap → Vec<Note>, then Calc::calc_ssr runs at any float rate.
+pub fn calc_osu(calc: &Calc, osu: &str, rate: f32, goal: f32) -> anyhow::Result<(rosu_map::Beatmap, SkillsetScores)> {
+    let (beatmap, notes) = notes(osu)?;
+    Ok((beatmap, ssr(calc, &notes, rate, goal)?))
+}
+
+/// `calc <file.osu> [--rate 1.1] [--goal 93] [--json]`: one chart's MSD without tosu, as a table,
+/// or with `--json` as the msd.json payload (with `MSD_CURVE`, `MSD_LADDER` and `MSD_SECTIONS`
+/// applied like in the live loop).
+pub fn run(cfg: &crate::config::Config) -> anyhow::Result<()> {
+    use anyhow::Context;
+    const USAGE: &str = "usage: minacalc-overlay calc <file.osu> [--rate 1.1] [--goal 93] [--json]";
+    let path = std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(USAGE)?;
+    let rate: f32 = crate::config::arg_value("--rate").map(|r| r.trim_end_matches('x').parse().ok().filter(|r| *r > 0.0 && *r <= 3.0).context("--rate must be a number between 0 and 3, like 1.1")).transpose()?.unwrap_or(1.0);
+    let bytes = std::fs::read(&path).with_context(|| format!("reading {path}"))?;
+    let (osu, decoded) = decode(&bytes);
+    let calc = Calc::new()?;
+    let goal = cfg.score_goal;
+    let (map, notes) = notes(&osu).with_context(|| path.clone())?;
+    let scores = ssr(&calc, &notes, rate, goal)?;
+    let song = crate::sanitize::meta(&song_label(&map.artist, &map.title), cfg);
+    let diff = crate::sanitize::meta(&map.version, cfg);
+
+    if !std::env::args().any(|a| a == "--json") {
+        println!("{song} [{diff}] @{}x, {goal}% goal", rate_label(rate));
+        let rows = [("overall", scores.overall), ("stamina", scores.stamina), ("jumpstream", scores.jumpstream), ("handstream", scores.handstream),
+            ("stream", scores.stream), ("chordjack", scores.chordjack), ("jacks", scores.jackspeed), ("technical", scores.technical)];
+        for (name, msd) in rows { println!("  {name:<11} {msd:>6.2}"); }
+        if let Some(n) = decoded { println!("  ({n})"); }
+        return Ok(());
+    }
+    let (beatmap_id, set_id) = map_ids(&map);
+    let out = MsdOut {
+        md5: crate::md5::hex(&bytes), goal, notes: decoded.into_iter().collect(),
+        curve: if cfg.curve.is_empty() { Vec::new() } else { curve(&calc, &notes, rate, &cfg.curve)? },
+        ladder: if cfg.ladder.is_empty() { Vec::new() } else { ladder(&calc, &notes, rate, &cfg.ladder)? },
+        sections: if cfg.sections { sections(&calc, &map, &notes, rate, goal, &AtomicBool::new(false))? } else { Vec::new() },
+        windows: Some(crate::wife::Windows::new(map.overall_difficulty, &[], rate)),
+        load: Some(load(&notes, rate)),
+        ..MsdOut::new(song, diff, &scores, rate_label(rate))
+    }.with_ids(beatmap_id, set_id);
+    println!("{}", String::from_utf8(crate::output::encode(&out, crate::output::Format::Json, cfg)?)?);
+    Ok(())
+}
+
+/// Overall MSD of the chart at each of `goals`.
+pub fn curve(calc: &Calc, notes: &[minacalc_rs::Note], rate: f32, goals: &[f32]) -> anyhow::Result<Vec<CurvePoint>> {
+    goals.iter().map(|&goal| Ok(CurvePoint { goal, overall: ssr(calc, notes, rate, goal)?.overall })).collect()
+}
+
+/// Every skillset of the chart at each of `goals`.
+pub fn ladder(calc: &Calc, notes: &[minacalc_rs::Note], rate: f32, goals: &[f32]) -> anyhow::Result<Vec<LadderStep>> {
+    goals.iter().map(|&goal| {
+        let s = ssr(calc, notes, rate, goal)?;
+        Ok(LadderStep {
+            goal, overall: s.overall, stamina: s.stamina, jumpstream: s.jumpstream, handstream: s.handstream,
+            stream: s.stream, chordjack: s.chordjack, jacks: s.jackspeed, technical: s.technical,
+        })
+    }).collect()
+}
+
+/// Fewer rows than this and MinaCalc has too little to go on.
+const MIN_ROWS: usize = 20;
+
+/// MSD per section. Sections come from the editor bookmarks if the mapper set any, else from
+/// kiai toggles, else from uninherited timing points that change the BPM; sections without notes
+/// are left out, and a chart that doesn't split gets one section. Setting `cancel` stops it
+/// with an error.
+pub fn sections(calc: &Calc, map: &rosu_map::Beatmap, notes: &[minacalc_rs::Note], rate: f32, goal: f32, cancel: &AtomicBool) -> anyhow::Result<Vec<Section>> {
+    let Some(last) = notes.last() else { return Ok(Vec::new()) };
+    let end = (last.row_time * 1000.0) as i32 + 1;
+    let mut cuts: Vec<(i32, String)> = vec![(i32::MIN, String::new())];
+    if !map.bookmarks.is_empty() {
+        cuts[0].1 = "Start".into();
+        cuts.extend(map.bookmarks.iter().enumerate().map(|(i, &t)| (t, format!("Bookmark {}", i + 1))));
+    } else if map.control_points.effect_points.iter().any(|p| p.kiai) {
+        let (mut kiai, mut parts) = (0, 1);
+        cuts[0].1 = "Part 1".into();
+        let mut on = false;
+        for p in &map.control_points.effect_points {
+            if p.kiai == on { continue; }
+            on = p.kiai;
+            let label = if on { kiai += 1; format!("Kiai {kiai}") } else { parts += 1; format!("Part {parts}") };
+            cuts.push((p.time as i32, label));
+        }
+    } else {
+        let bpm = |beat_len: f64| (60_000.0 / beat_len).round();
+        let mut prev = None;
+        for p in &map.control_points.timing_points {
+            let b = bpm(p.beat_len);
+            if prev == Some(b) { continue; }
+            prev = Some(b);
+            if cuts.len() == 1 && cuts[0].1.is_empty() { cuts[0].1 = format!("{b} BPM"); } else { cuts.push((p.time as i32, format!("{b} BPM"))); }
+        }
+        if cuts[0].1.is_empty() { cuts[0].1 = "Chart".into(); }
+    }
+    cuts.sort_by_key(|(t, _)| *t);
+
+    // (label, start, stop, rows) of the sections with notes; rows are in time order, so each
+    // section is a slice of `notes` rather than a copy
+    let at = |ms: i32| notes.partition_point(|n| ((n.row_time * 1000.0) as i32) < ms);
+    let parts: Vec<(&String, i32, i32, &[minacalc_rs::Note])> = cuts.iter().enumerate().map(|(i, (start, label))| {
+        let stop = cuts.get(i + 1).map_or(i32::MAX, |(t, _)| *t);
+        (label, *start, stop, &notes[at(*start)..at(stop)])
+    }).filter(|p| !p.3.is_empty()).collect();
+    let rated: Vec<&[minacalc_rs::Note]> = parts.iter().map(|p| p.3).filter(|rows| rows.len() >= MIN_ROWS).collect();
+    let mut scores = ssr_all(calc, &rated, rate, goal, cancel)?.into_iter();
+
+    let mut out = Vec::new();
+    for &(label, start, stop, rows) in &parts {
+        let Some(first) = rows.first() else { continue };
+        let scores = if rows.len() >= MIN_ROWS { scores.next() } else { None };
+        out.push(Section {
+            label: label.clone(),
+            start_ms: if start == i32::MIN { (first.row_time * 1000.0) as i32 } else { start },
+            end_ms: stop.min(end),
+            notes: rows.len(),
+            overall: scores.map(|s| s.overall),
+            skillset: scores.map(|s| strongest(&s)),
+        });
+    }
+    Ok(out)
+}
+
+/// The chart over real time at `rate`: notes in every second, and the MSD of every
+/// `chunk_secs` seconds, both counted from the start of the audio. Setting `cancel` stops it
+/// with an error.
+pub fn graph(calc: &Calc, notes: &[minacalc_rs::Note], rate: f32, goal: f32, chunk_secs: u32, cancel: &AtomicBool) -> anyhow::Result<Graph> {
+    let secs = |n: &minacalc_rs::Note| (n.row_time / rate).max(0.0) as usize;
+    let mut nps = vec![0; notes.last().map_or(0, |n| secs(n) + 1)];
+    for n in notes { nps[secs(n)] += n.notes.count_ones(); }
+
+    // rows are in time order, so each chunk is a slice of `notes`
+    let len = chunk_secs as f32 * rate;
+    let at = |t: f32| notes.partition_point(|n| n.row_time < t);
+    let parts: Vec<&[minacalc_rs::Note]> = (0..nps.len().div_ceil(chunk_secs as usize))
+        .map(|i| &notes[at(i as f32 * len)..at((i + 1) as f32 * len)]).collect();
+    let rated: Vec<&[minacalc_rs::Note]> = parts.iter().copied().filter(|rows| rows.len() >= MIN_ROWS).collect();
+    let mut scores = ssr_all(calc, &rated, rate, goal, cancel)?.into_iter();
+    let chunks = parts.iter().enumerate().map(|(i, rows)| {
+        let scores = if rows.len() >= MIN_ROWS { scores.next() } else { None };
+        Chunk { start_secs: i as u32 * chunk_secs, notes: rows.len(), overall: scores.map(|s| s.overall), skillset: scores.map(|s| strongest(&s)) }
+    }).collect();
+    Ok(Graph { md5: String::new(), rate: rate_label(rate), goal, chunk_secs, nps, chunks })
+}
+
+/// Column and hand breakdown of a 4K chart at `rate`, so a heavily one-handed file shows
+/// before it's played. Chords count one note per column; `peak_nps` is over a sliding second.
+pub fn load(notes: &[minacalc_rs::Note], rate: f32) -> Load {
+    const KEYS: u32 = 4;
+    let total: usize = notes.iter().map(|n| n.notes.count_ones() as usize).sum();
+    let share = |mask: u32| {
+        // (map time, notes) of the rows that touch `mask`
+        let rows: Vec<(f32, u32)> = notes.iter().map(|n| (n.row_time, (n.notes & mask).count_ones())).filter(|r| r.1 > 0).collect();
+        let count = rows.iter().map(|r| r.1 as usize).sum::<usize>();
+        Share { notes: count, share: if total == 0 { 0.0 } else { (count as f32 / total as f32 * 1000.0).round() / 1000.0 }, peak_nps: peak(&rows, rate) }
+    };
+    Load {
+        columns: (0..KEYS).map(|c| share(1 << c)).collect(),
+        left: share(0b0011),
+        right: share(0b1100),
+        peak_nps: share(0b1111).peak_nps,
+    }
+}
+
+/// Most notes within one real-time second; `rows` are in time order.
+fn peak(rows: &[(f32, u32)], rate: f32) -> f32 {
+    // a real second covers `rate` seconds of map time
+    let (mut start, mut inside, mut best) = (0, 0, 0);
+    for &(t, n) in rows {
+        inside += n;
+        while t - rows[start].0 >= rate {
+            inside -= rows[start].1;
+            start += 1;
+        }
+        best = best.max(inside);
+    }
+    best as f32
+}
+
+/// Rate several charts at once: the caller's `calc` plus a few helper threads with pooled ones,
+/// leaving a core or two for osu!. Results are in input order; the first failure, or `cancel`,
+/// stops what hasn't started.
+fn ssr_all(calc: &Calc, charts: &[&[minacalc_rs::Note]], rate: f32, goal: f32, cancel: &AtomicBool) -> anyhow::Result<Vec<SkillsetScores>> {
+    const MAX_HELPERS: usize = 3;
+    let helpers = std::thread::available_parallelism().map_or(0, |n| n.get().saturating_sub(2))
+        .min(MAX_HELPERS).min(charts.len().saturating_sub(1));
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let work = |calc: &Calc| -> anyhow::Result<Vec<(usize, SkillsetScores)>> {
+        let mut done = Vec::new();
+        while !failed.load(Ordering::Relaxed) && !cancel.load(Ordering::Relaxed) {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some(rows) = charts.get(i) else { break };
+            match ssr(calc, rows, rate, goal) {
+                Ok(s) => done.push((i, s)),
+                Err(e) => { failed.store(true, Ordering::Relaxed); return Err(e); }
+            }
+        }
+        Ok(done)
+    };
+    let mut out: Vec<Option<SkillsetScores>> = charts.iter().map(|_| None).collect();
+    std::thread::scope(|scope| -> anyhow::Result<()> {
+        let handles: Vec<_> = (0..helpers).map(|_| scope.spawn(|| {
+            crate::priority::pin_worker();
+            pooled(work)
+        })).collect();
+        let mut results = vec![work(calc)];
+        results.extend(handles.into_iter().map(|h| h.join().unwrap_or_else(|_| Err(anyhow::anyhow!("section worker panicked")))));
+        for (i, s) in results.into_iter().collect::<anyhow::Result<Vec<_>>>()?.into_iter().flatten() { out[i] = Some(s); }
+        Ok(())
+    })?;
+    anyhow::ensure!(!cancel.load(Ordering::Relaxed), "cancelled");
+    out.into_iter().map(|s| s.ok_or_else(|| anyhow::anyhow!("section left unrated"))).collect()
+}
+
+/// Run `f` with a calculator from a shared pool, so calculations outside the poll loop (section
+/// helpers, gRPC, IRC) reuse handles instead of creating and destroying one per request.
+pub fn pooled<T>(f: impl FnOnce(&Calc) -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let calc = ThreadSafeCalcPool::get_global_calc()?;
+    let r = f(&calc);
+    ThreadSafeCalcPool::return_global_calc(calc);
+    r
+}
+
+/// Name of the strongest skillset, stamina aside.
+pub fn strongest(s: &SkillsetScores) -> &'static str {
+    [("stream", s.stream), ("jumpstream", s.jumpstream), ("handstream", s.handstream), ("jacks", s.jackspeed), ("chordjack", s.chordjack), ("technical", s.technical)]
+        .into_iter().max_by(|a, b| a.1.total_cmp(&b.1)).map_or("stream", |(n, _)| n)
+}
+
+/// `.osu` text from the file's bytes. Files are meant to be UTF-8, but old ones can have
+/// Shift-JIS metadata or comments, or be saved as UTF-16; the notes read the same either way.
+/// The second value is a caveat for `notes` when the bytes weren't UTF-8.
+pub fn decode(bytes: &[u8]) -> (std::borrow::Cow<'_, str>, Option<String>) {
+    if let Ok(s) = std::str::from_utf8(bytes) { return (s.into(), None); }
+    if let Some((enc, _)) = encoding_rs::Encoding::for_bom(bytes) {
+        let (s, _) = enc.decode_with_bom_removal(bytes);
+        return (s, Some(format!("the .osu is {}, not UTF-8", enc.name())));
+    }
+    if let Some(s) = encoding_rs::SHIFT_JIS.decode_without_bom_handling_and_without_replacement(bytes) {
+        return (s, Some("the .osu isn't UTF-8; read as Shift_JIS".into()));
+    }
+    (String::from_utf8_lossy(bytes), Some("the .osu isn't UTF-8; unreadable characters in its text were replaced".into()))
+}
+
+pub fn notes(osu: &str) -> anyhow::Result<(rosu_map::Beatmap, Vec<minacalc_rs::Note>)> {
+    // parse & validate (uses rosu_map under the hood)
+    let beatmap: rosu_map::Beatmap = rosu_map::from_str(osu)
+        .map_err(|e| anyhow::anyhow!("parse failed: {e}"))?;
+    // a typed error, so the poll loop can still show something for these
+    if beatmap.mode != rosu_map::section::general::GameMode::Mania || beatmap.circle_size != 4.0 {
+        return Err(Unrated::of(&beatmap).into());
+    }
+    Calc::security_check(&beatmap)
+        .map_err(|e| anyhow::anyhow!("security_check: {e}"))?;
+    let notes = Calc::to_notes_merged(&beatmap)
+        .map_err(|e| anyhow::anyhow!("to_notes_merged: {e}"))?;
+    Ok((beatmap, notes))
+}
+
+/// MD5 of the note rows: each row's time from the first note (to the millisecond) and its
+/// columns. Reuploads with new metadata, audio, hitsounds or a shifted offset keep it.
+pub fn notes_hash(notes: &[minacalc_rs::Note]) -> String {
+    let first = notes.first().map_or(0.0, |n| n.row_time);
+    let mut bytes = Vec::with_capacity(notes.len() * 8);
+    for n in notes {
+        bytes.extend((((n.row_time - first) * 1000.0).round() as u32).to_le_bytes());
+        bytes.extend(n.notes.to_le_bytes());
+    }
+    crate::md5::hex(&bytes)
+}
+
+pub fn ssr(calc: &Calc, notes: &[minacalc_rs::Note], rate: f32, goal: f32) -> anyhow::Result<SkillsetScores> {
+    // the C side wants a fraction (0.93), the wrapper only checks it's within (0, 100]
+    Ok(calc.calc_ssr(notes, rate, goal / 100.0)?)
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+use reqwest::Client;
+use serde_json::json;
+use tracing::*;
+use url::Url;
+
+use crate::{config::Config, history::Play};
+
+enum Target {
+    /// Bot API token and chat ID (`@channel` or a number).
+    Telegram { token: String, chat: String },
+    /// Homeserver, room ID (`!abc:example.org`) and access token.
+    Matrix { homeserver: Url, room: String, token: String },
+}
+
+/// Chat rooms that get event notifications (finished plays, summaries): a Telegram chat via a
+/// bot (`MSD_TELEGRAM_TOKEN` + `MSD_TELEGRAM_CHAT`) and/or a Matrix room (`MSD_MATRIX_URL` +
+/// `MSD_MATRIX_ROOM` + `MSD_MATRIX_TOKEN`). Messages are plain text and fire-and-forget.
+pub struct Chat {
+    http: Client,
+    targets: Vec<Target>,
+}
+
+impl Chat {
+    pub fn new(cfg: &Config, http: Client) -> Chat {
+        let mut targets = Vec::new();
+        if let (Some(token), Some(chat)) = (&cfg.telegram_token, &cfg.telegram_chat) {
+            targets.push(Target::Telegram { token: token.clone(), chat: chat.clone() });
+        }
+        if let (Some(homeserver), Some(room), Some(token)) = (&cfg.matrix_url, &cfg.matrix_room, &cfg.matrix_token) {
+            targets.push(Target::Matrix { homeserver: homeserver.clone(), room: room.clone(), token: token.clone() });
+        }
+        Chat { http, targets }
+    }
+
+    /// `Played Artist - Title [Diff] 1.10x · 27.31 MSD (stream)`
+    pub fn play(&self, p: &Play) {
+        let mods = if p.mods.is_empty() { String::new() } else { format!(" +{}", p.mods.join("")) };
+        self.send(format!("Played {} [{}]{mods} {}x · {:.2} MSD ({})", p.song, p.diff, p.msd.rate, p.msd.overall, p.dominant));
+    }
+
+    pub fn send(&self, text: String) {
+        for target in &self.targets {
+            let (what, req) = match target {
+                Target::Telegram { token, chat } => ("telegram", self.http.post(format!("https://api.telegram.org/bot{token}/sendMessage"))
+                    .json(&json!({ "chat_id": chat, "text": text, "disable_web_page_preview": true }))),
+                Target::Matrix { homeserver, room, token } => {
+                    let mut url = homeserver.clone();
+                    // Matrix wants a fresh transaction ID per message so retries aren't posted twice
+                    let txn = format!("msd{}{:08x}", crate::hub::unix_ms(), rand_u32());
+                    if let Ok(mut seg) = url.path_segments_mut() {
+                        seg.pop_if_empty().extend(["_matrix", "client", "v3", "rooms", room, "send", "m.room.message", &txn]);
+                    }
+                    ("matrix", self.http.put(url).bearer_auth(token).json(&json!({ "msgtype": "m.text", "body": text })))
+                }
+            };
+            let req = req.timeout(Duration::from_secs(10));
+            tokio::spawn(async move {
+                match req.send().await {
+                    Ok(r) if !r.status().is_success() => warn!(target: "notify", status = %r.status(), "{what} message rejected"),
+                    Ok(_) => debug!(target: "notify", "{what} message sent"),
+                    // reqwest's errors carry the URL, and Telegram's has the bot token in it
+                    Err(e) => warn!(target: "notify", e = %e.without_url(), "{what} message failed"),
+                }
+            });
+        }
+    }
+}
+
+fn rand_u32() -> u32 {
+    let mut b = [0u8; 4];
+    getrandom::getrandom(&mut b).ok();
+    u32::from_le_bytes(b)
+}
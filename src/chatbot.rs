@@ -0,0 +1,84 @@
+use std::time::Duration;
+use anyhow::Context;
+use reqwest::Client;
+use serde_json::{json, Value};
+use tokio::sync::watch;
+use tracing::*;
+
+use crate::config::Config;
+
+/// Chat bot APIs only see the latest line; map hovers in song select come far faster than this.
+const MIN_GAP: Duration = Duration::from_secs(5);
+
+enum Bot {
+    /// OAuth token with the `commands` scope; command name as typed in chat (`!map`).
+    Nightbot { token: String, command: String },
+    /// JWT token from the StreamElements dashboard, channel ID, command name without the `!`.
+    StreamElements { token: String, channel: String, command: String },
+}
+
+impl Bot {
+    fn name(&self) -> &'static str { match self { Bot::Nightbot { .. } => "Nightbot", Bot::StreamElements { .. } => "StreamElements" } }
+
+    /// Set the command's reply to `line`; `id` caches the command's ID between calls.
+    async fn push(&self, http: &Client, id: &mut Option<String>, line: &str) -> anyhow::Result<()> {
+        match self {
+            Bot::Nightbot { token, command } => {
+                if id.is_none() {
+                    let list: Value = http.get("https://api.nightbot.tv/1/commands").bearer_auth(token).send().await?.error_for_status()?.json().await?;
+                    *id = find(&list["commands"], "name", command);
+                }
+                let cid = id.as_deref().with_context(|| format!("no Nightbot command {command:?}; create it first"))?;
+                let rsp = http.put(format!("https://api.nightbot.tv/1/commands/{cid}")).bearer_auth(token)
+                    .json(&json!({ "message": line })).send().await?;
+                if rsp.status() == reqwest::StatusCode::NOT_FOUND { *id = None; }
+                rsp.error_for_status()?;
+            }
+            Bot::StreamElements { token, channel, command } => {
+                let base = format!("https://api.streamelements.com/kappa/v2/bot/commands/{channel}");
+                // PUT replaces the whole command, so start from its current settings
+                let list: Value = http.get(&base).bearer_auth(token).send().await?.error_for_status()?.json().await?;
+                let mut cmd = list.as_array().and_then(|l| l.iter().find(|c| c["command"].as_str().is_some_and(|n| n.eq_ignore_ascii_case(command))))
+                    .cloned().with_context(|| format!("no StreamElements command {command:?}; create it first"))?;
+                let cid = cmd["_id"].as_str().context("StreamElements command without _id")?.to_string();
+                cmd["reply"] = json!(line);
+                http.put(format!("{base}/{cid}")).bearer_auth(token).json(&cmd).send().await?.error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `_id` of the entry in `list` whose `key` is `value`.
+fn find(list: &Value, key: &str, value: &str) -> Option<String> {
+    list.as_array()?.iter().find(|c| c[key].as_str().is_some_and(|n| n.eq_ignore_ascii_case(value)))?["_id"].as_str().map(str::to_string)
+}
+
+/// `MSD_NIGHTBOT_*` / `MSD_STREAMELEMENTS_*`: keep a chat command's reply set to the current
+/// map's `MSD_NP_FORMAT` line, so `!map` answers without the bot fetching anything. Send lines
+/// into the returned channel; bursts are coalesced and the latest one wins.
+pub fn spawn(cfg: &Config, http: Client) -> Option<watch::Sender<String>> {
+    let mut bots = Vec::new();
+    if let (Some(token), Some(command)) = (&cfg.nightbot_token, &cfg.nightbot_command) {
+        bots.push(Bot::Nightbot { token: token.clone(), command: command.clone() });
+    }
+    if let (Some(token), Some(channel), Some(command)) = (&cfg.streamelements_token, &cfg.streamelements_channel, &cfg.streamelements_command) {
+        bots.push(Bot::StreamElements { token: token.clone(), channel: channel.clone(), command: command.trim_start_matches('!').to_string() });
+    }
+    if bots.is_empty() { return None; }
+    let (tx, mut rx) = watch::channel(String::new());
+    tokio::spawn(async move {
+        let mut ids = vec![None; bots.len()];
+        while rx.changed().await.is_ok() {
+            let line = rx.borrow_and_update().clone();
+            for (bot, id) in bots.iter().zip(&mut ids) {
+                match bot.push(&http, id, &line).await {
+                    Ok(()) => debug!(target: "notify", "{} command updated", bot.name()),
+                    Err(e) => warn!(target: "notify", "{} command update failed: {e:#}", bot.name()),
+                }
+            }
+            tokio::time::sleep(MIN_GAP).await;
+        }
+    });
+    Some(tx)
+}
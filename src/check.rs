@@ -0,0 +1,367 @@
+use std::{net::SocketAddr, path::{Path, PathBuf}};
+use tracing_subscriber::EnvFilter;
+
+use crate::{config::{self, Config}, find, output::{self, Format}, priority, recalc, retry::Policy};
+
+/// What a setting's value has to look like.
+#[derive(Clone, Copy)]
+enum Kind {
+    Flag,
+    /// A whole number above zero, with a unit for the message.
+    Count(&'static str),
+    /// A whole number, zero allowed (zero usually means off).
+    Number(&'static str),
+    Range(u64, u64),
+    Positive,
+    Addr,
+    AddrOrOff,
+    Url,
+    /// A URL with `{id}` in it.
+    UrlTemplate,
+    HostPort,
+    Format,
+    /// `json`, `msgpack` or `protobuf`: what the aggregator can read.
+    UplinkFormat,
+    Sinks,
+    /// One wife%, `96.5`.
+    Goal,
+    Goals,
+    /// `MSD_GOALS` entries, `pass 28 cj`.
+    PlayerGoals,
+    /// `HH:MM`.
+    Time,
+    WeekdayOrOff,
+    Mods,
+    Size,
+    Level,
+    Levels,
+    Retry,
+    Cores,
+    Rating,
+    Hotkey,
+    /// A file that has to exist.
+    File,
+    /// A file or folder that's written to; its folder gets created, so only the nearest existing
+    /// parent has to be writable.
+    Output,
+    OutputOrOff,
+    /// Comma separated static folders or `tosu.env` files.
+    StaticRoots,
+    Provider,
+    Text,
+    Secret,
+}
+
+/// Every `MSD_` setting, for type checks and typo suggestions.
+const KEYS: &[(&str, Kind)] = &[
+    ("MSD_LOG_LEVEL", Kind::Level), ("MSD_LOG_LEVELS", Kind::Levels), ("MSD_CRASH_AFTER", Kind::Number("failures")),
+    ("MSD_CRASH_DIR", Kind::Output), ("MSD_ERROR_LOG", Kind::OutputOrOff), ("MSD_SELF_TEST", Kind::Flag),
+    ("MSD_HEARTBEAT_URL", Kind::Url), ("MSD_HEARTBEAT_SECS", Kind::Count("seconds")), ("MSD_NOTIFY_AFTER_MINS", Kind::Number("minutes")),
+    ("MSD_JSON_PRETTY", Kind::Flag), ("MSD_JSON_SORT_KEYS", Kind::Flag), ("MSD_LEGACY_JSON", Kind::Flag), ("MSD_OUTPUTS", Kind::Sinks), ("MSD_RECENT", Kind::Number("results")),
+    ("MSD_GRPC", Kind::Addr), ("MSD_GRPC_TOKEN", Kind::Secret), ("MSD_UDP", Kind::Addr), ("MSD_UDP_FORMAT", Kind::Format), ("MSD_MDNS", Kind::Flag),
+    ("MSD_HTTP_FALLBACK", Kind::AddrOrOff), ("MSD_SERVE", Kind::Addr), ("MSD_STATIC_ROOTS", Kind::StaticRoots), ("MSD_PROVIDER", Kind::Provider), ("MSD_AGGREGATOR_URL", Kind::Url), ("MSD_AGGREGATOR_NAME", Kind::Text),
+    ("MSD_AGGREGATOR_TOKEN", Kind::Secret), ("MSD_AGGREGATOR_FORMAT", Kind::UplinkFormat), ("MSD_AGGREGATE", Kind::Addr),
+    ("MSD_AGGREGATE_TOKEN", Kind::Secret), ("MSD_QR", Kind::Flag), ("MSD_QR_SCALE", Kind::Count("pixels per module")),
+    ("MSD_THEME", Kind::Flag), ("MSD_THEME_COLORS", Kind::Count("colours")), ("MSD_COVER", Kind::Size),
+    ("MSD_COVER_BLUR", Kind::Number("pixels")), ("MSD_COVER_QUALITY", Kind::Range(1, 100)),
+    ("MSD_POLL_MS", Kind::Count("milliseconds")), ("MSD_POLL_SLEEP_MS", Kind::Number("milliseconds")),
+    ("MSD_CONNECT_TIMEOUT_MS", Kind::Count("milliseconds")), ("MSD_V2_TIMEOUT_MS", Kind::Count("milliseconds")),
+    ("MSD_FILE_TIMEOUT_MS", Kind::Count("milliseconds")), ("MSD_RETRY_POLL", Kind::Retry), ("MSD_RETRY_BULK", Kind::Retry),
+    ("MSD_KEYS", Kind::Flag), ("MSD_KEYS_WINDOW_MS", Kind::Count("milliseconds")), ("MSD_SCORE", Kind::Flag),
+    ("MSD_MODS_IGNORE", Kind::Mods), ("MSD_MODS_UNSUPPORTED", Kind::Mods), ("MSD_LAST_PLAYED_MINS", Kind::Number("minutes")),
+    ("MSD_GOAL_FOLLOW", Kind::Flag), ("MSD_GOAL_FOLLOW_STEP", Kind::Positive), ("MSD_CURVE", Kind::Goals), ("MSD_SECTIONS", Kind::Flag), ("MSD_GRAPH", Kind::Flag), ("MSD_GRAPH_SECS", Kind::Count("s")),
+    ("MSD_GOALS", Kind::PlayerGoals), ("MSD_SHARED_MEMORY", Kind::Text), ("MSD_SHARED_MEMORY_FORMAT", Kind::Format), ("MSD_RATE_SETTLE_MS", Kind::Number("ms")),
+    ("MSD_META_STRIP_CONTROL", Kind::Flag), ("MSD_META_MAX_LEN", Kind::Number("characters")), ("MSD_META_ASCII", Kind::Flag),
+    ("MSD_SCORE_GOAL", Kind::Goal), ("MSD_GOAL", Kind::Goal), ("MSD_LADDER", Kind::Goals), ("MSD_CALC_CACHE", Kind::Flag), ("MSD_RATE_TABLE", Kind::Flag),
+    ("MSD_LIBRARY", Kind::Output), ("MSD_LIBRARY_CACHE", Kind::Flag), ("MSD_PERCENTILE", Kind::Flag), ("MSD_BEATMAP_CACHE", Kind::Output),
+    ("MSD_MIRROR_URL", Kind::UrlTemplate), ("MSD_WORKERS", Kind::Count("threads")), ("MSD_IO_CONCURRENCY", Kind::Count("reads")),
+    ("MSD_LOW_PRIORITY", Kind::Flag), ("MSD_CPU_AFFINITY", Kind::Cores), ("MSD_RECALC_HOTKEY", Kind::Hotkey),
+    ("MSD_PLAYER_RATING", Kind::Rating), ("MSD_HISTORY", Kind::Flag), ("MSD_HISTORY_FILE", Kind::Output),
+    ("MSD_SKILL_GAP", Kind::Flag), ("MSD_SKILL_GAP_DAYS", Kind::Count("days")), ("MSD_SUMMARY_AT", Kind::Time),
+    ("MSD_SUMMARY_WEEKDAY", Kind::WeekdayOrOff),
+    ("MSD_IRC_USER", Kind::Text), ("MSD_IRC_PASSWORD", Kind::Secret), ("MSD_IRC_SERVER", Kind::HostPort), ("MSD_IRC_ALLOW", Kind::Text),
+    ("MSD_TELEGRAM_TOKEN", Kind::Secret), ("MSD_TELEGRAM_CHAT", Kind::Text),
+    ("MSD_MATRIX_URL", Kind::Url), ("MSD_MATRIX_ROOM", Kind::Text), ("MSD_MATRIX_TOKEN", Kind::Secret),
+    ("MSD_NP", Kind::Flag), ("MSD_NP_FORMAT", Kind::Text), ("MSD_NIGHTBOT_TOKEN", Kind::Secret), ("MSD_NIGHTBOT_COMMAND", Kind::Text),
+    ("MSD_STREAMELEMENTS_TOKEN", Kind::Secret), ("MSD_STREAMELEMENTS_CHANNEL", Kind::Text), ("MSD_STREAMELEMENTS_COMMAND", Kind::Text),
+    ("MSD_LANGUAGE", Kind::Text), ("MSD_LANGUAGE_FILE", Kind::File), ("MSD_TOSU_PATH", Kind::File), ("MSD_OSU_PROCESS", Kind::Text),
+    ("MSD_CALC_COMPARE", Kind::File),
+];
+
+/// Settings read outside `minacalc.env`'s `MSD_` namespace.
+const OTHER_KEYS: [&str; 3] = ["RUST_LOG", "TOSU_ENV_PATH", "STATIC_FOLDER_PATH"];
+
+/// Settings that only do something together: `(set, needs)`.
+const NEEDS: [(&str, &str); 7] = [
+    ("MSD_TELEGRAM_TOKEN", "MSD_TELEGRAM_CHAT"), ("MSD_MATRIX_URL", "MSD_MATRIX_ROOM"), ("MSD_MATRIX_URL", "MSD_MATRIX_TOKEN"),
+    ("MSD_IRC_USER", "MSD_IRC_PASSWORD"), ("MSD_STREAMELEMENTS_TOKEN", "MSD_STREAMELEMENTS_CHANNEL"), ("MSD_AGGREGATE_TOKEN", "MSD_AGGREGATE"),
+    ("MSD_GRPC_TOKEN", "MSD_GRPC"),
+];
+
+/// `config check`: read `minacalc.env` (and the `MSD_` environment variables, which override
+/// it) and the `tosu.env` the sidecar would use, and report every setting that won't do what
+/// it says, with the line it's on and what to write instead. Exits with an error if any did.
+pub fn run(cfg: &Config, tosu_env: Option<PathBuf>) -> anyhow::Result<()> {
+    let mut problems = 0;
+    let mut report = |source: &str, line: Option<usize>, msg: String| {
+        problems += 1;
+        match line {
+            Some(n) => println!("  {source}:{n}: {msg}"),
+            None => println!("  {source}: {msg}"),
+        }
+    };
+
+    let mut set: Vec<String> = Vec::new();
+    match &cfg.path {
+        Some(path) => {
+            println!("{}", path.display());
+            match std::fs::read_to_string(path) {
+                Ok(text) => for (n, key, value) in entries(&text) {
+                    let name = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+                    match value {
+                        None => report(&name, Some(n), format!("{key:?} isn't KEY=value, and none of the file is loaded because of it; add `=` and a value, or start the line with # to comment it out")),
+                        Some(v) => {
+                            if let Some(msg) = check_entry(&key, &v) { report(&name, Some(n), msg); }
+                            set.push(key);
+                        }
+                    }
+                },
+                Err(e) => report(&path.display().to_string(), None, format!("can't read it: {e}")),
+            }
+        }
+        None => println!("no minacalc.env (looked for --config, MINACALC_CONFIG and ./minacalc.env); defaults and environment variables only"),
+    }
+    // real environment variables win over the file, so they're what actually applies
+    let file_keys = set.clone();
+    for (key, value) in std::env::vars().filter(|(k, _)| k.starts_with("MSD_") && !k.starts_with("MSD_TOAST_")) {
+        if let Some(msg) = check_entry(&key, &value) { report("environment", None, msg); }
+        if file_keys.contains(&key) {
+            println!("  note: {key} is also set in the environment, which is what applies instead of the file's line");
+        } else {
+            set.push(key);
+        }
+    }
+    for (a, b) in NEEDS {
+        if set.iter().any(|k| k == a) && !set.iter().any(|k| k == b) {
+            report("settings", None, format!("{a} is set but {b} isn't; it does nothing without it"));
+        }
+    }
+
+    match &tosu_env {
+        Some(path) => {
+            println!("{}", path.display());
+            let name = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+            match std::fs::read_to_string(path) {
+                Ok(text) => {
+                    let static_folder = entries(&text).into_iter().find(|(_, k, _)| k == "STATIC_FOLDER_PATH");
+                    match static_folder {
+                        Some((n, _, Some(v))) => {
+                            let p = crate::paths::plain(Path::new(v.trim()));
+                            let p = if p.is_absolute() { p } else { path.parent().unwrap_or(Path::new(".")).join(p) };
+                            if let Some(msg) = check_dir(&p) { report(&name, Some(n), format!("STATIC_FOLDER_PATH: {msg}")); }
+                        }
+                        _ => report(&name, None, "no STATIC_FOLDER_PATH; tosu serves overlays from it, add STATIC_FOLDER_PATH=./static".into()),
+                    }
+                }
+                Err(e) => report(&name, None, format!("can't read it: {e}")),
+            }
+        }
+        None => println!("no tosu.env found (looked for --tosu-env, TOSU_ENV_PATH, ./tosu.env, ../tosu.env and next to MSD_TOSU_PATH); the overlay goes to ./overlay"),
+    }
+
+    if problems == 0 {
+        println!("everything looks fine");
+        Ok(())
+    } else {
+        anyhow::bail!("{problems} problem{} found", if problems == 1 { "" } else { "s" })
+    }
+}
+
+/// `(line number, key, value)` for each setting line; `None` for a line without `=`.
+fn entries(text: &str) -> Vec<(usize, String, Option<String>)> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { return None; }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        Some(match line.split_once('=') {
+            Some((k, v)) => {
+                let v = v.trim();
+                let v = v.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+                    .or_else(|| v.strip_prefix('\'').and_then(|v| v.strip_suffix('\''))).unwrap_or(v);
+                (i + 1, k.trim().to_string(), Some(v.to_string()))
+            }
+            None => (i + 1, line.to_string(), None),
+        })
+    }).collect()
+}
+
+/// What's wrong with `key=value`, if anything.
+fn check_entry(key: &str, value: &str) -> Option<String> {
+    match KEYS.iter().find(|(k, _)| *k == key) {
+        Some((_, kind)) => check_value(*kind, value.trim()).map(|msg| format!("{key}={value}: {msg}")),
+        None if OTHER_KEYS.contains(&key) => None,
+        None => Some(match suggest(key) {
+            Some(s) => format!("{key} isn't a setting; did you mean {s}?"),
+            None => format!("{key} isn't a setting of minacalc-overlay (the README's Configuration tables list them)"),
+        }),
+    }
+}
+
+/// The known key closest to `key`, if it's close enough to be a typo.
+fn suggest(key: &str) -> Option<&'static str> {
+    let upper = key.trim().to_ascii_uppercase().replace('-', "_");
+    let prefixed = if upper.starts_with("MSD_") { upper } else { format!("MSD_{upper}") };
+    KEYS.iter().map(|(k, _)| (*k, distance(&prefixed, k)))
+        .filter(|&(k, d)| d <= 2.max(k.len() / 5))
+        .min_by_key(|&(_, d)| d)
+        .map(|(k, _)| k)
+}
+
+/// Levenshtein distance.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = (prev + usize::from(ca != *cb)).min(row[j] + 1).min(cur + 1);
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+fn is_off(v: &str) -> bool { matches!(v.to_ascii_lowercase().as_str(), "off" | "0" | "false" | "no") }
+
+fn check_value(kind: Kind, v: &str) -> Option<String> {
+    let fail = |msg: &str| Some(msg.to_string());
+    match kind {
+        Kind::Flag => (!matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on" | "0" | "false" | "no" | "off"))
+            .then(|| "expected true or false".into()),
+        Kind::Count(unit) => match v.parse::<u64>() {
+            Ok(0) => Some(format!("must be at least 1 ({unit}); remove the line for the default")),
+            Ok(_) => None,
+            Err(_) => Some(format!("expected a whole number ({unit})")),
+        },
+        Kind::Number(unit) => v.parse::<u64>().is_err().then(|| format!("expected a whole number ({unit}), or 0")),
+        Kind::Range(lo, hi) => (!v.parse::<u64>().is_ok_and(|n| (lo..=hi).contains(&n))).then(|| format!("expected a whole number from {lo} to {hi}")),
+        Kind::Positive => (!v.parse::<f32>().is_ok_and(|n| n.is_finite() && n > 0.0)).then(|| "expected a number above 0, like 0.5".into()),
+        Kind::Addr => check_addr(v),
+        Kind::AddrOrOff => if is_off(v) { None } else { check_addr(v) },
+        Kind::Url => check_url(v),
+        Kind::UrlTemplate => check_url(v).or_else(|| (!v.contains("{id}")).then(|| "has no {id}; put {id} where the beatmap set ID goes, e.g. https://catboy.best/d/{id}".into())),
+        Kind::HostPort => match v.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => None,
+            _ => fail("expected host:port, like irc.ppy.sh:6667"),
+        },
+        Kind::Provider => (!v.eq_ignore_ascii_case("auto") && crate::provider::Source::from_name(v).is_none())
+            .then(|| "expected auto, tosu, tosu-v1 or gosumemory".into()),
+        Kind::Format => Format::from_name(v).is_none().then(|| "expected json, yaml, xml, msgpack, protobuf or text".into()),
+        Kind::UplinkFormat => (!matches!(Format::from_name(v), Some(Format::Json | Format::MsgPack | Format::Protobuf)))
+            .then(|| "expected json, msgpack or protobuf; other formats are sent as json".into()),
+        Kind::Sinks => check_sinks(v),
+        Kind::Goal => (!v.trim_end_matches('%').parse::<f32>().is_ok_and(|g| g > 0.0 && g <= 100.0)).then(|| "expected a wife% between 0 and 100, like 96.5".into()),
+        Kind::Goals => {
+            if matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on") { return None; }
+            let bad: Vec<&str> = v.split(',').map(str::trim).filter(|g| !g.is_empty())
+                .filter(|g| !g.trim_end_matches('%').parse::<f32>().is_ok_and(|g| g > 0.0 && g <= 100.0)).collect();
+            if !bad.is_empty() { Some(format!("{} {} not a goal between 0 and 100; write them like 90,93,96.5", bad.join(", "), if bad.len() == 1 { "is" } else { "are" })) }
+            else if config::parse_goals(v).is_empty() { fail("no goals; write them like 90,93,96.5, or true for a default set") }
+            else { None }
+        }
+        Kind::PlayerGoals => v.split(',').map(str::trim).filter(|g| !g.is_empty())
+            .find_map(|g| crate::goals::Spec::parse(g).err()).map(|e| format!("{e}; write goals like pass 28 cj,AA 27 at 1.1x")),
+        Kind::Time => crate::summary::parse_time(v).is_none().then(|| "expected a 24-hour time, like 21:30".into()),
+        Kind::WeekdayOrOff => (!is_off(v) && crate::summary::parse_weekday(v).is_none()).then(|| "expected a day of the week, like sunday, or off".into()),
+        Kind::Mods => {
+            let bad: Vec<String> = config::mod_list(v).into_iter().filter(|m| m.len() < 2 || m.len() > 3 || !m.chars().all(|c| c.is_ascii_alphanumeric())).collect();
+            (!bad.is_empty()).then(|| format!("{} {} not a mod acronym; write them like HD,FL", bad.join(", "), if bad.len() == 1 { "is" } else { "are" }))
+        }
+        Kind::Size => config::parse_size(v).is_none().then(|| "expected WIDTHxHEIGHT, each 1 to 4096, like 1280x720".into()),
+        Kind::Level => (!matches!(v.to_ascii_lowercase().as_str(), "trace" | "debug" | "info" | "warn" | "error" | "off"))
+            .then(|| "expected trace, debug, info, warn, error or off".into()),
+        Kind::Levels => EnvFilter::try_new(v).err().map(|e| format!("{e}; write target=level pairs like tosu=error,calc=debug")),
+        Kind::Retry => Policy::parse(v, Policy::POLL).is_none().then(|| "expected attempts[,base ms[,jitter 0-1]], like 3,200,0.3".into()),
+        Kind::Cores => match priority::parse_cores(v) {
+            None => fail("expected core numbers and ranges, like 0,2,4-7"),
+            Some(c) => {
+                let n = std::thread::available_parallelism().map_or(1, |n| n.get());
+                c.iter().all(|&c| c >= n).then(|| format!("this machine only has cores 0-{}", n - 1))
+            }
+        },
+        Kind::Rating => match find::parse_rating(v) {
+            None => Some(format!("expected skillset=MSD pairs, like stream=25,jacks=22 (skillsets: {})", find::SKILLSETS.join(", "))),
+            Some(r) => r.iter().find(|(_, m)| !(0.0..=100.0).contains(m)).map(|(s, m)| format!("{s}={m} is out of range; MSD is between 0 and 100")),
+        },
+        Kind::Hotkey => recalc::parse_hotkey(v).is_none().then(|| "expected a key with optional Ctrl/Shift/Alt, like F8 or Ctrl+Shift+R".into()),
+        Kind::File => {
+            let p = crate::paths::plain(Path::new(v));
+            if p.is_file() { None }
+            else if p.is_dir() { fail("is a folder; point it at the file inside") }
+            else { Some(format!("{} doesn't exist", p.display())) }
+        }
+        Kind::Output => check_dir(Path::new(v).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."))),
+        Kind::OutputOrOff => if is_off(v) { None } else { check_value(Kind::Output, v) },
+        Kind::StaticRoots => v.split(',').map(str::trim).filter(|s| !s.is_empty()).find_map(|s| {
+            let p = Path::new(s);
+            if !p.extension().is_some_and(|e| e.eq_ignore_ascii_case("env")) { return check_dir(p).map(|m| format!("{s}: {m}")); }
+            match std::fs::read_to_string(p) {
+                Ok(text) if entries(&text).iter().any(|(_, k, _)| k == "STATIC_FOLDER_PATH") => None,
+                Ok(_) => Some(format!("{s} has no STATIC_FOLDER_PATH")),
+                Err(e) => Some(format!("{s}: {e}")),
+            }
+        }),
+        Kind::Text | Kind::Secret => v.is_empty().then(|| "is empty; remove the line to leave it unset".into()),
+    }
+}
+
+fn check_addr(v: &str) -> Option<String> {
+    if v.parse::<SocketAddr>().is_ok() { return None; }
+    Some(if v.parse::<u16>().is_ok() { format!("needs an address as well as the port, like 127.0.0.1:{v}") }
+        else if v.starts_with("localhost:") { format!("use the IP address, like 127.0.0.1:{}", v.trim_start_matches("localhost:")) }
+        else { "expected ip:port, like 127.0.0.1:50051".into() })
+}
+
+fn check_url(v: &str) -> Option<String> {
+    match url::Url::parse(v) {
+        Ok(u) if matches!(u.scheme(), "http" | "https" | "ws" | "wss") => None,
+        Ok(u) => Some(format!("{}: is not a web address; use http:// or https://", u.scheme())),
+        Err(url::ParseError::RelativeUrlWithoutBase) => Some(format!("is missing the scheme; try https://{v}")),
+        Err(e) => Some(format!("not a URL ({e})")),
+    }
+}
+
+fn check_sinks(v: &str) -> Option<String> {
+    let mut msgs = Vec::new();
+    for part in v.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if let Some((f, _)) = part.split_once(':').filter(|(f, _)| f.len() > 1 && Format::from_name(f).is_none()) {
+            if !f.chars().all(|c| c.is_ascii_alphabetic()) { continue; }
+            msgs.push(format!("{part}: unknown format {f:?} (json, yaml, xml, msgpack, protobuf, text)"));
+        }
+    }
+    for sink in output::parse_sinks(v) {
+        if sink.path.as_os_str().is_empty() { msgs.push("an entry has no file name".into()); }
+        else if let Some(msg) = sink.path.parent().filter(|p| p.is_absolute()).and_then(check_dir) { msgs.push(format!("{}: {msg}", sink.path.display())); }
+    }
+    (!msgs.is_empty()).then(|| msgs.join("; "))
+}
+
+/// Whether files can be written below `dir`: it, or the nearest parent that exists, must be a
+/// writable folder.
+fn check_dir(dir: &Path) -> Option<String> {
+    let mut p = dir;
+    while !p.exists() {
+        match p.parent().filter(|q| !q.as_os_str().is_empty()) {
+            Some(q) => p = q,
+            None => return None,
+        }
+    }
+    if !p.is_dir() { return Some(format!("{} is a file, not a folder", p.display())); }
+    match std::fs::metadata(p) {
+        Ok(m) if m.permissions().readonly() => Some(format!("{} is read-only", p.display())),
+        Ok(_) => None,
+        Err(e) => Some(format!("{}: {e}", p.display())),
+    }
+}
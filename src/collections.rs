@@ -0,0 +1,143 @@
+use std::path::PathBuf;
+use anyhow::Context;
+use tracing::*;
+
+use crate::{config::{arg_value, Config}, find, game, library::Library};
+
+/// osu!stable's `collection.db` version when there's no file to take it from.
+const DB_VERSION: i32 = 20150203;
+
+/// A collection: its name and the MD5s of its beatmaps.
+pub struct Collection { pub name: String, pub md5s: Vec<String> }
+
+/// `collection.db`: an int version, an int count, then per collection a name, an int count
+/// and that many MD5 strings (ints little-endian, strings as osu! writes them).
+pub struct Db { pub version: i32, pub collections: Vec<Collection> }
+
+struct Reader<'a> { b: &'a [u8], at: usize }
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&[u8]> {
+        let s = self.b.get(self.at..self.at + n).context("collection.db ends early")?;
+        self.at += n;
+        Ok(s)
+    }
+
+    fn int(&mut self) -> anyhow::Result<i32> { Ok(i32::from_le_bytes(self.take(4)?.try_into()?)) }
+
+    /// `0x00` (no string) or `0x0b`, a ULEB128 length and UTF-8.
+    fn string(&mut self) -> anyhow::Result<String> {
+        match self.take(1)?[0] {
+            0x00 => Ok(String::new()),
+            0x0b => {
+                let (mut len, mut shift) = (0usize, 0);
+                loop {
+                    let b = self.take(1)?[0];
+                    anyhow::ensure!(shift < 64, "collection.db: bad string length");
+                    len |= ((b & 0x7f) as usize) << shift;
+                    if b & 0x80 == 0 { break; }
+                    shift += 7;
+                }
+                Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+            }
+            b => anyhow::bail!("collection.db: unexpected string marker {b:#04x} at byte {}", self.at - 1),
+        }
+    }
+}
+
+fn put_string(out: &mut Vec<u8>, s: &str) {
+    if s.is_empty() { out.push(0x00); return; }
+    out.push(0x0b);
+    let mut len = s.len();
+    loop {
+        let b = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 { out.push(b); break; }
+        out.push(b | 0x80);
+    }
+    out.extend_from_slice(s.as_bytes());
+}
+
+impl Db {
+    pub fn parse(b: &[u8]) -> anyhow::Result<Db> {
+        let mut r = Reader { b, at: 0 };
+        let version = r.int()?;
+        let count = r.int()?;
+        let mut collections = Vec::new();
+        for _ in 0..count.max(0) {
+            let name = r.string()?;
+            let n = r.int()?;
+            let md5s = (0..n.max(0)).map(|_| r.string()).collect::<anyhow::Result<Vec<_>>>()?;
+            collections.push(Collection { name, md5s });
+        }
+        Ok(Db { version, collections })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&(self.collections.len() as i32).to_le_bytes());
+        for c in &self.collections {
+            put_string(&mut out, &c.name);
+            out.extend_from_slice(&(c.md5s.len() as i32).to_le_bytes());
+            for m in &c.md5s { put_string(&mut out, m); }
+        }
+        out
+    }
+
+    /// Replace the collection called `name`, or add it at the end.
+    pub fn set(&mut self, name: &str, md5s: Vec<String>) {
+        match self.collections.iter_mut().find(|c| c.name == name) {
+            Some(c) => c.md5s = md5s,
+            None => self.collections.push(Collection { name: name.to_string(), md5s }),
+        }
+    }
+}
+
+/// `collection.db` of a default osu!stable install.
+fn default_db() -> Option<PathBuf> {
+    Some(dirs::data_local_dir()?.join("osu!").join("collection.db")).filter(|p| p.exists())
+}
+
+/// `minacalc-overlay collection <name> [find filters] [--db <collection.db>] [--force]`: put the
+/// charts a `find` query matches into an osu!stable collection, replacing one with the same name.
+/// Refused while osu! is running (`MSD_OSU_PROCESS`), which would write its own copy back on exit.
+pub fn run(cfg: &Config) -> anyhow::Result<()> {
+    const USAGE: &str = "usage: minacalc-overlay collection <name> [--overall 26..28] [--<skillset> <range>] [--dominant <skillset>] [--rate 1.0] [--db <collection.db>] [--force]";
+    let name = std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(USAGE)?;
+    let db_path = arg_value("--db").map(PathBuf::from).or_else(default_db)
+        .context("no collection.db found in the default osu! folder; pass --db <osu! folder>/collection.db")?;
+    if !std::env::args().any(|a| a == "--force") {
+        // with the check off for the poll loop, still look for stable itself
+        let names = if cfg.osu_process.is_empty() { vec!["osu!".to_string()] } else { cfg.osu_process.clone() };
+        match game::running(&names) {
+            Some(true) => anyhow::bail!("osu! is running and rewrites collection.db when it exits; close it first (or pass --force)"),
+            Some(false) => {}
+            None => warn!(target: "output", "can't tell whether osu! is running here; make sure it's closed"),
+        }
+    }
+    let query = find::query_args()?;
+    let library = Library::open(&Library::default_path())?;
+    let (mut md5s, mut no_md5) = (Vec::new(), 0);
+    for c in library.charts.values().filter(|c| query.matches(c).is_some()) {
+        if c.md5.is_empty() { no_md5 += 1; } else { md5s.push(c.md5.clone()); }
+    }
+    if no_md5 > 0 { warn!(target: "output", "{no_md5} matching charts have no MD5 (library entries from an older version); warm-cache again to add them"); }
+
+    let mut db = match std::fs::read(&db_path) {
+        Ok(b) => Db::parse(&b).with_context(|| format!("reading {}", db_path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Db { version: DB_VERSION, collections: Vec::new() },
+        Err(e) => return Err(e).with_context(|| format!("reading {}", db_path.display())),
+    };
+    if db_path.exists() {
+        let backup = db_path.with_extension("db.bak");
+        std::fs::copy(&db_path, &backup).with_context(|| format!("backing up to {}", backup.display()))?;
+    }
+    let count = md5s.len();
+    db.set(&name, md5s);
+    let tmp = db_path.with_extension("db.tmp");
+    std::fs::write(&tmp, db.to_bytes()).with_context(|| format!("writing {}", tmp.display()))?;
+    std::fs::rename(&tmp, &db_path).with_context(|| format!("writing {}", db_path.display()))?;
+    info!(target: "output", "collection {name:?}: {count} charts ({}), {} collections in {}", query.describe(), db.collections.len(), db_path.display());
+    Ok(())
+}
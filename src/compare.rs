@@ -0,0 +1,117 @@
+use std::{cell::RefCell, ffi::{c_int, c_uint, c_void}, path::Path};
+use anyhow::Context;
+use libloading::Library;
+use minacalc_rs::{Calc, Note};
+use serde::Serialize;
+use tracing::*;
+
+/// The C API every MinaCalc build exposes (`API.h`).
+#[repr(C)]
+struct NoteInfo { notes: c_uint, row_time: f32 }
+
+#[repr(C)]
+struct Ssr { overall: f32, stream: f32, jumpstream: f32, handstream: f32, stamina: f32, jackspeed: f32, chordjack: f32, technical: f32 }
+
+type CreateFn = unsafe extern "C" fn() -> *mut c_void;
+type DestroyFn = unsafe extern "C" fn(*mut c_void);
+type VersionFn = unsafe extern "C" fn() -> c_int;
+type SsrFn = unsafe extern "C" fn(*mut c_void, *mut NoteInfo, usize, f32, f32) -> Ssr;
+
+/// A second calculator build loaded at runtime (`MSD_CALC_COMPARE`), so two MinaCalc versions
+/// can be run on the same chart while a rerate is being argued over.
+pub struct OtherCalc {
+    handle: *mut c_void,
+    destroy: DestroyFn,
+    ssr: SsrFn,
+    pub version: i32,
+    /// The C side's copy of the notes, kept between charts instead of allocated per call.
+    rows: RefCell<Vec<NoteInfo>>,
+    // keeps the function pointers above valid; dropped last
+    _lib: Library,
+}
+
+/// The other calculator's result, next to the built-in one.
+#[derive(Serialize, Clone)]
+pub struct Comparison {
+    pub calc_version: i32,
+    pub overall: f32,
+    pub stamina: f32,
+    pub jumpstream: f32,
+    pub handstream: f32,
+    pub stream: f32,
+    pub chordjack: f32,
+    pub jacks: f32,
+    pub technical: f32,
+    /// `overall` minus the built-in calculator's.
+    pub delta: f32,
+}
+
+impl OtherCalc {
+    /// `path` is a MinaCalc shared library (`minacalc.dll`, `libminacalc.so`, ...) built from any
+    /// version's `API.cpp`.
+    pub fn load(path: &Path) -> anyhow::Result<OtherCalc> {
+        // SAFETY: the library is trusted config; loading runs its initialisers
+        let lib = unsafe { Library::new(path) }.with_context(|| format!("loading {}", path.display()))?;
+        // SAFETY: the signatures match API.h
+        let (create, destroy, version, ssr) = unsafe {
+            (
+                *lib.get::<CreateFn>(b"create_calc\0").context("create_calc")?,
+                *lib.get::<DestroyFn>(b"destroy_calc\0").context("destroy_calc")?,
+                *lib.get::<VersionFn>(b"calc_version\0").context("calc_version")?,
+                *lib.get::<SsrFn>(b"calc_ssr\0").context("calc_ssr")?,
+            )
+        };
+        // SAFETY: plain constructor calls
+        let (handle, version) = unsafe { (create(), version()) };
+        anyhow::ensure!(!handle.is_null(), "create_calc returned null");
+        Ok(OtherCalc { handle, destroy, ssr, version, rows: RefCell::new(Vec::new()), _lib: lib })
+    }
+
+    /// Same contract as `Calc::calc_ssr`: non-empty, validated notes and a goal fraction.
+    fn calc_ssr(&self, notes: &[Note], rate: f32, goal: f32) -> Ssr {
+        let mut rows = self.rows.borrow_mut();
+        rows.clear();
+        rows.extend(notes.iter().map(|n| NoteInfo { notes: n.notes, row_time: n.row_time }));
+        // SAFETY: `rows` outlives the call and the handle is live until drop
+        unsafe { (self.ssr)(self.handle, rows.as_mut_ptr(), rows.len(), rate, goal) }
+    }
+
+    /// Run the chart through this calculator at a wife% `goal`; `base_overall` is the built-in result.
+    pub fn compare(&self, notes: &[Note], rate: f32, goal: f32, base_overall: f32) -> anyhow::Result<Comparison> {
+        anyhow::ensure!(!notes.is_empty(), "no notes");
+        let s = self.calc_ssr(notes, rate, goal / 100.0);
+        anyhow::ensure!([s.overall, s.stamina, s.stream, s.technical].iter().all(|v| v.is_finite()), "calc_ssr returned garbage");
+        Ok(Comparison {
+            calc_version: self.version,
+            overall: s.overall,
+            stamina: s.stamina,
+            jumpstream: s.jumpstream,
+            handstream: s.handstream,
+            stream: s.stream,
+            chordjack: s.chordjack,
+            jacks: s.jackspeed,
+            technical: s.technical,
+            delta: s.overall - base_overall,
+        })
+    }
+}
+
+impl Drop for OtherCalc {
+    fn drop(&mut self) {
+        // SAFETY: created by this library's create_calc, destroyed once
+        unsafe { (self.destroy)(self.handle) }
+    }
+}
+
+/// Load `MSD_CALC_COMPARE`, logging (not failing) when it can't be used.
+pub fn load(path: Option<&Path>) -> Option<OtherCalc> {
+    let path = path?;
+    match OtherCalc::load(path) {
+        Ok(c) if c.version == Calc::version() => {
+            warn!(target: "calc", version = c.version, "MSD_CALC_COMPARE is the same MinaCalc version as the built-in one");
+            Some(c)
+        }
+        Ok(c) => { info!(target: "calc", "comparing against MinaCalc version {} from {}", c.version, path.display()); Some(c) }
+        Err(e) => { warn!(target: "calc", "MSD_CALC_COMPARE disabled: {e:#}"); None }
+    }
+}
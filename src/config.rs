@@ -0,0 +1,447 @@
+use std::{net::SocketAddr, path::{Path, PathBuf}};
+use dotenvy::{from_path, var};
+use url::Url;
+use tracing_subscriber::EnvFilter;
+
+use crate::output::{parse_sinks, FileSink, Format};
+use crate::retry::Policy;
+use crate::calc;
+
+/// Value following `flag` on the command line (`--flag <value>`).
+pub fn arg_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args();
+    while let Some(a) = args.next() {
+        if a == flag { return args.next(); }
+    }
+    None
+}
+
+/// `MSD_STATIC_ROOTS` entries: folders as given, `.env` files by their `STATIC_FOLDER_PATH`
+/// (relative to the file, as tosu reads it). Files without one are left out; `config check`
+/// points them out.
+fn static_roots(spec: &str) -> Vec<PathBuf> {
+    spec.split(',').map(str::trim).filter(|s| !s.is_empty()).filter_map(|s| {
+        let p = crate::paths::plain(Path::new(s));
+        if !p.extension().is_some_and(|e| e.eq_ignore_ascii_case("env")) { return Some(p); }
+        let (_, v) = dotenvy::from_path_iter(&p).ok()?.flatten().find(|(k, _)| k == "STATIC_FOLDER_PATH")?;
+        let root = crate::paths::plain(Path::new(&v));
+        Some(crate::paths::redirected(if root.is_absolute() { root } else { p.parent().unwrap_or(Path::new(".")).join(root) }))
+    }).collect()
+}
+
+fn parse_var<T: std::str::FromStr>(key: &str) -> Option<T> {
+    var(key).ok().and_then(|v| v.trim().parse().ok())
+}
+
+/// `1`/`true`/`yes`/`on` (any case) → true; unset or anything else → false.
+fn flag_var(key: &str) -> bool { flag_var_or(key, false) }
+
+/// Like `flag_var`, but unset means `default` and only `0`/`false`/`no`/`off` turn it off.
+fn flag_var_or(key: &str, default: bool) -> bool {
+    match var(key).map(|v| v.trim().to_ascii_lowercase()) {
+        Ok(v) if matches!(v.as_str(), "1" | "true" | "yes" | "on") => true,
+        Ok(v) if matches!(v.as_str(), "0" | "false" | "no" | "off") => false,
+        _ => default,
+    }
+}
+
+/// Mods whose effect on the chart MinaCalc doesn't model: the notes get shuffled, flipped, added or removed.
+const DEFAULT_UNSUPPORTED: &str = "RD,MR,IN,HO,DS,CS,1K,2K,3K,5K,6K,7K,8K,9K,10K";
+
+/// `hd, FL ,fi` → `["HD", "FL", "FI"]`
+pub fn mod_list(s: &str) -> Vec<String> {
+    s.split(',').map(|m| m.trim().to_ascii_uppercase()).filter(|m| !m.is_empty()).collect()
+}
+
+/// `--goal`, else `MSD_SCORE_GOAL`, else its alias `MSD_GOAL`, else 93. A value that isn't a wife%
+/// is an error rather than quietly 93, since every number written would be for the wrong goal.
+fn score_goal() -> anyhow::Result<f32> {
+    let given = arg_value("--goal").map(|g| ("--goal", g))
+        .or_else(|| ["MSD_SCORE_GOAL", "MSD_GOAL"].into_iter().find_map(|k| Some((k, var(k).ok()?))));
+    let Some((from, g)) = given else { return Ok(calc::SCORE_GOAL) };
+    match g.trim().trim_end_matches('%').parse::<f32>() {
+        Ok(goal) if goal > 0.0 && goal <= 100.0 => Ok(goal),
+        _ => anyhow::bail!("{from} is {g:?}; expected a wife% above 0 and at most 100, like 96.5"),
+    }
+}
+
+/// `90,93,96.5` → sorted goals; entries outside (0, 100] are dropped, `true` / `on` means a default sweep.
+pub fn parse_goals(s: &str) -> Vec<f32> {
+    if matches!(s.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on") {
+        return vec![80.0, 85.0, 90.0, 93.0, 94.0, 95.0, 96.0, 96.5];
+    }
+    let mut goals: Vec<f32> = s.split(',').filter_map(|g| g.trim().trim_end_matches('%').parse().ok()).filter(|g| *g > 0.0 && *g <= 100.0).collect();
+    goals.sort_by(f32::total_cmp);
+    goals.dedup();
+    goals
+}
+
+/// `1280x720` → `(1280, 720)`; both sides must be 1–4096.
+pub fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.trim().to_ascii_lowercase().split_once('x').map(|(w, h)| (w.trim().parse().ok(), h.trim().parse().ok()))?;
+    let ok = |n: u32| (1..=4096).contains(&n);
+    Some((w?, h?)).filter(|&(w, h)| ok(w) && ok(h))
+}
+
+/// Find minacalc.env: CLI `--config <path>`, then env `MINACALC_CONFIG`, then `./minacalc.env`.
+fn find_config() -> Option<PathBuf> {
+    if let Some(p) = arg_value("--config") { return Some(PathBuf::from(p)); }
+    if let Ok(p) = std::env::var("MINACALC_CONFIG") { return Some(PathBuf::from(p)); }
+    let p = PathBuf::from("./minacalc.env");
+    p.exists().then_some(p)
+}
+
+/// Sidecar settings. Read from `minacalc.env` (same syntax as tosu.env); real env vars win.
+pub struct Config {
+    pub path: Option<PathBuf>,
+    /// `MSD_LOG_LEVEL`: default level for everything (`info`).
+    pub log_level: String,
+    /// `MSD_LOG_LEVELS`: per-target overrides, e.g. `tosu=error,calc=info`.
+    pub log_levels: String,
+    /// `MSD_CRASH_AFTER`: consecutive calc failures before a crash bundle is written (`5`, `0` = never).
+    pub crash_after: u32,
+    /// `MSD_HEARTBEAT_URL`: pinged (GET) after successful cycles, e.g. a healthchecks.io check URL.
+    pub heartbeat_url: Option<String>,
+    /// `MSD_HEARTBEAT_SECS`: minimum seconds between pings (`60`).
+    pub heartbeat_secs: u64,
+    /// `MSD_NOTIFY_AFTER_MINS`: desktop notification once tosu/calc has been failing this long (off by default).
+    pub notify_after_mins: Option<u64>,
+    /// `MSD_JSON_PRETTY`: indent msd.json (compact by default).
+    pub json_pretty: bool,
+    /// `MSD_JSON_SORT_KEYS`: alphabetical keys instead of the default field order.
+    pub json_sort_keys: bool,
+    /// `MSD_OUTPUTS`: extra files written beside msd.json, `[json|yaml|xml:]path` comma separated.
+    pub outputs: Vec<FileSink>,
+    /// `MSD_GRPC`: serve the gRPC API (proto/minacalc.proto) on this address, e.g. `127.0.0.1:50051`.
+    pub grpc: Option<SocketAddr>,
+    /// `MSD_GRPC_TOKEN`: bearer token for the gRPC calls that change something; without it they're
+    /// only answered on loopback.
+    pub grpc_token: Option<String>,
+    /// `MSD_UDP`: broadcast / multicast every update to this address, e.g. `255.255.255.255:7728`.
+    pub udp: Option<SocketAddr>,
+    /// `MSD_UDP_FORMAT`: `json` (default), `msgpack` or `protobuf`.
+    pub udp_format: Format,
+    /// `MSD_MDNS`: advertise enabled servers on the LAN via mDNS / DNS-SD (on by default).
+    pub mdns: bool,
+    /// `MSD_AGGREGATOR_URL`: `ws://` / `wss://` URL to connect out to and stream every update.
+    pub aggregator_url: Option<Url>,
+    /// `MSD_AGGREGATOR_NAME`: how this instance identifies itself to the aggregator (computer name).
+    pub aggregator_name: String,
+    /// `MSD_AGGREGATOR_TOKEN`: sent as `Authorization: Bearer <token>` on connect.
+    pub aggregator_token: Option<String>,
+    /// `MSD_AGGREGATOR_FORMAT`: `json` (default), `msgpack` or `protobuf`.
+    pub aggregator_format: Format,
+    /// `MSD_AGGREGATE`: run an aggregator on this address that uplinks stream into, e.g. `0.0.0.0:7729`.
+    pub aggregate: Option<SocketAddr>,
+    /// `MSD_AGGREGATE_TOKEN`: bearer token uplinks must present (open if unset).
+    pub aggregate_token: Option<String>,
+    /// `MSD_QR`: write `qr.png` with the current map's link on every map change.
+    pub qr: bool,
+    /// `MSD_QR_SCALE`: pixels per QR module (`8`).
+    pub qr_scale: u32,
+    /// `MSD_THEME`: write `theme.json` with colours taken from the map's background.
+    pub theme: bool,
+    /// `MSD_THEME_COLORS`: palette size in `theme.json` (`5`).
+    pub theme_colors: usize,
+    /// `MSD_COVER`: write `cover.jpg`, the background scaled and cropped to `WIDTHxHEIGHT`.
+    pub cover: Option<(u32, u32)>,
+    /// `MSD_COVER_BLUR`: blur radius in pixels (`0`).
+    pub cover_blur: u32,
+    /// `MSD_COVER_QUALITY`: JPEG quality, 1–100 (`85`).
+    pub cover_quality: u8,
+    /// `MSD_POLL_MS`: how often tosu is polled (`600`).
+    pub poll_ms: u64,
+    /// `MSD_POLL_SLEEP_MS`: extra pause after each poll iteration (`150`).
+    pub poll_sleep_ms: u64,
+    /// `MSD_CONNECT_TIMEOUT_MS`: TCP connect timeout for every outgoing request (`2000`).
+    pub connect_timeout_ms: u64,
+    /// `MSD_V2_TIMEOUT_MS`: whole-request timeout for tosu's `/json/v2` (`3000`).
+    pub v2_timeout_ms: u64,
+    /// `MSD_FILE_TIMEOUT_MS`: whole-request timeout for the `.osu` and background downloads (`10000`).
+    pub file_timeout_ms: u64,
+    /// `MSD_KEYS`: write `keys.json` (key overlay press counts + KPS) during gameplay.
+    pub keys: bool,
+    /// `MSD_KEYS_WINDOW_MS`: span the rolling KPS is measured over (`1000`).
+    pub keys_window_ms: u64,
+    /// `MSD_SCORE`: write `score.json` (osu! accuracy and a wife% estimate) while playing.
+    pub score: bool,
+    /// `MSD_MODS_IGNORE`: mods dropped from the mod list before anything reads it (none).
+    pub mods_ignore: Vec<String>,
+    /// `MSD_MODS_UNSUPPORTED`: mods that get a note in the output because MSD can't account for them.
+    pub mods_unsupported: Vec<String>,
+    /// `MSD_LAST_PLAYED_MINS`: keep the finished play in `last_played.json` for this long (off if unset).
+    pub last_played_mins: Option<u64>,
+    /// `MSD_GOAL_FOLLOW`: during gameplay, calculate for the player's live wife% estimate instead of `score_goal`.
+    pub goal_follow: bool,
+    /// `MSD_GOAL_FOLLOW_STEP`: how far (in wife%) the estimate must move before recalculating (`0.5`).
+    pub goal_follow_step: f32,
+    /// `MSD_CURVE`: wife% goals to list overall MSD at (`curve` in msd.json), empty = off.
+    pub curve: Vec<f32>,
+    /// `MSD_SECTIONS`: add a per-section MSD breakdown (`sections` in msd.json).
+    pub sections: bool,
+    /// `MSD_GRAPH`: write graph.json, NPS per second and MSD per `graph_secs` chunk.
+    pub graph: bool,
+    /// `MSD_GRAPH_SECS`: length of a graph.json chunk in seconds (`10`).
+    pub graph_secs: u32,
+    /// `MSD_CALC_COMPARE`: a second MinaCalc shared library to run every chart through as well.
+    pub calc_compare: Option<PathBuf>,
+    /// `MSD_RECALC_HOTKEY`: key combination that forces a recalc (Windows only).
+    pub recalc_hotkey: Option<String>,
+    /// `MSD_LIBRARY_CACHE`: take scores for charts already in the library instead of calculating (`true`).
+    pub library_cache: bool,
+    /// `MSD_PERCENTILE`: rank each result against the library per skillset (`true`).
+    pub percentile: bool,
+    /// `MSD_WORKERS`: calculator threads for batch commands (CPU count minus one).
+    pub workers: usize,
+    /// `MSD_LOW_PRIORITY`: run below normal process priority, sidecar included (`false`).
+    pub low_priority: bool,
+    /// `MSD_CPU_AFFINITY`: cores calculator workers are pinned to (`0,2-3`); empty = any.
+    pub cpu_affinity: Vec<usize>,
+    /// `MSD_IO_CONCURRENCY`: files read at once by batch commands (`4`).
+    pub io_concurrency: usize,
+    /// `MSD_PLAYER_RATING`: the player's skillset ratings, e.g. `stream=27.1,jumpstream=25.4,...`.
+    pub player_rating: Vec<(&'static str, f32)>,
+    /// `MSD_HISTORY`: append every play to `history.jsonl` (`true`).
+    pub history: bool,
+    /// `MSD_SKILL_GAP`: write `skill_gap.json` after each play.
+    pub skill_gap: bool,
+    /// `MSD_SKILL_GAP_DAYS`: how far back the skill-gap summary looks (`30`).
+    pub skill_gap_days: u64,
+    /// `MSD_IRC_USER`: osu! account the Bancho IRC bot logs in as (off if unset).
+    pub irc_user: Option<String>,
+    /// `MSD_IRC_PASSWORD`: that account's IRC password.
+    pub irc_password: Option<String>,
+    /// `MSD_IRC_SERVER`: `irc.ppy.sh:6667`.
+    pub irc_server: String,
+    /// `MSD_IRC_ALLOW`: who the bot answers (IRC names, comma separated); anyone if empty.
+    pub irc_allow: Vec<String>,
+    /// `MSD_TELEGRAM_TOKEN` / `MSD_TELEGRAM_CHAT`: bot token and chat for event messages.
+    pub telegram_token: Option<String>,
+    pub telegram_chat: Option<String>,
+    /// `MSD_MATRIX_URL` / `MSD_MATRIX_ROOM` / `MSD_MATRIX_TOKEN`: homeserver, room ID and access token for event messages.
+    pub matrix_url: Option<Url>,
+    pub matrix_room: Option<String>,
+    pub matrix_token: Option<String>,
+    /// `MSD_NP`: write `np.txt`, the current map as one line for chat bots.
+    pub np: bool,
+    /// `MSD_NP_FORMAT`: that line's template (`np::DEFAULT_FORMAT`).
+    pub np_format: String,
+    /// `MSD_NIGHTBOT_TOKEN` / `MSD_NIGHTBOT_COMMAND`: keep this Nightbot command's reply on the `MSD_NP_FORMAT` line.
+    pub nightbot_token: Option<String>,
+    pub nightbot_command: Option<String>,
+    /// `MSD_STREAMELEMENTS_TOKEN` / `_CHANNEL` / `_COMMAND`: the same for a StreamElements command.
+    pub streamelements_token: Option<String>,
+    pub streamelements_channel: Option<String>,
+    pub streamelements_command: Option<String>,
+    /// `MSD_LANGUAGE`: language of the labels in `i18n.json` (`en`).
+    pub language: String,
+    /// `MSD_LANGUAGE_FILE`: JSON of label overrides laid over the built-in ones.
+    pub language_file: Option<PathBuf>,
+    /// `MSD_RETRY_POLL`: retries for the requests to tosu on every poll.
+    pub retry_poll: Policy,
+    /// `MSD_RETRY_BULK`: retries for mirror / osu.ppy.sh downloads.
+    pub retry_bulk: Policy,
+    /// `MSD_RECENT`: how many of the latest results `recent.json` and `GetRecent` keep (0 = off).
+    pub recent: usize,
+    /// `MSD_HTTP_FALLBACK`: where to serve the overlay when `STATIC_FOLDER_PATH` isn't writable
+    /// (`127.0.0.1:24051`; `off` never falls back).
+    pub http_fallback: Option<SocketAddr>,
+    /// `MSD_SERVE` (or `--serve <address>`): serve the overlay and its JSON on this address, e.g.
+    /// `127.0.0.1:7727`, from a folder of our own instead of tosu's static folder.
+    pub serve: Option<SocketAddr>,
+    /// `MSD_STATIC_ROOTS`: more static folders (or `tosu.env` files naming one) that get the
+    /// overlay and a copy of msd.json, comma separated.
+    pub static_roots: Vec<PathBuf>,
+    /// `MSD_PROVIDER`: `tosu`, `tosu-v1` or `gosumemory`; unset (`auto`) probes for one.
+    pub provider: Option<crate::provider::Source>,
+    /// `MSD_ERROR_LOG`: where warnings and errors are appended as NDJSON (`off` = nowhere).
+    pub error_log: Option<PathBuf>,
+    /// `MSD_TOSU_PATH`: tosu executable to start and keep running.
+    pub tosu_path: Option<PathBuf>,
+    /// `MSD_OSU_PROCESS`: process names that mean osu! is running (`osu!`; `off` = always poll).
+    pub osu_process: Vec<String>,
+    /// `MSD_SELF_TEST`: check the calculator against a reference chart at startup (`true`).
+    pub self_test: bool,
+    /// `MSD_LEGACY_JSON`: keep msd.json in the original flat shape; the full one goes to msd.v2.json.
+    pub legacy_json: bool,
+    /// `MSD_GOALS`: player goals like `pass 28 cj`, comma separated.
+    pub goals: Vec<String>,
+    /// `MSD_SHARED_MEMORY`: name of a Windows shared-memory region to keep the latest update in
+    /// (`on` for `MinaCalcOverlay`).
+    pub shared_memory: Option<String>,
+    /// `MSD_SHARED_MEMORY_FORMAT`: `json` (default), `msgpack` or `protobuf`.
+    pub shared_memory_format: Format,
+    /// `MSD_RATE_SETTLE_MS`: on lazer, how long a new rate on the same chart has to hold before
+    /// it's calculated (`400`; `0` calculates every step of the speed slider).
+    pub rate_settle_ms: u64,
+    /// `MSD_META_STRIP_CONTROL`: drop control and invisible formatting characters from song and
+    /// difficulty names (on by default).
+    pub meta_strip_control: bool,
+    /// `MSD_META_MAX_LEN`: cut song and difficulty names to this many characters (`200`; `0` keeps them whole).
+    pub meta_max_len: usize,
+    /// `MSD_META_ASCII`: fold song and difficulty names to ASCII, for chats and fonts that can't show the rest.
+    pub meta_ascii: bool,
+    /// `MSD_SCORE_GOAL` (or `MSD_GOAL`, or `--goal`): wife% the MSD is calculated for (`93`).
+    pub score_goal: f32,
+    /// `MSD_LADDER`: wife% goals to list every skillset at (`ladder` in msd.json), empty = off.
+    pub ladder: Vec<f32>,
+    /// `MSD_CALC_CACHE`: keep every live calculation on disk by chart, rate and goal (`true`;
+    /// `--no-cache` turns it off for one run).
+    pub calc_cache: bool,
+    /// `MSD_RATE_TABLE`: rate each new chart at every rate from 0.7x to 2.0x in the background
+    /// (`rates` in msd.json), so rate changes after that don't run MinaCalc.
+    pub rate_table: bool,
+    /// `MSD_SUMMARY_AT`: local time (minutes past midnight) of the daily play summary; off by default.
+    pub summary_at: Option<u32>,
+    /// `MSD_SUMMARY_WEEKDAY`: day the weekly summary goes out too, 0 = Monday (`sunday`; `off` = never).
+    pub summary_weekday: Option<u32>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        let path = find_config();
+        if let Some(p) = &path {
+            // can't log yet, the subscriber is built from this config
+            if let Err(e) = from_path(p) { eprintln!("config {p:?} not loaded: {e}"); }
+        }
+        let score_goal = score_goal()?;
+        Ok(Config {
+            path,
+            log_level: var("MSD_LOG_LEVEL").unwrap_or_else(|_| "info".into()),
+            log_levels: var("MSD_LOG_LEVELS").unwrap_or_default(),
+            crash_after: parse_var("MSD_CRASH_AFTER").unwrap_or(5),
+            heartbeat_url: var("MSD_HEARTBEAT_URL").ok().filter(|u| !u.trim().is_empty()),
+            heartbeat_secs: parse_var("MSD_HEARTBEAT_SECS").unwrap_or(60),
+            notify_after_mins: parse_var("MSD_NOTIFY_AFTER_MINS").filter(|&m| m > 0),
+            json_pretty: flag_var("MSD_JSON_PRETTY"),
+            json_sort_keys: flag_var("MSD_JSON_SORT_KEYS"),
+            outputs: parse_sinks(&var("MSD_OUTPUTS").unwrap_or_default()),
+            grpc: parse_var("MSD_GRPC"),
+            grpc_token: var("MSD_GRPC_TOKEN").ok().filter(|t| !t.trim().is_empty()),
+            udp: parse_var("MSD_UDP"),
+            mdns: flag_var_or("MSD_MDNS", true),
+            udp_format: var("MSD_UDP_FORMAT").ok().and_then(|f| Format::from_name(f.trim())).unwrap_or(Format::Json),
+            aggregator_url: parse_var("MSD_AGGREGATOR_URL"),
+            aggregator_name: var("MSD_AGGREGATOR_NAME").ok().map(|n| n.trim().to_string()).filter(|n| !n.is_empty())
+                .unwrap_or_else(crate::mdns::host_label),
+            aggregator_token: var("MSD_AGGREGATOR_TOKEN").ok().filter(|t| !t.trim().is_empty()),
+            // the aggregator reads these three; `config check` points out the others
+            aggregator_format: var("MSD_AGGREGATOR_FORMAT").ok().and_then(|f| Format::from_name(f.trim()))
+                .filter(|f| matches!(f, Format::Json | Format::MsgPack | Format::Protobuf)).unwrap_or(Format::Json),
+            aggregate: parse_var("MSD_AGGREGATE"),
+            aggregate_token: var("MSD_AGGREGATE_TOKEN").ok().filter(|t| !t.trim().is_empty()),
+            qr: flag_var("MSD_QR"),
+            qr_scale: parse_var("MSD_QR_SCALE").filter(|&s| s > 0).unwrap_or(8),
+            theme: flag_var("MSD_THEME"),
+            theme_colors: parse_var("MSD_THEME_COLORS").filter(|&n| n > 0).unwrap_or(5),
+            cover: var("MSD_COVER").ok().and_then(|s| parse_size(&s)),
+            cover_blur: parse_var("MSD_COVER_BLUR").unwrap_or(0),
+            cover_quality: parse_var("MSD_COVER_QUALITY").filter(|q| (1..=100).contains(q)).unwrap_or(85),
+            poll_ms: parse_var("MSD_POLL_MS").filter(|&ms| ms > 0).unwrap_or(600),
+            poll_sleep_ms: parse_var("MSD_POLL_SLEEP_MS").unwrap_or(150),
+            connect_timeout_ms: parse_var("MSD_CONNECT_TIMEOUT_MS").filter(|&ms| ms > 0).unwrap_or(2000),
+            v2_timeout_ms: parse_var("MSD_V2_TIMEOUT_MS").filter(|&ms| ms > 0).unwrap_or(3000),
+            file_timeout_ms: parse_var("MSD_FILE_TIMEOUT_MS").filter(|&ms| ms > 0).unwrap_or(10_000),
+            keys: flag_var("MSD_KEYS"),
+            keys_window_ms: parse_var("MSD_KEYS_WINDOW_MS").filter(|&ms| ms > 0).unwrap_or(1000),
+            score: flag_var("MSD_SCORE"),
+            mods_ignore: mod_list(var("MSD_MODS_IGNORE").as_deref().unwrap_or("")),
+            mods_unsupported: mod_list(var("MSD_MODS_UNSUPPORTED").as_deref().unwrap_or(DEFAULT_UNSUPPORTED)),
+            last_played_mins: parse_var("MSD_LAST_PLAYED_MINS").filter(|&m| m > 0),
+            goal_follow: flag_var("MSD_GOAL_FOLLOW"),
+            goal_follow_step: parse_var("MSD_GOAL_FOLLOW_STEP").filter(|&s: &f32| s > 0.0).unwrap_or(0.5),
+            curve: parse_goals(&var("MSD_CURVE").unwrap_or_default()),
+            sections: flag_var("MSD_SECTIONS"),
+            graph: flag_var("MSD_GRAPH"),
+            graph_secs: parse_var("MSD_GRAPH_SECS").filter(|&s| s > 0).unwrap_or(10),
+            library_cache: flag_var_or("MSD_LIBRARY_CACHE", true),
+            percentile: flag_var_or("MSD_PERCENTILE", true),
+            // one core stays free for osu!
+            workers: parse_var("MSD_WORKERS").filter(|&n| n > 0)
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1).max(1))),
+            low_priority: flag_var("MSD_LOW_PRIORITY"),
+            cpu_affinity: var("MSD_CPU_AFFINITY").ok().and_then(|c| crate::priority::parse_cores(&c)).unwrap_or_default(),
+            io_concurrency: parse_var("MSD_IO_CONCURRENCY").filter(|&n| n > 0).unwrap_or(4),
+            recalc_hotkey: var("MSD_RECALC_HOTKEY").ok().filter(|k| !k.trim().is_empty()),
+            player_rating: var("MSD_PLAYER_RATING").ok().and_then(|r| crate::find::parse_rating(&r)).unwrap_or_default(),
+            history: flag_var_or("MSD_HISTORY", true),
+            skill_gap: flag_var("MSD_SKILL_GAP"),
+            skill_gap_days: parse_var("MSD_SKILL_GAP_DAYS").filter(|&d| d > 0).unwrap_or(30),
+            irc_user: var("MSD_IRC_USER").ok().map(|u| u.trim().to_string()).filter(|u| !u.is_empty()),
+            irc_password: var("MSD_IRC_PASSWORD").ok().filter(|p| !p.trim().is_empty()),
+            irc_server: var("MSD_IRC_SERVER").ok().filter(|s| !s.trim().is_empty()).unwrap_or_else(|| "irc.ppy.sh:6667".into()),
+            irc_allow: var("MSD_IRC_ALLOW").unwrap_or_default().split(',').map(|n| n.trim().replace(' ', "_")).filter(|n| !n.is_empty()).collect(),
+            telegram_token: var("MSD_TELEGRAM_TOKEN").ok().filter(|t| !t.trim().is_empty()),
+            telegram_chat: var("MSD_TELEGRAM_CHAT").ok().map(|c| c.trim().to_string()).filter(|c| !c.is_empty()),
+            matrix_url: parse_var("MSD_MATRIX_URL"),
+            matrix_room: var("MSD_MATRIX_ROOM").ok().map(|r| r.trim().to_string()).filter(|r| !r.is_empty()),
+            matrix_token: var("MSD_MATRIX_TOKEN").ok().filter(|t| !t.trim().is_empty()),
+            np: flag_var("MSD_NP"),
+            np_format: var("MSD_NP_FORMAT").ok().filter(|f| !f.trim().is_empty()).unwrap_or_else(|| crate::np::DEFAULT_FORMAT.into()),
+            nightbot_token: var("MSD_NIGHTBOT_TOKEN").ok().filter(|t| !t.trim().is_empty()),
+            nightbot_command: var("MSD_NIGHTBOT_COMMAND").ok().map(|c| c.trim().to_string()).filter(|c| !c.is_empty()),
+            streamelements_token: var("MSD_STREAMELEMENTS_TOKEN").ok().filter(|t| !t.trim().is_empty()),
+            streamelements_channel: var("MSD_STREAMELEMENTS_CHANNEL").ok().map(|c| c.trim().to_string()).filter(|c| !c.is_empty()),
+            streamelements_command: var("MSD_STREAMELEMENTS_COMMAND").ok().map(|c| c.trim().to_string()).filter(|c| !c.is_empty()),
+            language: var("MSD_LANGUAGE").ok().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).unwrap_or_else(|| "en".into()),
+            language_file: var("MSD_LANGUAGE_FILE").ok().filter(|p| !p.trim().is_empty()).map(PathBuf::from),
+            retry_poll: var("MSD_RETRY_POLL").ok().and_then(|p| Policy::parse(&p, Policy::POLL)).unwrap_or(Policy::POLL),
+            retry_bulk: var("MSD_RETRY_BULK").ok().and_then(|p| Policy::parse(&p, Policy::BULK)).unwrap_or(Policy::BULK),
+            recent: parse_var("MSD_RECENT").unwrap_or(10),
+            http_fallback: match var("MSD_HTTP_FALLBACK").ok().map(|a| a.trim().to_ascii_lowercase()) {
+                Some(a) if ["off", "0", "false", "no"].contains(&a.as_str()) => None,
+                Some(a) => a.parse().ok().or(Some(SocketAddr::from(([127, 0, 0, 1], 24051)))),
+                None => Some(SocketAddr::from(([127, 0, 0, 1], 24051))),
+            },
+            serve: arg_value("--serve").or_else(|| var("MSD_SERVE").ok()).and_then(|a| a.trim().parse().ok()),
+            provider: var("MSD_PROVIDER").ok().and_then(|v| crate::provider::Source::from_name(v.trim())),
+            static_roots: static_roots(&var("MSD_STATIC_ROOTS").unwrap_or_default()),
+            error_log: match var("MSD_ERROR_LOG").ok().map(|p| p.trim().to_string()) {
+                Some(p) if ["off", "0", "false", "no"].contains(&p.to_ascii_lowercase().as_str()) => None,
+                Some(p) if !p.is_empty() => Some(PathBuf::from(p)),
+                _ => Some(dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("errors.ndjson")),
+            },
+            tosu_path: var("MSD_TOSU_PATH").ok().filter(|p| !p.trim().is_empty()).map(PathBuf::from),
+            osu_process: match var("MSD_OSU_PROCESS").ok().map(|p| p.trim().to_ascii_lowercase()).filter(|p| !p.is_empty()) {
+                Some(p) if ["off", "0", "false", "no"].contains(&p.as_str()) => Vec::new(),
+                p => p.as_deref().unwrap_or("osu!").split(',').map(|n| n.trim().trim_end_matches(".exe").to_string()).filter(|n| !n.is_empty()).collect(),
+            },
+            self_test: flag_var_or("MSD_SELF_TEST", true),
+            legacy_json: flag_var("MSD_LEGACY_JSON"),
+            summary_at: var("MSD_SUMMARY_AT").ok().and_then(|t| crate::summary::parse_time(&t)),
+            summary_weekday: match var("MSD_SUMMARY_WEEKDAY") {
+                Ok(d) if matches!(d.trim().to_ascii_lowercase().as_str(), "off" | "0" | "false" | "no") => None,
+                Ok(d) => crate::summary::parse_weekday(&d).or(Some(6)),
+                Err(_) => Some(6),
+            },
+            goals: var("MSD_GOALS").unwrap_or_default().split(',').map(str::trim).filter(|g| !g.is_empty()).map(String::from).collect(),
+            shared_memory: var("MSD_SHARED_MEMORY").ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+                .and_then(|v| match v.to_ascii_lowercase().as_str() {
+                    "0" | "false" | "no" | "off" => None,
+                    "1" | "true" | "yes" | "on" => Some("MinaCalcOverlay".into()),
+                    _ => Some(v),
+                }),
+            shared_memory_format: var("MSD_SHARED_MEMORY_FORMAT").ok().and_then(|f| Format::from_name(f.trim())).unwrap_or(Format::Json),
+            rate_settle_ms: parse_var("MSD_RATE_SETTLE_MS").unwrap_or(400),
+            meta_strip_control: flag_var_or("MSD_META_STRIP_CONTROL", true),
+            meta_max_len: parse_var("MSD_META_MAX_LEN").unwrap_or(200),
+            meta_ascii: flag_var("MSD_META_ASCII"),
+            score_goal,
+            ladder: parse_goals(&var("MSD_LADDER").unwrap_or_default()),
+            calc_cache: flag_var_or("MSD_CALC_CACHE", true) && !std::env::args().any(|a| a == "--no-cache"),
+            rate_table: flag_var("MSD_RATE_TABLE"),
+            calc_compare: var("MSD_CALC_COMPARE").ok().filter(|p| !p.trim().is_empty()).map(PathBuf::from),
+        })
+    }
+
+    /// `RUST_LOG` still takes precedence when set; otherwise the config levels apply.
+    pub fn log_filter(&self) -> EnvFilter {
+        if let Ok(f) = EnvFilter::try_from_default_env() { return f; }
+        let mut directives = vec![self.log_level.trim()];
+        directives.extend(self.log_levels.split(',').map(str::trim).filter(|d| !d.is_empty()));
+        EnvFilter::try_new(directives.join(",")).unwrap_or_else(|e| {
+            eprintln!("invalid log levels ({e}), falling back to info");
+            EnvFilter::new("info")
+        })
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::VecDeque, io, path::PathBuf, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+use std::fmt::Write as _;
+
+const TAIL_LINES: usize = 200;
+
+static LOG_TAIL: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static SNAPSHOT: Mutex<String> = Mutex::new(String::new());
+
+/// `io::Write` sink for a tracing fmt layer; keeps the last `TAIL_LINES` log lines in memory.
+pub struct TailWriter;
+
+impl io::Write for TailWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut tail = LOG_TAIL.lock().unwrap_or_else(|p| p.into_inner());
+        for line in String::from_utf8_lossy(buf).lines() {
+            if tail.len() == TAIL_LINES { tail.pop_front(); }
+            tail.push_back(line.to_string());
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Remember the raw tracker response, so a bundle shows what we were looking at.
+pub fn record_snapshot(raw: &str) {
+    let mut s = SNAPSHOT.lock().unwrap_or_else(|p| p.into_inner());
+    s.clear();
+    s.push_str(raw);
+}
+
+/// Chain a panic hook that writes a bundle before the default hook prints the panic.
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match write_bundle(&format!("panic: {info}")) {
+            Ok(dir) => eprintln!("crash report written to {}", dir.display()),
+            Err(e) => eprintln!("failed to write crash report: {e}"),
+        }
+        default(info);
+    }));
+}
+
+/// `MSD_CRASH_DIR`, else `<local data dir>/minacalc-overlay/crash`.
+pub fn crash_root() -> PathBuf {
+    if let Ok(p) = std::env::var("MSD_CRASH_DIR") { return PathBuf::from(p); }
+    dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("crash")
+}
+
+/// Write `crash-<unix time>/` with reason + versions, redacted config, log tail and last snapshot.
+/// Sync on purpose: it runs from the panic hook.
+pub fn write_bundle(reason: &str) -> io::Result<PathBuf> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let dir = crash_root().join(format!("crash-{secs}"));
+    std::fs::create_dir_all(&dir)?;
+
+    let mut report = String::new();
+    let _ = writeln!(report, "reason: {reason}");
+    let _ = writeln!(report, "time: {secs} (unix)");
+    let _ = writeln!(report, "minacalc-overlay: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "minacalc: {}", minacalc_rs::Calc::version());
+    let _ = writeln!(report, "os: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+    let _ = writeln!(report, "args: {:?}", std::env::args().collect::<Vec<_>>());
+    std::fs::write(dir.join("report.txt"), report)?;
+
+    std::fs::write(dir.join("config.txt"), redacted_config())?;
+
+    let tail = LOG_TAIL.lock().unwrap_or_else(|p| p.into_inner());
+    std::fs::write(dir.join("log.txt"), tail.iter().fold(String::new(), |acc, l| acc + l + "\n"))?;
+    drop(tail);
+
+    let snap = SNAPSHOT.lock().unwrap_or_else(|p| p.into_inner());
+    std::fs::write(dir.join("snapshot.json"), snap.as_bytes())?;
+    Ok(dir)
+}
+
+/// Sidecar-related env (config file values land there too), secrets masked.
+fn redacted_config() -> String {
+    let mut vars: Vec<(String, String)> = std::env::vars()
+        .filter(|(k, _)| k.starts_with("MSD_") || ["MINACALC_CONFIG", "TOSU_ENV_PATH", "STATIC_FOLDER_PATH", "RUST_LOG"].contains(&k.as_str()))
+        .collect();
+    vars.sort();
+    vars.into_iter().fold(String::new(), |mut out, (k, v)| {
+        let v = if is_secret(&k) && !v.is_empty() { "<redacted>".to_string() } else { v };
+        let _ = writeln!(out, "{k}={v}");
+        out
+    })
+}
+
+fn is_secret(key: &str) -> bool {
+    key.split('_').any(|w| ["TOKEN", "SECRET", "PASSWORD", "KEY", "WEBHOOK", "URL"].contains(&w))
+}
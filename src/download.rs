@@ -0,0 +1,100 @@
+use anyhow::Context;
+use minacalc_rs::Calc;
+use reqwest::Client;
+use tracing::*;
+
+use crate::{beatmaps, calc::{self, MsdOut}, config::{arg_value, Config}, hub::unix_ms, library::{Chart, Library}, progress::Progress, retry::Policy, zip};
+
+/// `{id}` is replaced with the beatmap set ID; must serve the `.osz`.
+const DEFAULT_MIRROR: &str = "https://catboy.best/d/{id}";
+
+/// `minacalc-overlay download <file> [--rates 1.0,1.1] [--quiet | --json-progress]`: fetch every set listed in `<file>` from
+/// a mirror, rate its 4K mania difficulties and add them to the library.
+pub async fn run(cfg: &Config) -> anyhow::Result<()> {
+    let input = std::env::args().nth(2).filter(|a| !a.starts_with("--"))
+        .context("usage: minacalc-overlay download <file with beatmap set IDs> [--rates 1.0,1.1]")?;
+    let text = std::fs::read_to_string(&input).with_context(|| format!("reading {input}"))?;
+    let mut sets = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        sets.push(parse_set(line).with_context(|| format!("line {}: not a beatmap set ID or link: {line:?}", n + 1))?);
+    }
+    let rates = match arg_value("--rates") {
+        Some(r) => calc::parse_rates(&r).context("--rates: comma separated rates like 1.0,1.1")?,
+        None => vec![1.0],
+    };
+    let mirror = std::env::var("MSD_MIRROR_URL").unwrap_or_else(|_| DEFAULT_MIRROR.into());
+
+    let mut library = Library::open(&Library::default_path())?;
+    let http = Client::new();
+    let calc = Calc::new()?;
+    let (mut added, mut empty) = (0, 0);
+    let mut progress = Progress::new("sets", sets.len());
+    for set in &sets {
+        let label = format!("set {set}");
+        match rate_set(&http, &calc, &mirror, *set, &rates, cfg.retry_bulk).await {
+            Ok(charts) => {
+                if charts.is_empty() { debug!(target: "calc", "{label}: no 4K mania difficulties"); empty += 1; }
+                for c in charts {
+                    debug!(target: "calc", "{label}: {} [{}] {:.2}", c.song, c.diff, c.msd.first().map_or(0.0, |m| m.overall));
+                    library.insert(c);
+                    added += 1;
+                }
+                progress.tick(&label);
+            }
+            Err(e) => progress.fail(&label, &e),
+        }
+    }
+    library.save()?;
+    progress.finish(&[("charts added", added), ("sets without 4K mania", empty)]);
+    info!(target: "output", "library at {}", library.path().display());
+    Ok(())
+}
+
+/// `123`, `https://osu.ppy.sh/beatmapsets/123` (with or without `#mania/456`), or `.../s/123`.
+fn parse_set(s: &str) -> Option<u64> {
+    if let Ok(id) = s.parse() { return Some(id); }
+    let url = url::Url::parse(s).ok()?;
+    let mut seg = url.path_segments()?.filter(|p| !p.is_empty());
+    match (seg.next(), seg.next()) {
+        (Some("beatmapsets" | "s"), Some(id)) => id.parse().ok(),
+        _ => None,
+    }
+}
+
+async fn rate_set(http: &Client, calc: &Calc, mirror: &str, set: u64, rates: &[f32], retry: Policy) -> anyhow::Result<Vec<Chart>> {
+    debug!(target: "calc", "downloading set {set}");
+    let url = mirror.replace("{id}", &set.to_string());
+    let osz = retry.run(&format!("set {set}"), || async { Ok(http.get(&url).send().await?.error_for_status()?.bytes().await?) }).await?;
+    let files = zip::read(&osz, |n| n.to_ascii_lowercase().ends_with(".osu")).context("reading .osz")?;
+    let mut out = Vec::new();
+    for (name, bytes) in files {
+        let (text, _) = calc::decode(&bytes);
+        let Ok(map) = rosu_map::from_str::<rosu_map::Beatmap>(&text) else { debug!(target: "calc", "{name}: unparsable"); continue };
+        if map.mode != rosu_map::section::general::GameMode::Mania || map.circle_size != 4.0 { continue; }
+        let (_, notes) = calc::notes(&text).with_context(|| name.clone())?;
+        let mut msd = Vec::with_capacity(rates.len());
+        for &rate in rates {
+            let scores = calc::ssr(calc, &notes, rate, calc::SCORE_GOAL).with_context(|| name.clone())?;
+            msd.push((&MsdOut::new(String::new(), String::new(), &scores, calc::rate_label(rate))).into());
+        }
+        let (beatmap_id, _) = calc::map_ids(&map);
+        // keep the .osu so later lookups by ID (mappool etc.) don't download again
+        if let Some(id) = beatmap_id { beatmaps::store(id, &bytes).await; }
+        out.push(Chart {
+            sha1: sha1_smol::Sha1::from(&bytes).hexdigest(),
+            md5: crate::md5::hex(&bytes),
+            song: calc::song_label(&map.artist, &map.title),
+            diff: map.version.clone(),
+            beatmap_id,
+            set_id: Some(set),
+            path: None,
+            msd,
+            added_unix: unix_ms() / 1000,
+            calc_version: Calc::version(),
+            notes_hash: calc::notes_hash(&notes),
+        });
+    }
+    Ok(out)
+}
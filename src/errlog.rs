@@ -0,0 +1,88 @@
+use std::{fs::{File, OpenOptions}, io::Write, path::PathBuf, sync::Mutex};
+use serde_json::{Map, Value};
+use tracing::{field::{Field, Visit}, Event, Level, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::hub::unix_ms;
+
+/// Start over in a fresh file past this size; the previous one is kept as `errors.ndjson.1`.
+const MAX_BYTES: u64 = 1024 * 1024;
+
+/// md5 of the chart being rated, for records logged while it's current.
+static BEATMAP: Mutex<String> = Mutex::new(String::new());
+
+/// Tag later records with this chart's md5.
+pub fn set_beatmap(md5: &str) {
+    let mut b = BEATMAP.lock().unwrap_or_else(|p| p.into_inner());
+    b.clear();
+    b.push_str(md5);
+}
+
+/// `MSD_ERROR_LOG`: a tracing layer that appends every warning and error to `errors.ndjson`,
+/// one JSON object per line: `unix_ms`, `level`, `code` (the event's `code` field, else its
+/// target), `message`, `context` (target and any other fields) and `md5` of the chart that was
+/// current, so recurring failures can be counted without parsing the human log.
+pub struct ErrorLog { path: PathBuf, file: Mutex<Option<File>> }
+
+impl ErrorLog {
+    pub fn new(path: PathBuf) -> ErrorLog { ErrorLog { path, file: Mutex::new(None) } }
+
+    fn append(&self, line: &[u8]) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|p| p.into_inner());
+        if file.as_ref().and_then(|f| f.metadata().ok()).is_some_and(|m| m.len() > MAX_BYTES) {
+            *file = None;
+            std::fs::rename(&self.path, self.path.with_extension("ndjson.1")).ok();
+        }
+        if file.is_none() {
+            if let Some(dir) = self.path.parent() { std::fs::create_dir_all(dir)?; }
+            *file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        }
+        file.as_mut().map_or(Ok(()), |f| f.write_all(line))
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ErrorLog {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let meta = event.metadata();
+        if *meta.level() > Level::WARN { return; }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let mut context = fields.rest;
+        context.insert("target".into(), meta.target().into());
+        let md5 = BEATMAP.lock().unwrap_or_else(|p| p.into_inner()).clone();
+        let record = serde_json::json!({
+            "unix_ms": unix_ms(),
+            "level": meta.level().as_str(),
+            "code": fields.code.unwrap_or_else(|| meta.target().to_string()),
+            "message": fields.message,
+            "context": context,
+            "md5": (!md5.is_empty()).then_some(md5),
+        });
+        let mut line = record.to_string().into_bytes();
+        line.push(b'\n');
+        // nowhere to report this that wouldn't come straight back here
+        let _ = self.append(&line);
+    }
+}
+
+#[derive(Default)]
+struct Fields { code: Option<String>, message: String, rest: Map<String, Value> }
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "code" => self.code = Some(value.to_string()),
+            "message" => self.message = value.to_string(),
+            name => { self.rest.insert(name.into(), value.into()); }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) { self.rest.insert(field.name().into(), value.into()); }
+    fn record_u64(&mut self, field: &Field, value: u64) { self.rest.insert(field.name().into(), value.into()); }
+    fn record_f64(&mut self, field: &Field, value: f64) { self.rest.insert(field.name().into(), value.into()); }
+    fn record_bool(&mut self, field: &Field, value: bool) { self.rest.insert(field.name().into(), value.into()); }
+}
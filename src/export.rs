@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use tracing::*;
+
+use crate::{config::arg_value, output};
+
+const USAGE: &str = "usage: minacalc-overlay export-overlay <dir> [--url <where the sidecar's overlay folder is served>]";
+
+/// `minacalc-overlay export-overlay <dir> [--url https://host/MinaCalcOnOsu/]`: the installed
+/// overlay as a folder to upload to a static host, for OBS running somewhere tosu isn't. With
+/// `--url` the page reads the JSON from there (the built-in server behind a tunnel, say);
+/// without it the current JSON files are copied in and the page shows that snapshot.
+pub fn run(static_root: &Path) -> anyhow::Result<()> {
+    let out = PathBuf::from(std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(USAGE)?);
+    let url = arg_value("--url").map(|u| if u.ends_with('/') { u } else { format!("{u}/") });
+    if let Some(u) = &url {
+        anyhow::ensure!(u.starts_with("http://") || u.starts_with("https://"), "--url: an http:// or https:// address");
+    }
+    let installed = output::overlay_dir(static_root);
+    let from = if installed.join("index.html").exists() { installed } else { PathBuf::from("overlay") };
+    anyhow::ensure!(from.join("index.html").exists(), "no overlay at {} to export", from.display());
+    anyhow::ensure!(std::fs::canonicalize(&from).ok() != std::fs::canonicalize(&out).ok(), "{} is the overlay itself; export somewhere else", out.display());
+
+    // with a live source the JSON would only be stale copies of what the page reads anyway
+    let skip = |p: &Path| url.is_some() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let n = copy(&from, &out, &skip)?;
+
+    let config = serde_json::json!({ "source": url.as_deref().unwrap_or("./"), "tosu": false });
+    std::fs::write(out.join("config.js"), format!("window.MSD_OVERLAY = {config};\n"))?;
+    let index = out.join("index.html");
+    let html = std::fs::read_to_string(&index)?;
+    // before the page's own script, which reads it; an edited page without one gets it first
+    let tag = "<script src=\"./config.js\"></script>\n";
+    let html = match html.find("<script") {
+        Some(i) => format!("{}{tag}{}", &html[..i], &html[i..]),
+        None => format!("{tag}{html}"),
+    };
+    std::fs::write(&index, html)?;
+
+    match &url {
+        Some(u) => info!(target: "overlay", "exported the overlay ({n} files) to {}; it reads {u}msd.json", out.display()),
+        None => info!(target: "overlay", "exported a snapshot of the overlay ({n} files) to {}", out.display()),
+    }
+    if url.as_deref().is_some_and(|u| u.starts_with("http://")) {
+        info!(target: "overlay", "pages served over https can't read from an http:// address; use an https URL (e.g. a tunnel) if the host is https");
+    }
+    Ok(())
+}
+
+/// Copy the files under `from` into `to` (replacing what's there), minus the ones `skip` says;
+/// returns how many.
+fn copy(from: &Path, to: &Path, skip: &dyn Fn(&Path) -> bool) -> anyhow::Result<usize> {
+    let mut copied = 0;
+    let mut stack = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((src, dest)) = stack.pop() {
+        std::fs::create_dir_all(&dest).with_context(|| format!("creating {}", dest.display()))?;
+        for entry in std::fs::read_dir(&src).with_context(|| format!("reading {}", src.display()))? {
+            let s = entry?.path();
+            let d = dest.join(s.file_name().unwrap_or_default());
+            if s.is_dir() { stack.push((s, d)); continue; }
+            // the sidecar's own write-test and temp files
+            if skip(&s) || s.extension().is_some_and(|e| e == "tmp") || s.file_name().is_some_and(|n| n == ".minacalc-write-test") { continue; }
+            std::fs::copy(&s, &d).with_context(|| format!("copying {}", s.display()))?;
+            copied += 1;
+        }
+    }
+    debug!(target: "overlay", "copied {copied} files from {}", from.display());
+    Ok(copied)
+}
@@ -0,0 +1,239 @@
+use std::{collections::{BTreeMap, HashMap}, ops::RangeInclusive};
+use anyhow::Context;
+use minacalc_rs::SkillsetScores;
+use serde::Serialize;
+use tracing::*;
+
+use crate::{calc, config::{arg_value, Config}, library::{Chart, Library, Ratings}, output::{self, Format}, tags};
+
+pub const SKILLSETS: [&str; 8] = ["overall", "stamina", "jumpstream", "handstream", "stream", "chordjack", "jacks", "technical"];
+
+pub fn skillset_value(r: &Ratings, name: &str) -> f32 {
+    match name {
+        "stamina" => r.stamina,
+        "jumpstream" => r.jumpstream,
+        "handstream" => r.handstream,
+        "stream" => r.stream,
+        "chordjack" => r.chordjack,
+        "jacks" => r.jacks,
+        "technical" => r.technical,
+        _ => r.overall,
+    }
+}
+
+/// `stream=27.1,jumpstream=25.4,...`: skillset ratings; `None` if any entry isn't one.
+pub fn parse_rating(s: &str) -> Option<Vec<(&'static str, f32)>> {
+    s.split(',').filter(|p| !p.trim().is_empty()).map(|p| {
+        let (name, v) = p.split_once('=')?;
+        let name = SKILLSETS.iter().find(|n| n.eq_ignore_ascii_case(name.trim()))?;
+        Some((*name, v.trim().parse::<f32>().ok().filter(|v| v.is_finite())?))
+    }).collect()
+}
+
+/// `26..28`, `26..`, `..28`, or `27` for 26.5..27.5.
+fn parse_range(s: &str) -> Option<RangeInclusive<f32>> {
+    let num = |p: &str, open: f32| if p.trim().is_empty() { Some(open) } else { p.trim().parse::<f32>().ok() };
+    match s.split_once("..") {
+        Some((lo, hi)) => Some(num(lo, f32::MIN)?..=num(hi.trim_start_matches('='), f32::MAX)?),
+        None => { let v: f32 = s.trim().parse().ok()?; Some(v - 0.5..=v + 0.5) }
+    }
+}
+
+/// Every tagged chart's tags, by MD5.
+type Tagged = BTreeMap<String, Vec<String>>;
+
+/// What `find` matches charts against.
+pub struct Query {
+    pub rate: String,
+    /// Skillset name and the MSD range it has to fall in.
+    pub ranges: Vec<(&'static str, RangeInclusive<f32>)>,
+    /// The strongest skillset, stamina aside.
+    pub dominant: Option<String>,
+    /// Tags the chart has to have (`--tag`).
+    pub tags: Vec<String>,
+    pub tagged: Tagged,
+}
+
+impl Query {
+    /// `jumpstream 26–28, dominant jumpstream, 1.00x`, for titles.
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = self.ranges.iter().map(|(name, r)| match (*r.start() == f32::MIN, *r.end() == f32::MAX) {
+            (true, true) => name.to_string(),
+            (true, false) => format!("{name} ≤{}", r.end()),
+            (false, true) => format!("{name} ≥{}", r.start()),
+            (false, false) => format!("{name} {}–{}", r.start(), r.end()),
+        }).collect();
+        if let Some(d) = &self.dominant { parts.push(format!("dominant {d}")); }
+        if !self.tags.is_empty() { parts.push(format!("tagged {}", self.tags.join(", "))); }
+        parts.push(format!("{}x", self.rate));
+        parts.join(", ")
+    }
+
+    /// The chart's ratings at the query rate, if it matches.
+    pub fn matches<'a>(&self, chart: &'a Chart) -> Option<&'a Ratings> {
+        let r = chart.ratings(&self.rate)?;
+        if !self.ranges.iter().all(|(name, range)| range.contains(&skillset_value(r, name))) { return None; }
+        if self.dominant.as_deref().is_some_and(|d| d != calc::strongest(&SkillsetScores::from(r))) { return None; }
+        if !self.tags.is_empty() && !self.tagged.get(&chart.md5).is_some_and(|t| self.tags.iter().all(|w| t.contains(w))) { return None; }
+        Some(r)
+    }
+
+    /// The player's tags on `chart`.
+    pub fn tags_of(&self, chart: &Chart) -> &[String] { self.tagged.get(&chart.md5).map_or(&[], Vec::as_slice) }
+}
+
+/// `--tag speed practice,stamina` and the tags file to check charts against.
+fn tag_args() -> anyhow::Result<(Vec<String>, Tagged)> {
+    let want = arg_value("--tag").map(|t| tags::parse_list(&t)).unwrap_or_default();
+    let tagged = tags::load_all(&tags::default_path())?.into_iter().map(|(md5, e)| (md5, e.tags)).collect();
+    Ok((want, tagged))
+}
+
+#[derive(Serialize)]
+struct Found<'a> {
+    song: &'a str,
+    diff: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    beatmap_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    set_id: Option<u64>,
+    sha1: &'a str,
+    dominant: &'static str,
+    msd: &'a Ratings,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    tags: &'a [String],
+    /// Other charts in the library with the same notes (reuploads), listed once under this one.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    duplicates: Vec<Duplicate<'a>>,
+}
+
+#[derive(Serialize)]
+struct Duplicate<'a> {
+    song: &'a str,
+    diff: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    beatmap_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    set_id: Option<u64>,
+    sha1: &'a str,
+}
+
+/// `minacalc-overlay find [--overall 26..28] [--<skillset> <range>] [--dominant <skillset>] [--rate 1.0] [--limit N] [--json]`:
+/// charts in the library index matching every filter, easiest first.
+pub fn run(cfg: &Config) -> anyhow::Result<()> {
+    let query = query_args()?;
+    let limit = count_arg("--limit")?.unwrap_or(usize::MAX);
+    let library = Library::open(&Library::default_path())?;
+    let mut found = search(&library, &query);
+    found.sort_by(|a, b| a.msd.overall.total_cmp(&b.msd.overall));
+    found.truncate(limit);
+    print(&library, &found, cfg)
+}
+
+/// The `find` filters on the command line: `--rate`, `--<skillset> <range>`, `--dominant`.
+pub fn query_args() -> anyhow::Result<Query> {
+    let rate = rate_arg()?;
+    let mut ranges = Vec::new();
+    for name in SKILLSETS {
+        let Some(v) = arg_value(&format!("--{name}")) else { continue };
+        ranges.push((name, parse_range(&v).with_context(|| format!("--{name}: a range like 26..28, 26.. or ..28"))?));
+    }
+    let dominant = arg_value("--dominant").map(|d| d.to_ascii_lowercase());
+    if let Some(d) = &dominant {
+        anyhow::ensure!(SKILLSETS[2..].contains(&d.as_str()), "--dominant: one of {}", SKILLSETS[2..].join(", "));
+    }
+    let (tags, tagged) = tag_args()?;
+    Ok(Query { rate: calc::rate_label(rate), ranges, dominant, tags, tagged })
+}
+
+/// `minacalc-overlay recommend [--rating stream=27,...] [--skillset <skillset>] [--target 26.5] [--spread 1.0] [--count 20] [--rate 1.0] [--json]`:
+/// a practice queue of charts that mainly test the player's weakest skillset, closest to their rating in it first.
+pub fn recommend(cfg: &Config) -> anyhow::Result<()> {
+    let rating = match arg_value("--rating") {
+        Some(r) => parse_rating(&r).context("--rating: skillset=rating pairs like stream=27.1,jumpstream=25.4")?,
+        None => cfg.player_rating.clone(),
+    };
+    let skillset = match arg_value("--skillset") {
+        Some(s) => *SKILLSETS[2..].iter().find(|n| n.eq_ignore_ascii_case(&s))
+            .with_context(|| format!("--skillset: one of {}", SKILLSETS[2..].join(", ")))?,
+        // stamina and overall aren't what a chart is "about"
+        None => rating.iter().filter(|(n, _)| SKILLSETS[2..].contains(n)).min_by(|a, b| a.1.total_cmp(&b.1)).map(|(n, _)| *n)
+            .context("no player rating: set MSD_PLAYER_RATING or pass --rating stream=27.1,jumpstream=25.4,... (or --skillset with --target)")?,
+    };
+    let target = match arg_value("--target") {
+        Some(t) => t.parse::<f32>().context("--target: an MSD like 26.5")?,
+        None => rating.iter().find(|(n, _)| *n == skillset).map(|(_, v)| *v)
+            .with_context(|| format!("no {skillset} rating to aim at; pass --target"))?,
+    };
+    let spread = match arg_value("--spread") {
+        Some(s) => s.parse::<f32>().ok().filter(|s| *s >= 0.0).context("--spread: MSD either side of the target, like 1.0")?,
+        None => 1.0,
+    };
+    let count = count_arg("--count")?.unwrap_or(20);
+    let (tags, tagged) = tag_args()?;
+    let query = Query { rate: calc::rate_label(rate_arg()?), ranges: vec![(skillset, target - spread..=target + spread)], dominant: Some(skillset.to_string()), tags, tagged };
+    info!(target: "calc", "practice queue: {skillset} around {target:.2} ({:.2}..{:.2})", target - spread, target + spread);
+
+    let library = Library::open(&Library::default_path())?;
+    let mut found = search(&library, &query);
+    found.sort_by(|a, b| (skillset_value(a.msd, skillset) - target).abs().total_cmp(&(skillset_value(b.msd, skillset) - target).abs()));
+    found.truncate(count);
+    print(&library, &found, cfg)
+}
+
+fn rate_arg() -> anyhow::Result<f32> {
+    match arg_value("--rate") {
+        Some(r) => calc::parse_rates(&r).and_then(|r| r.first().copied()).context("--rate: a rate like 1.0 or 1.1x"),
+        None => Ok(1.0),
+    }
+}
+
+fn count_arg(flag: &str) -> anyhow::Result<Option<usize>> {
+    arg_value(flag).map(|n| n.parse::<usize>().with_context(|| format!("{flag}: a number"))).transpose()
+}
+
+/// Matching charts; copies with the same notes come out once, as the oldest set's (else the
+/// first added) with the rest under `duplicates`.
+fn search<'a>(library: &'a Library, query: &'a Query) -> Vec<Found<'a>> {
+    let mut found: Vec<Found> = Vec::new();
+    let mut by_notes: HashMap<&str, usize> = HashMap::new();
+    let mut charts: Vec<&Chart> = library.charts.values().collect();
+    charts.sort_by_key(|c| (c.set_id.unwrap_or(u64::MAX), c.added_unix));
+    for c in charts {
+        let Some(msd) = query.matches(c) else { continue };
+        let key = (!c.notes_hash.is_empty()).then_some(c.notes_hash.as_str());
+        if let Some(&i) = key.and_then(|k| by_notes.get(k)) {
+            found[i].duplicates.push(Duplicate { song: &c.song, diff: &c.diff, path: c.path.as_deref(), beatmap_id: c.beatmap_id, set_id: c.set_id, sha1: &c.sha1 });
+            continue;
+        }
+        if let Some(k) = key { by_notes.insert(k, found.len()); }
+        found.push(Found {
+            song: &c.song, diff: &c.diff, path: c.path.as_deref(), beatmap_id: c.beatmap_id, set_id: c.set_id, sha1: &c.sha1,
+            dominant: calc::strongest(&SkillsetScores::from(msd)), msd, tags: query.tags_of(c), duplicates: Vec::new(),
+        });
+    }
+    found
+}
+
+/// One line per chart, or the whole list as JSON with `--json`.
+fn print(library: &Library, found: &[Found], cfg: &Config) -> anyhow::Result<()> {
+    debug!(target: "calc", "{} of {} charts match", found.len(), library.charts.len());
+    if std::env::args().any(|a| a == "--json") {
+        println!("{}", String::from_utf8_lossy(&output::encode(&found, Format::Json, cfg)?));
+        return Ok(());
+    }
+    for f in found {
+        let place = f.path.map(str::to_string)
+            .or_else(|| f.beatmap_id.map(|id| format!("https://osu.ppy.sh/b/{id}")))
+            .unwrap_or_default();
+        let copies = match f.duplicates.len() { 0 => String::new(), 1 => "  (+1 duplicate)".into(), n => format!("  (+{n} duplicates)") };
+        let tags = if f.tags.is_empty() { String::new() } else { format!("  #{}", f.tags.join(" #")) };
+        println!("{:>5.2}  {:<10}  {} [{}]  {place}{copies}{tags}", f.msd.overall, f.dominant, f.song, f.diff);
+    }
+    if found.is_empty() { info!(target: "output", "no charts in {} match", library.path().display()); }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+use tracing::*;
+
+use crate::config::Config;
+
+/// How often to look for the game while it's running, and while waiting for it to start.
+const RECHECK: Duration = Duration::from_secs(3);
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+
+/// `MSD_OSU_PROCESS`: whether osu! itself is running, as opposed to tosu answering. tosu keeps
+/// serving its last snapshot with the game closed, so without this the sidecar would poll and
+/// compare all day for nothing.
+pub struct Game { names: Vec<String>, running: bool, checked: Option<Instant> }
+
+impl Game {
+    /// `None` when the check is off.
+    pub fn new(cfg: &Config) -> Option<Game> {
+        (!cfg.osu_process.is_empty()).then(|| Game { names: cfg.osu_process.clone(), running: true, checked: None })
+    }
+
+    /// Return right away while osu! is running (it's looked for every few seconds); otherwise
+    /// wait for it, checking once a second and doing nothing else in between.
+    pub async fn wait(&mut self) {
+        if self.checked.is_some_and(|t| t.elapsed() < RECHECK) && self.running { return; }
+        loop {
+            let names = self.names.clone();
+            // a platform where processes can't be listed counts as running: no idling there
+            let now = tokio::task::spawn_blocking(move || running(&names)).await.ok().flatten().unwrap_or(true);
+            self.checked = Some(Instant::now());
+            match (self.running, now) {
+                (true, false) => info!(target: "tosu", "osu! isn't running; idle until it starts"),
+                (false, true) => info!(target: "tosu", "osu! started; polling tosu again"),
+                _ => {}
+            }
+            self.running = now;
+            if now { return; }
+            tokio::time::sleep(IDLE_CHECK).await;
+        }
+    }
+}
+
+/// Whether a process with one of `names` (no `.exe`, lowercase) is running; `None` where
+/// processes can't be listed.
+pub fn running(names: &[String]) -> Option<bool> {
+    let matches = |exe: &str| {
+        let exe = exe.trim().to_ascii_lowercase();
+        names.iter().any(|n| exe.strip_suffix(".exe").unwrap_or(&exe) == n)
+    };
+    #[cfg(target_os = "linux")]
+    {
+        // `comm` is the executable name (`osu!.exe` under Wine, `osu!` for lazer); it's cut to
+        // 15 bytes, which all the usual names fit in
+        let found = std::fs::read_dir("/proc").ok()?.flatten()
+            .filter(|e| e.file_name().to_str().is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit())))
+            .any(|e| std::fs::read_to_string(e.path().join("comm")).is_ok_and(|c| matches(&c)));
+        Some(found)
+    }
+    #[cfg(windows)]
+    {
+        use std::ffi::c_void;
+        #[repr(C)]
+        struct ProcessEntry32W {
+            size: u32, usage: u32, pid: u32, heap: usize, module: u32, threads: u32, parent: u32, priority: i32, flags: u32,
+            exe: [u16; 260],
+        }
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn CreateToolhelp32Snapshot(flags: u32, pid: u32) -> *mut c_void;
+            fn Process32FirstW(snapshot: *mut c_void, entry: *mut ProcessEntry32W) -> i32;
+            fn Process32NextW(snapshot: *mut c_void, entry: *mut ProcessEntry32W) -> i32;
+            fn CloseHandle(handle: *mut c_void) -> i32;
+        }
+        const TH32CS_SNAPPROCESS: u32 = 2;
+        // SAFETY: the entry is sized as the API expects and the snapshot handle is closed below
+        unsafe {
+            let snap = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+            if snap.is_null() || snap as isize == -1 { return None; }
+            let mut entry: ProcessEntry32W = std::mem::zeroed();
+            entry.size = std::mem::size_of::<ProcessEntry32W>() as u32;
+            let mut found = false;
+            let mut ok = Process32FirstW(snap, &mut entry);
+            while ok != 0 && !found {
+                let len = entry.exe.iter().position(|&c| c == 0).unwrap_or(entry.exe.len());
+                found = matches(&String::from_utf16_lossy(&entry.exe[..len]));
+                ok = Process32NextW(snap, &mut entry);
+            }
+            CloseHandle(snap);
+            Some(found)
+        }
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    { let _ = matches; None }
+}
@@ -0,0 +1,337 @@
+use std::{path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Mutex}};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{calc::{self, MsdOut}, chat::Chat, config::Config, find, hub::unix_ms, library::Ratings, output::{self, Format}, wife};
+
+/// What the play has to score.
+#[derive(Clone, Copy, PartialEq)]
+enum Grade { Pass, Wife(f32) }
+
+/// Etterna's grade names, by the wife% they start at.
+const GRADES: [(&str, f32); 4] = [("AA", 93.0), ("AAA", 99.7), ("AAAA", 99.955), ("AAAAA", 99.9935)];
+
+/// Words that make a goal read like a sentence and mean nothing here.
+const FILLER: [&str; 12] = ["a", "an", "any", "file", "chart", "map", "at", "on", "in", "or", "better", "msd"];
+
+/// A goal: a play of a chart rated at least `msd` in `skillset`, at `rate` or faster, with the grade.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Spec { grade: Grade, msd: f32, skillset: &'static str, rate: Option<f32> }
+
+impl Spec {
+    /// `pass 28 chordjack`, `AA any 27 at 1.1x`, `96% 25 stream @1.2x`: a grade (`pass`, `AA`,
+    /// `AAA`, `AAAA`, `AAAAA` or a wife%), the MSD, then optionally a skillset (`cj`, `js`, `hs`,
+    /// `tech`, `stam` and `jack` work too; overall otherwise) and a minimum rate, in any order.
+    pub fn parse(s: &str) -> Result<Spec, String> {
+        let (mut grade, mut msd, mut skillset, mut rate) = (None, None, None, None);
+        for word in s.split_whitespace().map(str::to_ascii_lowercase) {
+            let w = word.as_str();
+            if FILLER.contains(&w) { continue; }
+            let g = if w == "pass" || w == "clear" {
+                Some(Grade::Pass)
+            } else if let Some(&(_, g)) = GRADES.iter().find(|(n, _)| n.eq_ignore_ascii_case(w)) {
+                Some(Grade::Wife(g))
+            } else if let Some(p) = w.strip_suffix('%') {
+                Some(Grade::Wife(p.parse::<f32>().ok().filter(|p| *p > 0.0 && *p <= 100.0).ok_or_else(|| format!("{w} isn't a wife% between 0 and 100"))?))
+            } else { None };
+            if let Some(g) = g {
+                if grade.replace(g).is_some() { return Err(format!("{s:?} has more than one grade")); }
+            } else if w.starts_with('@') || w.ends_with('x') && w.len() > 1 && w[..w.len() - 1].parse::<f32>().is_ok() {
+                let r = calc::parse_rates(w.trim_start_matches('@')).and_then(|r| r.first().copied()).ok_or_else(|| format!("{w} isn't a rate like 1.1x"))?;
+                if rate.replace(r).is_some() { return Err(format!("{s:?} has more than one rate")); }
+            } else if let Ok(n) = w.parse::<f32>() {
+                if !(n > 0.0 && n < 100.0) { return Err(format!("{w} isn't an MSD")); }
+                if msd.replace(n).is_some() { return Err(format!("{s:?} has more than one MSD; write the rate as 1.1x")); }
+            } else if let Some(name) = skillset_name(w) {
+                if skillset.replace(name).is_some() { return Err(format!("{s:?} has more than one skillset")); }
+            } else {
+                return Err(format!("{word:?} in {s:?} isn't a grade, MSD, skillset or rate"));
+            }
+        }
+        let grade = grade.ok_or_else(|| format!("{s:?} needs a grade: pass, AA, AAA or a wife% like 96%"))?;
+        let msd = msd.ok_or_else(|| format!("{s:?} needs an MSD, like 27"))?;
+        Ok(Spec { grade, msd, skillset: skillset.unwrap_or("overall"), rate })
+    }
+
+    /// The canonical spelling, `AA 27 overall at 1.1x`; goals are told apart by it.
+    pub fn label(&self) -> String {
+        let grade = match self.grade {
+            Grade::Pass => "pass".to_string(),
+            Grade::Wife(w) => GRADES.iter().find(|(_, g)| *g == w).map_or_else(|| format!("{w}%"), |(n, _)| n.to_string()),
+        };
+        let rate = self.rate.map(|r| if r.fract() == 0.0 { format!(" at {r:.1}x") } else { format!(" at {r}x") }).unwrap_or_default();
+        format!("{grade} {} {}{rate}", self.msd, self.skillset)
+    }
+
+    /// How far `m` gets in the skillset when the grade and rate are met; `None` when they aren't.
+    fn reached(&self, m: &MsdOut, wife: Option<f32>, passed: bool) -> Option<f32> {
+        let graded = passed && match self.grade { Grade::Pass => true, Grade::Wife(w) => wife.is_some_and(|got| got >= w) };
+        let rate_ok = self.rate.is_none_or(|r| m.rate.parse::<f32>().is_ok_and(|got| got >= r - 0.005));
+        (graded && rate_ok).then(|| find::skillset_value(&Ratings::from(m), self.skillset))
+    }
+}
+
+fn skillset_name(w: &str) -> Option<&'static str> {
+    let w = match w {
+        "cj" => "chordjack", "js" => "jumpstream", "hs" => "handstream", "tech" => "technical", "stam" => "stamina",
+        "jack" | "jackspeed" => "jacks", w => w,
+    };
+    find::SKILLSETS.iter().find(|n| **n == w).copied()
+}
+
+/// The play that came closest so far.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Attempt {
+    pub song: String,
+    pub diff: String,
+    pub rate: String,
+    /// The chart's MSD in the goal's skillset.
+    pub msd: f32,
+    /// Estimated wife% (`None` when the hit windows weren't known yet).
+    pub wife: Option<f32>,
+    pub unix_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Goal {
+    /// `Spec::label`.
+    pub goal: String,
+    /// From `MSD_GOALS`, so it goes away when it's taken out there; goals added over gRPC stay.
+    #[serde(default)]
+    pub config: bool,
+    pub completed_unix_ms: Option<u64>,
+    /// The highest MSD reached in a play that met the grade and rate.
+    pub best: Option<Attempt>,
+}
+
+#[derive(Serialize)]
+struct GoalStatus<'a> {
+    goal: &'a str,
+    completed: bool,
+    completed_unix_ms: Option<u64>,
+    /// `best`'s MSD over the goal's, 0-1.
+    progress: f32,
+    best: Option<&'a Attempt>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    goals: Vec<Goal>,
+    /// Goals the last play completed, until the next one starts.
+    #[serde(skip)]
+    just_completed: Vec<String>,
+}
+
+/// `goals.json` in the app's data folder.
+pub fn default_path() -> PathBuf {
+    dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("goals.json")
+}
+
+/// Fold another machine's `goals.json` into the one at `path`: goals it doesn't have are added,
+/// and for shared ones the earlier completion and the better attempt win. Returns how many
+/// goals were added.
+pub fn merge_file(path: &Path, other: &[u8]) -> anyhow::Result<usize> {
+    let other: State = serde_json::from_slice(other).context("reading the imported goals")?;
+    let mut state: State = match std::fs::read(path) {
+        Ok(b) => serde_json::from_slice(&b).with_context(|| format!("reading {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => State::default(),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let mut added = 0;
+    for g in other.goals {
+        let Some(mine) = state.goals.iter_mut().find(|m| m.goal == g.goal) else {
+            // MSD_GOALS differs per machine; an imported one is kept like one added over gRPC
+            state.goals.push(Goal { config: false, ..g });
+            added += 1;
+            continue;
+        };
+        mine.completed_unix_ms = match (mine.completed_unix_ms, g.completed_unix_ms) { (Some(a), Some(b)) => Some(a.min(b)), (a, b) => a.or(b) };
+        if g.best.as_ref().is_some_and(|b| mine.best.as_ref().is_none_or(|m| b.msd > m.msd)) { mine.best = g.best; }
+    }
+    if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+    std::fs::write(path, serde_json::to_vec_pretty(&state)?).with_context(|| format!("writing {}", path.display()))?;
+    Ok(added)
+}
+
+/// Every goal with its progress, shared between the poll loop (which checks plays against
+/// them) and gRPC (which adds and removes them). Kept in `goals.json` in the app's data folder,
+/// so completions survive a restart.
+pub struct Board { path: PathBuf, state: Mutex<State>, changed: AtomicBool }
+
+impl Board {
+    /// The saved goals plus `MSD_GOALS`; a goal taken out of `MSD_GOALS` is dropped with its progress.
+    pub fn load(cfg: &Config) -> Board {
+        let path = default_path();
+        let mut state: State = match std::fs::read(&path) {
+            Ok(b) => serde_json::from_slice(&b).unwrap_or_else(|e| { warn!(target: "goals", code = "goals.load", %e, "{} is unreadable; starting over", path.display()); State::default() }),
+            Err(_) => State::default(),
+        };
+        let configured: Vec<String> = cfg.goals.iter().filter_map(|g| match Spec::parse(g) {
+            Ok(s) => Some(s.label()),
+            Err(e) => { warn!(target: "goals", code = "goals.invalid", "MSD_GOALS: {e}"); None }
+        }).collect();
+        state.goals.retain(|g| !g.config || configured.contains(&g.goal));
+        for label in configured {
+            match state.goals.iter_mut().find(|g| g.goal == label) {
+                Some(g) => g.config = true,
+                None => state.goals.push(Goal { goal: label, config: true, completed_unix_ms: None, best: None }),
+            }
+        }
+        Board { path, state: Mutex::new(state), changed: AtomicBool::new(true) }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> { self.state.lock().unwrap_or_else(|p| p.into_inner()) }
+
+    /// Add a goal (written any way `Spec::parse` takes); returns its label.
+    pub fn add(&self, text: &str) -> Result<String, String> {
+        let label = Spec::parse(text)?.label();
+        let mut state = self.lock();
+        if !state.goals.iter().any(|g| g.goal == label) {
+            state.goals.push(Goal { goal: label.clone(), config: false, completed_unix_ms: None, best: None });
+            self.save(&state);
+        }
+        Ok(label)
+    }
+
+    /// Whether there was such a goal.
+    pub fn remove(&self, text: &str) -> bool {
+        let label = Spec::parse(text).map_or_else(|_| text.trim().to_string(), |s| s.label());
+        let mut state = self.lock();
+        let before = state.goals.len();
+        state.goals.retain(|g| g.goal != label);
+        let removed = state.goals.len() != before;
+        if removed { self.save(&state); }
+        removed
+    }
+
+    /// Check a finished play against every open goal; returns the ones it completed.
+    fn record(&self, m: &MsdOut, wife: Option<f32>, passed: bool) -> Vec<String> {
+        let mut state = self.lock();
+        let mut done = Vec::new();
+        let mut changed = false;
+        for g in state.goals.iter_mut().filter(|g| g.completed_unix_ms.is_none()) {
+            let Ok(spec) = Spec::parse(&g.goal) else { continue };
+            let Some(msd) = spec.reached(m, wife, passed) else { continue };
+            if g.best.as_ref().is_none_or(|b| msd > b.msd) {
+                g.best = Some(Attempt { song: m.song.clone(), diff: m.diff.clone(), rate: m.rate.clone(), msd, wife, unix_ms: unix_ms() });
+                changed = true;
+            }
+            if msd >= spec.msd {
+                g.completed_unix_ms = Some(unix_ms());
+                done.push(g.goal.clone());
+            }
+        }
+        if changed || !state.just_completed.is_empty() || !done.is_empty() {
+            state.just_completed = done.clone();
+            self.save(&state);
+        }
+        done
+    }
+
+    /// A new play started: the last one's completions aren't news any more.
+    fn clear_just_completed(&self) {
+        let mut state = self.lock();
+        if !state.just_completed.is_empty() {
+            state.just_completed.clear();
+            self.changed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn save(&self, state: &State) {
+        self.changed.store(true, Ordering::Relaxed);
+        let res = serde_json::to_vec_pretty(state).map_err(std::io::Error::other).and_then(|b| {
+            if let Some(dir) = self.path.parent() { std::fs::create_dir_all(dir)?; }
+            std::fs::write(&self.path, b)
+        });
+        if let Err(e) = res { warn!(target: "goals", code = "goals.save", %e, "failed to save {}", self.path.display()); }
+    }
+
+    /// `{"goals": [{"goal", "completed", "completed_unix_ms", "progress", "best"}], "just_completed": [...]}`
+    pub fn status(&self) -> serde_json::Value {
+        let state = self.lock();
+        let goals: Vec<GoalStatus> = state.goals.iter().map(|g| {
+            let target = Spec::parse(&g.goal).map_or(f32::MAX, |s| s.msd);
+            GoalStatus {
+                goal: &g.goal,
+                completed: g.completed_unix_ms.is_some(),
+                completed_unix_ms: g.completed_unix_ms,
+                progress: if g.completed_unix_ms.is_some() { 1.0 } else { g.best.as_ref().map_or(0.0, |b| (b.msd / target).min(1.0)) },
+                best: g.best.as_ref(),
+            }
+        }).collect();
+        output::to_value(&serde_json::json!({ "goals": goals, "just_completed": state.just_completed })).unwrap_or_default()
+    }
+}
+
+/// Follows plays for the goal board and rewrites `goals.json` in the overlay folder when
+/// anything on it changes.
+#[derive(Default)]
+pub struct Tracker {
+    /// The map being played and the latest wife% estimate.
+    in_play: Option<(MsdOut, Option<f32>)>,
+}
+
+impl Tracker {
+    /// Runs on every poll. A play counts as passed when it ends on the results screen (state 7).
+    #[allow(clippy::too_many_arguments)]
+    pub async fn update(&mut self, static_root: &Path, board: &Board, out: Option<&MsdOut>, state: Option<u32>,
+                        hits: Option<&wife::Hits>, windows: Option<wife::Windows>, chat: &Chat, cfg: &Config) {
+        if state == Some(2) {
+            if self.in_play.is_none() { board.clear_just_completed(); }
+            let wife = hits.filter(|h| h.judged() > 0).zip(windows).and_then(|(h, w)| wife::from_hits(h, &w));
+            match (&mut self.in_play, out) {
+                // MSD_GOAL_FOLLOW recalculates at other goals mid-play; goals are about the MSD_SCORE_GOAL rating
+                (Some((m, _)), Some(o)) if o.md5 == m.md5 && o.rate == m.rate && o.goal != cfg.score_goal => {}
+                (slot, Some(o)) => *slot = Some((o.clone(), None)),
+                (_, None) => {}
+            }
+            if let Some((_, w)) = &mut self.in_play { if wife.is_some() { *w = wife; } }
+        } else if let Some((m, wife)) = self.in_play.take() {
+            for goal in board.record(&m, wife, state == Some(7)) {
+                info!(target: "goals", "goal completed: {goal} ({} [{}] {}x)", m.song, m.diff, m.rate);
+                let wife = wife.map(|w| format!(", {w:.2}%")).unwrap_or_default();
+                chat.send(format!("Goal completed: {goal} · {} [{}] {}x{wife}", m.song, m.diff, m.rate));
+            }
+        }
+        if !board.changed.swap(false, Ordering::Relaxed) { return; }
+        let res = match output::encode(&board.status(), Format::Json, cfg) {
+            Ok(b) => tokio::fs::write(output::overlay_dir(static_root).join("goals.json"), b).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            warn!(target: "output", %e, "failed to write goals.json");
+            board.changed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(s: &str) -> String { Spec::parse(s).unwrap().label() }
+
+    fn error(s: &str) -> String { Spec::parse(s).err().unwrap() }
+
+    #[test]
+    fn parse_and_label_round_trip() {
+        assert_eq!(label("AA 27 cj @1.1x"), "AA 27 chordjack at 1.1x");
+        assert_eq!(label(&label("AA 27 cj @1.1x")), "AA 27 chordjack at 1.1x");
+        assert_eq!(label("pass 28 cj"), "pass 28 chordjack");
+        assert_eq!(label("AA any 27 at 1.1x"), "AA 27 overall at 1.1x");
+        assert_eq!(label("96% 25 stream"), "96% 25 stream");
+        assert_eq!(label("1.2x jack 30 aaa"), "AAA 30 jacks at 1.2x");
+        assert_eq!(label("clear 20 @2x"), "pass 20 overall at 2.0x");
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_goal() {
+        assert!(error("27 cj").contains("needs a grade"));
+        assert!(error("AA cj").contains("needs an MSD"));
+        assert!(error("AA 27 28").contains("more than one MSD"));
+        assert!(error("AA AAA 27").contains("more than one grade"));
+        assert!(error("120% 27").contains("wife%"));
+        assert!(error("AA 27 speed").contains("\"speed\""));
+    }
+}
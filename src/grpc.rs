@@ -0,0 +1,290 @@
+use std::{net::SocketAddr, sync::Arc};
+use anyhow::Context;
+use bytes::Bytes;
+use h2::{server::SendResponse, RecvStream, SendStream};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use minacalc_rs::Calc;
+use serde_json::json;
+use tokio::{net::TcpListener, sync::broadcast::error::RecvError};
+use tracing::*;
+
+use crate::{calc, hub::Hub, proto, tags};
+
+/// `package minacalc_overlay.v1; service Msd` — see proto/minacalc.proto.
+const SERVICE: &str = "/minacalc_overlay.v1.Msd/";
+
+// grpc-status codes
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+const UNAUTHENTICATED: u32 = 16;
+
+/// Who may make the calls that change something (`Recalc`, the goal, goals, tags): with a token, whoever
+/// sends it; without one, anyone, but only while the server is on loopback.
+struct Access { token: Option<String>, loopback: bool }
+
+impl Access {
+    fn allows(&self, bearer: Option<&str>) -> bool {
+        match &self.token { Some(t) => bearer == Some(t.as_str()), None => self.loopback }
+    }
+}
+
+/// gRPC over cleartext HTTP/2 (h2c, prior knowledge), which is what grpcurl / grpc clients use
+/// with "insecure" channels. `score_goal` is `CalcFile`'s goal when the request has none, and
+/// `token` (`MSD_GRPC_TOKEN`) the bearer token the editing calls need.
+pub async fn serve(addr: SocketAddr, hub: Arc<Hub>, score_goal: f32, token: Option<String>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await.with_context(|| format!("binding gRPC on {addr}"))?;
+    info!(target: "grpc", "gRPC listening on {addr}");
+    let access = Arc::new(Access { token, loopback: addr.ip().is_loopback() });
+    if access.token.is_none() && !access.loopback {
+        warn!(target: "grpc", "MSD_GRPC is reachable from other machines and MSD_GRPC_TOKEN isn't set; Recalc, SetScoreGoal, AddGoal, RemoveGoal and TagMap are refused");
+    }
+    loop {
+        let (sock, peer) = match listener.accept().await {
+            Ok(c) => c,
+            Err(e) => { warn!(target: "grpc", %e, "accept failed"); continue; }
+        };
+        let (hub, access) = (hub.clone(), access.clone());
+        tokio::spawn(async move {
+            let mut conn = match h2::server::handshake(sock).await {
+                Ok(c) => c,
+                Err(e) => { debug!(target: "grpc", %e, %peer, "handshake failed"); return; }
+            };
+            while let Some(req) = conn.accept().await {
+                match req {
+                    Ok((req, respond)) => { tokio::spawn(handle(req, respond, hub.clone(), access.clone(), score_goal)); }
+                    Err(e) => { debug!(target: "grpc", %e, %peer, "connection closed"); break; }
+                }
+            }
+        });
+    }
+}
+
+async fn handle(req: Request<RecvStream>, mut respond: SendResponse<Bytes>, hub: Arc<Hub>, access: Arc<Access>, score_goal: f32) {
+    let method = req.uri().path().strip_prefix(SERVICE).unwrap_or_default().to_string();
+    let bearer = req.headers().get("authorization").and_then(|v| v.to_str().ok()).and_then(|a| a.strip_prefix("Bearer ")).map(str::to_string);
+    let msg = match read_message(req.into_body()).await {
+        Ok(m) => m,
+        Err(e) => return trailers_only(&mut respond, INVALID_ARGUMENT, &e.to_string()),
+    };
+    debug!(target: "grpc", method, "call");
+    match method.as_str() {
+        "Recalc" | "SetScoreGoal" | "AddGoal" | "RemoveGoal" | "TagMap" if !access.allows(bearer.as_deref()) => {
+            let why = if access.token.is_some() { "wrong or missing bearer token" } else { "set MSD_GRPC_TOKEN to allow this off loopback" };
+            trailers_only(&mut respond, UNAUTHENTICATED, why)
+        }
+        "SubscribeResults" => subscribe(respond, hub).await,
+        "CalcFile" => match calc_file(&msg, score_goal).await {
+            Ok(body) => unary(&mut respond, body),
+            Err(e) => trailers_only(&mut respond, INTERNAL, &format!("{e:#}")),
+        },
+        "GetStatus" => unary(&mut respond, status(&hub)),
+        "GetRecent" => unary(&mut respond, recent(&hub)),
+        "Recalc" => { hub.request_recalc(); unary(&mut respond, Vec::new()) }
+        "GetScoreGoal" => unary(&mut respond, score_goal_msg(&hub, score_goal)),
+        "SetScoreGoal" => match wife_arg(&msg) {
+            g if (0.0..=100.0).contains(&g) => {
+                hub.set_score_goal(Some(g).filter(|g| *g > 0.0));
+                unary(&mut respond, score_goal_msg(&hub, score_goal))
+            }
+            g => trailers_only(&mut respond, INVALID_ARGUMENT, &format!("goal is a wife% up to 100, got {g}")),
+        },
+        "GetGoals" => unary(&mut respond, goals(&hub)),
+        "AddGoal" => match hub.goals.add(&goal_arg(&msg)) {
+            Ok(_) => unary(&mut respond, goals(&hub)),
+            Err(e) => trailers_only(&mut respond, INVALID_ARGUMENT, &e),
+        },
+        "RemoveGoal" => if hub.goals.remove(&goal_arg(&msg)) { unary(&mut respond, goals(&hub)) } else {
+            trailers_only(&mut respond, NOT_FOUND, "no such goal")
+        },
+        "GetTags" | "TagMap" => match tag_map(&hub, &msg, method == "TagMap") {
+            Ok(body) => unary(&mut respond, body),
+            Err((code, e)) => trailers_only(&mut respond, code, &e),
+        },
+        _ => trailers_only(&mut respond, UNIMPLEMENTED, "unknown method"),
+    }
+}
+
+/// Collect the request body and unwrap its single length-prefixed message.
+async fn read_message(mut body: RecvStream) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        buf.extend_from_slice(&chunk);
+        anyhow::ensure!(buf.len() <= 64 << 20, "request too large");
+    }
+    if buf.is_empty() { return Ok(buf); }
+    anyhow::ensure!(buf.len() >= 5, "truncated message");
+    anyhow::ensure!(buf[0] == 0, "compressed messages are not supported");
+    let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+    anyhow::ensure!(buf.len() >= 5 + len, "truncated message");
+    Ok(buf[5..5 + len].to_vec())
+}
+
+fn frame(msg: &[u8]) -> Bytes {
+    let mut out = Vec::with_capacity(5 + msg.len());
+    out.push(0);
+    out.extend((msg.len() as u32).to_be_bytes());
+    out.extend(msg);
+    out.into()
+}
+
+fn headers() -> Response<()> {
+    Response::builder().status(200).header("content-type", "application/grpc").body(()).unwrap_or_default()
+}
+
+fn status_trailers(code: u32, message: &str) -> HeaderMap {
+    let mut t = HeaderMap::new();
+    t.insert("grpc-status", HeaderValue::from(code));
+    if !message.is_empty() {
+        if let Ok(v) = HeaderValue::from_str(&percent_encode(message)) { t.insert("grpc-message", v); }
+    }
+    t
+}
+
+/// grpc-message is percent-encoded UTF-8.
+fn percent_encode(s: &str) -> String {
+    s.bytes().map(|b| if (0x20..0x7f).contains(&b) && b != b'%' { (b as char).to_string() } else { format!("%{b:02X}") }).collect()
+}
+
+fn unary(respond: &mut SendResponse<Bytes>, body: Vec<u8>) {
+    let Ok(mut stream) = respond.send_response(headers(), false) else { return };
+    let _ = stream.send_data(frame(&body), false);
+    let _ = stream.send_trailers(status_trailers(0, ""));
+}
+
+/// Error without a body: status goes in the response headers.
+fn trailers_only(respond: &mut SendResponse<Bytes>, code: u32, message: &str) {
+    let mut rsp = headers();
+    rsp.headers_mut().extend(status_trailers(code, message));
+    let _ = respond.send_response(rsp, true);
+}
+
+async fn subscribe(mut respond: SendResponse<Bytes>, hub: Arc<Hub>) {
+    let Ok(mut stream) = respond.send_response(headers(), false) else { return };
+    let (latest, mut rx) = hub.subscribe();
+    if let Some(u) = latest {
+        if !send_update(&mut stream, &u) { return; }
+    }
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(u) => if !send_update(&mut stream, &u) { return },
+                Err(RecvError::Lagged(n)) => debug!(target: "grpc", n, "subscriber lagged"),
+                Err(RecvError::Closed) => break,
+            },
+            // client cancelled / went away
+            _ = std::future::poll_fn(|cx| stream.poll_reset(cx)) => return,
+        }
+    }
+    let _ = stream.send_trailers(status_trailers(0, ""));
+}
+
+fn send_update(stream: &mut SendStream<Bytes>, u: &crate::hub::Update) -> bool {
+    let Ok(v) = serde_json::to_value(u) else { return true };
+    stream.send_data(frame(&proto::encode(proto::UPDATE, &v)), false).is_ok()
+}
+
+/// `CalcFileRequest { bytes osu = 2; float rate = 3; float goal = 4; }`. Field 1 was a path to
+/// read, which handed any client the sidecar's files; it's ignored.
+async fn calc_file(msg: &[u8], score_goal: f32) -> anyhow::Result<Vec<u8>> {
+    let fields = proto::decode(msg).context("malformed CalcFileRequest")?;
+    let (mut osu, mut rate, mut goal) = (Vec::new(), 0f32, 0f32);
+    for (n, v) in fields {
+        match (n, v) {
+            (2, proto::Wire::Bytes(b)) => osu = b.to_vec(),
+            (3, proto::Wire::Fixed32(b)) => rate = f32::from_le_bytes(b),
+            (4, proto::Wire::Fixed32(b)) => goal = f32::from_le_bytes(b),
+            _ => {}
+        }
+    }
+    anyhow::ensure!(!osu.is_empty(), "osu (the file's contents) is required");
+    let rate = if rate > 0.0 { rate } else { 1.0 };
+    anyhow::ensure!(goal <= 100.0, "goal is a wife% up to 100, got {goal}");
+    let goal = if goal > 0.0 { goal } else { score_goal };
+
+    // a pooled calculator keeps the poll loop's one untouched
+    let out = tokio::task::spawn_blocking(move || -> anyhow::Result<calc::MsdOut> {
+        let (text, decoded) = calc::decode(&osu);
+        let (map, scores) = calc::pooled(|c| calc::calc_osu(c, &text, rate, goal))?;
+        let (beatmap_id, set_id) = calc::map_ids(&map);
+        let out = calc::MsdOut::new(calc::song_label(&map.artist, &map.title), map.version, &scores, calc::rate_label(rate));
+        Ok(calc::MsdOut { md5: crate::md5::hex(&osu), notes: decoded.into_iter().collect(), goal, ..out }.with_ids(beatmap_id, set_id))
+    }).await??;
+    Ok(proto::encode(proto::MSD_RESULT, &crate::output::to_value(&out)?))
+}
+
+/// `string goal = 1` of `AddGoalRequest` / `RemoveGoalRequest`.
+fn goal_arg(msg: &[u8]) -> String {
+    proto::decode(msg).unwrap_or_default().into_iter().find_map(|(n, v)| match (n, v) {
+        (1, proto::Wire::Bytes(b)) => Some(String::from_utf8_lossy(b).into_owned()),
+        _ => None,
+    }).unwrap_or_default()
+}
+
+/// `float goal = 1` of `ScoreGoal`; 0 when it's missing.
+fn wife_arg(msg: &[u8]) -> f32 {
+    proto::decode(msg).unwrap_or_default().into_iter().find_map(|(n, v)| match (n, v) {
+        (1, proto::Wire::Fixed32(b)) => Some(f32::from_le_bytes(b)),
+        _ => None,
+    }).unwrap_or(0.0)
+}
+
+/// The goal the live MSD is calculated for: `SetScoreGoal`'s, else `MSD_SCORE_GOAL`.
+fn score_goal_msg(hub: &Hub, score_goal: f32) -> Vec<u8> {
+    proto::encode(proto::SCORE_GOAL, &json!({ "goal": hub.score_goal().unwrap_or(score_goal) }))
+}
+
+/// `TagMapRequest { string md5 = 1; repeated string add = 2; repeated string remove = 3; string note = 4; bool clear_note = 5; }`,
+/// of which `GetTagsRequest` only has `md5`; an empty one is the current map.
+fn tag_map(hub: &Hub, msg: &[u8], edit: bool) -> Result<Vec<u8>, (u32, String)> {
+    let fields = proto::decode(msg).ok_or((INVALID_ARGUMENT, "malformed request".to_string()))?;
+    let (mut md5, mut change) = (String::new(), tags::Edit::default());
+    for (n, v) in fields {
+        let text = |v: &proto::Wire| match v { proto::Wire::Bytes(b) => String::from_utf8_lossy(b).into_owned(), _ => String::new() };
+        match n {
+            1 => md5 = text(&v).trim().to_ascii_lowercase(),
+            2 => change.add.extend(tags::normalize(&text(&v))),
+            3 => change.remove.extend(tags::normalize(&text(&v))),
+            4 => change.note = Some(Some(text(&v))),
+            5 if matches!(v, proto::Wire::Varint(1)) => change.note = Some(None),
+            _ => {}
+        }
+    }
+    let latest = hub.latest();
+    let current = latest.as_ref().map(|u| &u.result).filter(|r| md5.is_empty() || r["md5"] == md5.as_str());
+    let label = |k: &str| current.and_then(|r| r[k].as_str()).unwrap_or_default().to_string();
+    if md5.is_empty() { md5 = label("md5"); }
+    if md5.is_empty() { return Err((NOT_FOUND, "no current map; pass its md5".into())); }
+    let entry = if edit && !change.is_empty() {
+        hub.tags.edit(&md5, &label("song"), &label("diff"), &change).map_err(|e| (INTERNAL, format!("{e:#}")))?
+    } else {
+        hub.tags.get(&md5).unwrap_or_default()
+    };
+    let v = json!({ "md5": md5, "song": entry.song, "diff": entry.diff, "tags": entry.tags, "note": entry.note });
+    Ok(proto::encode(proto::MAP_TAGS, &v))
+}
+
+fn goals(hub: &Hub) -> Vec<u8> { proto::encode(proto::GOALS, &hub.goals.status()) }
+
+fn recent(hub: &Hub) -> Vec<u8> {
+    let v = json!({ "updates": hub.recent().iter().map(|u| json!({ "seq": u.seq, "unix_ms": u.unix_ms, "result": u.result })).collect::<Vec<_>>() });
+    proto::encode(proto::RECENT, &v)
+}
+
+fn status(hub: &Hub) -> Vec<u8> {
+    let (unwritten, write_error) = hub.backlog();
+    let v = json!({
+        "unwritten": unwritten,
+        "write_error": write_error,
+        "version": env!("CARGO_PKG_VERSION"),
+        "calc_version": Calc::version(),
+        "updates": hub.updates(),
+        "uptime_secs": hub.started.elapsed().as_secs(),
+        "tosu_connected": hub.tosu_connected(),
+        "last": hub.latest().map(|u| u.result.clone()),
+    });
+    proto::encode(proto::STATUS, &v)
+}
@@ -0,0 +1,31 @@
+use std::time::{Duration, Instant};
+use reqwest::Client;
+use tracing::*;
+
+/// healthchecks.io-style dead man's switch: GET `url` after successful cycles, at most once per `every`.
+pub struct Heartbeat {
+    url: Option<String>,
+    every: Duration,
+    last: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(url: Option<String>, every: Duration) -> Self {
+        Heartbeat { url, every, last: None }
+    }
+
+    /// Ping if due. Fire-and-forget so a slow endpoint never stalls polling.
+    pub fn beat(&mut self, http: &Client) {
+        let Some(url) = &self.url else { return };
+        if self.last.is_some_and(|t| t.elapsed() < self.every) { return; }
+        self.last = Some(Instant::now());
+        let req = http.get(url).timeout(Duration::from_secs(10));
+        tokio::spawn(async move {
+            match req.send().await {
+                Ok(r) if !r.status().is_success() => warn!(target: "heartbeat", status = %r.status(), "heartbeat rejected"),
+                Ok(_) => debug!(target: "heartbeat", "heartbeat sent"),
+                Err(e) => warn!(target: "heartbeat", %e, "heartbeat failed"),
+            }
+        });
+    }
+}
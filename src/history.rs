@@ -0,0 +1,191 @@
+use std::{io::Write, path::{Path, PathBuf}};
+use anyhow::Context;
+use minacalc_rs::SkillsetScores;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{calc::{self, MsdOut}, config::{arg_value, Config}, hub::unix_ms, library::Ratings, output::{self, Format}};
+
+/// One finished (or quit) play.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Play {
+    pub ended_unix_ms: u64,
+    pub song: String,
+    pub diff: String,
+    #[serde(default)]
+    pub md5: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beatmap_id: Option<u64>,
+    pub mods: Vec<String>,
+    pub msd: Ratings,
+    /// Strongest skillset of the chart at that rate, stamina aside.
+    pub dominant: String,
+}
+
+/// `MSD_HISTORY`: every play is appended to `history.jsonl` next to the library index, one JSON
+/// object per line, so the file can be tailed or imported elsewhere and a crash loses at most a line.
+#[derive(Default)]
+pub struct History {
+    /// The map of the play in progress.
+    in_play: Option<MsdOut>,
+}
+
+impl History {
+    /// `MSD_HISTORY_FILE`, else `history.jsonl` in the app's data folder.
+    pub fn default_path() -> PathBuf {
+        if let Ok(p) = std::env::var("MSD_HISTORY_FILE") { return PathBuf::from(p); }
+        dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("history.jsonl")
+    }
+
+    /// Same contract as `Tracks::update`; returns the play once gameplay ends, after recording
+    /// it when `MSD_HISTORY` is on.
+    pub fn update(&mut self, static_root: &Path, out: Option<&MsdOut>, playing: bool, cfg: &Config) -> Option<Play> {
+        if playing {
+            if out.is_some() { self.in_play = out.cloned(); }
+            return None;
+        }
+        let m = self.in_play.take()?;
+        let play = Play {
+            ended_unix_ms: unix_ms(), song: m.song.clone(), diff: m.diff.clone(), md5: m.md5.clone(), beatmap_id: m.beatmap_id,
+            mods: m.mods.clone(), dominant: calc::strongest(&SkillsetScores::from(&Ratings::from(&m))).to_string(), msd: Ratings::from(&m),
+        };
+        if !cfg.history { return Some(play); }
+        match append(&History::default_path(), &play) {
+            Ok(()) => {
+                debug!(target: "output", "play recorded: {} [{}]", play.song, play.diff);
+                if cfg.skill_gap { write_skill_gap(static_root, cfg); }
+            }
+            Err(e) => warn!(target: "output", "history: {e:#}"),
+        }
+        Some(play)
+    }
+}
+
+fn append(path: &Path, play: &Play) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+    let mut line = serde_json::to_vec(play)?;
+    line.push(b'\n');
+    let mut f = std::fs::OpenOptions::new().create(true).append(true).open(path).with_context(|| format!("opening {}", path.display()))?;
+    f.write_all(&line).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Every recorded play, oldest first; lines that don't parse are skipped.
+pub fn load(path: &Path) -> anyhow::Result<Vec<Play>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    Ok(text.lines().filter(|l| !l.trim().is_empty()).filter_map(|l| serde_json::from_str(l).ok()).collect())
+}
+
+/// Add the plays from another machine's `history.jsonl` that the one at `path` doesn't have
+/// (same end time and chart), rewriting it oldest first; returns how many were added.
+pub fn merge_file(path: &Path, other: &[u8]) -> anyhow::Result<usize> {
+    let key = |p: &Play| (p.ended_unix_ms, if p.md5.is_empty() { p.song.clone() } else { p.md5.clone() }, p.diff.clone());
+    let mut plays = load(path)?;
+    let mut seen: std::collections::HashSet<_> = plays.iter().map(key).collect();
+    let before = plays.len();
+    let text = String::from_utf8_lossy(other);
+    plays.extend(text.lines().filter_map(|l| serde_json::from_str::<Play>(l).ok()).filter(|p| seen.insert(key(p))));
+    let added = plays.len() - before;
+    if added == 0 { return Ok(0); }
+    plays.sort_by_key(|p| p.ended_unix_ms);
+    if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+    let mut body = Vec::new();
+    for p in &plays {
+        serde_json::to_writer(&mut body, p)?;
+        body.push(b'\n');
+    }
+    let tmp = path.with_extension("jsonl.tmp");
+    std::fs::write(&tmp, body)?;
+    std::fs::rename(&tmp, path).with_context(|| format!("writing {}", path.display()))?;
+    Ok(added)
+}
+
+pub const SKILLSETS: [&str; 6] = ["stream", "jumpstream", "handstream", "jacks", "chordjack", "technical"];
+
+#[derive(Serialize)]
+pub struct SkillsetPlays {
+    pub skillset: &'static str,
+    /// Plays whose chart is strongest in this skillset.
+    pub plays: usize,
+    /// `plays` as a fraction of all plays counted.
+    pub share: f32,
+    /// The player's rating in it (`MSD_PLAYER_RATING`), if known.
+    pub rating: Option<f32>,
+    /// Average MSD in this skillset over those plays.
+    pub played_msd: Option<f32>,
+}
+
+/// Written to `skill_gap.json` and printed by `skill-gap`.
+#[derive(Serialize)]
+pub struct SkillGap {
+    /// Plays counted (the last `days`).
+    pub plays: usize,
+    pub days: u64,
+    /// The skillset played least; ties go to the lower rating.
+    pub underplayed: Option<&'static str>,
+    pub skillsets: Vec<SkillsetPlays>,
+}
+
+/// How the last `days` of plays spread over the skillsets, next to the player's rating in each.
+pub fn skill_gap(plays: &[Play], rating: &[(&'static str, f32)], days: u64) -> SkillGap {
+    let since = unix_ms().saturating_sub(days * 86_400_000);
+    let recent: Vec<&Play> = plays.iter().filter(|p| p.ended_unix_ms >= since).collect();
+    let skillsets: Vec<SkillsetPlays> = SKILLSETS.iter().map(|&s| {
+        let of: Vec<&&Play> = recent.iter().filter(|p| p.dominant == s).collect();
+        let value = |p: &Play| match s {
+            "stream" => p.msd.stream, "jumpstream" => p.msd.jumpstream, "handstream" => p.msd.handstream,
+            "jacks" => p.msd.jacks, "chordjack" => p.msd.chordjack, _ => p.msd.technical,
+        };
+        SkillsetPlays {
+            skillset: s,
+            plays: of.len(),
+            share: if recent.is_empty() { 0.0 } else { of.len() as f32 / recent.len() as f32 },
+            rating: rating.iter().find(|(n, _)| *n == s).map(|(_, v)| *v),
+            played_msd: (!of.is_empty()).then(|| of.iter().map(|p| value(p)).sum::<f32>() / of.len() as f32),
+        }
+    }).collect();
+    let underplayed = if recent.is_empty() { None } else {
+        skillsets.iter().min_by(|a, b| a.plays.cmp(&b.plays).then(a.rating.unwrap_or(f32::MAX).total_cmp(&b.rating.unwrap_or(f32::MAX))))
+            .map(|s| s.skillset)
+    };
+    SkillGap { plays: recent.len(), days, underplayed, skillsets }
+}
+
+/// `MSD_SKILL_GAP`: rewrite `skill_gap.json` in the overlay folder after each play.
+fn write_skill_gap(static_root: &Path, cfg: &Config) {
+    let gap = match load(&History::default_path()) {
+        Ok(plays) => skill_gap(&plays, &cfg.player_rating, cfg.skill_gap_days),
+        Err(e) => { warn!(target: "output", "history: {e:#}"); return; }
+    };
+    let path = output::overlay_dir(static_root).join("skill_gap.json");
+    match output::encode(&gap, Format::Json, cfg) {
+        Ok(b) => if let Err(e) = std::fs::write(&path, b) { warn!(target: "output", %e, "failed to write skill_gap.json"); },
+        Err(e) => warn!(target: "output", %e, "encode skill_gap.json"),
+    }
+}
+
+/// `minacalc-overlay skill-gap [--days 30] [--json]`: which skillsets the recent plays cover, and the most underplayed one.
+pub fn run(cfg: &Config) -> anyhow::Result<()> {
+    let days = match arg_value("--days") {
+        Some(d) => d.parse::<u64>().ok().filter(|d| *d > 0).context("--days: a number of days")?,
+        None => cfg.skill_gap_days,
+    };
+    let path = History::default_path();
+    let gap = skill_gap(&load(&path)?, &cfg.player_rating, days);
+    if std::env::args().any(|a| a == "--json") {
+        println!("{}", String::from_utf8_lossy(&output::encode(&gap, Format::Json, cfg)?));
+        return Ok(());
+    }
+    anyhow::ensure!(gap.plays > 0, "no plays in the last {days} days in {}", path.display());
+    println!("{} plays in the last {} days", gap.plays, gap.days);
+    for s in &gap.skillsets {
+        let rating = s.rating.map_or_else(|| "   -".into(), |r| format!("{r:>5.2}"));
+        let played = s.played_msd.map_or_else(|| "   -".into(), |m| format!("{m:>5.2}"));
+        println!("{:<10}  {:>4} plays ({:>3.0}%)  rating {rating}  played {played}", s.skillset, s.plays, s.share * 100.0);
+    }
+    if let Some(u) = gap.underplayed { println!("most underplayed: {u}"); }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::sync::{atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering}, Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+/// One published result, numbered; the `Update` message in proto/minacalc.proto.
+#[derive(Serialize, Clone)]
+pub struct Update {
+    pub seq: u64,
+    pub unix_ms: u64,
+    pub result: Value,
+}
+
+/// Fan-out point between the poll loop and anything serving results live.
+pub struct Hub {
+    tx: broadcast::Sender<Arc<Update>>,
+    latest: Mutex<Option<Arc<Update>>>,
+    /// The last `recent_cap` updates, oldest first.
+    recent: Mutex<VecDeque<Arc<Update>>>,
+    recent_cap: usize,
+    seq: AtomicU64,
+    tosu_connected: AtomicBool,
+    recalc: AtomicBool,
+    /// `SetScoreGoal`'s wife% as f32 bits; 0 = `MSD_SCORE_GOAL`.
+    score_goal: AtomicU32,
+    /// Results that couldn't be written yet and the last write error (`output::Backlog`).
+    backlog: Mutex<(u64, Option<String>)>,
+    /// Player goals; the poll loop checks plays against them, gRPC edits them.
+    pub goals: crate::goals::Board,
+    /// The player's chart tags; the poll loop reads them, gRPC edits them.
+    pub tags: crate::tags::Store,
+    pub started: Instant,
+}
+
+impl Hub {
+    pub fn new(recent_cap: usize, goals: crate::goals::Board, tags: crate::tags::Store) -> Arc<Hub> {
+        let (tx, _) = broadcast::channel(16);
+        Arc::new(Hub { tx, latest: Mutex::new(None), recent: Mutex::new(VecDeque::with_capacity(recent_cap)), recent_cap, seq: AtomicU64::new(0), tosu_connected: AtomicBool::new(false), recalc: AtomicBool::new(false), score_goal: AtomicU32::new(0), backlog: Mutex::new((0, None)), goals, tags, started: Instant::now() })
+    }
+
+    pub fn publish<T: Serialize>(&self, out: &T) {
+        let Ok(result) = crate::output::to_value(out) else { return };
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed) + 1;
+        let update = Arc::new(Update { seq, unix_ms: unix_ms(), result });
+        if self.recent_cap > 0 {
+            let mut recent = self.recent.lock().unwrap_or_else(|p| p.into_inner());
+            if recent.len() == self.recent_cap { recent.pop_front(); }
+            recent.push_back(update.clone());
+        }
+        // send under the lock so `subscribe` never sees an update both as latest and on the channel
+        let mut latest = self.latest.lock().unwrap_or_else(|p| p.into_inner());
+        *latest = Some(update.clone());
+        let _ = self.tx.send(update); // no subscribers is fine
+    }
+
+    /// Latest update (if any) plus a receiver for everything after it.
+    pub fn subscribe(&self) -> (Option<Arc<Update>>, broadcast::Receiver<Arc<Update>>) {
+        let latest = self.latest.lock().unwrap_or_else(|p| p.into_inner());
+        (latest.clone(), self.tx.subscribe())
+    }
+
+    pub fn latest(&self) -> Option<Arc<Update>> { self.latest.lock().unwrap_or_else(|p| p.into_inner()).clone() }
+
+    /// Up to `MSD_RECENT` of the latest updates, oldest first, for sparklines and the like.
+    pub fn recent(&self) -> Vec<Arc<Update>> { self.recent.lock().unwrap_or_else(|p| p.into_inner()).iter().cloned().collect() }
+
+    pub fn updates(&self) -> u64 { self.seq.load(Ordering::Relaxed) }
+
+    pub fn set_tosu_connected(&self, ok: bool) { self.tosu_connected.store(ok, Ordering::Relaxed); }
+
+    pub fn tosu_connected(&self) -> bool { self.tosu_connected.load(Ordering::Relaxed) }
+
+    pub fn set_backlog(&self, unwritten: u64, error: Option<String>) {
+        *self.backlog.lock().unwrap_or_else(|p| p.into_inner()) = (unwritten, error);
+    }
+
+    pub fn backlog(&self) -> (u64, Option<String>) { self.backlog.lock().unwrap_or_else(|p| p.into_inner()).clone() }
+
+    /// Ask the poll loop to recalculate on its next cycle even if nothing changed.
+    pub fn request_recalc(&self) { self.recalc.store(true, Ordering::Relaxed); }
+
+    pub fn take_recalc(&self) -> bool { self.recalc.swap(false, Ordering::Relaxed) }
+
+    /// Calculate for `goal` from the next poll on instead of `MSD_SCORE_GOAL`; `None` goes back to it.
+    pub fn set_score_goal(&self, goal: Option<f32>) { self.score_goal.store(goal.map_or(0, f32::to_bits), Ordering::Relaxed); }
+
+    pub fn score_goal(&self) -> Option<f32> {
+        Some(f32::from_bits(self.score_goal.load(Ordering::Relaxed))).filter(|g| *g > 0.0)
+    }
+}
+
+pub fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
@@ -0,0 +1,71 @@
+use std::{collections::BTreeMap, path::Path};
+use anyhow::Context;
+use serde::Serialize;
+use tracing::*;
+
+use crate::{config::Config, output::{self, Format}};
+
+/// Label keys, in the order of each table below: the overlay rows, then tosu's game states.
+const KEYS: [&str; 19] = [
+    "song", "diff", "rate", "goal", "overall", "stamina", "jumpstream", "handstream", "stream", "chordjack", "jacks", "technical",
+    "state_menu", "state_editing", "state_song_select", "state_playing", "state_results", "state_multiplayer", "state_unknown",
+];
+
+/// Built-in translations. Etterna players use the English skillset names in most communities,
+/// so those stay as they are where there's no settled local term.
+const TABLES: [(&str, [&str; 19]); 9] = [
+    ("en", ["Song", "Diff", "Rate", "Goal", "Overall", "Stamina", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "Technical",
+        "Main menu", "Editing", "Song select", "Playing", "Results", "Multiplayer", "Idle"]),
+    ("de", ["Song", "Schwierigkeit", "Rate", "Ziel", "Gesamt", "Ausdauer", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "Technik",
+        "Hauptmenü", "Editor", "Songauswahl", "Im Spiel", "Ergebnis", "Mehrspieler", "Leerlauf"]),
+    ("fr", ["Musique", "Difficulté", "Vitesse", "Objectif", "Global", "Endurance", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "Technique",
+        "Menu principal", "Éditeur", "Sélection", "En jeu", "Résultats", "Multijoueur", "Inactif"]),
+    ("es", ["Canción", "Dificultad", "Velocidad", "Objetivo", "General", "Resistencia", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "Técnica",
+        "Menú principal", "Editor", "Selección", "Jugando", "Resultados", "Multijugador", "Inactivo"]),
+    ("pt", ["Música", "Dificuldade", "Velocidade", "Meta", "Geral", "Resistência", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "Técnica",
+        "Menu principal", "Editor", "Seleção", "Jogando", "Resultados", "Multijogador", "Inativo"]),
+    ("ru", ["Песня", "Сложность", "Скорость", "Цель", "Общий", "Выносливость", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "Техника",
+        "Главное меню", "Редактор", "Выбор песни", "Игра", "Результаты", "Мультиплеер", "Ожидание"]),
+    ("ja", ["曲", "難易度", "レート", "目標", "総合", "スタミナ", "ジャンプストリーム", "ハンドストリーム", "ストリーム", "コードジャック", "ジャック", "テクニカル",
+        "メインメニュー", "エディター", "選曲", "プレイ中", "リザルト", "マルチプレイ", "待機中"]),
+    ("ko", ["곡", "난이도", "배속", "목표", "종합", "스태미나", "점프스트림", "핸드스트림", "스트림", "코드잭", "잭", "테크니컬",
+        "메인 메뉴", "에디터", "곡 선택", "플레이 중", "결과", "멀티플레이", "대기 중"]),
+    ("zh", ["歌曲", "难度", "倍速", "目标", "综合", "耐力", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "技术",
+        "主菜单", "编辑器", "选歌", "游戏中", "结算", "多人游戏", "空闲"]),
+];
+
+#[derive(Serialize)]
+struct Bundle {
+    /// The language the labels are in; `en` when `MSD_LANGUAGE` isn't one of the built-in ones.
+    language: String,
+    labels: BTreeMap<String, String>,
+}
+
+/// `MSD_LANGUAGE`: write `i18n.json` (overlay labels and game state names) next to the theme
+/// file, so the bundled overlay, or any other, can show them translated. `MSD_LANGUAGE_FILE`
+/// is a JSON object of `key: label` pairs laid over the built-in table, for other languages or wording.
+pub fn write(static_root: &Path, cfg: &Config) {
+    if let Err(e) = try_write(static_root, cfg) { warn!(target: "output", "i18n.json: {e:#}"); }
+}
+
+fn try_write(static_root: &Path, cfg: &Config) -> anyhow::Result<()> {
+    let wanted = cfg.language.to_ascii_lowercase().replace('_', "-");
+    // `pt-BR` falls back to `pt`
+    let (language, table) = TABLES.iter().find(|(l, _)| *l == wanted || wanted.split('-').next() == Some(l))
+        .map_or((TABLES[0].0, &TABLES[0].1), |(l, t)| (*l, t));
+    if language != wanted.split('-').next().unwrap_or_default() && cfg.language_file.is_none() {
+        warn!(target: "output", "no built-in labels for MSD_LANGUAGE={:?}; using English (MSD_LANGUAGE_FILE can supply them)", cfg.language);
+    }
+    let mut labels: BTreeMap<String, String> = KEYS.iter().zip(table).map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let mut language = language.to_string();
+    if let Some(path) = &cfg.language_file {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let extra: BTreeMap<String, String> = serde_json::from_str(&text).with_context(|| format!("{}: expected {{\"key\": \"label\", ...}}", path.display()))?;
+        if let Some(k) = extra.keys().find(|k| !KEYS.contains(&k.as_str())) { warn!(target: "output", "{}: unknown label {k:?}", path.display()); }
+        labels.extend(extra);
+        language = wanted;
+    }
+    let bytes = output::encode(&Bundle { language, labels }, Format::Json, cfg)?;
+    std::fs::write(output::overlay_dir(static_root).join("i18n.json"), bytes)?;
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! Decoded RGB images (beatmap backgrounds) and colour palettes taken from them.
+
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// Rows of `width` RGB triples, top to bottom.
+    pub rgb: Vec<u8>,
+}
+
+/// JPEG or PNG, told apart by magic bytes.
+pub fn decode(data: &[u8]) -> anyhow::Result<Image> {
+    if data.starts_with(&[0xff, 0xd8]) { crate::jpeg::decode(data) }
+    else if data.starts_with(b"\x89PNG") { crate::png::decode(data) }
+    else { anyhow::bail!("unsupported image format (expected JPEG or PNG)") }
+}
+
+/// `#rrggbb`
+pub fn hex(c: [u8; 3]) -> String { format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]) }
+
+/// Black or white, whichever reads better on `c` (WCAG relative luminance).
+pub fn contrast(c: [u8; 3]) -> [u8; 3] {
+    let lin = |v: u8| { let v = v as f32 / 255.0; if v <= 0.039_28 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) } };
+    let l = 0.2126 * lin(c[0]) + 0.7152 * lin(c[1]) + 0.0722 * lin(c[2]);
+    if l > 0.179 { [0, 0, 0] } else { [255, 255, 255] }
+}
+
+pub struct Palette {
+    /// Most common colour.
+    pub dominant: [u8; 3],
+    /// The most vivid colour that still covers a noticeable part of the image.
+    pub accent: [u8; 3],
+    /// Up to `n` clearly different colours, most common first.
+    pub colors: Vec<[u8; 3]>,
+}
+
+/// Histogram of 4-bit-per-channel buckets over a ~128px sample grid; each bucket's colour is
+/// the mean of its pixels so the result isn't snapped to the bucket grid.
+pub fn palette(img: &Image, n: usize) -> Option<Palette> {
+    let (w, h) = (img.width as usize, img.height as usize);
+    if w == 0 || h == 0 { return None; }
+    let step = (w.max(h) / 128).max(1);
+    let mut buckets = vec![(0u32, [0u32; 3]); 4096];
+    let mut total = 0u32;
+    for y in (0..h).step_by(step) {
+        for x in (0..w).step_by(step) {
+            let p = &img.rgb[(y * w + x) * 3..][..3];
+            let b = &mut buckets[(p[0] as usize >> 4) << 8 | (p[1] as usize >> 4) << 4 | p[2] as usize >> 4];
+            b.0 += 1;
+            for (s, &v) in b.1.iter_mut().zip(p) { *s += v as u32; }
+            total += 1;
+        }
+    }
+    let mut ranked: Vec<(u32, [u8; 3])> = buckets.iter().filter(|b| b.0 > 0)
+        .map(|&(count, sum)| (count, sum.map(|s| (s / count) as u8)))
+        .collect();
+    ranked.sort_by_key(|b| std::cmp::Reverse(b.0));
+
+    let dist = |a: [u8; 3], b: [u8; 3]| (0..3).map(|i| (a[i] as i32 - b[i] as i32).pow(2)).sum::<i32>();
+    let mut colors: Vec<[u8; 3]> = Vec::new();
+    for &(_, c) in &ranked {
+        if colors.len() >= n { break; }
+        if colors.iter().all(|&k| dist(k, c) > 48 * 48) { colors.push(c); }
+    }
+    let dominant = *colors.first()?;
+    // saturation × value, among buckets holding at least 0.5% of the samples
+    let vivid = |c: [u8; 3]| {
+        let (max, min) = (*c.iter().max().unwrap() as f32, *c.iter().min().unwrap() as f32);
+        if max == 0.0 { 0.0 } else { (max - min) / max * max / 255.0 }
+    };
+    let accent = ranked.iter().filter(|b| b.0 * 200 >= total).map(|b| b.1)
+        .max_by(|a, b| vivid(*a).total_cmp(&vivid(*b)))
+        .unwrap_or(dominant);
+    Some(Palette { dominant, accent, colors })
+}
+
+impl Image {
+    /// Scale to fill `width`×`height`, cropping the overflow around the centre (CSS `object-fit: cover`).
+    pub fn cover(&self, width: u32, height: u32) -> Image {
+        let (sw, sh) = (self.width as f32, self.height as f32);
+        let scale = (width as f32 / sw).max(height as f32 / sh);
+        let (cw, ch) = (width as f32 / scale, height as f32 / scale); // source region kept
+        let (x0, y0) = ((sw - cw) / 2.0, (sh - ch) / 2.0);
+        let rows = resample(&self.rgb, self.width as usize, self.height as usize, width as usize, x0, cw, true);
+        let rgb = resample(&rows, width as usize, self.height as usize, height as usize, y0, ch, false);
+        Image { width, height, rgb }
+    }
+
+    /// Three box-blur passes, close to a gaussian with σ ≈ `radius`.
+    pub fn blur(&mut self, radius: u32) {
+        if radius == 0 { return; }
+        let (w, h) = (self.width as usize, self.height as usize);
+        let r = radius as usize;
+        for _ in 0..3 {
+            box_blur(&mut self.rgb, w, h, r, 3, w * 3);
+            box_blur(&mut self.rgb, h, w, r, w * 3, 3);
+        }
+    }
+}
+
+/// Resample one axis of a packed RGB buffer from the span `[start, start + span)` onto `out_len`
+/// pixels with a triangle filter widened to the scale factor, so downscaling averages properly.
+fn resample(src: &[u8], w: usize, h: usize, out_len: usize, start: f32, span: f32, horizontal: bool) -> Vec<u8> {
+    let src_len = if horizontal { w } else { h };
+    let lines = if horizontal { h } else { w };
+    let (ow, oh) = if horizontal { (out_len, h) } else { (w, out_len) };
+    let ratio = span / out_len as f32;
+    let support = ratio.max(1.0);
+    let mut out = vec![0u8; ow * oh * 3];
+    // weights only depend on the output position
+    let taps: Vec<(usize, Vec<f32>)> = (0..out_len).map(|o| {
+        let centre = start + (o as f32 + 0.5) * ratio;
+        let lo = ((centre - support).floor().max(0.0) as usize).min(src_len - 1);
+        let hi = ((centre + support).ceil() as usize).min(src_len);
+        let mut ws: Vec<f32> = (lo..hi).map(|i| (1.0 - ((i as f32 + 0.5 - centre) / support).abs()).max(0.0)).collect();
+        let sum: f32 = ws.iter().sum();
+        if sum > 0.0 { ws.iter_mut().for_each(|w| *w /= sum); } else { ws = vec![1.0]; }
+        (lo, ws)
+    }).collect();
+    for line in 0..lines {
+        for (o, (lo, ws)) in taps.iter().enumerate() {
+            let mut acc = [0f32; 3];
+            for (k, wgt) in ws.iter().enumerate() {
+                let (x, y) = if horizontal { (lo + k, line) } else { (line, lo + k) };
+                let p = &src[(y * w + x) * 3..][..3];
+                for c in 0..3 { acc[c] += p[c] as f32 * wgt; }
+            }
+            let (x, y) = if horizontal { (o, line) } else { (line, o) };
+            out[(y * ow + x) * 3..][..3].copy_from_slice(&acc.map(|v| v.round().clamp(0.0, 255.0) as u8));
+        }
+    }
+    out
+}
+
+/// Running-sum box blur along lines of `len` pixels, `step` bytes apart, lines `line_step` apart.
+fn box_blur(buf: &mut [u8], len: usize, lines: usize, r: usize, step: usize, line_step: usize) {
+    let mut line = vec![[0u32; 3]; len];
+    for l in 0..lines {
+        for (i, px) in line.iter_mut().enumerate() {
+            let p = l * line_step + i * step;
+            *px = [buf[p] as u32, buf[p + 1] as u32, buf[p + 2] as u32];
+        }
+        let at = |i: isize| line[i.clamp(0, len as isize - 1) as usize];
+        let mut sum = [0u32; 3];
+        for i in -(r as isize)..=r as isize { for (s, v) in sum.iter_mut().zip(at(i)) { *s += v; } }
+        let n = (2 * r + 1) as u32;
+        for i in 0..len {
+            let p = l * line_step + i * step;
+            for c in 0..3 { buf[p + c] = (sum[c] / n) as u8; }
+            let (add, sub) = (at(i as isize + r as isize + 1), at(i as isize - r as isize));
+            for c in 0..3 { sum[c] = sum[c] + add[c] - sub[c]; }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+use anyhow::Context;
+use reqwest::Client;
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::TcpStream};
+use tracing::*;
+
+use crate::{beatmaps::{self, MapRef}, calc, config::Config};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Bancho drops clients that talk faster than this.
+const SEND_GAP: Duration = Duration::from_millis(1200);
+
+/// `MSD_IRC_USER`: answer private messages on osu!'s IRC (Bancho) with the MSD of the map in
+/// them: an in-game `/np` (the map's link rides along in the action) or a pasted beatmap link,
+/// optionally with a rate (`1.2x`) or mods (`+DT`). Maps that aren't on disk come from the mirror
+/// the same way `mappool` gets them. Reconnects with backoff.
+pub async fn run(cfg: Arc<Config>) {
+    let mut backoff = Duration::from_secs(2);
+    loop {
+        match session(&cfg).await {
+            Ok(()) => { info!(target: "irc", "Bancho closed the connection"); backoff = Duration::from_secs(2); }
+            Err(e) => warn!(target: "irc", "{e:#}; retrying in {}s", backoff.as_secs()),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn session(cfg: &Config) -> anyhow::Result<()> {
+    let (Some(user), Some(password)) = (&cfg.irc_user, &cfg.irc_password) else { anyhow::bail!("MSD_IRC_PASSWORD not set") };
+    let stream = TcpStream::connect(&cfg.irc_server).await.with_context(|| format!("connecting to {}", cfg.irc_server))?;
+    let (rd, mut wr) = stream.into_split();
+    // IRC names have underscores where osu! names have spaces
+    let nick = user.replace(' ', "_");
+    wr.write_all(format!("PASS {password}\r\nNICK {nick}\r\nUSER {nick} 0 * :{nick}\r\n").as_bytes()).await?;
+    let http = Client::new();
+    let mut lines = BufReader::new(rd).lines();
+    let mut last_send = Instant::now() - SEND_GAP;
+    while let Some(line) = lines.next_line().await? {
+        let Some(msg) = Line::parse(&line) else { continue };
+        match msg.command {
+            "PING" => wr.write_all(format!("PONG :{}\r\n", msg.text).as_bytes()).await?,
+            "001" => info!(target: "irc", "logged in to {} as {nick}", cfg.irc_server),
+            "464" => anyhow::bail!("Bancho rejected the login: {} (MSD_IRC_PASSWORD is the IRC password from https://osu.ppy.sh/home/account/edit#legacy-api)", msg.text),
+            // only private messages; channels are far too busy
+            "PRIVMSG" if msg.target.eq_ignore_ascii_case(&nick) => {
+                let Some(from) = msg.nick else { continue };
+                if !cfg.irc_allow.is_empty() && !cfg.irc_allow.iter().any(|a| a.eq_ignore_ascii_case(from)) {
+                    debug!(target: "irc", "ignoring {from}: not in MSD_IRC_ALLOW");
+                    continue;
+                }
+                let Some((map, rate)) = request(msg.text) else { continue };
+                let reply = match rate_map(&http, map, rate, cfg).await {
+                    Ok(r) => r,
+                    Err(e) => { debug!(target: "irc", "{from}: {e:#}"); format!("Couldn't rate that map: {e}") }
+                };
+                let wait = SEND_GAP.saturating_sub(last_send.elapsed());
+                if !wait.is_zero() { tokio::time::sleep(wait).await; }
+                wr.write_all(format!("PRIVMSG {from} :{reply}\r\n").as_bytes()).await?;
+                last_send = Instant::now();
+                info!(target: "irc", "answered {from}: {reply}");
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// One IRC line: `[:nick!user@host] COMMAND [target] [:text]`.
+struct Line<'a> { nick: Option<&'a str>, command: &'a str, target: &'a str, text: &'a str }
+
+impl Line<'_> {
+    fn parse(line: &str) -> Option<Line<'_>> {
+        let (prefix, rest) = match line.strip_prefix(':') {
+            Some(l) => { let (p, r) = l.split_once(' ')?; (Some(p), r) }
+            None => (None, line),
+        };
+        let (head, text) = rest.split_once(" :").unwrap_or((rest, ""));
+        let mut parts = head.split(' ');
+        let command = parts.next()?;
+        let target = parts.next().unwrap_or("");
+        // PING carries its token as the first parameter
+        let text = if text.is_empty() && command == "PING" { target } else { text };
+        Some(Line { nick: prefix.map(|p| p.split('!').next().unwrap_or(p)), command, target, text })
+    }
+}
+
+/// The map and rate a message asks about: the first difficulty link, then an explicit `1.2x`,
+/// else the rate of speed mods (`+DoubleTime` in `/np`, `+DT` typed).
+fn request(text: &str) -> Option<(MapRef, f32)> {
+    let text = text.trim_matches('\u{1}').trim_start_matches("ACTION ");
+    let map = text.split(|c: char| c.is_whitespace() || c == '[' || c == ']')
+        .filter(|w| w.starts_with("https://osu.ppy.sh/") || w.starts_with("http://osu.ppy.sh/"))
+        .find_map(MapRef::parse)?;
+    // in `/np` the title and difficulty sit inside the brackets; a `0.9x` there isn't a request
+    let words: Vec<&str> = text.rsplit_once(']').map_or(text, |(_, after)| after).split_whitespace().collect();
+    let rate = words.iter().find_map(|w| w.strip_suffix('x').and_then(|r| r.parse::<f32>().ok()).filter(|r| *r > 0.0 && *r <= 3.0))
+        .or_else(|| words.iter().find_map(|w| match w.trim_start_matches('+').to_ascii_lowercase().as_str() {
+            "dt" | "doubletime" | "nc" | "nightcore" => Some(1.5),
+            "ht" | "halftime" | "dc" | "daycore" => Some(0.75),
+            _ => None,
+        }))
+        .unwrap_or(1.0);
+    Some((map, rate))
+}
+
+/// `Artist - Title [Diff] 1.50x: 27.31 MSD (stream 28.10, jumpstream 26.40, technical 25.90)`
+async fn rate_map(http: &Client, map: MapRef, rate: f32, cfg: &Config) -> anyhow::Result<String> {
+    let bytes = beatmaps::load(http, &map, cfg.retry_bulk).await?;
+    let osu = calc::decode(&bytes).0.into_owned();
+    let goal = cfg.score_goal;
+    let (beatmap, s) = tokio::task::spawn_blocking(move || calc::pooled(|c| calc::calc_osu(c, &osu, rate, goal))).await??;
+    let mut top = [("stream", s.stream), ("jumpstream", s.jumpstream), ("handstream", s.handstream), ("stamina", s.stamina),
+        ("jacks", s.jackspeed), ("chordjack", s.chordjack), ("technical", s.technical)];
+    top.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let top: Vec<String> = top[..3].iter().map(|(n, v)| format!("{n} {v:.2}")).collect();
+    Ok(format!("{} [{}] {}x: {:.2} MSD ({})", crate::sanitize::meta(&calc::song_label(&beatmap.artist, &beatmap.title), cfg), crate::sanitize::meta(&beatmap.version, cfg),
+        calc::rate_label(rate), s.overall, top.join(", ")))
+}
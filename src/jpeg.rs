@@ -0,0 +1,660 @@
+//! JPEG decoder (huffman-coded baseline and progressive, 8-bit, any chroma subsampling,
+//! restart markers) and a baseline encoder. Lossless, arithmetic-coded and CMYK files are refused.
+use anyhow::{bail, ensure, Context};
+
+use crate::image::Image;
+
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// 8K UHD (7680x4320) and a little more; anything larger is corrupt or a decompression bomb.
+const MAX_PIXELS: usize = 1 << 25;
+
+/// Canonical code table: for each length, the first code and where its values start.
+#[derive(Clone)]
+struct Huffman { first: [i32; 17], count: [i32; 17], offset: [usize; 17], values: Vec<u8> }
+
+impl Huffman {
+    fn new(counts: &[u8; 16], values: &[u8]) -> Huffman {
+        let mut h = Huffman { first: [0; 17], count: [0; 17], offset: [0; 17], values: values.to_vec() };
+        let (mut code, mut k) = (0i32, 0usize);
+        for len in 1..=16 {
+            h.first[len] = code;
+            h.count[len] = counts[len - 1] as i32;
+            h.offset[len] = k;
+            code = (code + h.count[len]) << 1;
+            k += counts[len - 1] as usize;
+        }
+        h
+    }
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    tq: usize,
+    td: usize,
+    ta: usize,
+    /// Blocks per row / column, padded to whole MCUs.
+    bw: usize,
+    bh: usize,
+    /// Quantized coefficients in natural order, one `[i32; 64]` per block.
+    coefs: Vec<[i32; 64]>,
+    pred: i32,
+}
+
+struct Bits<'a> { data: &'a [u8], pos: usize, buf: u32, cnt: u32 }
+
+impl Bits<'_> {
+    fn fill(&mut self) {
+        while self.cnt <= 24 {
+            let mut b = 0u32;
+            if self.pos < self.data.len() {
+                let x = self.data[self.pos];
+                if x == 0xff {
+                    // anything but a stuffed 0xff00 is a marker: feed zeros, the caller handles it
+                    if self.data.get(self.pos + 1) == Some(&0) { b = 0xff; self.pos += 2; }
+                } else { b = x as u32; self.pos += 1; }
+            }
+            self.buf |= b << (24 - self.cnt);
+            self.cnt += 8;
+        }
+    }
+
+    fn bit(&mut self) -> bool { self.bits(1) == 1 }
+
+    fn bits(&mut self, n: u32) -> u32 {
+        if n == 0 { return 0; }
+        self.fill();
+        let v = self.buf >> (32 - n);
+        self.buf <<= n;
+        self.cnt -= n;
+        v
+    }
+
+    fn decode(&mut self, h: &Huffman) -> anyhow::Result<u8> {
+        let mut code = 0i32;
+        for len in 1..=16 {
+            code = (code << 1) | self.bits(1) as i32;
+            if code - h.first[len] < h.count[len] {
+                return h.values.get(h.offset[len] + (code - h.first[len]) as usize).copied().context("bad huffman table");
+            }
+        }
+        bail!("bad huffman code")
+    }
+
+    /// Drop buffered bits and step past the next RSTn marker.
+    fn restart(&mut self) {
+        self.buf = 0;
+        self.cnt = 0;
+        while self.pos + 1 < self.data.len() {
+            if self.data[self.pos] == 0xff && (0xd0..=0xd7).contains(&self.data[self.pos + 1]) { self.pos += 2; return; }
+            self.pos += 1;
+        }
+    }
+}
+
+fn extend(v: u32, n: u32) -> i32 {
+    if n == 0 { 0 } else if v < 1 << (n - 1) { v as i32 - (1 << n) + 1 } else { v as i32 }
+}
+
+/// Spectral selection and successive approximation of one scan (`0, 63, 0, 0` for baseline).
+#[derive(Clone, Copy)]
+struct Scan { ss: usize, se: usize, ah: u32, al: u32, progressive: bool }
+
+pub fn decode(data: &[u8]) -> anyhow::Result<Image> {
+    ensure!(data.starts_with(&[0xff, 0xd8]), "not a JPEG");
+    let mut qt = [[0u16; 64]; 4];
+    let mut dc: Vec<Option<Huffman>> = vec![None; 4];
+    let mut ac: Vec<Option<Huffman>> = vec![None; 4];
+    let mut comps: Vec<Component> = Vec::new();
+    let (mut width, mut height, mut restart) = (0usize, 0usize, 0usize);
+    let (mut progressive, mut scans) = (false, 0);
+    let mut adobe_transform: Option<u8> = None;
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xff { pos += 1; continue; } // garbage / the tail of a scan
+        let marker = data[pos + 1];
+        if marker == 0xff || marker == 0 || (0xd0..=0xd7).contains(&marker) || marker == 0x01 { pos += 1; continue; }
+        pos += 2;
+        if marker == 0xd9 { break; }
+        let len = u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
+        let seg = data.get(pos + 2..pos + len).context("truncated JPEG")?;
+        match marker {
+            0xdb => {
+                let mut s = seg;
+                while !s.is_empty() {
+                    let (precision, id) = ((s[0] >> 4) as usize, (s[0] & 3) as usize);
+                    let n = if precision == 0 { 64 } else { 128 };
+                    ensure!(s.len() > n, "bad DQT");
+                    for k in 0..64 {
+                        qt[id][ZIGZAG[k]] = if precision == 0 { s[1 + k] as u16 } else { u16::from_be_bytes([s[1 + 2 * k], s[2 + 2 * k]]) };
+                    }
+                    s = &s[1 + n..];
+                }
+            }
+            0xc4 => {
+                let mut s = seg;
+                while s.len() >= 17 {
+                    let (class, id) = (s[0] >> 4, (s[0] & 3) as usize);
+                    let counts: [u8; 16] = s[1..17].try_into()?;
+                    let total: usize = counts.iter().map(|&c| c as usize).sum();
+                    let values = s.get(17..17 + total).context("bad DHT")?;
+                    let h = Some(Huffman::new(&counts, values));
+                    if class == 0 { dc[id] = h; } else { ac[id] = h; }
+                    s = &s[17 + total..];
+                }
+            }
+            0xc0..=0xc2 => {
+                ensure!(seg.len() >= 6 && seg[0] == 8, "only 8-bit JPEGs are supported");
+                progressive = marker == 0xc2;
+                height = u16::from_be_bytes([seg[1], seg[2]]) as usize;
+                width = u16::from_be_bytes([seg[3], seg[4]]) as usize;
+                ensure!(width > 0 && height > 0, "JPEG without dimensions");
+                // coefficients cost ~4 bytes per pixel and component before anything is decoded
+                ensure!(width * height <= MAX_PIXELS, "{width}x{height} JPEG is too large");
+                ensure!(matches!(seg[5], 1 | 3), "{}-component JPEGs are not supported", seg[5]);
+                for c in seg[6..].chunks_exact(3).take(seg[5] as usize) {
+                    comps.push(Component {
+                        id: c[0], h: (c[1] >> 4).clamp(1, 4) as usize, v: (c[1] & 15).clamp(1, 4) as usize, tq: (c[2] & 3) as usize,
+                        td: 0, ta: 0, bw: 0, bh: 0, coefs: Vec::new(), pred: 0,
+                    });
+                }
+                let hmax = comps.iter().map(|c| c.h).max().unwrap_or(1);
+                let vmax = comps.iter().map(|c| c.v).max().unwrap_or(1);
+                for c in comps.iter_mut() {
+                    c.bw = width.div_ceil(8 * hmax) * c.h;
+                    c.bh = height.div_ceil(8 * vmax) * c.v;
+                    c.coefs = vec![[0; 64]; c.bw * c.bh];
+                }
+            }
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => bail!("lossless / arithmetic-coded JPEGs are not supported"),
+            0xdd => restart = u16::from_be_bytes(seg.get(..2).context("bad DRI")?.try_into()?) as usize,
+            0xee if seg.starts_with(b"Adobe") && seg.len() >= 12 => adobe_transform = Some(seg[11]),
+            0xda => {
+                ensure!(!comps.is_empty(), "scan before frame header");
+                let n = *seg.first().context("bad SOS")? as usize;
+                ensure!(seg.len() >= 1 + 2 * n + 3, "bad SOS");
+                let mut members = Vec::with_capacity(n);
+                for c in seg[1..1 + 2 * n].chunks_exact(2) {
+                    let i = comps.iter().position(|k| k.id == c[0]).context("scan names an unknown component")?;
+                    comps[i].td = (c[1] >> 4) as usize & 3;
+                    comps[i].ta = (c[1] & 3) as usize;
+                    members.push(i);
+                }
+                let p = &seg[1 + 2 * n..];
+                let scan = Scan { ss: p[0] as usize, se: (p[1] as usize).min(63), ah: (p[2] >> 4) as u32, al: (p[2] & 15) as u32, progressive };
+                let used = decode_scan(&data[pos + len..], &mut comps, &members, width, height, restart, scan, &dc, &ac)?;
+                scans += 1;
+                pos += len + used;
+                continue;
+            }
+            _ => {}
+        }
+        pos += len;
+    }
+    ensure!(scans > 0, "no image data");
+    Ok(to_rgb(&comps, &qt, width, height, adobe_transform))
+}
+
+/// Entropy-decode one scan into the components' coefficients; returns the bytes consumed.
+#[allow(clippy::too_many_arguments)]
+fn decode_scan(data: &[u8], comps: &mut [Component], members: &[usize], width: usize, height: usize, restart: usize,
+               scan: Scan, dc: &[Option<Huffman>], ac: &[Option<Huffman>]) -> anyhow::Result<usize> {
+    let hmax = comps.iter().map(|c| c.h).max().unwrap_or(1);
+    let vmax = comps.iter().map(|c| c.v).max().unwrap_or(1);
+    let mut bits = Bits { data, pos: 0, buf: 0, cnt: 0 };
+    let mut eobrun = 0u32;
+    for &i in members { comps[i].pred = 0; }
+
+    // a single-component scan covers just that component's blocks, not whole MCUs
+    let single = members.len() == 1;
+    let (units_x, units_y) = if single {
+        let c = &comps[members[0]];
+        ((width * c.h).div_ceil(hmax * 8), (height * c.v).div_ceil(vmax * 8))
+    } else {
+        (width.div_ceil(8 * hmax), height.div_ceil(8 * vmax))
+    };
+    let mut n = 0;
+    for uy in 0..units_y {
+        for ux in 0..units_x {
+            if restart > 0 && n > 0 && n % restart == 0 {
+                bits.restart();
+                eobrun = 0;
+                for &i in members { comps[i].pred = 0; }
+            }
+            n += 1;
+            for &i in members {
+                let c = &mut comps[i];
+                let (nx, ny) = if single { (1, 1) } else { (c.h, c.v) };
+                for by in 0..ny {
+                    for bx in 0..nx {
+                        let (x, y) = if single { (ux, uy) } else { (ux * c.h + bx, uy * c.v + by) };
+                        let idx = y * c.bw + x;
+                        let mut block = c.coefs[idx];
+                        decode_block(&mut bits, &mut block, &mut c.pred, &mut eobrun, scan, dc[c.td].as_ref(), ac[c.ta].as_ref())?;
+                        c.coefs[idx] = block;
+                    }
+                }
+            }
+        }
+    }
+    Ok(bits.pos)
+}
+
+fn decode_block(bits: &mut Bits, block: &mut [i32; 64], pred: &mut i32, eobrun: &mut u32, scan: Scan,
+                dc: Option<&Huffman>, ac: Option<&Huffman>) -> anyhow::Result<()> {
+    let Scan { ss, se, ah, al, progressive } = scan;
+    if ss == 0 {
+        if ah == 0 {
+            let t = bits.decode(dc.context("missing DC table")?)? as u32;
+            // a difference category; more than 16 bits can't be read (or be right)
+            ensure!(t <= 16, "bad DC code");
+            *pred += extend(bits.bits(t), t);
+            block[0] = *pred << al;
+        } else if bits.bit() {
+            block[0] |= 1 << al;
+        }
+        if progressive { return Ok(()); }
+    }
+    let ac = ac.context("missing AC table")?;
+    let mut k = ss.max(1);
+    if !progressive || ah == 0 {
+        if *eobrun > 0 { *eobrun -= 1; return Ok(()); }
+        while k <= se {
+            let rs = bits.decode(ac)?;
+            let (r, s) = ((rs >> 4) as u32, (rs & 15) as u32);
+            if s == 0 {
+                if r < 15 {
+                    if progressive { *eobrun = (1 << r) - 1 + bits.bits(r); }
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += r as usize;
+            if k > 63 { break; }
+            block[ZIGZAG[k]] = extend(bits.bits(s), s) * (1 << al);
+            k += 1;
+        }
+        return Ok(());
+    }
+
+    // AC refinement: one correction bit for every already-nonzero coefficient passed over
+    let (p1, m1) = (1i32 << al, -1i32 << al);
+    let refine = |bits: &mut Bits, coef: &mut i32| {
+        if bits.bit() && *coef & p1 == 0 { *coef += if *coef >= 0 { p1 } else { m1 }; }
+    };
+    if *eobrun == 0 {
+        while k <= se {
+            let rs = bits.decode(ac)?;
+            let (mut r, s) = ((rs >> 4) as i32, rs & 15);
+            let mut val = 0;
+            if s != 0 {
+                val = if bits.bit() { p1 } else { m1 };
+            } else if r != 15 {
+                *eobrun = (1 << r) + bits.bits(r as u32);
+                break;
+            }
+            while k <= se {
+                let z = ZIGZAG[k];
+                if block[z] != 0 {
+                    refine(bits, &mut block[z]);
+                } else {
+                    if r == 0 {
+                        if val != 0 { block[z] = val; }
+                        k += 1;
+                        break;
+                    }
+                    r -= 1;
+                }
+                k += 1;
+            }
+        }
+    }
+    if *eobrun > 0 {
+        while k <= se {
+            let z = ZIGZAG[k];
+            if block[z] != 0 { refine(bits, &mut block[z]); }
+            k += 1;
+        }
+        *eobrun -= 1;
+    }
+    Ok(())
+}
+
+/// Straightforward separable float IDCT of a dequantized block.
+fn idct(block: &[i32; 64], q: &[u16; 64], cos: &[[f32; 8]; 8], out: &mut [u8; 64]) {
+    let mut tmp = [0f32; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            tmp[y * 8 + x] = (0..8).map(|u| cos[x][u] * (block[y * 8 + u] * q[y * 8 + u] as i32) as f32).sum::<f32>() / 2.0;
+        }
+    }
+    for x in 0..8 {
+        for (y, cy) in cos.iter().enumerate() {
+            let v = (0..8).map(|v| cy[v] * tmp[v * 8 + x]).sum::<f32>() / 2.0 + 128.0;
+            out[y * 8 + x] = v.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+fn to_rgb(comps: &[Component], qt: &[[u16; 64]; 4], width: usize, height: usize, adobe: Option<u8>) -> Image {
+    let mut cos = [[0f32; 8]; 8];
+    for (x, row) in cos.iter_mut().enumerate() {
+        for (u, c) in row.iter_mut().enumerate() {
+            let cu = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+            *c = cu * (((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI) / 16.0).cos();
+        }
+    }
+    let planes: Vec<Vec<u8>> = comps.iter().map(|c| {
+        let stride = c.bw * 8;
+        let mut plane = vec![0u8; stride * c.bh * 8];
+        let mut px = [0u8; 64];
+        for (i, block) in c.coefs.iter().enumerate() {
+            idct(block, &qt[c.tq], &cos, &mut px);
+            let (x0, y0) = (i % c.bw * 8, i / c.bw * 8);
+            for y in 0..8 { plane[(y0 + y) * stride + x0..][..8].copy_from_slice(&px[y * 8..][..8]); }
+        }
+        plane
+    }).collect();
+
+    let hmax = comps.iter().map(|c| c.h).max().unwrap_or(1);
+    let vmax = comps.iter().map(|c| c.v).max().unwrap_or(1);
+    let sample = |i: usize, x: usize, y: usize| {
+        let c = &comps[i];
+        planes[i][(y * c.v / vmax) * c.bw * 8 + x * c.h / hmax] as f32
+    };
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    // 3 components are YCbCr unless an Adobe marker says RGB (transform 0)
+    let ycc = comps.len() == 3 && adobe != Some(0);
+    for y in 0..height {
+        for x in 0..width {
+            if comps.len() == 1 {
+                let l = sample(0, x, y) as u8;
+                rgb.extend([l, l, l]);
+            } else if ycc {
+                let (yy, cb, cr) = (sample(0, x, y), sample(1, x, y) - 128.0, sample(2, x, y) - 128.0);
+                let px = [yy + 1.402 * cr, yy - 0.344_136 * cb - 0.714_136 * cr, yy + 1.772 * cb];
+                rgb.extend(px.map(|v| v.round().clamp(0.0, 255.0) as u8));
+            } else {
+                rgb.extend([0, 1, 2].map(|i| sample(i, x, y) as u8));
+            }
+        }
+    }
+    Image { width: width as u32, height: height as u32, rgb }
+}
+
+// Annex K luminance / chrominance tables, natural order
+const LUMA_Q: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56, 14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+const CHROMA_Q: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99, 47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+const DC_LUMA: ([u8; 16], &[u8]) = ([0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+const DC_CHROMA: ([u8; 16], &[u8]) = ([0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+const AC_LUMA: ([u8; 16], &[u8]) = ([0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d], &[
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08,
+    0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59,
+    0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6,
+    0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+]);
+const AC_CHROMA: ([u8; 16], &[u8]) = ([0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77], &[
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91,
+    0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0, 0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58,
+    0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4,
+    0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+]);
+
+/// (code, length) per symbol for a DHT spec.
+fn codes((counts, values): ([u8; 16], &[u8])) -> [(u16, u8); 256] {
+    let mut out = [(0, 0); 256];
+    let (mut code, mut k) = (0u16, 0);
+    for (len, &n) in counts.iter().enumerate() {
+        for _ in 0..n { out[values[k] as usize] = (code, len as u8 + 1); code += 1; k += 1; }
+        code <<= 1;
+    }
+    out
+}
+
+struct BitWriter { out: Vec<u8>, acc: u32, n: u32 }
+
+impl BitWriter {
+    fn put(&mut self, bits: u32, len: u32) {
+        self.acc = (self.acc << len) | (bits & ((1 << len) - 1));
+        self.n += len;
+        while self.n >= 8 {
+            let b = (self.acc >> (self.n - 8)) as u8;
+            self.out.push(b);
+            if b == 0xff { self.out.push(0); }
+            self.n -= 8;
+        }
+    }
+}
+
+/// Magnitude category and the low bits that encode `v`.
+fn category(v: i32) -> (u32, u32) {
+    let n = 32 - v.unsigned_abs().leading_zeros();
+    (n, if v < 0 { (v - 1) as u32 } else { v as u32 })
+}
+
+/// Baseline 4:4:4 JPEG with the standard tables scaled to `quality` (1–100, IJG scaling).
+pub fn encode(img: &Image, quality: u8) -> Vec<u8> {
+    let q = quality.clamp(1, 100) as u32;
+    let scale = if q < 50 { 5000 / q } else { 200 - 2 * q };
+    let table = |base: &[u8; 64]| base.map(|b| ((b as u32 * scale + 50) / 100).clamp(1, 255) as u8);
+    let (lq, cq) = (table(&LUMA_Q), table(&CHROMA_Q));
+
+    let (w, h) = (img.width as usize, img.height as usize);
+    let mut out = vec![0xff, 0xd8];
+    let mut segment = |marker: u8, body: &[u8]| {
+        out.extend([0xff, marker]);
+        out.extend((body.len() as u16 + 2).to_be_bytes());
+        out.extend(body);
+    };
+    segment(0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    for (id, t) in [(0u8, &lq), (1, &cq)] {
+        let mut body = vec![id];
+        body.extend(ZIGZAG.map(|z| t[z]));
+        segment(0xdb, &body);
+    }
+    let mut sof = vec![8];
+    sof.extend((h as u16).to_be_bytes());
+    sof.extend((w as u16).to_be_bytes());
+    sof.extend([3, 1, 0x11, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    segment(0xc0, &sof);
+    for (class_id, (counts, values)) in [(0x00, DC_LUMA), (0x10, AC_LUMA), (0x01, DC_CHROMA), (0x11, AC_CHROMA)] {
+        let mut body = vec![class_id];
+        body.extend(counts);
+        body.extend(values);
+        segment(0xc4, &body);
+    }
+    segment(0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+
+    let tables = [(codes(DC_LUMA), codes(AC_LUMA)), (codes(DC_CHROMA), codes(AC_CHROMA))];
+    let mut cos = [[0f32; 8]; 8];
+    for (x, row) in cos.iter_mut().enumerate() {
+        for (u, c) in row.iter_mut().enumerate() {
+            let cu = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+            *c = cu * (((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI) / 16.0).cos();
+        }
+    }
+    let mut bits = BitWriter { out: Vec::new(), acc: 0, n: 0 };
+    let mut pred = [0i32; 3];
+    let mut px = [[0f32; 64]; 3];
+    for by in (0..h).step_by(8) {
+        for bx in (0..w).step_by(8) {
+            for y in 0..8 {
+                for x in 0..8 {
+                    // edge blocks repeat the last row / column
+                    let p = &img.rgb[((by + y).min(h - 1) * w + (bx + x).min(w - 1)) * 3..][..3];
+                    let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+                    px[0][y * 8 + x] = 0.299 * r + 0.587 * g + 0.114 * b - 128.0;
+                    px[1][y * 8 + x] = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+                    px[2][y * 8 + x] = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+                }
+            }
+            for c in 0..3 {
+                let quant = if c == 0 { &lq } else { &cq };
+                let (dc, ac) = &tables[(c > 0) as usize];
+                // forward DCT: transpose of the decoder's basis
+                let mut tmp = [0f32; 64];
+                for y in 0..8 {
+                    for u in 0..8 { tmp[y * 8 + u] = (0..8).map(|x| cos[x][u] * px[c][y * 8 + x]).sum::<f32>() / 2.0; }
+                }
+                let mut coef = [0i32; 64];
+                for u in 0..8 {
+                    for v in 0..8 {
+                        let s = (0..8).map(|y| cos[y][v] * tmp[y * 8 + u]).sum::<f32>() / 2.0;
+                        coef[v * 8 + u] = (s / quant[v * 8 + u] as f32).round() as i32;
+                    }
+                }
+                let (n, b) = category(coef[0] - pred[c]);
+                pred[c] = coef[0];
+                bits.put(dc[n as usize].0 as u32, dc[n as usize].1 as u32);
+                bits.put(b, n);
+                let mut run = 0;
+                for k in 1..64 {
+                    let v = coef[ZIGZAG[k]];
+                    if v == 0 { run += 1; continue; }
+                    while run > 15 { bits.put(ac[0xf0].0 as u32, ac[0xf0].1 as u32); run -= 16; }
+                    let (n, b) = category(v);
+                    let sym = (run << 4 | n) as usize;
+                    bits.put(ac[sym].0 as u32, ac[sym].1 as u32);
+                    bits.put(b, n);
+                    run = 0;
+                }
+                if run > 0 { bits.put(ac[0].0 as u32, ac[0].1 as u32); }
+            }
+        }
+    }
+    if bits.n > 0 { bits.put(0x7f, 8 - bits.n); } // pad with ones
+    out.extend(bits.out);
+    out.extend([0xff, 0xd9]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // made by an encoder outside this crate with an all-ones quantizer, so the pixels come back
+    // within rounding of the formulas below. The progressive files carry the same coefficients
+    // as the baseline ones in libjpeg's default scan script (DC and AC refinement included).
+    const GRAY: &[u8] = include_bytes!("testdata/gray_baseline.jpg");
+    const GRAY_PROGRESSIVE: &[u8] = include_bytes!("testdata/gray_progressive.jpg");
+    /// 4:2:0, a restart marker after every MCU.
+    const COLOR: &[u8] = include_bytes!("testdata/color_restart.jpg");
+    /// 4:2:0, progressive, a restart marker after every MCU or block.
+    const COLOR_PROGRESSIVE: &[u8] = include_bytes!("testdata/color_progressive.jpg");
+
+    fn gray(x: u32, y: u32) -> [u8; 3] {
+        let l = if (5..9).contains(&x) && (9..13).contains(&y) { 230 } else { 40 + 6 * x + 3 * y } as u8;
+        [l, l, l]
+    }
+
+    /// 8x8 tiles of flat colour.
+    fn tiles(x: u32, y: u32) -> [u8; 3] {
+        const TILES: [[u8; 3]; 8] = [[200, 40, 40], [40, 160, 60], [50, 60, 200], [220, 200, 60], [90, 90, 90], [250, 250, 250], [10, 10, 10], [120, 30, 150]];
+        TILES[(y / 8 * 4 + x / 8) as usize]
+    }
+
+    fn assert_close(img: &Image, (w, h): (u32, u32), want: fn(u32, u32) -> [u8; 3], tolerance: u8) {
+        assert_eq!((img.width, img.height), (w, h));
+        for y in 0..h {
+            for x in 0..w {
+                let i = (y * w + x) as usize * 3;
+                let (got, want) = (&img.rgb[i..i + 3], want(x, y));
+                assert!(got.iter().zip(want).all(|(&g, w)| g.abs_diff(w) <= tolerance), "({x}, {y}): {got:?}, want {want:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn baseline_gray() {
+        assert_close(&decode(GRAY).unwrap(), (16, 16), gray, 2);
+    }
+
+    #[test]
+    fn baseline_color_with_restarts() {
+        assert_close(&decode(COLOR).unwrap(), (32, 16), tiles, 4);
+    }
+
+    #[test]
+    fn progressive_matches_baseline() {
+        assert_eq!(decode(GRAY_PROGRESSIVE).unwrap().rgb, decode(GRAY).unwrap().rgb);
+        assert_eq!(decode(COLOR_PROGRESSIVE).unwrap().rgb, decode(COLOR).unwrap().rgb);
+    }
+
+    #[test]
+    fn encoder_round_trips() {
+        let img = decode(COLOR).unwrap();
+        assert_close(&decode(&encode(&img, 95)).unwrap(), (32, 16), tiles, 24);
+    }
+
+    /// `file` with the segment starting at `marker` replaced by `body`.
+    fn patched(file: &[u8], marker: u8, body: &[u8]) -> Vec<u8> {
+        let at = file.windows(2).position(|w| w == [0xff, marker]).unwrap();
+        let len = u16::from_be_bytes([file[at + 2], file[at + 3]]) as usize;
+        let mut out = file[..at + 2].to_vec();
+        out.extend((body.len() as u16 + 2).to_be_bytes());
+        out.extend(body);
+        out.extend(&file[at + 2 + len..]);
+        out
+    }
+
+    #[test]
+    fn short_segments_are_errors() {
+        let err = |data: Vec<u8>| decode(&data).err().map(|e| e.to_string());
+        assert_eq!(err(patched(COLOR, 0xdd, &[0])).as_deref(), Some("bad DRI"));
+        assert_eq!(err(patched(GRAY, 0xda, &[])).as_deref(), Some("bad SOS"));
+        assert_eq!(err(patched(GRAY, 0xda, &[1, 1])).as_deref(), Some("bad SOS"));
+    }
+
+    #[test]
+    fn dc_codes_over_16_bits_are_errors() {
+        // both 1-bit DC codes decode to 17
+        let mut dht = vec![0x00];
+        dht.extend([2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        dht.extend([17, 17]);
+        assert_eq!(decode(&patched(GRAY, 0xc4, &dht)).err().map(|e| e.to_string()).as_deref(), Some("bad DC code"));
+    }
+
+    #[test]
+    fn truncated_and_garbage_input_are_errors() {
+        for file in [GRAY, GRAY_PROGRESSIVE, COLOR, COLOR_PROGRESSIVE] {
+            // cut before the first scan's data there's no image; past that, missing data
+            // decodes as zeros, but it mustn't panic
+            let first_scan = file.windows(2).position(|w| w == [0xff, 0xda]).unwrap();
+            for n in 0..file.len() {
+                let r = decode(&file[..n]);
+                if n <= first_scan { assert!(r.is_err(), "{n} of {} bytes", file.len()); }
+            }
+        }
+        let mut x = 1u32;
+        let mut noise = || { x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345); (x >> 16) as u8 };
+        for len in [0, 1, 20, 100, 1000] {
+            let mut data = vec![0xff, 0xd8];
+            data.extend((0..len).map(|_| noise()));
+            assert!(decode(&data).is_err());
+            // valid tables and frame in front of garbage scan data
+            let scan = COLOR.windows(2).position(|w| w == [0xff, 0xda]).unwrap();
+            let mut data = COLOR[..scan + 14].to_vec();
+            data.extend((0..len).map(|_| noise()));
+            let _ = decode(&data);
+        }
+        assert!(decode(b"\x89PNG").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use std::{collections::{BTreeMap, VecDeque}, path::Path, time::{Duration, Instant}};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{config::Config, output::{self, Format}};
+
+/// One entry of tosu's `keys` (the key overlay), e.g. `"k1": {"isPressed": false, "count": 42}`.
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct KeyV2 {
+    pub is_pressed: bool,
+    pub count: u64,
+}
+
+#[derive(Serialize)]
+struct Column<'a> { key: &'a str, count: u64, pressed: bool }
+
+#[derive(Serialize)]
+struct KeysOut<'a> {
+    active: bool,
+    columns: Vec<Column<'a>>,
+    total: u64,
+    /// Presses per second over the last `MSD_KEYS_WINDOW_MS`.
+    kps: f32,
+    /// Highest `kps` seen during this play.
+    max_kps: f32,
+}
+
+/// Writes `keys.json` (per-key press counts and a rolling KPS) while a map is being played.
+#[derive(Default)]
+pub struct Keys {
+    samples: VecDeque<(Instant, u64)>,
+    max_kps: f32,
+    last: Option<Vec<u8>>,
+}
+
+impl Keys {
+    pub async fn update(&mut self, static_root: &Path, keys: &BTreeMap<String, KeyV2>, playing: bool, cfg: &Config) {
+        let active = playing && !keys.is_empty();
+        if !active && self.last.is_none() { return; }
+        let total: u64 = keys.values().map(|k| k.count).sum();
+        let now = Instant::now();
+        // counts drop on a retry: start the window (and the peak) over
+        if !active || self.samples.back().is_some_and(|&(_, t)| total < t) {
+            self.samples.clear();
+            self.max_kps = 0.0;
+        }
+        let kps = if active {
+            self.samples.push_back((now, total));
+            let window = Duration::from_millis(cfg.keys_window_ms);
+            // keep one sample at or before the window start so the span covers it
+            while self.samples.len() > 2 && now - self.samples[1].0 >= window { self.samples.pop_front(); }
+            let (t0, c0) = self.samples[0];
+            let secs = (now - t0).as_secs_f32();
+            let kps = if secs > 0.0 { (total - c0) as f32 / secs } else { 0.0 };
+            self.max_kps = self.max_kps.max(kps);
+            kps
+        } else { 0.0 };
+
+        let columns = if active { keys.iter().map(|(k, v)| Column { key: k, count: v.count, pressed: v.is_pressed }).collect() } else { Vec::new() };
+        let doc = KeysOut { active, columns, total: if active { total } else { 0 }, kps, max_kps: self.max_kps };
+        let bytes = match output::encode(&doc, Format::Json, cfg) {
+            Ok(b) => b,
+            Err(e) => { warn!(target: "output", %e, "encode keys.json"); return; }
+        };
+        if self.last.as_ref() == Some(&bytes) { return; }
+        let path = output::overlay_dir(static_root).join("keys.json");
+        match tokio::fs::write(&path, &bytes).await {
+            Ok(()) => self.last = Some(bytes),
+            Err(e) => warn!(target: "output", %e, "failed to write keys.json"),
+        }
+    }
+}
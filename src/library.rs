@@ -0,0 +1,284 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, time::SystemTime};
+use anyhow::Context;
+use minacalc_rs::SkillsetScores;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::calc::MsdOut;
+
+/// Skillset ratings of a chart at one rate.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Ratings {
+    pub rate: String,
+    pub overall: f32,
+    pub stamina: f32,
+    pub jumpstream: f32,
+    pub handstream: f32,
+    pub stream: f32,
+    pub chordjack: f32,
+    pub jacks: f32,
+    pub technical: f32,
+}
+
+impl From<&Ratings> for SkillsetScores {
+    fn from(r: &Ratings) -> Self {
+        SkillsetScores {
+            overall: r.overall, stream: r.stream, jumpstream: r.jumpstream, handstream: r.handstream,
+            stamina: r.stamina, jackspeed: r.jacks, chordjack: r.chordjack, technical: r.technical,
+        }
+    }
+}
+
+impl From<&MsdOut> for Ratings {
+    fn from(m: &MsdOut) -> Self {
+        Ratings {
+            rate: m.rate.clone(), overall: m.overall, stamina: m.stamina, jumpstream: m.jumpstream, handstream: m.handstream,
+            stream: m.stream, chordjack: m.chordjack, jacks: m.jacks, technical: m.technical,
+        }
+    }
+}
+
+/// One rated chart, keyed by the SHA-1 of its `.osu`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Chart {
+    pub sha1: String,
+    /// osu!'s checksum, for joining against osu!.db / scores.db / the API.
+    #[serde(default)]
+    pub md5: String,
+    pub song: String,
+    pub diff: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beatmap_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set_id: Option<u64>,
+    /// Local `.osu` path, when the chart came from disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub msd: Vec<Ratings>,
+    pub added_unix: u64,
+    /// MinaCalc version the ratings came from; 0 for entries older than this field.
+    #[serde(default)]
+    pub calc_version: i32,
+    /// `calc::notes_hash`: the same for reuploads that only change metadata, audio or offset.
+    /// Empty for entries older than this field.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes_hash: String,
+}
+
+impl Chart {
+    /// Ratings at `rate` (`"1.00"`), if they're from the built-in calculator version.
+    pub fn ratings(&self, rate: &str) -> Option<&Ratings> {
+        if self.calc_version != minacalc_rs::Calc::version() { return None; }
+        self.msd.iter().find(|r| r.rate == rate)
+    }
+}
+
+/// What a scanned `.osu` looked like last time, so unchanged files aren't read again.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Stamp {
+    pub size: u64,
+    pub mtime_unix_ms: u64,
+    /// `None` for files that aren't 4K mania.
+    pub sha1: Option<String>,
+}
+
+impl Stamp {
+    /// Size and mtime of `path`, with `sha1` still to be filled in.
+    pub fn of(path: &Path) -> Option<Stamp> {
+        let m = std::fs::metadata(path).ok()?;
+        let mtime = m.modified().ok()?.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_millis() as u64;
+        Some(Stamp { size: m.len(), mtime_unix_ms: mtime, sha1: None })
+    }
+
+    /// Same size and mtime.
+    pub fn unchanged(&self, other: &Stamp) -> bool { self.size == other.size && self.mtime_unix_ms == other.mtime_unix_ms }
+}
+
+/// `library.json`'s format. 2: ratings are for the score goal they say; version 1 libraries
+/// were rated with the goal MinaCalc capped to 96.5%, so their ratings don't count.
+const VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct File {
+    version: u32,
+    charts: Vec<Chart>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    files: BTreeMap<String, Stamp>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    scans: BTreeMap<String, u64>,
+}
+
+impl File {
+    /// Charts from an older format keep their metadata, but their ratings are marked as from no
+    /// calculator version, so nothing uses them and warm-cache rates them again.
+    fn parse(b: &[u8]) -> serde_json::Result<File> {
+        let mut f: File = serde_json::from_slice(b)?;
+        if f.version < VERSION { for c in &mut f.charts { c.calc_version = 0; } }
+        Ok(f)
+    }
+}
+
+/// The library index: every chart rated so far, in one JSON file rewritten on save.
+pub struct Library {
+    path: PathBuf,
+    pub charts: BTreeMap<String, Chart>,
+    /// Scanned `.osu` files by path (`warm-cache`).
+    pub files: BTreeMap<String, Stamp>,
+    /// When each scanned folder's last completed scan started (unix ms), for `rescan --since last`.
+    pub scans: BTreeMap<String, u64>,
+}
+
+impl Library {
+    /// `MSD_LIBRARY`, else `library.json` in the app's data folder.
+    pub fn default_path() -> PathBuf {
+        if let Ok(p) = std::env::var("MSD_LIBRARY") { return PathBuf::from(p); }
+        dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("library.json")
+    }
+
+    /// Load the index at `path`; a missing file is an empty library.
+    pub fn open(path: &Path) -> anyhow::Result<Library> {
+        let (charts, files, scans) = match std::fs::read(path) {
+            Ok(b) => {
+                let f = File::parse(&b).with_context(|| format!("reading library {}", path.display()))?;
+                if f.version < VERSION && !f.charts.is_empty() {
+                    info!(target: "calc", "{} was rated with the wrong score goal; its {} charts need warm-cache again", path.display(), f.charts.len());
+                }
+                (f.charts.into_iter().map(|c| (c.sha1.clone(), c)).collect(), f.files, f.scans)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e).with_context(|| format!("reading library {}", path.display())),
+        };
+        Ok(Library { path: path.to_path_buf(), charts, files, scans })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    pub fn insert(&mut self, chart: Chart) { self.charts.insert(chart.sha1.clone(), chart); }
+
+    /// Charts with the same note data, by `notes_hash`; only groups of two or more.
+    pub fn duplicates(&self) -> BTreeMap<&str, Vec<&Chart>> {
+        let mut groups: BTreeMap<&str, Vec<&Chart>> = BTreeMap::new();
+        for c in self.charts.values().filter(|c| !c.notes_hash.is_empty()) { groups.entry(&c.notes_hash).or_default().push(c); }
+        groups.retain(|_, g| g.len() > 1);
+        groups
+    }
+
+    /// Fold another machine's `library.json` in: charts this one doesn't have are added (without
+    /// their local path), and for shared ones ratings from the built-in calculator version win and
+    /// missing rates, IDs and hashes are filled in. Scanned files stay this machine's. Returns how
+    /// many charts were added and how many gained something.
+    pub fn merge_file(&mut self, other: &[u8]) -> anyhow::Result<(usize, usize)> {
+        let other = File::parse(other).context("reading the imported library")?;
+        let current = minacalc_rs::Calc::version();
+        let (mut added, mut updated) = (0, 0);
+        for c in other.charts {
+            let Some(mine) = self.charts.get_mut(&c.sha1) else {
+                self.insert(Chart { path: None, ..c });
+                added += 1;
+                continue;
+            };
+            let before = serde_json::to_vec(mine)?;
+            if c.calc_version == current && mine.calc_version != current {
+                mine.msd = c.msd;
+                mine.calc_version = current;
+            } else if c.calc_version == mine.calc_version {
+                let had = mine.msd.len();
+                for r in c.msd {
+                    if !mine.msd.iter().any(|m| m.rate == r.rate) { mine.msd.push(r); }
+                }
+                if mine.msd.len() > had { mine.msd.sort_by(|a, b| a.rate.cmp(&b.rate)); }
+            }
+            mine.added_unix = mine.added_unix.min(c.added_unix);
+            if mine.md5.is_empty() { mine.md5 = c.md5; }
+            if mine.notes_hash.is_empty() { mine.notes_hash = c.notes_hash; }
+            mine.beatmap_id = mine.beatmap_id.or(c.beatmap_id);
+            mine.set_id = mine.set_id.or(c.set_id);
+            if serde_json::to_vec(mine)? != before { updated += 1; }
+        }
+        Ok((added, updated))
+    }
+
+    /// Write via a temp file so a crash mid-save can't truncate the index.
+    pub fn save(&self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() { std::fs::create_dir_all(dir)?; }
+        let file = File { version: VERSION, charts: self.charts.values().cloned().collect(), files: self.files.clone(), scans: self.scans.clone() };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&file)?)?;
+        std::fs::rename(&tmp, &self.path).with_context(|| format!("writing library {}", self.path.display()))
+    }
+}
+
+/// Where a result falls in the library: per skillset, the percentage of indexed charts (at
+/// 1.0x) rated below it, so 92 reads "harder than 92% of your library".
+#[derive(Serialize, Clone)]
+pub struct Percentile {
+    /// Charts compared against.
+    pub charts: usize,
+    pub overall: f32,
+    pub stamina: f32,
+    pub jumpstream: f32,
+    pub handstream: f32,
+    pub stream: f32,
+    pub chordjack: f32,
+    pub jacks: f32,
+    pub technical: f32,
+}
+
+/// Fewer charts than this and a percentile says more about the sample than the map.
+const MIN_PERCENTILE_CHARTS: usize = 20;
+
+/// The library as a score cache for the poll loop: charts rated ahead of time (`warm-cache`,
+/// `download`) skip the calculator, and results get a percentile against the rest. Reloaded
+/// whenever the file changes, e.g. while `warm-cache` is still running.
+pub struct Cache {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    library: Option<Library>,
+    /// Every chart's 1.0x ratings per skillset (`find::SKILLSETS` order), sorted.
+    sorted: Vec<Vec<f32>>,
+}
+
+impl Cache {
+    pub fn new(path: PathBuf) -> Cache { Cache { path, modified: None, library: None, sorted: Vec::new() } }
+
+    fn refresh(&mut self) {
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == self.modified { return; }
+        self.modified = modified;
+        self.library = match modified.map(|_| Library::open(&self.path)) {
+            Some(Ok(l)) => { debug!(target: "calc", charts = l.charts.len(), "library cache loaded"); Some(l) }
+            Some(Err(e)) => { warn!(target: "calc", "library cache: {e:#}"); None }
+            None => None,
+        };
+        let rated: Vec<&Ratings> = self.library.iter().flat_map(|l| l.charts.values()).filter_map(|c| c.ratings("1.00")).collect();
+        self.sorted = crate::find::SKILLSETS.iter().map(|s| {
+            let mut v: Vec<f32> = rated.iter().map(|r| crate::find::skillset_value(r, s)).collect();
+            v.sort_by(f32::total_cmp);
+            v
+        }).collect();
+    }
+
+    /// Scores for the chart with this `.osu` SHA-1 at `rate` (`"1.00"`).
+    pub fn lookup(&mut self, sha1: &str, rate: &str) -> Option<SkillsetScores> {
+        self.refresh();
+        self.library.as_ref()?.charts.get(sha1)?.ratings(rate).map(SkillsetScores::from)
+    }
+
+    /// `out`'s skillsets (at its own rate) against the library's at 1.0x; `None` while the
+    /// library is too small.
+    pub fn percentile(&mut self, out: &MsdOut) -> Option<Percentile> {
+        self.refresh();
+        let charts = self.sorted.first()?.len();
+        if charts < MIN_PERCENTILE_CHARTS { return None; }
+        let r = Ratings::from(out);
+        let at = |s: &str| {
+            let i = crate::find::SKILLSETS.iter().position(|n| *n == s).unwrap_or(0);
+            let below = self.sorted[i].partition_point(|v| *v < crate::find::skillset_value(&r, s));
+            (below as f32 * 1000.0 / charts as f32).round() / 10.0
+        };
+        Some(Percentile {
+            charts, overall: at("overall"), stamina: at("stamina"), jumpstream: at("jumpstream"), handstream: at("handstream"),
+            stream: at("stream"), chordjack: at("chordjack"), jacks: at("jacks"), technical: at("technical"),
+        })
+    }
+}
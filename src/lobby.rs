@@ -0,0 +1,81 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{calc::MsdOut, config::Config, output::{self, Format}};
+
+/// One row of tosu's `leaderboard` (the in-game scoreboard; live lobby scores in multiplayer).
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub score: u64,
+    pub accuracy: f64,
+    pub combo: u32,
+    pub max_combo: u32,
+    pub team: u32,
+    pub is_failed: bool,
+}
+
+#[derive(Serialize)]
+struct Player<'a> {
+    rank: usize,
+    name: &'a str,
+    score: u64,
+    accuracy: f64,
+    combo: u32,
+    max_combo: u32,
+    team: u32,
+    failed: bool,
+}
+
+#[derive(Serialize)]
+struct LobbyOut<'a, 'b> {
+    active: bool,
+    map: Option<&'a MsdOut>,
+    players: Vec<Player<'b>>,
+}
+
+/// Writes `lobby.json` (map MSD + players ranked by live score) while in a multiplayer match.
+#[derive(Default)]
+pub struct Lobby {
+    in_multi: bool,
+    last: Option<Vec<u8>>,
+}
+
+impl Lobby {
+    /// Follow osu!'s game state (`state.number`): the multiplayer screens mean the next play is a
+    /// match; song select / main menu mean it isn't.
+    pub fn track_state(&mut self, state: Option<u32>) {
+        match state {
+            Some(11..=14 | 17 | 18) => self.in_multi = true, // lobby, match setup, multi select, ranking screens
+            Some(0 | 5) => self.in_multi = false,
+            _ => {}
+        }
+    }
+
+    /// Rewrite lobby.json if its content changed; once the match is over it says `"active": false`.
+    pub async fn update(&mut self, static_root: &Path, board: &[LeaderboardEntry], map: Option<&MsdOut>, cfg: &Config) {
+        let active = self.in_multi && !board.is_empty();
+        if !active && self.last.is_none() { return; } // never was in a match
+        let mut players: Vec<Player> = if active {
+            board.iter().map(|e| Player {
+                rank: 0, name: &e.name, score: e.score, accuracy: e.accuracy, combo: e.combo, max_combo: e.max_combo,
+                team: e.team, failed: e.is_failed,
+            }).collect()
+        } else { Vec::new() };
+        players.sort_by_key(|p| std::cmp::Reverse(p.score));
+        for (i, p) in players.iter_mut().enumerate() { p.rank = i + 1; }
+        let doc = LobbyOut { active, map: map.filter(|_| active), players };
+        let bytes = match output::encode(&doc, Format::Json, cfg) {
+            Ok(b) => b,
+            Err(e) => { warn!(target: "output", %e, "encode lobby.json"); return; }
+        };
+        if self.last.as_ref() == Some(&bytes) { return; }
+        let path = output::overlay_dir(static_root).join("lobby.json");
+        match tokio::fs::write(&path, &bytes).await {
+            Ok(()) => self.last = Some(bytes),
+            Err(e) => warn!(target: "output", %e, "failed to write lobby.json"),
+        }
+    }
+}
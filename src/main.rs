@@ -1,252 +1,674 @@
-use std::{path::PathBuf, time::Duration};
-use minacalc_rs::{Calc, OsuCalcExt};
+use std::{path::PathBuf, sync::Arc, time::{Duration, Instant}};
+use minacalc_rs::Calc;
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use tokio::{fs, time};
+use serde::Deserialize;
+use tokio::time;
 use tracing::*;
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::{fmt, prelude::*};
 use std::path::{Path};
 use dotenvy::{from_path, from_path_iter, var};
-use fs_extra::dir::{copy as copy_dir, CopyOptions}; // recursive copy
 use anyhow::{Context, Result};
 
-const POLL_MS: u64 = 600;
-
-#[derive(Serialize, Default)]
-struct MsdOut {
-    song: String,
-    diff: String,
-    overall: f32,
-    stamina: f32,
-    jumpstream: f32,
-    handstream: f32,
-    stream: f32,
-    chordjack:f32,
-    jacks: f32,
-    technical: f32,
-    rate: String, // "1.60"
-}
+mod aggregate;
+mod archive;
+mod background;
+mod beatmaps;
+mod bench;
+mod calc;
+mod chat;
+mod check;
+mod chatbot;
+mod collections;
+mod compare;
+mod config;
+mod crash;
+mod download;
+mod errlog;
+mod export;
+mod find;
+mod game;
+mod goals;
+mod grpc;
+mod heartbeat;
+mod history;
+mod hub;
+mod i18n;
+mod image;
+mod irc;
+mod jpeg;
+mod keys;
+mod library;
+mod lobby;
+mod mappool;
+mod md5;
+mod mdns;
+mod mods;
+mod msdcache;
+mod msgpack;
+mod notify;
+mod np;
+mod nfc;
+mod output;
+mod pack;
+mod paths;
+mod png;
+mod priority;
+mod progress;
+mod proto;
+mod provider;
+mod qr;
+mod recalc;
+mod retry;
+mod sanitize;
+mod scan;
+mod selftest;
+mod serve;
+mod sheet;
+mod shm;
+mod simfile;
+mod skin;
+mod summary;
+mod supervisor;
+mod tags;
+mod tourney;
+mod tracks;
+mod udp;
+mod uplink;
+mod warm;
+mod wife;
+mod zip;
+mod ws;
+use calc::MsdOut;
+use config::{arg_value, Config};
+
 
 #[derive(Deserialize)]
 struct JsonV2 {
     beatmap: BeatmapV2,
     play: PlayV2,
     // mods also often exists at root on some builds:
-    mods: Option<ModsV2>,
+    mods: Option<mods::ModsV2>,
+    state: Option<StateV2>,
+    #[serde(default)]
+    leaderboard: Vec<lobby::LeaderboardEntry>,
+    tourney: Option<tourney::TourneyV2>,
+    #[serde(default)]
+    keys: std::collections::BTreeMap<String, keys::KeyV2>,
+    /// `stable` or `lazer`
+    client: Option<String>,
 }
 #[derive(Deserialize)]
-struct BeatmapV2 { artist: Option<String>, title: Option<String>, version: Option<String> }
+struct StateV2 { number: Option<u32> }
 #[derive(Deserialize)]
-struct PlayV2 { mods: ModsV2 }
+struct BeatmapV2 { artist: Option<String>, title: Option<String>, version: Option<String>, id: Option<u64>, set: Option<u64>, stats: Option<StatsV2> }
 #[derive(Deserialize)]
-struct ModsV2 {
-    name: Option<String>,
-    // newer builds expose array  rate/speed_change too:
-    array: Option<Vec<ModEntry>>,
-    rate: Option<f32>,
-}
+struct StatsV2 { stars: Option<StarsV2> }
+/// tosu's rosu-pp star rating with the current mods.
 #[derive(Deserialize)]
-struct ModEntry {
-    #[serde(default)]
-    settings: ModSettings,
-    rate: Option<f32>,
-}
-#[derive(Deserialize, Default)]
-struct ModSettings {
-    #[serde(default)]
-    speed_change: Option<f32>,
-}
-
+struct StarsV2 { total: Option<f32> }
+#[derive(Deserialize)]
+struct PlayV2 { mods: mods::ModsV2, hits: Option<wife::Hits> }
 /// Find a tosu.env: CLI `--tosu-env <path>`, then env `TOSU_ENV_PATH`,
-/// then `./tosu.env`, then `../tosu.env`.
-fn find_tosu_env() -> Option<PathBuf> {
-    let mut args = std::env::args();
-    while let Some(a) = args.next() {
-        if a == "--tosu-env" {
-            if let Some(p) = args.next() { return Some(PathBuf::from(p)); }
-        }
-    }
-    if let Ok(p) = std::env::var("TOSU_ENV_PATH") { return Some(PathBuf::from(p)); }
+/// then `./tosu.env`, then `../tosu.env`, then next to `MSD_TOSU_PATH`.
+fn find_tosu_env(cfg: &Config) -> Option<PathBuf> {
+    if let Some(p) = arg_value("--tosu-env") { return Some(paths::plain(Path::new(&p))); }
+    if let Ok(p) = std::env::var("TOSU_ENV_PATH") { return Some(paths::plain(Path::new(&p))); }
     for cand in ["./tosu.env", "../tosu.env"] {
         let p = PathBuf::from(cand);
         if p.exists() { return Some(p); }
     }
-    None
+    cfg.tosu_path.as_ref().and_then(|t| Some(t.parent()?.join("tosu.env"))).filter(|p| p.exists())
 }
 
-fn resolve_static_root_from_tosu_env() -> Result<PathBuf,anyhow::Error> {
-    if let Some(env_path) = find_tosu_env() {
+fn resolve_static_root_from_tosu_env(cfg: &Config) -> Result<PathBuf,anyhow::Error> {
+    if let Some(env_path) = find_tosu_env(cfg) {
         // Try strict load first (file values override process env)
         if let Err(e) = from_path(&env_path) {
             // Fallback only grab STATIC_FOLDER_PATH, ignore bad lines
             if let Ok(iter) = from_path_iter(&env_path) {
-                for item in iter {
-                    if let Ok((k, v)) = item {
-                        if k == "STATIC_FOLDER_PATH" {
-                            std::env::set_var(&k, &v);
-                            break;
-                        }
+                // malformed lines are skipped
+                for (k, v) in iter.flatten() {
+                    if k == "STATIC_FOLDER_PATH" {
+                        std::env::set_var(&k, &v);
+                        break;
                     }
-                    // else: Err(_) => skip malformed line
                 }
             } else {
                 return Err(e).with_context(|| format!("loading tosu.env at {:?}", env_path));
             }
         }
         if let Ok(val) = var("STATIC_FOLDER_PATH") {
-            let p = PathBuf::from(val);
-            return Ok(if p.is_absolute() { p } else {
+            let p = paths::plain(Path::new(&val));
+            return Ok(paths::redirected(if p.is_absolute() { p } else {
                 env_path.parent().unwrap_or(Path::new(".")).join(p)
-            });
+            }));
         }
     }
     // lenient dev fallback
     Ok(PathBuf::from("overlay"))
 }
 
+/// `minacalc-overlay/static` in the local app data folder, where the overlay goes when it's
+/// served by us rather than tosu.
+fn own_static_root() -> PathBuf {
+    dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("static")
+}
+
+/// The folder the overlay lives in: our own with `MSD_SERVE`, else tosu's static folder.
+fn static_root(cfg: &Config) -> anyhow::Result<PathBuf> {
+    if cfg.serve.is_some() { Ok(own_static_root()) } else { resolve_static_root_from_tosu_env(cfg) }
+}
+
+/// Whether `dir` can be created and written to; only permission problems and read-only drives
+/// count, anything else still lands on the usual install path and its error.
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(".minacalc-write-test");
+    match std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&probe, b"")) {
+        Ok(()) => { std::fs::remove_file(&probe).ok(); Ok(()) }
+        Err(e) if matches!(e.kind(), std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem) => Err(e),
+        Err(_) => Ok(()),
+    }
+}
+
 /// If `<static_root>/MinaCalcOnOsu/index.html` is missing, copy `./overlay` there (non-destructive).
 fn install_overlay_if_missing(static_root: &Path) -> anyhow::Result<()> {
     let dest = static_root.join("MinaCalcOnOsu");
     if dest.join("index.html").exists() {
         return Ok(());
     }
-    // Copy ./overlay -> <STATIC_FOLDER_PATH>/MinaCalcOnOsu (recursive).
-    fs_extra::dir::create_all(&dest, false).ok(); // ensure dir tree (best-effort).
-    let mut opt = CopyOptions::new(); // overwrite=false, skip_exist=false, copy_inside=false by default.
-    opt.overwrite = false;
-    opt.copy_inside = true;   // copy contents of ./overlay into dest (not the folder itself)
-    opt.content_only = true;
-    copy_dir("overlay", &dest, &opt).map(|_| ()).map_err(|e| anyhow::anyhow!(e))
+    // files a user already has there (their own msd.json, an edited style) are kept
+    let n = paths::copy_missing(Path::new("overlay"), &dest)?;
+    info!(target: "overlay", "installed the overlay into {} ({n} files)", dest.display());
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cfg = Arc::new(Config::load()?);
+    tracing_subscriber::registry()
+        .with(cfg.log_filter())
+        // stderr, so subcommands' stdout (`--json`) is only their output
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(fmt::layer().with_ansi(false).with_writer(|| crash::TailWriter))
+        .with(cfg.error_log.clone().map(errlog::ErrorLog::new))
+        .init();
+    crash::install_panic_hook();
+    priority::init(&cfg);
+    info!("minacalc-overlay {} (MinaCalc version {})", env!("CARGO_PKG_VERSION"), Calc::version());
+    if let Some(p) = &cfg.path { info!("config loaded from {:?}", p); }
 
-    let mut ticker = time::interval(Duration::from_millis(POLL_MS));
-    
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    fmt().with_env_filter(filter).init();
+    // subcommands run and exit; without one this is the tosu sidecar
+    match std::env::args().nth(1).as_deref() {
+        Some("mappool") => return mappool::run(&cfg).await,
+        Some("download") => return download::run(&cfg).await,
+        Some("skin") => return skin::run(&cfg, &static_root(&cfg)?).await,
+        Some("export-overlay") => return export::run(&static_root(&cfg)?),
+        Some("export-data") => return archive::export(),
+        Some("import-data") => return archive::import(),
+        Some("convert-acc") => return wife::run(),
+        Some("warm-cache") => return warm::run(&cfg).await,
+        Some("rescan") => return warm::rescan(&cfg).await,
+        Some("find") => return find::run(&cfg),
+        Some("recommend") => return find::recommend(&cfg),
+        Some("skill-gap") => return history::run(&cfg),
+        Some("pack") => return pack::run(&cfg),
+        Some("tag") => return tags::run(),
+        Some("scan") => return scan::run(&cfg),
+        Some("scan-packs") => return simfile::run(&cfg),
+        Some("collection") => return collections::run(&cfg),
+        Some("bench") => return bench::run(&cfg),
+        Some("calc") => return calc::run(&cfg),
+        Some("config") => match std::env::args().nth(2).as_deref() {
+            Some("check") => return check::run(&cfg, find_tosu_env(&cfg)),
+            _ => anyhow::bail!("usage: minacalc-overlay config check [--config minacalc.env] [--tosu-env tosu.env]"),
+        },
+        Some(c) if !c.starts_with("--") => anyhow::bail!("unknown command {c:?} (available: mappool, download, skin, export-overlay, export-data, import-data, convert-acc, warm-cache, rescan, find, recommend, skill-gap, pack, scan, scan-packs, tag, collection, bench, calc, config check)"),
+        _ => {}
+    }
 
-    let static_root = resolve_static_root_from_tosu_env()?;
+    if cfg.self_test { selftest::run()?; }
+    if let Some(exe) = cfg.tosu_path.clone() { tokio::spawn(supervisor::run(exe)); }
+    let hub = hub::Hub::new(cfg.recent, goals::Board::load(&cfg), tags::Store::load());
+    let mut static_root = static_root(&cfg)?;
+    // servers to announce over mDNS, with what's at which path in their TXT records
+    let mut services = Vec::new();
+    let version = format!("version={}", env!("CARGO_PKG_VERSION"));
+    if let Some(addr) = cfg.serve {
+        // the overlay folder itself is the site, so msd.json is at /msd.json
+        info!(target: "serve", "add http://{addr}/ as the browser source; tosu's static folder isn't used");
+        let (root, hub) = (static_root.join("MinaCalcOnOsu"), hub.clone());
+        tokio::spawn(async move {
+            if let Err(e) = serve::run(addr, root, hub).await { error!(target: "serve", "{e:#}"); }
+        });
+        services.push((addr, mdns::Service { kind: "_minacalc-http", port: addr.port(), txt: vec![version.clone(), "path=/".into(), "events=/events".into()] }));
+    } else if let (Err(e), Some(addr)) = (check_writable(&static_root.join("MinaCalcOnOsu")), cfg.http_fallback) {
+        // e.g. tosu under Program Files: write somewhere of our own and serve it ourselves
+        let own = own_static_root();
+        warn!(target: "overlay", code = "overlay.unwritable", "can't write to {} ({e}); serving the overlay from {} instead. Add http://{addr}/MinaCalcOnOsu/ as the browser source",
+            static_root.display(), own.display());
+        static_root = own;
+        let (root, hub) = (static_root.clone(), hub.clone());
+        tokio::spawn(async move {
+            if let Err(e) = serve::run(addr, root, hub).await { error!(target: "serve", "{e:#}"); }
+        });
+        services.push((addr, mdns::Service { kind: "_minacalc-http", port: addr.port(), txt: vec![version.clone(), "path=/MinaCalcOnOsu/".into(), "events=/events".into()] }));
+    }
     tokio::fs::create_dir_all(static_root.join("MinaCalcOnOsu")).await.ok();
 
     if let Err(e) = install_overlay_if_missing(&static_root) {
-        warn!(%e, "overlay install skipped");
+        warn!(target: "overlay", code = "overlay.install", %e, "overlay install skipped");
+    }
+    i18n::write(&static_root, &cfg);
+    for root in &cfg.static_roots {
+        info!(target: "overlay", "also keeping the overlay in {}", root.display());
+        if let Err(e) = install_overlay_if_missing(root) {
+            warn!(target: "overlay", code = "overlay.install", %e, "overlay install into {} skipped", root.display());
+        }
+        i18n::write(root, &cfg);
+    }
+
+    if let Some(k) = &cfg.recalc_hotkey { recalc::spawn_hotkey(k, hub.clone()); }
+    if let Some(addr) = cfg.grpc {
+        let (hub, goal, token) = (hub.clone(), cfg.score_goal, cfg.grpc_token.clone());
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(addr, hub, goal, token).await { error!(target: "grpc", "{e:#}"); }
+        });
+        services.push((addr, mdns::Service { kind: "_minacalc-grpc", port: addr.port(), txt: vec![version.clone()] }));
     }
-    
-    let http = Client::new();
+    if let Some(addr) = cfg.aggregate {
+        let token = cfg.aggregate_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = aggregate::serve(addr, token).await { error!(target: "aggregate", "{e:#}"); }
+        });
+        services.push((addr, mdns::Service { kind: "_minacalc-agg", port: addr.port(), txt: vec![version.clone(), "ingest=/ingest".into(), "feed=/feed".into()] }));
+    }
+    // loopback-only servers aren't worth announcing
+    services.retain(|(a, _)| !a.ip().is_loopback());
+    if cfg.mdns && !services.is_empty() {
+        // one host address for all services
+        match services.iter().find_map(|(a, _)| mdns::lan_ip(a.ip())) {
+            Some(ip) => {
+                let services = services.into_iter().map(|(_, s)| s).collect();
+                tokio::spawn(async move {
+                    if let Err(e) = mdns::advertise(ip, services).await { warn!(target: "mdns", "{e:#}"); }
+                });
+            }
+            None => info!(target: "mdns", "servers only listen on loopback; not advertising via mDNS"),
+        }
+    }
+    if let Some(addr) = cfg.udp {
+        let (hub, cfg) = (hub.clone(), cfg.clone());
+        tokio::spawn(async move {
+            if let Err(e) = udp::run(addr, hub, cfg).await { error!(target: "udp", "{e:#}"); }
+        });
+    }
+    if let Some(name) = cfg.shared_memory.clone() {
+        let (hub, cfg) = (hub.clone(), cfg.clone());
+        tokio::spawn(async move {
+            if let Err(e) = shm::run(name, hub, cfg).await { error!(target: "output", "{e:#}"); }
+        });
+    }
+    if let Some(url) = cfg.aggregator_url.clone() {
+        tokio::spawn(uplink::run(url, hub.clone(), cfg.clone()));
+    }
+    if cfg.irc_user.is_some() { tokio::spawn(irc::run(cfg.clone())); }
+
+    let http = Client::builder().connect_timeout(Duration::from_millis(cfg.connect_timeout_ms)).build()?;
+    let v2_timeout = Duration::from_millis(cfg.v2_timeout_ms);
+    let file_timeout = Duration::from_millis(cfg.file_timeout_ms);
+    let mut ticker = time::interval(Duration::from_millis(cfg.poll_ms));
+    // a slow iteration (or a sleep longer than the interval) shouldn't trigger back-to-back polls
+    ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
     let calc = Calc::new()?;
+    let other_calc = compare::load(cfg.calc_compare.as_deref());
+    let mut library_cache = (cfg.library_cache || cfg.percentile).then(|| library::Cache::new(library::Library::default_path()));
+    let mut msd_cache = cfg.calc_cache.then(|| msdcache::MsdCache::open(msdcache::MsdCache::default_path()));
+    let chat = chat::Chat::new(&cfg, http.clone());
+    let chatbot = chatbot::spawn(&cfg, http.clone());
+    if let Some(at) = cfg.summary_at {
+        if !cfg.history { warn!(target: "output", "MSD_SUMMARY_AT is set but MSD_HISTORY is off; summaries will only count plays already recorded"); }
+        tokio::spawn(summary::run(at, static_root.clone(), chat::Chat::new(&cfg, http.clone()), cfg.clone()));
+    }
+    let mut heartbeat = heartbeat::Heartbeat::new(cfg.heartbeat_url.clone(), Duration::from_secs(cfg.heartbeat_secs));
 
     // Recalc guard (sha1  truncated rate)
     let mut last_sha1: Option<String> = None;
-   // beatmap+rate dedupe
-    let mut last_key: Option<(String, String)> = None; // (sha1, rate_str)
+    // chart, rate, mods, goal and calc version of the last calculation
+    let mut last_key: Option<calc::CalcKey> = None;
+    // the last .osu tosu served; refetches that match it aren't copied
+    let mut osu_bytes: Vec<u8> = Vec::new();
+    // consecutive calc failures; a streak of `crash_after` writes one crash bundle
+    let mut failures: u32 = 0;
+    let notify_after = cfg.notify_after_mins.map(|m| Duration::from_secs(m * 60));
+    let mut tosu_watch = notify::FailureWatch::new("tosu unreachable", notify_after);
+    let mut calc_watch = notify::FailureWatch::new("MSD calculation failing", notify_after);
+    // lobby.json / h2h.json need the current map on polls that skip the calc
+    let mut current: Option<calc::Current> = None;
+    let mut backlog = output::Backlog::default();
+    // the last result of the chart before the current one, for `delta`
+    let mut last_chart: Option<MsdOut> = None;
+    let mut lobby = lobby::Lobby::default();
+    let mut h2h = tourney::HeadToHead::default();
+    let mut qr = qr::Sink::default();
+    let mut np = np::Sink::default();
+    let mut background = background::Background::default();
+    let mut keys = keys::Keys::default();
+    let mut tracks = tracks::Tracks::default();
+    let mut history = history::History::default();
+    let mut rate_mismatch: Option<(f32, f32)> = None;
+    let mut goal_follow = calc::GoalFollow::default();
+    let mut score = wife::Score::default();
+    let mut breakdowns = calc::Breakdowns::default();
+    let mut goal_tracker = goals::Tracker::default();
+    let mut rate_settle = calc::RateSettle::default();
+    let mut rate_table = calc::RateTable::default();
+    // MSD_PROVIDER, or the one that answered the last probe
+    let mut source = cfg.provider;
+    let mut game = game::Game::new(&cfg);
 
     loop {
         // 1) Pull v2 JSON snapshot
         ticker.tick().await;
-        let v2 = match http.get("http://127.0.0.1:24050/json/v2").send().await {
-            Ok(r) => match r.json::<JsonV2>().await { Ok(j) => j, Err(e) => { warn!(%e, "parse /json/v2"); sleep(); continue; } }
-            Err(e) => { warn!(%e, "GET /json/v2"); sleep(); continue; }
+        if let Some((key, b)) = breakdowns.poll().await {
+            if let Some(c) = current.as_mut().filter(|_| last_key.as_ref() == Some(&key)) {
+                c.out.sections = b.sections;
+                match output::write_all(&static_root, &c.out, &cfg).await {
+                    Ok(()) => if let Some(g) = b.graph { output::write_graph(&static_root, Some(&calc::Graph { md5: c.out.md5.clone(), ..g }), &cfg).await; },
+                    Err(e) => debug!(target: "output", %e, "sections not written"),
+                }
+                hub.publish(&c.out);
+            }
+        }
+        if let Some(g) = &mut game { g.wait().await; }
+        let poll_start = Instant::now();
+        backlog.flush(&static_root, &cfg, &hub).await;
+        if let Some((sha1, _, rows)) = rate_table.poll() {
+            if let Some(c) = current.as_mut().filter(|_| last_sha1.as_ref() == Some(sha1)) {
+                c.out.rates = rows.clone();
+                if let Err(e) = output::write_all(&static_root, &c.out, &cfg).await { debug!(target: "output", %e, "rate table not written"); }
+                hub.publish(&c.out);
+            }
+        }
+        if source.is_none() {
+            source = provider::probe(&http, v2_timeout).await;
+            match source {
+                Some(s) => info!(target: "tosu", "reading the game from {}", s.provider().name()),
+                None => { warn!(target: "tosu", code = "tosu.unreachable", "neither tosu nor gosumemory answers on {}", provider::ADDR); tosu_watch.fail(); hub.set_tosu_connected(false); sleep(&cfg).await; continue; }
+            }
+        }
+        let Some(provider) = source.map(provider::Source::provider) else { continue };
+        let path = provider.json_path();
+        let get_v2 = || async { Ok(http.get(format!("{}{path}", provider::ADDR)).timeout(v2_timeout).send().await?.text().await?) };
+        let raw: String = match cfg.retry_poll.run(&format!("GET {path}"), get_v2).await {
+            Ok(t) => t,
+            Err(e) => {
+                warn!(target: "tosu", code = "tosu.unreachable", "GET {path}: {e:#}");
+                // probe again, in case another provider took the port
+                if cfg.provider.is_none() { source = None; }
+                tosu_watch.fail(); hub.set_tosu_connected(false); sleep(&cfg).await; continue;
+            }
+        };
+        let snapshot_unix_ms = hub::unix_ms();
+        crash::record_snapshot(&raw);
+        let snapshot = match provider.read(raw) {
+            Ok(s) => s,
+            Err(e) => { warn!(target: "tosu", code = "tosu.bad_json", %e, "parse {path}"); sleep(&cfg).await; continue; }
         };
+        let v2 = match serde_json::from_str::<JsonV2>(&snapshot.json) {
+            Ok(j) => j,
+            Err(e) => { warn!(target: "tosu", code = "tosu.bad_json", %e, "parse /json/v2"); sleep(&cfg).await; continue; }
+        };
+        let state = v2.state.as_ref().and_then(|s| s.number);
+        lobby.track_state(state);
+        // 2 = playing
+        if cfg.keys { keys.update(&static_root, &v2.keys, state == Some(2), &cfg).await; }
 
         // labels
         let artist  = v2.beatmap.artist.as_deref().unwrap_or("");
         let title   = v2.beatmap.title.as_deref().unwrap_or("");
-        let version = v2.beatmap.version.clone().unwrap_or_default();
-        let song_full = if !artist.is_empty() || !title.is_empty() { format!("{artist} - {title}") } else { "Unknown Song".to_string() };
+        let version = sanitize::meta(v2.beatmap.version.as_deref().unwrap_or(""), &cfg);
+        let song_full = sanitize::meta(&calc::song_label(artist, title), &cfg);
 
         // 2) Extract rate from json/v2
-        let raw_rate = extract_rate_from_v2(&v2).unwrap_or(1.0);
-        let rate_str = format!("{:.2}", raw_rate);
+        let (active_mods, ignored) = mods::filter(mods::active(&v2.play.mods, v2.mods.as_ref()), &cfg.mods_ignore);
+        let parsed = mods::mod_rate(&active_mods);
+        // tosu's rate includes every speed mod, so it can't be used once one of them is ignored
+        let live = mods::live_rate(&v2.play.mods, v2.mods.as_ref()).filter(|_| !ignored.iter().any(|m| m.is_speed()));
+        let raw_rate = match live {
+            Some(live) => {
+                // the mod list can go stale in song select; say so once per disagreement
+                if (live - parsed).abs() > 0.005 {
+                    if rate_mismatch != Some((live, parsed)) { warn!(target: "tosu", live, mods = parsed, "tosu's rate and the mod list disagree, using tosu's"); }
+                    rate_mismatch = Some((live, parsed));
+                } else { rate_mismatch = None; }
+                live
+            }
+            None => parsed,
+        };
+        let rate_str = calc::rate_label(raw_rate);
+        let (speed_mod, pitched) = mods::speed_mod(&active_mods);
+        let mod_names: Vec<String> = active_mods.iter().map(|m| m.acronym.clone()).collect();
+        let mut mod_notes = mods::notes(&active_mods, &cfg.mods_unsupported);
+        // the map's OD is only known once it's been calculated
+        let live_windows = current.as_ref().map(|c| wife::Windows::new(c.od, &mod_names, raw_rate));
+        if cfg.score {
+            // lazer always scores MAX above 300, stable only with the ScoreV2 mod
+            let v2_scoring = v2.client.as_deref() == Some("lazer") || mod_names.iter().any(|m| m == "V2" || m == "SV2");
+            score.update(&static_root, v2.play.hits.as_ref(), live_windows, v2_scoring, state == Some(2), &cfg).await;
+        }
+        // DT -> NC (or HD on/off) at the same rate changes the output, not the MSD
+        // gRPC's SetScoreGoal replaces MSD_SCORE_GOAL while the sidecar runs
+        let score_goal = hub.score_goal().unwrap_or(cfg.score_goal);
+        let goal = if cfg.goal_follow && state == Some(2) {
+            goal_follow.next(v2.play.hits.as_ref(), live_windows.as_ref(), cfg.goal_follow_step, score_goal)
+        } else {
+            goal_follow.reset();
+            score_goal
+        };
         // 3) Get current .osu
-        let osu_bytes = match http.get("http://127.0.0.1:24050/files/beatmap/file").send().await {
-            Ok(rsp) => match rsp.bytes().await { Ok(b) => b.to_vec(), Err(e) => { warn!(%e, "bytes() failed"); continue; } },
-            Err(e) => { warn!(%e, "GET .osu failed"); continue; }
+        let sha1 = match cfg.retry_poll.run("GET .osu", || fetch_osu(&http, &snapshot.osu, file_timeout, &osu_bytes)).await {
+            Ok((sha1, fresh)) => { if let Some(b) = fresh { osu_bytes = b; } sha1 }
+            Err(e) => { warn!(target: "tosu", code = "tosu.osu_fetch", "GET .osu failed: {e:#}"); tosu_watch.fail(); continue; }
         };
+        tosu_watch.ok();
+        hub.set_tosu_connected(true);
         
-        if osu_bytes.is_empty() { warn!("No bytes from beatmap file"); continue; }
-        // dedupe by (content, rate_str)
-        let sha1 = sha1_smol::Sha1::from(&osu_bytes).hexdigest();
-        
-        if last_sha1.as_deref() == Some(&sha1) {
-            if last_key.as_ref().is_some_and(|(h, r)| h == &sha1 && r == &rate_str) {continue;}
+        if osu_bytes.is_empty() { warn!(target: "tosu", code = "tosu.osu_empty", "No bytes from beatmap file"); continue; }
+        // dedupe by everything the result depends on, unless a recalc was asked for
+        let key = calc::CalcKey::new(&sha1, &rate_str, &mod_names, &mods::conversion(&active_mods), pitched, goal);
+        let forced = hub.take_recalc() | recalc::file_requested(&static_root);
+        // the last chart's sections and graph are no use once another is up
+        if last_sha1.as_deref() != Some(&sha1) { breakdowns.cancel(); }
+        if forced { info!(target: "calc", "recalc requested"); }
+
+        // the speed slider is still moving: show where it is, calculate where it stops
+        let calculated = current.as_ref().filter(|_| last_sha1.as_deref() == Some(&sha1)).map(|c| c.out.rate.as_str());
+        let settling = v2.client.as_deref() == Some("lazer")
+            && rate_settle.waiting(&sha1, &rate_str, calculated, Duration::from_millis(cfg.rate_settle_ms));
+        if settling && !forced {
+            if let Some(c) = current.as_ref().filter(|c| c.out.live_rate.as_deref() != Some(&rate_str)) {
+                let out = MsdOut { live_rate: Some(rate_str.clone()), ..c.out.clone() };
+                if let Err(e) = output::write_all(&static_root, &out, &cfg).await { debug!(target: "output", %e, "live rate not written"); }
+                current = Some(calc::Current { out, ..*c });
+            }
+            continue;
+        }
+        // slid back to the calculated rate before settling anywhere else
+        let back = calculated == Some(rate_str.as_str());
+        if let Some(c) = current.as_mut().filter(|c| back && c.out.live_rate.is_some()) {
+            c.out.live_rate = None;
+            if let Err(e) = output::write_all(&static_root, &c.out, &cfg).await { debug!(target: "output", %e, "live rate not cleared"); }
+        }
+        if !forced && last_sha1.as_deref() == Some(&sha1) && last_key.as_ref() == Some(&key) {
+            // tags edited since the calc (`tag`, gRPC `TagMap`)
+            if let Some(c) = current.as_mut() {
+                let (tags, user_note) = hub.tags.get(&c.out.md5).map(|t| (t.tags, t.note)).unwrap_or_default();
+                if tags != c.out.tags || user_note != c.out.user_note {
+                    c.out.tags = tags;
+                    c.out.user_note = user_note;
+                    if let Err(e) = output::write_all(&static_root, &c.out, &cfg).await { debug!(target: "output", %e, "tags not written"); }
+                    hub.publish(&c.out);
+                }
+            }
+            heartbeat.beat(&http);
+            lobby.update(&static_root, &v2.leaderboard, current.as_ref().map(|c| &c.out), &cfg).await;
+            h2h.update(&static_root, v2.tourney.as_ref(), current.as_ref(), &cfg).await;
+            tracks.update(&static_root, current.as_ref().map(|c| &c.out), state == Some(2), &cfg).await;
+            if let Some(p) = history.update(&static_root, current.as_ref().map(|c| &c.out), state == Some(2), &cfg) { chat.play(&p); }
+            goal_tracker.update(&static_root, &hub.goals, current.as_ref().map(|c| &c.out), state, v2.play.hits.as_ref(), live_windows, &chat, &cfg).await;
+            continue;
         }
 
         last_sha1 = Some(sha1.clone());
-        last_key = Some((sha1, rate_str.clone()));
+        last_key = Some(key.clone());
+        let chart_md5 = md5::hex(&osu_bytes);
+        errlog::set_beatmap(&chart_md5);
 
         // parse string → notes
-        let osu_str = match String::from_utf8(osu_bytes) {
-            Ok(s) => s,
-            Err(e) => { error!(%e, "invalid UTF8 .osu"); continue; }
-        };
+        let (osu_str, decoded) = calc::decode(&osu_bytes);
+        if let Some(n) = &decoded { warn!(target: "calc", code = "calc.encoding", "{n}"); }
+        mod_notes.extend(decoded);
 
-        // Build notes from the osu!mania 4K map and compute SSR *at the exact rate*. 
-        // OsuCalcExt::to_notes_merged converts Beatmap → Vec<Note>, then Calc::calc_ssr runs at any float rate. :contentReference[oaicite:5]{index=5}
-        let scores = match (|| -> anyhow::Result<minacalc_rs::SkillsetScores> {
-            // parse & validate (uses rosu_map under the hood)
-            let beatmap: rosu_map::Beatmap = rosu_map::from_str(&osu_str)
-                .map_err(|e| anyhow::anyhow!("parse failed: {e}"))?;
-                minacalc_rs::Calc::security_check(&beatmap)
-                .map_err(|e| anyhow::anyhow!("security_check: {e}"))?;
-                let notes = minacalc_rs::Calc::to_notes_merged(&beatmap)
-                .map_err(|e| anyhow::anyhow!("to_notes_merged: {e}"))?;
-                // 93.0 is the common Etterna score goal used for MSD
-                Ok(calc.calc_ssr(&notes, raw_rate, 93.0)?)
-        })() {
-            Ok(s) => s,
-            Err(e) => { error!(%e, "calc_ssr failed"); continue; }
+        let calc_start = Instant::now();
+        let fetch_ms = (calc_start - poll_start).as_millis() as u64;
+        // the library holds 93% scores only, and a forced recalc skips every cache
+        let cached = library_cache.as_mut().filter(|_| cfg.library_cache && goal == calc::SCORE_GOAL && !forced).and_then(|c| c.lookup(&sha1, &rate_str))
+            .or_else(|| rate_table.scores(&sha1, &rate_str, goal).filter(|_| !forced))
+            .or_else(|| msd_cache.as_ref().filter(|_| !forced)?.get(&sha1, &rate_str, goal));
+        let from_cache = cached.is_some();
+        // parsed once; the curve, sections and comparison reuse the same notes
+        let result = calc::notes(&osu_str).and_then(|(map, notes)| {
+            let scores = match cached { Some(s) => s, None => calc::ssr(&calc, &notes, raw_rate, goal)? };
+            Ok((map, notes, scores))
+        });
+        let (map, notes, scores) = match result {
+            Ok(r) => r,
+            // not 4K mania: say so with tosu's stars rather than keep showing the last mania map
+            Err(e) if e.downcast_ref::<calc::Unrated>().is_some() => {
+                let unrated = calc::Unrated { stars: v2.beatmap.stats.as_ref().and_then(|s| s.stars.as_ref()?.total), ..e.downcast::<calc::Unrated>()? };
+                let tosu_id = |n: Option<u64>| n.filter(|&n| n > 0);
+                mod_notes.push(unrated.to_string());
+                let (tags, user_note) = hub.tags.get(&chart_md5).map(|t| (t.tags, t.note)).unwrap_or_default();
+                let out = MsdOut { md5: chart_md5, goal, speed_mod, pitched, mods: mod_names, notes: mod_notes, tags, user_note, ..MsdOut::unrated(song_full, version, rate_str, unrated) }
+                    .with_ids(tosu_id(v2.beatmap.id), tosu_id(v2.beatmap.set));
+                match output::write_all(&static_root, &out, &cfg).await {
+                    Ok(()) => {
+                        backlog.clear(&hub);
+                        info!(target: "output", "msd.json updated: {} [{}] ({}, not rated)", out.song, out.diff, out.unrated.as_ref().map_or("", |u| u.mode));
+                        if cfg.graph { output::write_graph(&static_root, None, &cfg).await; }
+                    }
+                    Err(e) => { warn!(target: "output", code = "output.write", %e, "failed to write msd.json; keeping it until the folder is writable"); backlog.fail(&out, &e, &hub); }
+                }
+                hub.publish(&out);
+                if let Some(c) = current.take() { last_chart = Some(c.out); }
+                failures = 0;
+                calc_watch.ok();
+                continue;
+            }
+            Err(e) => { error!(target: "calc", code = "calc.failed", %e, "calc_ssr failed"); note_failure(&mut failures, cfg.crash_after); calc_watch.fail(); continue; }
         };
+        let calc_ms = calc_start.elapsed().as_millis() as u64;
+        if let Some(c) = msd_cache.as_mut().filter(|_| !from_cache) { c.insert(&sha1, &rate_str, goal, &scores); }
+        if cfg.rate_table { rate_table.want(&sha1, score_goal, &notes); }
+        let calc_unix_ms = hub::unix_ms();
+        failures = 0;
+        calc_watch.ok();
 
         // write msd.json
-        let out = MsdOut {
-            song: song_full.clone(),
-            diff: version.clone(),
-            overall: scores.overall,
-            stamina: scores.stamina,
-            jumpstream: scores.jumpstream,
-            handstream: scores.handstream,
-            stream: scores.stream,
-            chordjack: scores.chordjack,
-            jacks: scores.jackspeed,
-            technical: scores.technical,
-            rate: rate_str,
+        // the .osu's IDs first; tosu knows them for maps whose file predates the fields
+        let (beatmap_id, set_id) = calc::map_ids(&map);
+        let tosu_id = |n: Option<u64>| n.filter(|&n| n > 0);
+        let out = MsdOut { md5: chart_md5, goal, ..MsdOut::new(song_full, version, &scores, rate_str) }
+            .with_ids(beatmap_id.or(tosu_id(v2.beatmap.id)), set_id.or(tosu_id(v2.beatmap.set)));
+        let curve = if cfg.curve.is_empty() { Vec::new() } else {
+            calc::curve(&calc, &notes, raw_rate, &cfg.curve).unwrap_or_else(|e| { warn!(target: "calc", code = "calc.curve", %e, "MSD curve failed"); Vec::new() })
+        };
+        let ladder = if cfg.ladder.is_empty() { Vec::new() } else {
+            calc::ladder(&calc, &notes, raw_rate, &cfg.ladder).unwrap_or_else(|e| { warn!(target: "calc", code = "calc.ladder", %e, "MSD ladder failed"); Vec::new() })
         };
-        if let Err(e) = write_msd_json(&static_root, &out).await {
-            warn!(%e, "failed to write msd.json");
+        let compare = other_calc.as_ref().and_then(|c| c.compare(&notes, raw_rate, goal, scores.overall)
+            .map_err(|e| warn!(target: "calc", code = "calc.compare", "MSD_CALC_COMPARE: {e:#}")).ok());
+        let windows = Some(wife::Windows::new(map.overall_difficulty, &mod_names, raw_rate));
+        let load = Some(calc::load(&notes, raw_rate));
+        let percentile = library_cache.as_mut().filter(|_| cfg.percentile).and_then(|c| c.percentile(&out));
+        let (tags, user_note) = hub.tags.get(&out.md5).map(|t| (t.tags, t.note)).unwrap_or_default();
+        let previous = current.as_ref().map(|c| c.out.previous());
+        if let Some(c) = current.as_ref().filter(|c| c.out.md5 != out.md5) { last_chart = Some(c.out.clone()); }
+        let delta = last_chart.as_ref().and_then(|before| out.delta(before));
+        // after everything that goes into the result, right before it goes out
+        let emitted_unix_ms = hub::unix_ms();
+        let out = MsdOut { speed_mod, pitched, mods: mod_names, notes: mod_notes, windows, curve, ladder, percentile, load, compare, previous, delta, tags, user_note, rates: rate_table.rows(&sha1), timing: Some(calc::Timing {
+            snapshot_unix_ms, calc_unix_ms, emitted_unix_ms, fetch_ms, calc_ms,
+            latency_ms: poll_start.elapsed().as_millis() as u64,
+        }), ..out };
+        if let Err(e) = output::write_all(&static_root, &out, &cfg).await {
+            warn!(target: "output", code = "output.write", %e, "failed to write msd.json; keeping it until the folder is writable");
+            backlog.fail(&out, &e, &hub);
         } else {
-            info!("msd.json updated: {} [{}] @{}x", out.song, out.diff, out.rate);
+            backlog.clear(&hub);
+            info!(target: "output", "msd.json updated: {} [{}] @{}x", out.song, out.diff, out.rate);
+            if cfg.qr { qr.update(&output::overlay_dir(&static_root), out.url.as_deref(), cfg.qr_scale).await; }
+            if cfg.np { np.update(&output::overlay_dir(&static_root), &out, &cfg.np_format).await; }
+            if let Some(tx) = &chatbot {
+                let line = np::format(&cfg.np_format, &out);
+                tx.send_if_modified(|l| if *l == line { false } else { *l = line; true });
+            }
+            if cfg.theme || cfg.cover.is_some() {
+                background.update(&static_root, &http, &snapshot.background, format!("{}|{}", out.song, map.background_file), &cfg).await;
+            }
+            heartbeat.beat(&http);
         }
+        hub.publish(&out);
+        if cfg.recent > 0 { output::write_recent(&static_root, &hub.recent(), &cfg).await; }
+        let cur = calc::Current { out, od: map.overall_difficulty, notes: map.hit_objects.len() };
+        if cfg.sections || cfg.graph { breakdowns.start(key, map, notes, raw_rate, goal, &cfg); }
+        lobby.update(&static_root, &v2.leaderboard, Some(&cur.out), &cfg).await;
+        h2h.update(&static_root, v2.tourney.as_ref(), Some(&cur), &cfg).await;
+        tracks.update(&static_root, Some(&cur.out), state == Some(2), &cfg).await;
+        if let Some(p) = history.update(&static_root, Some(&cur.out), state == Some(2), &cfg) { chat.play(&p); }
+        goal_tracker.update(&static_root, &hub.goals, Some(&cur.out), state, v2.play.hits.as_ref(), cur.out.windows, &chat, &cfg).await;
+        current = Some(cur);
 
-    sleep();
+    sleep(&cfg).await;
 }
 }
 
-fn sleep() { tokio::spawn(async { time::sleep(Duration::from_millis(150)).await; }); }
-
-fn extract_rate_from_v2(v2: &JsonV2) -> Option<f32> {
-    // Prefer explicit fields if present (newer Tosu builds):
-    v2.play.mods.rate
-        .or(v2.play.mods.array.as_ref()
-            .and_then(|a| a.get(0))
-            .and_then(|m| m.rate.or(m.settings.speed_change)))
-        // Some builds also echo a top-level `mods` with the same structure:
-        .or(v2.mods.as_ref().and_then(|m| m.rate.or_else(|| {
-            m.array.as_ref().and_then(|a| a.get(0)).and_then(|e| e.rate.or(e.settings.speed_change))
-        })))
-        // Fallback: derive from name (DT/NC 1.5, HT/DC 0.75)
-        .or_else(|| {
-            let s = v2.play.mods.name.as_deref().unwrap_or("");
-            if s.contains("NC") || s.contains("DT") { Some(1.5) }
-            else if s.contains("HT") || s.contains("DC") { Some(0.75) }
-            else { Some(1.0) }
-        })
+/// GET the current `.osu` from tosu, hashing it (SHA-1) as it streams in. Nothing is buffered
+/// while the bytes match `previous`, the last file fetched, so polling an unchanged map costs
+/// no copy; that case comes back as `None`.
+async fn fetch_osu(http: &reqwest::Client, url: &str, timeout: Duration, previous: &[u8]) -> anyhow::Result<(String, Option<Vec<u8>>)> {
+    let mut rsp = http.get(url).timeout(timeout).send().await?.error_for_status()?;
+    let mut sha1 = sha1_smol::Sha1::new();
+    let (mut len, mut fresh) = (0, None::<Vec<u8>>);
+    while let Some(chunk) = rsp.chunk().await? {
+        sha1.update(&chunk);
+        match &mut fresh {
+            Some(buf) => buf.extend_from_slice(&chunk),
+            None if previous.get(len..len + chunk.len()) == Some(&chunk[..]) => {}
+            None => {
+                let size = rsp.content_length().map_or(len + chunk.len(), |n| n as usize);
+                let mut buf = Vec::with_capacity(size);
+                buf.extend_from_slice(&previous[..len]);
+                buf.extend_from_slice(&chunk);
+                fresh = Some(buf);
+            }
+        }
+        len += chunk.len();
+    }
+    // a prefix of the previous file
+    if fresh.is_none() && len != previous.len() { fresh = Some(previous[..len].to_vec()); }
+    Ok((sha1.hexdigest(), fresh))
 }
 
-async fn write_msd_json(static_root: &PathBuf, out: &MsdOut) -> anyhow::Result<()> {
-    let path = static_root.join("MinaCalcOnOsu").join("msd.json");
-    if let Some(dir) = path.parent() { fs::create_dir_all(dir).await.ok(); }
-    fs::write(&path, serde_json::to_vec(out)?).await?;
-    Ok(())
+fn note_failure(failures: &mut u32, crash_after: u32) {
+    *failures += 1;
+    if *failures == crash_after {
+        match crash::write_bundle(&format!("{failures} consecutive calc failures")) {
+            Ok(dir) => error!("crash report written to {}", dir.display()),
+            Err(e) => warn!(%e, "failed to write crash report"),
+        }
+    }
 }
+
+/// Pause after an iteration (`MSD_POLL_SLEEP_MS`), on top of the poll interval.
+async fn sleep(cfg: &Config) { time::sleep(Duration::from_millis(cfg.poll_sleep_ms)).await; }
@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use minacalc_rs::Calc;
+use reqwest::Client;
+use serde::Serialize;
+use tracing::*;
+
+use crate::{beatmaps::{self, MapRef}, calc::{self, MsdOut}, config::{arg_value, Config}, output::{self, Format}, progress::Progress, sheet::{Cell, Table}};
+
+/// One pool line: `[slot] <beatmap id | link | .osu path> [rate[,rate...]]`.
+struct Entry { slot: Option<String>, map: MapRef, source: String, rates: Vec<f32> }
+
+#[derive(Serialize)]
+struct PoolMap {
+    slot: Option<String>,
+    source: String,
+    beatmap_id: Option<u64>,
+    song: String,
+    diff: String,
+    /// One result per rate, in the order given.
+    msd: Vec<MsdOut>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Pool { name: String, maps: Vec<PoolMap> }
+
+/// `minacalc-overlay mappool <file> [--out <path>] [--quiet | --json-progress]`; `.csv` / `.xlsx` outputs get the spreadsheet layout.
+pub async fn run(cfg: &Config) -> anyhow::Result<()> {
+    let input = std::env::args().nth(2).filter(|a| !a.starts_with("--"))
+        .context("usage: minacalc-overlay mappool <file> [--out <pool.json|.yaml|.xml|.csv|.xlsx>]")?;
+    let input = PathBuf::from(input);
+    let text = std::fs::read_to_string(&input).with_context(|| format!("reading {}", input.display()))?;
+    let entries = parse(&text)?;
+    let out_path = arg_value("--out").map(PathBuf::from).unwrap_or_else(|| input.with_extension("pool.json"));
+
+    let http = Client::new();
+    let calc = Calc::new()?;
+    let mut maps = Vec::with_capacity(entries.len());
+    let mut progress = Progress::new("maps", entries.len());
+    for e in entries {
+        let label = e.slot.clone().unwrap_or_else(|| e.source.clone());
+        let m = match calc_entry(&http, &calc, &e, cfg).await {
+            Ok(m) => { debug!(target: "calc", "{label}: {} [{}]", m.song, m.diff); progress.tick(&label); m }
+            Err(err) => {
+                progress.fail(&label, &err);
+                PoolMap { slot: e.slot, source: e.source, beatmap_id: e.map.beatmap_id(), song: String::new(), diff: String::new(), msd: Vec::new(), error: Some(format!("{err:#}")) }
+            }
+        };
+        maps.push(m);
+    }
+
+    let maps_ok = maps.iter().filter(|m| m.error.is_none()).count();
+    let name = input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = out_path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let bytes = match ext.as_str() {
+        "csv" => table(&maps).to_csv().into_bytes(),
+        "xlsx" => table(&maps).to_xlsx(&name),
+        _ => output::encode(&Pool { name, maps }, format_for(&out_path), cfg)?,
+    };
+    std::fs::write(&out_path, bytes).with_context(|| format!("writing {}", out_path.display()))?;
+    progress.finish(&[("rated", maps_ok)]);
+    info!(target: "output", "pool written to {}", out_path.display());
+    Ok(())
+}
+
+fn format_for(p: &Path) -> Format {
+    p.extension().and_then(|e| e.to_str()).and_then(Format::from_name).unwrap_or(Format::Json)
+}
+
+const SKILLSETS: [&str; 8] = ["Overall", "Stamina", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "Technical"];
+
+fn skillsets(m: &MsdOut) -> [f32; 8] {
+    [m.overall, m.stamina, m.jumpstream, m.handstream, m.stream, m.chordjack, m.jacks, m.technical]
+}
+
+/// One row per map; for every rate used anywhere in the pool, a column per skillset
+/// (`1.00x Overall`, `1.00x Stamina`, ...). Cells stay empty for rates a map wasn't rated at.
+fn table(maps: &[PoolMap]) -> Table {
+    let mut rates: Vec<&str> = maps.iter().flat_map(|m| m.msd.iter().map(|o| o.rate.as_str())).collect();
+    rates.sort_by(|a, b| a.parse::<f32>().unwrap_or(0.0).total_cmp(&b.parse().unwrap_or(0.0)));
+    rates.dedup();
+    let mut header: Vec<String> = ["Slot", "Song", "Difficulty", "Beatmap ID"].map(String::from).to_vec();
+    for r in &rates { header.extend(SKILLSETS.iter().map(|s| format!("{r}x {s}"))); }
+    header.push("Error".into());
+    let rows = maps.iter().map(|m| {
+        let mut row = vec![
+            m.slot.clone().map_or(Cell::Empty, Cell::Text),
+            Cell::Text(m.song.clone()),
+            Cell::Text(m.diff.clone()),
+            m.beatmap_id.map_or(Cell::Empty, Cell::Int),
+        ];
+        for r in &rates {
+            match m.msd.iter().find(|o| o.rate == *r) {
+                Some(o) => row.extend(skillsets(o).map(|v| Cell::Num((v as f64 * 100.0).round() / 100.0))),
+                None => row.extend((0..SKILLSETS.len()).map(|_| Cell::Empty)),
+            }
+        }
+        row.push(m.error.clone().map_or(Cell::Empty, Cell::Text));
+        row
+    }).collect();
+    Table { header, rows }
+}
+
+async fn calc_entry(http: &Client, calc: &Calc, e: &Entry, cfg: &Config) -> anyhow::Result<PoolMap> {
+    let bytes = beatmaps::load(http, &e.map, cfg.retry_bulk).await?;
+    let (osu, _) = calc::decode(&bytes);
+    let mut msd = Vec::with_capacity(e.rates.len());
+    let mut labels = (String::new(), String::new());
+    let md5 = crate::md5::hex(&bytes);
+    for &rate in &e.rates {
+        let (map, scores) = calc::calc_osu(calc, &osu, rate, cfg.score_goal)?;
+        labels = (calc::song_label(&map.artist, &map.title), map.version.clone());
+        let (beatmap_id, set_id) = calc::map_ids(&map);
+        let out = MsdOut { md5: md5.clone(), goal: cfg.score_goal, ..MsdOut::new(labels.0.clone(), labels.1.clone(), &scores, calc::rate_label(rate)) };
+        msd.push(out.with_ids(beatmap_id.or(e.map.beatmap_id()), set_id));
+    }
+    let beatmap_id = msd.first().and_then(|m| m.beatmap_id).or(e.map.beatmap_id());
+    Ok(PoolMap { slot: e.slot.clone(), source: e.source.clone(), beatmap_id, song: labels.0, diff: labels.1, msd, error: None })
+}
+
+/// Blank lines and `#` comments are skipped. Without explicit rates, DT/NC slots get 1.5x,
+/// HT/DC slots 0.75x, everything else 1.0x.
+fn parse(text: &str) -> anyhow::Result<Vec<Entry>> {
+    let mut out = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let mut rest = line;
+        let mut slot = None;
+        if let Some((first, tail)) = rest.split_once(char::is_whitespace) {
+            if is_slot(first) { slot = Some(first.to_string()); rest = tail.trim(); }
+        }
+        let mut rates = Vec::new();
+        if let Some((head, last)) = rest.rsplit_once(char::is_whitespace) {
+            if let Some(r) = calc::parse_rates(last) { rates = r; rest = head.trim(); }
+        }
+        let map = MapRef::parse(rest).with_context(|| format!("line {}: not a beatmap ID, link or path: {rest:?}", n + 1))?;
+        if rates.is_empty() { rates.push(slot.as_deref().map_or(1.0, slot_rate)); }
+        out.push(Entry { slot, map, source: rest.to_string(), rates });
+    }
+    Ok(out)
+}
+
+/// `NM1`, `DT2`, `TB`: letters then optional digits.
+fn is_slot(s: &str) -> bool {
+    let letters = s.trim_end_matches(|c: char| c.is_ascii_digit());
+    !letters.is_empty() && letters.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn slot_rate(slot: &str) -> f32 {
+    let s = slot.to_ascii_uppercase();
+    if s.starts_with("DT") || s.starts_with("NC") { 1.5 } else if s.starts_with("HT") || s.starts_with("DC") { 0.75 } else { 1.0 }
+}
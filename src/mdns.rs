@@ -0,0 +1,182 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tracing::*;
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+const TTL: u32 = 120;
+
+// record types
+const A: u16 = 1;
+const PTR: u16 = 12;
+const TXT: u16 = 16;
+const SRV: u16 = 33;
+const ANY: u16 = 255;
+
+/// One DNS-SD service, e.g. `_minacalc-grpc._tcp` on port 50051.
+pub struct Service {
+    pub kind: &'static str,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+
+/// Minimal mDNS responder (RFC 6762/6763): announces on start, then answers queries for our
+/// service types, instances and host name. IPv4 only.
+pub async fn advertise(ip: Ipv4Addr, services: Vec<Service>) -> anyhow::Result<()> {
+    let host = format!("{}.local", host_label());
+    let instance = format!("minacalc-overlay on {}", host_label());
+    let sock = bind().context("binding mDNS socket on 5353")?;
+    let dest = SocketAddr::V4(SocketAddrV4::new(GROUP, PORT));
+    for s in &services { info!(target: "mdns", "advertising {}._tcp \"{instance}\" at {ip}:{}", s.kind, s.port); }
+
+    let records = Records { host, instance, ip, services };
+    let announce = records.response();
+    for _ in 0..2 {
+        sock.send_to(&announce, dest).await.ok();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let mut buf = [0u8; 1500];
+    loop {
+        let (n, from) = match sock.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) => { debug!(target: "mdns", %e, "recv failed"); continue; }
+        };
+        let Some(questions) = parse_query(&buf[..n]) else { continue };
+        let asked = records.all().iter().any(|r| questions.iter().any(|(q, qt)| q.eq_ignore_ascii_case(&r.name) && (*qt == r.ty || *qt == ANY)));
+        // the whole set is small; answering with all of it saves clients the SRV/TXT/A follow-ups
+        if asked {
+            trace!(target: "mdns", %from, "answering query");
+            sock.send_to(&announce, dest).await.ok();
+        }
+    }
+}
+
+/// Shared 5353 socket in the group; `SO_REUSEADDR`/`SO_REUSEPORT` so Bonjour/Avahi keep working.
+fn bind() -> std::io::Result<UdpSocket> {
+    let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    s.set_reuse_address(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    s.set_reuse_port(true)?;
+    s.bind(&SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, PORT)).into())?;
+    s.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    s.set_multicast_ttl_v4(255)?; // RFC 6762 §11
+    s.set_nonblocking(true)?;
+    UdpSocket::from_std(s.into())
+}
+
+/// LAN address a service bound to `0.0.0.0` is reachable on (no packet is sent).
+pub fn lan_ip(bound: IpAddr) -> Option<Ipv4Addr> {
+    match bound {
+        IpAddr::V4(v4) if !v4.is_unspecified() => Some(v4).filter(|ip| !ip.is_loopback()),
+        IpAddr::V4(_) => {
+            let s = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+            s.connect((GROUP, PORT)).ok()?;
+            match s.local_addr().ok()?.ip() { IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip), _ => None }
+        }
+        IpAddr::V6(_) => None,
+    }
+}
+
+/// Machine name as a DNS label.
+pub fn host_label() -> String {
+    let raw = std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .unwrap_or_default();
+    let label: String = raw.trim().chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' }).take(63).collect();
+    let label = label.trim_matches('-').to_string();
+    if label.is_empty() { "minacalc".into() } else { label }
+}
+
+struct Record { name: String, ty: u16, unique: bool, data: Vec<u8> }
+
+struct Records { host: String, instance: String, ip: Ipv4Addr, services: Vec<Service> }
+
+impl Records {
+    fn all(&self) -> Vec<Record> {
+        let mut out = Vec::new();
+        for s in &self.services {
+            let kind = format!("{}._tcp.local", s.kind);
+            let inst = format!("{}.{kind}", self.instance);
+            out.push(Record { name: "_services._dns-sd._udp.local".into(), ty: PTR, unique: false, data: name(&kind) });
+            out.push(Record { name: kind.clone(), ty: PTR, unique: false, data: name(&inst) });
+            let mut srv = vec![0, 0, 0, 0]; // priority, weight
+            srv.extend(s.port.to_be_bytes());
+            srv.extend(name(&self.host));
+            out.push(Record { name: inst.clone(), ty: SRV, unique: true, data: srv });
+            let mut txt = Vec::new();
+            for t in &s.txt { txt.push(t.len().min(255) as u8); txt.extend(&t.as_bytes()[..t.len().min(255)]); }
+            if txt.is_empty() { txt.push(0); }
+            out.push(Record { name: inst, ty: TXT, unique: true, data: txt });
+        }
+        out.push(Record { name: self.host.clone(), ty: A, unique: true, data: self.ip.octets().to_vec() });
+        out
+    }
+
+    fn response(&self) -> Vec<u8> {
+        let records = self.all();
+        let mut p = vec![0, 0, 0x84, 0x00, 0, 0]; // id 0, QR + AA, no questions
+        p.extend((records.len() as u16).to_be_bytes());
+        p.extend([0, 0, 0, 0]);
+        for r in &records {
+            p.extend(name(&r.name));
+            p.extend(r.ty.to_be_bytes());
+            p.extend((if r.unique { 0x8001u16 } else { 1 }).to_be_bytes()); // IN, cache-flush for unique
+            p.extend(TTL.to_be_bytes());
+            p.extend((r.data.len() as u16).to_be_bytes());
+            p.extend(&r.data);
+        }
+        p
+    }
+}
+
+/// Uncompressed wire name. Instance names may contain spaces; labels are raw bytes.
+fn name(n: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for label in n.split('.').filter(|l| !l.is_empty()) {
+        out.push(label.len().min(63) as u8);
+        out.extend(&label.as_bytes()[..label.len().min(63)]);
+    }
+    out.push(0);
+    out
+}
+
+/// Questions of a query packet; `None` for responses and garbage.
+fn parse_query(p: &[u8]) -> Option<Vec<(String, u16)>> {
+    if p.len() < 12 || p[2] & 0x80 != 0 { return None; }
+    let qd = u16::from_be_bytes([p[4], p[5]]);
+    let mut at = 12;
+    let mut out = Vec::new();
+    for _ in 0..qd {
+        let (n, next) = read_name(p, at)?;
+        let ty = u16::from_be_bytes([*p.get(next)?, *p.get(next + 1)?]);
+        at = next + 4;
+        out.push((n, ty));
+    }
+    Some(out)
+}
+
+/// Name at `at`, following compression pointers; returns it and the offset after it.
+fn read_name(p: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    for _ in 0..32 { // pointer loop guard
+        let len = *p.get(at)? as usize;
+        match len {
+            0 => return Some((labels.join("."), end.unwrap_or(at + 1))),
+            l if l & 0xc0 == 0xc0 => {
+                let ptr = ((l & 0x3f) << 8) | *p.get(at + 1)? as usize;
+                end.get_or_insert(at + 2);
+                at = ptr;
+            }
+            l => {
+                labels.push(String::from_utf8_lossy(p.get(at + 1..at + 1 + l)?).into_owned());
+                at += 1 + l;
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,179 @@
+//! tosu's mod list: which mods are on (after `MSD_MODS_IGNORE`), the rate they imply and which
+//! ones MSD can't account for.
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct ModsV2 {
+    name: Option<String>,
+    // newer builds expose array  rate/speed_change too:
+    array: Option<Vec<ModEntry>>,
+    rate: Option<f32>,
+}
+#[derive(Deserialize)]
+struct ModEntry {
+    acronym: Option<String>,
+    #[serde(default)]
+    settings: ModSettings,
+    rate: Option<f32>,
+}
+#[derive(Deserialize, Default)]
+struct ModSettings {
+    #[serde(default)]
+    speed_change: Option<f32>,
+    /// lazer's DT/HT "Adjust pitch"
+    #[serde(default)]
+    adjust_pitch: Option<bool>,
+}
+
+const SPEED: [&str; 4] = ["NC", "DC", "DT", "HT"];
+
+/// One active mod, from tosu's `array` or split out of the `name` string.
+pub struct Mod<'a> { pub acronym: String, entry: Option<&'a ModEntry> }
+
+impl Mod<'_> {
+    pub fn is_speed(&self) -> bool { SPEED.contains(&self.acronym.as_str()) }
+}
+
+/// Mods from `play.mods`, falling back to the top-level `mods` some builds echo; `array` entries
+/// when there are any, else the name string.
+pub fn active<'a>(play: &'a ModsV2, root: Option<&'a ModsV2>) -> Vec<Mod<'a>> {
+    let sources = [Some(play), root];
+    let listed = sources.iter().flatten().find_map(|m| m.array.as_ref().filter(|a| !a.is_empty()));
+    if let Some(array) = listed {
+        return array.iter().filter_map(|e| Some(Mod { acronym: e.acronym.as_deref()?.trim().to_ascii_uppercase(), entry: Some(e) })).collect();
+    }
+    let name = sources.iter().flatten().find_map(|m| m.name.as_deref().filter(|n| !n.is_empty())).unwrap_or("");
+    split_name(name).into_iter().map(|acronym| Mod { acronym, entry: None }).collect()
+}
+
+/// stable's `"HDNC4K"` → `HD`, `NC`, `4K`; `NM` / `None` mean no mods.
+fn split_name(name: &str) -> Vec<String> {
+    let name = name.trim().to_ascii_uppercase();
+    if name.is_empty() || name == "NM" || name == "NONE" { return Vec::new(); }
+    let chars: Vec<char> = name.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        // key mods are digits + K ("4K", "10K")
+        let digits = chars[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+        let len = if digits > 0 && chars.get(i + digits) == Some(&'K') { digits + 1 } else { 2 };
+        out.push(chars[i..(i + len).min(chars.len())].iter().collect());
+        i += len;
+    }
+    out
+}
+
+/// Split off the mods named in `ignore`; they're dropped before anything else looks at the list.
+pub fn filter<'a>(mods: Vec<Mod<'a>>, ignore: &[String]) -> (Vec<Mod<'a>>, Vec<Mod<'a>>) {
+    mods.into_iter().partition(|m| !ignore.contains(&m.acronym))
+}
+
+/// tosu's own effective playback rate (it folds DT/HT and custom speed changes in), on builds that have it.
+pub fn live_rate(play: &ModsV2, root: Option<&ModsV2>) -> Option<f32> {
+    play.rate
+        // Some builds also echo a top-level `mods` with the same structure:
+        .or(root.and_then(|m| m.rate))
+        .filter(|r| *r > 0.0)
+}
+
+/// Rate worked out from the mod list: a speed-change setting, else DT/NC 1.5, HT/DC 0.75, else 1.0.
+pub fn mod_rate(mods: &[Mod]) -> f32 {
+    mods.iter().filter_map(|m| m.entry).find_map(|e| e.rate.or(e.settings.speed_change))
+        .unwrap_or_else(|| {
+            if mods.iter().any(|m| matches!(m.acronym.as_str(), "NC" | "DT")) { 1.5 }
+            else if mods.iter().any(|m| matches!(m.acronym.as_str(), "HT" | "DC")) { 0.75 }
+            else { 1.0 }
+        })
+}
+
+/// The active speed mod and whether it changes pitch: NC / DC always do, DT / HT only with lazer's `adjust_pitch`.
+pub fn speed_mod(mods: &[Mod]) -> (Option<String>, bool) {
+    // stable lists NC without DT, lazer lists just one of them too; prefer the pitched name either way
+    let Some(m) = SPEED.iter().find_map(|s| mods.iter().find(|m| m.acronym == *s)) else { return (None, false) };
+    let pitched = match m.acronym.as_str() {
+        "NC" | "DC" => true,
+        _ => m.entry.and_then(|e| e.settings.adjust_pitch).unwrap_or(false),
+    };
+    (Some(m.acronym.clone()), pitched)
+}
+
+/// Mods that turn the chart into another one: key count (`4K`..`10K`, `DS`), LNs (`HO`, `IN`) and
+/// lazer's `NR`, in a fixed order so the same set always reads the same; empty = played as mapped.
+pub fn conversion(mods: &[Mod]) -> String {
+    let mut conv: Vec<&str> = mods.iter().map(|m| m.acronym.as_str())
+        .filter(|a| matches!(*a, "HO" | "IN" | "NR" | "DS") || (a.ends_with('K') && a[..a.len() - 1].parse::<u8>().is_ok()))
+        .collect();
+    conv.sort_unstable();
+    conv.dedup();
+    conv.concat()
+}
+
+/// A note per active mod listed in `MSD_MODS_UNSUPPORTED`.
+pub fn notes(mods: &[Mod], unsupported: &[String]) -> Vec<String> {
+    mods.iter().filter(|m| unsupported.contains(&m.acronym))
+        .map(|m| format!("{} changes the chart; MSD is for the unmodded map", m.acronym))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mods(json: &str) -> ModsV2 { serde_json::from_str(json).unwrap() }
+
+    fn acronyms(m: &[Mod]) -> Vec<String> { m.iter().map(|m| m.acronym.clone()).collect() }
+
+    #[test]
+    fn split_stable_names() {
+        assert_eq!(split_name("HDNC4K"), ["HD", "NC", "4K"]);
+        assert_eq!(split_name("dt10k"), ["DT", "10K"]);
+        assert_eq!(split_name("HD, HR"), ["HD", "HR"]);
+        assert!(split_name("NM").is_empty() && split_name("None").is_empty() && split_name("").is_empty());
+    }
+
+    #[test]
+    fn rate_from_the_mod_list() {
+        let play = mods(r#"{"name": "HDNC"}"#);
+        assert_eq!(mod_rate(&active(&play, None)), 1.5);
+        assert_eq!(mod_rate(&active(&mods(r#"{"name": "HT"}"#), None)), 0.75);
+        assert_eq!(mod_rate(&active(&mods(r#"{"name": "HD"}"#), None)), 1.0);
+        // lazer's custom speed beats the mod's default
+        let lazer = mods(r#"{"array": [{"acronym": "DT", "settings": {"speed_change": 1.3}}]}"#);
+        assert_eq!(mod_rate(&active(&lazer, None)), 1.3);
+        // the top-level list stands in for an empty play list
+        assert_eq!(acronyms(&active(&mods("{}"), Some(&play))), ["HD", "NC"]);
+    }
+
+    #[test]
+    fn live_rate_when_tosu_has_one() {
+        assert_eq!(live_rate(&mods(r#"{"rate": 1.2}"#), None), Some(1.2));
+        assert_eq!(live_rate(&mods("{}"), Some(&mods(r#"{"rate": 0.9}"#))), Some(0.9));
+        assert_eq!(live_rate(&mods(r#"{"rate": 0}"#), None), None);
+    }
+
+    #[test]
+    fn pitched_speed_mods() {
+        assert_eq!(speed_mod(&active(&mods(r#"{"name": "HDNC"}"#), None)), (Some("NC".into()), true));
+        assert_eq!(speed_mod(&active(&mods(r#"{"name": "DT"}"#), None)), (Some("DT".into()), false));
+        // stable's NC comes with DT in some lists; the pitched name wins
+        assert_eq!(speed_mod(&active(&mods(r#"{"name": "DTNC"}"#), None)).0.as_deref(), Some("NC"));
+        let lazer = mods(r#"{"array": [{"acronym": "HT", "settings": {"adjust_pitch": true}}]}"#);
+        assert_eq!(speed_mod(&active(&lazer, None)), (Some("HT".into()), true));
+        assert_eq!(speed_mod(&active(&mods(r#"{"name": "HR"}"#), None)), (None, false));
+    }
+
+    #[test]
+    fn ignored_and_unsupported_mods() {
+        let play = mods(r#"{"name": "HDDTMR"}"#);
+        let (kept, ignored) = filter(active(&play, None), &["DT".into()]);
+        assert_eq!((acronyms(&kept), acronyms(&ignored)), (vec!["HD".to_string(), "MR".into()], vec!["DT".to_string()]));
+        assert_eq!(notes(&kept, &["MR".into()]), ["MR changes the chart; MSD is for the unmodded map"]);
+    }
+
+    #[test]
+    fn conversions_in_a_fixed_order() {
+        assert_eq!(conversion(&active(&mods(r#"{"name": "IN7KHD"}"#), None)), "7KIN");
+        assert_eq!(conversion(&active(&mods(r#"{"name": "HOHR"}"#), None)), "HO");
+        assert_eq!(conversion(&active(&mods(r#"{"name": "HDDT"}"#), None)), "");
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashMap, io::Write, path::PathBuf};
+use anyhow::Context;
+use minacalc_rs::{Calc, SkillsetScores};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+/// Entries kept when the file is compacted; at ~150 bytes a line that's around 15 MB.
+const MAX_ENTRIES: usize = 100_000;
+
+/// One line of `msd_cache.jsonl`.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    /// MinaCalc version the scores came from.
+    v: i32,
+    /// `<sha1> <rate> <goal>`.
+    k: String,
+    /// overall, stamina, jumpstream, handstream, stream, chordjack, jacks, technical.
+    s: [f32; 8],
+}
+
+/// `MSD_CALC_CACHE`: every live calculation by (chart SHA-1, rate, goal), so coming back to a
+/// map costs no calculation, across restarts too. It's appended to like the play history;
+/// entries from another MinaCalc version are dropped when it's opened.
+pub struct MsdCache { path: PathBuf, entries: HashMap<String, [f32; 8]> }
+
+impl MsdCache {
+    /// `msd_cache.jsonl` in the OS cache folder, next to the beatmap cache.
+    pub fn default_path() -> PathBuf {
+        dirs::cache_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("msd_cache.jsonl")
+    }
+
+    /// Load the cache at `path`, rewriting it without stale entries if it has any; a cache that
+    /// can't be read starts empty.
+    pub fn open(path: PathBuf) -> MsdCache {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => { warn!(target: "calc", code = "calc.cache", %e, "can't read {}; starting empty", path.display()); String::new() }
+        };
+        let (version, mut lines, mut stale) = (Calc::version(), Vec::new(), 0);
+        for l in text.lines().filter(|l| !l.trim().is_empty()) {
+            match serde_json::from_str::<Line>(l) {
+                Ok(line) if line.v == version => lines.push(line),
+                _ => stale += 1,
+            }
+        }
+        // later lines win, and the oldest go first when there are too many
+        let mut entries: HashMap<String, [f32; 8]> = HashMap::new();
+        let mut order = Vec::new();
+        for line in lines.into_iter().rev() {
+            if entries.len() >= MAX_ENTRIES { stale += 1; continue; }
+            if entries.contains_key(&line.k) { stale += 1; continue; }
+            entries.insert(line.k.clone(), line.s);
+            order.push(line);
+        }
+        let cache = MsdCache { path, entries };
+        if stale > 0 {
+            order.reverse();
+            match cache.rewrite(&order) {
+                Ok(()) => debug!(target: "calc", "MSD cache: dropped {stale} stale entries, {} left", order.len()),
+                Err(e) => warn!(target: "calc", code = "calc.cache", "MSD cache: {e:#}"),
+            }
+        }
+        debug!(target: "calc", "MSD cache: {} entries from {}", cache.entries.len(), cache.path.display());
+        cache
+    }
+
+    fn key(sha1: &str, rate: &str, goal: f32) -> String { format!("{sha1} {rate} {goal:.2}") }
+
+    pub fn get(&self, sha1: &str, rate: &str, goal: f32) -> Option<SkillsetScores> {
+        let [overall, stamina, jumpstream, handstream, stream, chordjack, jackspeed, technical] = *self.entries.get(&Self::key(sha1, rate, goal))?;
+        Some(SkillsetScores { overall, stamina, jumpstream, handstream, stream, chordjack, jackspeed, technical })
+    }
+
+    /// Remember `scores` and append them to the file.
+    pub fn insert(&mut self, sha1: &str, rate: &str, goal: f32, scores: &SkillsetScores) {
+        let s = [scores.overall, scores.stamina, scores.jumpstream, scores.handstream, scores.stream, scores.chordjack, scores.jackspeed, scores.technical];
+        let k = Self::key(sha1, rate, goal);
+        if self.entries.insert(k.clone(), s) == Some(s) { return; }
+        if let Err(e) = self.append(&Line { v: Calc::version(), k, s }) {
+            warn!(target: "calc", code = "calc.cache", "MSD cache: {e:#}");
+        }
+    }
+
+    fn append(&self, line: &Line) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() { std::fs::create_dir_all(dir)?; }
+        let mut bytes = serde_json::to_vec(line)?;
+        bytes.push(b'\n');
+        let mut f = std::fs::OpenOptions::new().create(true).append(true).open(&self.path).with_context(|| format!("opening {}", self.path.display()))?;
+        f.write_all(&bytes).with_context(|| format!("writing {}", self.path.display()))
+    }
+
+    fn rewrite(&self, lines: &[Line]) -> anyhow::Result<()> {
+        let mut body = Vec::new();
+        for l in lines {
+            serde_json::to_writer(&mut body, l)?;
+            body.push(b'\n');
+        }
+        // via a temp file, so a crash can't leave half a cache
+        if let Some(dir) = self.path.parent() { std::fs::create_dir_all(dir)?; }
+        let tmp = self.path.with_extension("jsonl.tmp");
+        std::fs::write(&tmp, body)?;
+        std::fs::rename(&tmp, &self.path).with_context(|| format!("writing {}", self.path.display()))
+    }
+}
@@ -0,0 +1,217 @@
+use serde_json::Value;
+
+/// MessagePack encoding of a JSON value. Floats that are exactly an f32 in disguise (all our skillset
+/// values) go out as float32, so a payload is about half the size of its JSON.
+pub fn to_msgpack(v: &Value) -> Vec<u8> {
+    let mut out = Vec::with_capacity(256);
+    write_value(&mut out, v);
+    out
+}
+
+fn write_value(out: &mut Vec<u8>, v: &Value) {
+    match v {
+        Value::Null => out.push(0xc0),
+        Value::Bool(b) => out.push(if *b { 0xc3 } else { 0xc2 }),
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() { write_uint(out, u) }
+            else if let Some(i) = n.as_i64() { write_int(out, i) }
+            else {
+                let f = n.as_f64().unwrap_or(0.0);
+                // values are tidied to the f32's shortest spelling (see output::to_value), compare on that
+                let g = f as f32;
+                if g.to_string().parse::<f64>().ok() == Some(f) { out.push(0xca); out.extend(g.to_be_bytes()); }
+                else { out.push(0xcb); out.extend(f.to_be_bytes()); }
+            }
+        }
+        Value::String(s) => {
+            let len = s.len();
+            match len {
+                0..=31 => out.push(0xa0 | len as u8),
+                32..=0xff => { out.push(0xd9); out.push(len as u8); }
+                0x100..=0xffff => { out.push(0xda); out.extend((len as u16).to_be_bytes()); }
+                _ => { out.push(0xdb); out.extend((len as u32).to_be_bytes()); }
+            }
+            out.extend(s.as_bytes());
+        }
+        Value::Array(a) => {
+            write_len(out, a.len(), 0x90, 0xdc);
+            for v in a { write_value(out, v); }
+        }
+        Value::Object(m) => {
+            write_len(out, m.len(), 0x80, 0xde);
+            for (k, v) in m {
+                write_value(out, &Value::String(k.clone()));
+                write_value(out, v);
+            }
+        }
+    }
+}
+
+/// fix-size header for < 16 entries, else the 16/32-bit variant (`code16 + 1` is the 32-bit one).
+fn write_len(out: &mut Vec<u8>, len: usize, fix: u8, code16: u8) {
+    match len {
+        0..=15 => out.push(fix | len as u8),
+        16..=0xffff => { out.push(code16); out.extend((len as u16).to_be_bytes()); }
+        _ => { out.push(code16 + 1); out.extend((len as u32).to_be_bytes()); }
+    }
+}
+
+fn write_uint(out: &mut Vec<u8>, u: u64) {
+    match u {
+        0..=0x7f => out.push(u as u8),
+        0x80..=0xff => { out.push(0xcc); out.push(u as u8); }
+        0x100..=0xffff => { out.push(0xcd); out.extend((u as u16).to_be_bytes()); }
+        0x1_0000..=0xffff_ffff => { out.push(0xce); out.extend((u as u32).to_be_bytes()); }
+        _ => { out.push(0xcf); out.extend(u.to_be_bytes()); }
+    }
+}
+
+fn write_int(out: &mut Vec<u8>, i: i64) {
+    match i {
+        -32..=-1 => out.push(i as u8), // negative fixint
+        -0x80..=-33 => { out.push(0xd0); out.push(i as i8 as u8); }
+        -0x8000..=-0x81 => { out.push(0xd1); out.extend((i as i16).to_be_bytes()); }
+        -0x8000_0000..=-0x8001 => { out.push(0xd2); out.extend((i as i32).to_be_bytes()); }
+        _ => { out.push(0xd3); out.extend(i.to_be_bytes()); }
+    }
+}
+
+/// The JSON value a MessagePack buffer holds (what `to_msgpack` writes, plus the other integer and
+/// float widths); `None` if it's malformed, has bytes left over, or uses bin/ext types.
+pub fn from_msgpack(mut buf: &[u8]) -> Option<Value> {
+    let v = read_value(&mut buf, 0)?;
+    buf.is_empty().then_some(v)
+}
+
+fn read_value(buf: &mut &[u8], depth: usize) -> Option<Value> {
+    // nesting past this is no payload of ours
+    if depth > 64 { return None; }
+    let (&b, rest) = buf.split_first()?;
+    *buf = rest;
+    let n = |buf: &mut &[u8], len: usize| -> Option<u64> { Some(take(buf, len)?.iter().fold(0, |n, &b| n << 8 | b as u64)) };
+    Some(match b {
+        0x00..=0x7f => Value::from(b),
+        0x80..=0x8f => read_map(buf, (b & 0x0f) as usize, depth)?,
+        0x90..=0x9f => read_array(buf, (b & 0x0f) as usize, depth)?,
+        0xa0..=0xbf => read_str(buf, (b & 0x1f) as usize)?,
+        0xc0 => Value::Null,
+        0xc2 => Value::Bool(false),
+        0xc3 => Value::Bool(true),
+        // the f32's shortest spelling, the value `encode` narrowed
+        0xca => Value::from(f32::from_be_bytes(take(buf, 4)?.try_into().ok()?).to_string().parse::<f64>().ok()?),
+        0xcb => Value::from(f64::from_be_bytes(take(buf, 8)?.try_into().ok()?)),
+        0xcc => Value::from(n(buf, 1)?),
+        0xcd => Value::from(n(buf, 2)?),
+        0xce => Value::from(n(buf, 4)?),
+        0xcf => Value::from(n(buf, 8)?),
+        0xd0 => Value::from(n(buf, 1)? as u8 as i8),
+        0xd1 => Value::from(n(buf, 2)? as u16 as i16),
+        0xd2 => Value::from(n(buf, 4)? as u32 as i32),
+        0xd3 => Value::from(n(buf, 8)? as i64),
+        0xd9 => { let len = n(buf, 1)? as usize; read_str(buf, len)? }
+        0xda => { let len = n(buf, 2)? as usize; read_str(buf, len)? }
+        0xdb => { let len = n(buf, 4)? as usize; read_str(buf, len)? }
+        0xdc => { let len = n(buf, 2)? as usize; read_array(buf, len, depth)? }
+        0xdd => { let len = n(buf, 4)? as usize; read_array(buf, len, depth)? }
+        0xde => { let len = n(buf, 2)? as usize; read_map(buf, len, depth)? }
+        0xdf => { let len = n(buf, 4)? as usize; read_map(buf, len, depth)? }
+        0xe0..=0xff => Value::from(b as i8),
+        _ => return None,
+    })
+}
+
+fn read_str(buf: &mut &[u8], len: usize) -> Option<Value> {
+    Some(Value::String(std::str::from_utf8(take(buf, len)?).ok()?.to_string()))
+}
+
+fn read_array(buf: &mut &[u8], len: usize, depth: usize) -> Option<Value> {
+    // every entry is at least a byte, so a bogus length fails here rather than in an allocation
+    if len > buf.len() { return None; }
+    (0..len).map(|_| read_value(buf, depth + 1)).collect::<Option<Vec<_>>>().map(Value::Array)
+}
+
+fn read_map(buf: &mut &[u8], len: usize, depth: usize) -> Option<Value> {
+    if len > buf.len() / 2 { return None; }
+    let mut m = serde_json::Map::new();
+    for _ in 0..len {
+        let Value::String(k) = read_value(buf, depth + 1)? else { return None };
+        m.insert(k, read_value(buf, depth + 1)?);
+    }
+    Some(Value::Object(m))
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if buf.len() < n { return None; }
+    let (head, rest) = buf.split_at(n);
+    *buf = rest;
+    Some(head)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A result as `output::to_value` hands it over.
+    pub(crate) fn sample() -> Value {
+        json!({
+            "song": "Artist - Title", "diff": "Insane", "rate": "1.50",
+            "overall": 23.5, "stamina": 21.25, "jumpstream": 22.75, "handstream": 20.5,
+            "stream": 19.0, "chordjack": 18.25, "jacks": 17.5, "technical": 22.0,
+        })
+    }
+
+    #[test]
+    fn encodes_a_known_result() {
+        // fixmap of 11 keys in serde_json's (sorted) order; every skillset narrows to float32
+        let want = [
+        b"\x8b".as_slice(),
+        b"\xa9chordjack\xca\x41\x92\x00\x00",
+        b"\xa4diff\xa6Insane",
+        b"\xaahandstream\xca\x41\xa4\x00\x00",
+        b"\xa5jacks\xca\x41\x8c\x00\x00",
+        b"\xaajumpstream\xca\x41\xb6\x00\x00",
+        b"\xa7overall\xca\x41\xbc\x00\x00",
+        b"\xa4rate\xa41.50",
+        b"\xa4song\xaeArtist - Title",
+        b"\xa7stamina\xca\x41\xaa\x00\x00",
+        b"\xa6stream\xca\x41\x98\x00\x00",
+        b"\xa9technical\xca\x41\xb0\x00\x00",
+        ].concat();
+        assert_eq!(to_msgpack(&sample()), want);
+        assert_eq!(from_msgpack(&want), Some(sample()));
+    }
+
+    #[test]
+    fn value_and_length_widths() {
+        let cases: [(Value, &[u8]); 12] = [
+            (json!(null), b"\xc0"),
+            (json!(false), b"\xc2"),
+            (json!([1, "a"]), b"\x92\x01\xa1a"),
+            (json!(127), b"\x7f"),
+            (json!(128), b"\xcc\x80"),
+            (json!(65536), b"\xce\x00\x01\x00\x00"),
+            (json!(1u64 << 32), b"\xcf\x00\x00\x00\x01\x00\x00\x00\x00"),
+            (json!(-32), b"\xe0"),
+            (json!(-33), b"\xd0\xdf"),
+            (json!(-129), b"\xd1\xff\x7f"),
+            (json!(0.1), b"\xca\x3d\xcc\xcc\xcd"),
+            (json!(0.1f64 + 0.2), b"\xcb\x3f\xd3\x33\x33\x33\x33\x33\x34"),
+        ];
+        for (v, want) in cases {
+            assert_eq!(to_msgpack(&v), want, "{v}");
+            assert_eq!(from_msgpack(want), Some(v));
+        }
+        assert_eq!(to_msgpack(&Value::from(vec![0; 16]))[..3], [0xdc, 0x00, 0x10]);
+        assert_eq!(to_msgpack(&json!("x".repeat(32)))[..2], [0xd9, 32]);
+    }
+
+    #[test]
+    fn rejects_malformed_buffers() {
+        let good = to_msgpack(&sample());
+        for n in 0..good.len() { assert_eq!(from_msgpack(&good[..n]), None, "prefix of {n}"); }
+        assert_eq!(from_msgpack(&[good.as_slice(), b"\xc0"].concat()), None);
+        // bin8, a non-string key, a map claiming more entries than there are bytes
+        for bad in [&b"\xc4\x01\x00"[..], b"\x81\x01\x02", b"\xdf\xff\xff\xff\xff"] { assert_eq!(from_msgpack(bad), None); }
+    }
+}
@@ -0,0 +1,67 @@
+use std::{process::Command, time::{Duration, Instant}};
+use tracing::*;
+
+/// Tracks how long something has been failing; raises one desktop notification once it passes `after`,
+/// and another when it recovers.
+pub struct FailureWatch {
+    what: &'static str,
+    after: Option<Duration>,
+    since: Option<Instant>,
+    notified: bool,
+}
+
+impl FailureWatch {
+    /// `after == None` disables notifications (the watch still tracks state).
+    pub fn new(what: &'static str, after: Option<Duration>) -> Self {
+        FailureWatch { what, after, since: None, notified: false }
+    }
+
+    pub fn fail(&mut self) {
+        let since = *self.since.get_or_insert_with(Instant::now);
+        let Some(after) = self.after else { return };
+        if !self.notified && since.elapsed() >= after {
+            self.notified = true;
+            desktop("minacalc-overlay", &format!("{} for {} min", self.what, since.elapsed().as_secs() / 60));
+        }
+    }
+
+    pub fn ok(&mut self) {
+        if self.notified { desktop("minacalc-overlay", &format!("recovered: {}", self.what)); }
+        self.since = None;
+        self.notified = false;
+    }
+}
+
+/// Native notification through the OS' own tooling (toast via PowerShell, osascript, notify-send).
+/// Text goes through env/argv, never spliced into a script.
+pub fn desktop(title: &str, body: &str) {
+    let mut cmd = if cfg!(windows) {
+        let mut c = Command::new("powershell");
+        c.args(["-NoProfile", "-NonInteractive", "-Command", TOAST_PS])
+            .env("MSD_TOAST_TITLE", title)
+            .env("MSD_TOAST_BODY", body);
+        c
+    } else if cfg!(target_os = "macos") {
+        let mut c = Command::new("osascript");
+        c.args(["-e", "on run argv", "-e", "display notification (item 2 of argv) with title (item 1 of argv)", "-e", "end run", title, body]);
+        c
+    } else {
+        let mut c = Command::new("notify-send");
+        c.args([title, body]);
+        c
+    };
+    tokio::task::spawn_blocking(move || match cmd.output() {
+        Ok(o) if !o.status.success() => warn!(target: "notify", status = %o.status, "desktop notification failed"),
+        Ok(_) => {}
+        Err(e) => warn!(target: "notify", %e, "desktop notification unavailable"),
+    });
+}
+
+const TOAST_PS: &str = r#"
+[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null
+$t = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02)
+$x = $t.GetElementsByTagName('text')
+$x.Item(0).AppendChild($t.CreateTextNode($env:MSD_TOAST_TITLE)) > $null
+$x.Item(1).AppendChild($t.CreateTextNode($env:MSD_TOAST_BODY)) > $null
+[Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('minacalc-overlay').Show([Windows.UI.Notifications.ToastNotification]::new($t))
+"#;
@@ -0,0 +1,61 @@
+use std::path::Path;
+use tracing::*;
+
+use crate::calc::MsdOut;
+
+/// `Camellia - GHOST [MX] +1.1x | 27.43 overall (JS 27.9)`
+pub const DEFAULT_FORMAT: &str = "{song} [{diff}] {rate_mod} | {overall} overall ({top} {top_msd})";
+
+/// Chat-style short name of the strongest skillset, stamina aside.
+fn short(m: &MsdOut) -> (&'static str, f32) {
+    [("Stream", m.stream), ("JS", m.jumpstream), ("HS", m.handstream), ("Jacks", m.jacks), ("CJ", m.chordjack), ("Tech", m.technical)]
+        .into_iter().max_by(|a, b| a.1.total_cmp(&b.1)).unwrap_or(("Stream", m.stream))
+}
+
+/// Fill `template`'s `{placeholders}`: `song`, `diff` (and `song_file` / `diff_file`, safe to
+/// use in file names), `rate` (`1.10`), `rate_mod` (`+1.1x`,
+/// empty at 1.0x), `mods` (`+HDDT`), each skillset (`overall`, `stamina`, `jumpstream`, ...),
+/// `top` / `top_msd` (strongest skillset) and `url`. Runs of spaces left by empty ones are collapsed.
+pub fn format(template: &str, m: &MsdOut) -> String {
+    let rate = m.rate.trim_end_matches('0').trim_end_matches('.');
+    let (top, top_msd) = short(m);
+    let vars = [
+        ("song", m.song.clone()),
+        ("diff", m.diff.clone()),
+        ("song_file", crate::sanitize::file_safe(&m.song)),
+        ("diff_file", crate::sanitize::file_safe(&m.diff)),
+        ("rate", m.rate.clone()),
+        ("rate_mod", if rate == "1" { String::new() } else { format!("+{rate}x") }),
+        ("mods", if m.mods.is_empty() { String::new() } else { format!("+{}", m.mods.join("")) }),
+        ("overall", format!("{:.2}", m.overall)),
+        ("stamina", format!("{:.2}", m.stamina)),
+        ("jumpstream", format!("{:.2}", m.jumpstream)),
+        ("handstream", format!("{:.2}", m.handstream)),
+        ("stream", format!("{:.2}", m.stream)),
+        ("chordjack", format!("{:.2}", m.chordjack)),
+        ("jacks", format!("{:.2}", m.jacks)),
+        ("technical", format!("{:.2}", m.technical)),
+        ("top", top.to_string()),
+        ("top_msd", format!("{top_msd:.1}")),
+        ("url", m.url.clone().unwrap_or_default()),
+    ];
+    let mut s = template.to_string();
+    for (k, v) in vars { s = s.replace(&format!("{{{k}}}"), &v); }
+    s.split(' ').filter(|w| !w.is_empty()).collect::<Vec<_>>().join(" ")
+}
+
+/// `MSD_NP`: `np.txt` in the overlay folder holds the current map as one `MSD_NP_FORMAT` line,
+/// for chat bots (`$(urlfetch http://127.0.0.1:24050/MinaCalcOnOsu/np.txt)`).
+#[derive(Default)]
+pub struct Sink { last: Option<String> }
+
+impl Sink {
+    pub async fn update(&mut self, overlay_dir: &Path, out: &MsdOut, template: &str) {
+        let line = format(template, out);
+        if self.last.as_ref() == Some(&line) { return; }
+        match tokio::fs::write(overlay_dir.join("np.txt"), &line).await {
+            Ok(()) => self.last = Some(line),
+            Err(e) => warn!(target: "output", %e, "failed to write np.txt"),
+        }
+    }
+}
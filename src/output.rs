@@ -0,0 +1,339 @@
+use std::path::{Path, PathBuf};
+use std::fmt::Write as _;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
+
+use crate::config::Config;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format { Json, Yaml, Xml, MsgPack, Protobuf, Text }
+
+impl Format {
+    pub fn from_name(s: &str) -> Option<Format> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "xml" => Some(Format::Xml),
+            "msgpack" | "mpk" => Some(Format::MsgPack),
+            "protobuf" | "pb" => Some(Format::Protobuf),
+            "text" | "txt" => Some(Format::Text),
+            _ => None,
+        }
+    }
+}
+
+/// An extra file written next to msd.json on every update.
+#[derive(Clone, Debug)]
+pub struct FileSink { pub path: PathBuf, pub format: Format }
+
+/// `MSD_OUTPUTS` entries: `[format:]path`, comma separated. Without a prefix the format comes
+/// from the extension (`.yaml`/`.yml`/`.xml`/`.msgpack`/`.pb`/`.txt`, else JSON). Relative paths live in the overlay folder.
+pub fn parse_sinks(spec: &str) -> Vec<FileSink> {
+    spec.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|s| {
+        // `C:\...` must not read as format "C"
+        if let Some((f, p)) = s.split_once(':') {
+            if let Some(format) = Format::from_name(f) { return FileSink { path: PathBuf::from(p), format }; }
+        }
+        let path = PathBuf::from(s);
+        let format = path.extension().and_then(|e| e.to_str()).and_then(Format::from_name).unwrap_or(Format::Json);
+        FileSink { path, format }
+    }).collect()
+}
+
+pub fn overlay_dir(static_root: &Path) -> PathBuf { static_root.join("MinaCalcOnOsu") }
+
+/// msd.json as it was before anything was added to it, for `MSD_LEGACY_JSON`: these fields in
+/// this order, compact, whatever `MSD_JSON_PRETTY` / `MSD_JSON_SORT_KEYS` say.
+#[derive(Serialize, Deserialize)]
+struct Legacy {
+    song: String,
+    diff: String,
+    overall: f32,
+    stamina: f32,
+    jumpstream: f32,
+    handstream: f32,
+    stream: f32,
+    chordjack: f32,
+    jacks: f32,
+    technical: f32,
+    rate: String,
+}
+
+/// Write msd.json (always; the overlay reads it) plus every configured sink. With
+/// `MSD_LEGACY_JSON` msd.json keeps the flat legacy shape and the full one is msd.v2.json.
+/// The `MSD_STATIC_ROOTS` folders get the same files, bar sinks with absolute paths; a folder
+/// there that can't be written to is logged, not failed on.
+pub async fn write_all<T: Serialize>(static_root: &Path, out: &T, cfg: &Config) -> anyhow::Result<()> {
+    let mut files = Vec::with_capacity(cfg.outputs.len() + 2);
+    if cfg.legacy_json {
+        let legacy: Legacy = serde_json::from_value(to_value(out)?)?;
+        files.push((PathBuf::from("msd.v2.json"), encode(out, Format::Json, cfg)?));
+        files.push((PathBuf::from("msd.json"), serde_json::to_vec(&legacy)?));
+    } else {
+        files.push((PathBuf::from("msd.json"), encode(out, Format::Json, cfg)?));
+    }
+    for sink in &cfg.outputs { files.push((sink.path.clone(), encode(out, sink.format, cfg)?)); }
+    write_files(&overlay_dir(static_root), &files, true).await?;
+    for root in &cfg.static_roots {
+        if let Err(e) = write_files(&overlay_dir(root), &files, false).await {
+            tracing::warn!(target: "output", code = "output.mirror", "MSD_STATIC_ROOTS: {e:#}");
+        }
+    }
+    Ok(())
+}
+
+async fn write_files(dir: &Path, files: &[(PathBuf, Vec<u8>)], absolute: bool) -> anyhow::Result<()> {
+    fs::create_dir_all(dir).await.ok();
+    for (name, body) in files {
+        if name.is_absolute() && !absolute { continue; }
+        let path = dir.join(name); // absolute sink paths replace `dir`
+        if let Some(parent) = path.parent() { fs::create_dir_all(parent).await.ok(); }
+        fs::write(&path, body).await.map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// The latest result that couldn't be written (static folder on a network drive that dropped,
+/// a file locked by another program), kept until a write goes through. Newer results replace
+/// it, so only the current map is ever flushed; the hub reports how many are waiting.
+#[derive(Default)]
+pub struct Backlog { out: Option<Value>, unwritten: u64 }
+
+impl Backlog {
+    pub fn fail<T: Serialize>(&mut self, out: &T, e: &anyhow::Error, hub: &crate::hub::Hub) {
+        let Ok(v) = to_value(out) else { return };
+        self.out = Some(v);
+        self.unwritten += 1;
+        hub.set_backlog(self.unwritten, Some(format!("{e:#}")));
+    }
+
+    /// After a good write of a newer result.
+    pub fn clear(&mut self, hub: &crate::hub::Hub) {
+        if self.unwritten > 0 {
+            tracing::info!(target: "output", "outputs writable again after {} unwritten result(s)", self.unwritten);
+            *self = Backlog::default();
+            hub.set_backlog(0, None);
+        }
+    }
+
+    /// Retry the waiting result, if any.
+    pub async fn flush(&mut self, static_root: &Path, cfg: &Config, hub: &crate::hub::Hub) {
+        let Some(out) = &self.out else { return };
+        match write_all(static_root, out, cfg).await {
+            Ok(()) => self.clear(hub),
+            Err(e) => { tracing::debug!(target: "output", %e, "outputs still unwritable"); hub.set_backlog(self.unwritten, Some(e.to_string())); }
+        }
+    }
+}
+
+/// `recent.json`: `{"updates": [...]}`, the hub's last `MSD_RECENT` updates, oldest first.
+pub async fn write_recent(static_root: &Path, recent: &[std::sync::Arc<crate::hub::Update>], cfg: &Config) {
+    let body = serde_json::json!({ "updates": recent.iter().map(|u| &**u).collect::<Vec<_>>() });
+    let res = match encode(&body, Format::Json, cfg) {
+        Ok(bytes) => fs::write(overlay_dir(static_root).join("recent.json"), bytes).await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res { tracing::warn!(target: "output", %e, "failed to write recent.json"); }
+}
+
+/// `graph.json`; None (an unrated map) removes it, so the last chart's curve doesn't stay up.
+pub async fn write_graph(static_root: &Path, graph: Option<&crate::calc::Graph>, cfg: &Config) {
+    let path = overlay_dir(static_root).join("graph.json");
+    let Some(graph) = graph else { fs::remove_file(path).await.ok(); return };
+    let res = match encode(graph, Format::Json, cfg) {
+        Ok(bytes) => fs::write(path, bytes).await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res { tracing::warn!(target: "output", %e, "failed to write graph.json"); }
+}
+
+pub fn encode<T: Serialize>(out: &T, format: Format, cfg: &Config) -> anyhow::Result<Vec<u8>> {
+    Ok(match format {
+        Format::Json => encode_json(out, cfg.json_pretty, cfg.json_sort_keys)?,
+        Format::Yaml => to_yaml(&to_value(out)?).into_bytes(),
+        Format::Xml => to_xml("msd", &to_value(out)?).into_bytes(),
+        Format::MsgPack => crate::msgpack::to_msgpack(&to_value(out)?),
+        // `MsdResult` in proto/minacalc.proto
+        Format::Protobuf => crate::proto::encode(crate::proto::MSD_RESULT, &to_value(out)?),
+        Format::Text => to_text(&to_value(out)?).into_bytes(),
+    })
+}
+
+/// A numbered update for push sinks; same formats, but protobuf uses the `Update` message.
+pub fn encode_update(u: &crate::hub::Update, format: Format, cfg: &Config) -> anyhow::Result<Vec<u8>> {
+    match format {
+        Format::Protobuf => Ok(crate::proto::encode(crate::proto::UPDATE, &serde_json::to_value(u)?)),
+        _ => encode(u, format, cfg),
+    }
+}
+
+/// `serde_json::to_value` widens f32 to f64 (23.45 → 23.450000762939453); put the short spelling back.
+pub fn to_value<T: Serialize>(out: &T) -> serde_json::Result<Value> {
+    let mut v = serde_json::to_value(out)?;
+    tidy_floats(&mut v);
+    Ok(v)
+}
+
+fn tidy_floats(v: &mut Value) {
+    match v {
+        Value::Number(n) if n.is_f64() => {
+            let f = n.as_f64().unwrap_or(0.0);
+            if (f as f32) as f64 == f {
+                if let Some(t) = (f as f32).to_string().parse::<f64>().ok().and_then(serde_json::Number::from_f64) { *n = t; }
+            }
+        }
+        Value::Array(a) => a.iter_mut().for_each(tidy_floats),
+        Value::Object(m) => m.values_mut().for_each(tidy_floats),
+        _ => {}
+    }
+}
+
+/// Compact by default; `pretty` indents, `sort_keys` orders keys alphabetically
+/// (going through `Value`, whose map is sorted) instead of struct order.
+fn encode_json<T: Serialize>(out: &T, pretty: bool, sort_keys: bool) -> serde_json::Result<Vec<u8>> {
+    if sort_keys {
+        let v = to_value(out)?;
+        return if pretty { serde_json::to_vec_pretty(&v) } else { serde_json::to_vec(&v) };
+    }
+    if pretty { serde_json::to_vec_pretty(out) } else { serde_json::to_vec(out) }
+}
+
+/// Block-style YAML. Scalars reuse JSON spelling, which is valid YAML (double-quoted strings).
+pub fn to_yaml(v: &Value) -> String {
+    let mut out = String::new();
+    yaml_node(&mut out, v, 0);
+    if !out.ends_with('\n') { out.push('\n'); }
+    out
+}
+
+fn yaml_node(out: &mut String, v: &Value, indent: usize) {
+    let pad = " ".repeat(indent);
+    match v {
+        Value::Object(m) if !m.is_empty() => for (k, v) in m {
+            let _ = write!(out, "{pad}{}:", yaml_key(k));
+            yaml_child(out, v, indent);
+        },
+        Value::Array(a) if !a.is_empty() => for v in a {
+            let _ = write!(out, "{pad}-");
+            yaml_child(out, v, indent);
+        },
+        _ => { let _ = writeln!(out, "{pad}{}", yaml_scalar(v)); }
+    }
+}
+
+fn yaml_child(out: &mut String, v: &Value, indent: usize) {
+    match v {
+        Value::Object(m) if !m.is_empty() => { out.push('\n'); yaml_node(out, v, indent + 2); }
+        Value::Array(a) if !a.is_empty() => { out.push('\n'); yaml_node(out, v, indent + 2); }
+        _ => { let _ = writeln!(out, " {}", yaml_scalar(v)); }
+    }
+}
+
+fn yaml_scalar(v: &Value) -> String {
+    match v {
+        Value::Object(_) => "{}".into(),
+        Value::Array(_) => "[]".into(),
+        v => v.to_string(),
+    }
+}
+
+fn yaml_key(k: &str) -> String {
+    let plain = !k.is_empty() && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if plain { k.to_string() } else { Value::from(k).to_string() }
+}
+
+/// Spoken names of the mods osu! shows as acronyms.
+const MOD_NAMES: [(&str, &str); 24] = [
+    ("EZ", "Easy"), ("NF", "No Fail"), ("HT", "Half Time"), ("DC", "Daycore"), ("HR", "Hard Rock"), ("SD", "Sudden Death"),
+    ("PF", "Perfect"), ("DT", "Double Time"), ("NC", "Nightcore"), ("HD", "Hidden"), ("FI", "Fade In"), ("FL", "Flashlight"),
+    ("MR", "Mirror"), ("RD", "Random"), ("CO", "Dual Stages"), ("IN", "Invert"), ("CS", "Constant Speed"), ("HO", "Hold Off"),
+    ("NR", "No Release"), ("V2", "Score V2"), ("SV2", "Score V2"), ("AT", "Autoplay"), ("CN", "Cinema"), ("DA", "Difficulty Adjust"),
+];
+
+/// Plain sentences for screen readers and text-to-speech: full words, no abbreviations, always
+/// in the same order. Takes an msd.json value or an update wrapping one (`result`).
+pub fn to_text(v: &Value) -> String {
+    let m = v.get("result").unwrap_or(v);
+    let mut out = String::new();
+    let s = |k: &str| m.get(k).and_then(Value::as_str).filter(|s| !s.is_empty());
+    let n = |k: &str| m.get(k).and_then(Value::as_f64);
+    if let Some(song) = s("song") { let _ = writeln!(out, "Song: {song}."); }
+    if let Some(diff) = s("diff") { let _ = writeln!(out, "Difficulty: {diff}."); }
+    match s("rate").and_then(|r| r.parse::<f64>().ok()) {
+        Some(1.0) => { let _ = writeln!(out, "Rate: normal speed."); }
+        Some(rate) => { let _ = writeln!(out, "Rate: {rate} times normal speed."); }
+        None => {}
+    }
+    let mods: Vec<String> = m.get("mods").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str).map(mod_name).collect();
+    let _ = writeln!(out, "Mods: {}.", if mods.is_empty() { "none".to_string() } else { mods.join(", ") });
+    const SKILLSETS: [(&str, &str); 8] = [("overall", "Overall"), ("stamina", "Stamina"), ("jumpstream", "Jumpstream"), ("handstream", "Handstream"),
+        ("stream", "Stream"), ("chordjack", "Chordjack"), ("jacks", "Jacks"), ("technical", "Technical")];
+    for (key, name) in SKILLSETS {
+        if let Some(x) = n(key) { let _ = writeln!(out, "{name}: {x:.2}."); }
+    }
+    // overall and stamina aside, as in `calc::strongest`
+    if let Some((name, _)) = SKILLSETS[2..].iter().filter_map(|(k, name)| Some((name, n(k)?))).max_by(|a, b| a.1.total_cmp(&b.1)) {
+        let _ = writeln!(out, "Strongest skillset: {name}.");
+    }
+    if let Some(goal) = n("goal") { let _ = writeln!(out, "Score goal: {goal} percent."); }
+    for note in m.get("notes").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+        let _ = writeln!(out, "Note: {note}");
+    }
+    out
+}
+
+/// `DT` → `Double Time`, `4K` → `4 Keys`; unknown acronyms are spelled out letter by letter.
+fn mod_name(acronym: &str) -> String {
+    if let Some((_, name)) = MOD_NAMES.iter().find(|(k, _)| *k == acronym) { return name.to_string(); }
+    if let Some(keys) = acronym.strip_suffix('K').filter(|k| k.parse::<u8>().is_ok()) { return format!("{keys} Keys"); }
+    acronym.chars().map(String::from).collect::<Vec<_>>().join(" ")
+}
+
+/// Element-per-field XML; arrays repeat `<item>`, nulls become empty elements.
+pub fn to_xml(root: &str, v: &Value) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml_node(&mut out, root, v, 0);
+    out
+}
+
+fn xml_node(out: &mut String, name: &str, v: &Value, indent: usize) {
+    let pad = "  ".repeat(indent);
+    let name = xml_name(name);
+    match v {
+        Value::Object(m) => {
+            let _ = writeln!(out, "{pad}<{name}>");
+            for (k, v) in m { xml_node(out, k, v, indent + 1); }
+            let _ = writeln!(out, "{pad}</{name}>");
+        }
+        Value::Array(a) => {
+            let _ = writeln!(out, "{pad}<{name}>");
+            for v in a { xml_node(out, "item", v, indent + 1); }
+            let _ = writeln!(out, "{pad}</{name}>");
+        }
+        Value::Null => { let _ = writeln!(out, "{pad}<{name}/>"); }
+        Value::String(s) => { let _ = writeln!(out, "{pad}<{name}>{}</{name}>", xml_escape(s)); }
+        v => { let _ = writeln!(out, "{pad}<{name}>{v}</{name}>"); }
+    }
+}
+
+/// Keys are ours (snake_case), but keep the output well-formed whatever ends up in a map.
+fn xml_name(k: &str) -> String {
+    let mut n: String = k.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' { c } else { '_' }).collect();
+    if !n.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') { n.insert(0, '_'); }
+    n
+}
+
+fn xml_escape(s: &str) -> String {
+    s.chars().filter(|&c| c == '\t' || c == '\n' || c == '\r' || c >= ' ').fold(String::with_capacity(s.len()), |mut o, c| {
+        match c {
+            '&' => o.push_str("&amp;"),
+            '<' => o.push_str("&lt;"),
+            '>' => o.push_str("&gt;"),
+            '"' => o.push_str("&quot;"),
+            '\'' => o.push_str("&apos;"),
+            c => o.push(c),
+        }
+        o
+    })
+}
@@ -0,0 +1,116 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+use anyhow::Context;
+use minacalc_rs::SkillsetScores;
+use rosu_map::section::hit_objects::HitObjectKind;
+use serde::Serialize;
+use tracing::*;
+
+use crate::{calc, config::{arg_value, Config}, find, library::{Library, Ratings}, output::{self, Format}};
+
+#[derive(Serialize)]
+struct PackChart<'a> {
+    diff: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chartkey: Option<String>,
+    dominant: &'static str,
+    msd: &'a Ratings,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct PackFolder<'a> {
+    folder: String,
+    song: &'a str,
+    charts: Vec<PackChart<'a>>,
+}
+
+#[derive(Serialize)]
+struct Pack<'a> {
+    name: String,
+    query: String,
+    folders: Vec<PackFolder<'a>>,
+}
+
+/// Etterna's chart key for a 4K `.osu`: `X` plus the SHA-1 of every non-empty row, each
+/// written as one tap-note type per column (0 empty, 1 tap, 2 hold head) followed by the
+/// whole BPM at that row. Etterna derives it from the converted `.sm`, so the keys agree when
+/// the conversion keeps the rows and BPMs.
+pub fn chartkey(osu: &str) -> anyhow::Result<String> {
+    let map: rosu_map::Beatmap = rosu_map::from_str(osu).map_err(|e| anyhow::anyhow!("parse failed: {e}"))?;
+    let mut rows: BTreeMap<i64, [u8; 4]> = BTreeMap::new();
+    for h in &map.hit_objects {
+        let (x, kind) = match &h.kind {
+            HitObjectKind::Circle(c) => (c.pos.x, 1),
+            HitObjectKind::Hold(hold) => (hold.pos_x, 2),
+            _ => continue,
+        };
+        let column = ((x * 4.0 / 512.0) as usize).min(3);
+        rows.entry(h.start_time.round() as i64).or_default()[column] = kind;
+    }
+    anyhow::ensure!(!rows.is_empty(), "no notes");
+    let timing = &map.control_points.timing_points;
+    let mut k = String::new();
+    for (time, cols) in &rows {
+        for c in cols { k.push(char::from(b'0' + c)); }
+        let point = timing.iter().rev().find(|p| p.time <= *time as f64).or(timing.first());
+        let bpm = point.map_or(0, |p| (60_000.0 / p.beat_len) as i32);
+        k.push_str(&bpm.to_string());
+    }
+    Ok(format!("X{}", sha1_smol::Sha1::from(k.as_bytes()).hexdigest()))
+}
+
+/// Folder a chart is listed under: its beatmap folder, else its set.
+fn folder_of(path: Option<&str>, set_id: Option<u64>) -> String {
+    path.and_then(|p| Path::new(p).parent()?.file_name().map(|n| crate::nfc::nfc(&n.to_string_lossy()).into_owned()))
+        .or_else(|| set_id.map(|id| format!("set {id}")))
+        .unwrap_or_else(|| "Unknown".into())
+}
+
+/// `minacalc-overlay pack [find filters] [--name <pack name>] [--out <listing.txt|.json>]`: the
+/// charts a `find` query matches, as an Etterna pack-style listing grouped by folder.
+pub fn run(cfg: &Config) -> anyhow::Result<()> {
+    let query = find::query_args()?;
+    let library = Library::open(&Library::default_path())?;
+    let mut folders: BTreeMap<String, PackFolder> = BTreeMap::new();
+    for c in library.charts.values() {
+        let Some(msd) = query.matches(c) else { continue };
+        let chartkey = c.path.as_deref().and_then(|p| match std::fs::read_to_string(p).map_err(anyhow::Error::from).and_then(|t| chartkey(&t)) {
+            Ok(k) => Some(k),
+            Err(e) => { debug!(target: "calc", "no chartkey for {p}: {e:#}"); None }
+        });
+        let folder = folder_of(c.path.as_deref(), c.set_id);
+        folders.entry(folder.clone()).or_insert_with(|| PackFolder { folder, song: &c.song, charts: Vec::new() }).charts.push(PackChart {
+            diff: &c.diff, chartkey, dominant: calc::strongest(&SkillsetScores::from(msd)), msd, path: c.path.as_deref(),
+        });
+    }
+    let mut folders: Vec<PackFolder> = folders.into_values().collect();
+    for f in &mut folders { f.charts.sort_by(|a, b| a.msd.overall.total_cmp(&b.msd.overall)); }
+    let charts: usize = folders.iter().map(|f| f.charts.len()).sum();
+    let pack = Pack { name: arg_value("--name").unwrap_or_else(|| format!("MSD {}", query.describe())), query: query.describe(), folders };
+
+    let out = arg_value("--out").map(PathBuf::from);
+    let json = std::env::args().any(|a| a == "--json")
+        || out.as_ref().and_then(|p| p.extension()).is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let bytes = if json { output::encode(&pack, Format::Json, cfg)? } else { listing(&pack).into_bytes() };
+    match out {
+        Some(path) => {
+            std::fs::write(&path, bytes).with_context(|| format!("writing {}", path.display()))?;
+            info!(target: "output", "{charts} charts in {} folders written to {}", pack.folders.len(), path.display());
+        }
+        None => println!("{}", String::from_utf8_lossy(&bytes)),
+    }
+    Ok(())
+}
+
+/// Plain-text listing: the pack name, then each folder with its charts, easiest first.
+fn listing(pack: &Pack) -> String {
+    let mut s = format!("{}\n{}\n", pack.name, "=".repeat(pack.name.chars().count()));
+    for f in &pack.folders {
+        s.push_str(&format!("\n{}\n  {}\n", f.folder, f.song));
+        for c in &f.charts {
+            s.push_str(&format!("    {:>5.2}  {:<10}  {:<24}  {}\n", c.msd.overall, c.dominant, c.diff, c.chartkey.as_deref().unwrap_or("-")));
+        }
+    }
+    s
+}
@@ -0,0 +1,91 @@
+//! Paths as they turn up on players' machines: `\\?\` long-path forms, OneDrive-moved
+//! Documents folders, and names that differ only in Unicode normalization.
+use std::{io, path::{Path, PathBuf}};
+use tracing::*;
+
+use crate::nfc::nfc;
+
+/// `p` without a `\\?\` / `\\?\UNC\` prefix, so it can be shown, compared and joined with
+/// `/`-separated parts (a verbatim path takes `/` literally). std adds the prefix back by
+/// itself where a path is too long for plain Win32 calls. A no-op elsewhere.
+pub fn plain(p: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        use std::path::{Component, Prefix};
+        let mut parts = p.components();
+        let mut out = match parts.next() {
+            Some(Component::Prefix(pre)) => match pre.kind() {
+                Prefix::VerbatimDisk(d) => PathBuf::from(format!("{}:\\", d as char)),
+                Prefix::VerbatimUNC(server, share) => {
+                    let mut s = std::ffi::OsString::from(r"\\");
+                    s.push(server);
+                    s.push(r"\");
+                    s.push(share);
+                    s.push(r"\");
+                    PathBuf::from(s)
+                }
+                _ => return p.to_path_buf(),
+            },
+            _ => return p.to_path_buf(),
+        };
+        for c in parts.filter(|c| !matches!(c, Component::RootDir)) { out.push(c.as_os_str()); }
+        out
+    }
+    #[cfg(not(windows))]
+    p.to_path_buf()
+}
+
+/// What to compare and key a path by: the plain form, in NFC, so the same file matches
+/// whichever way its name was composed. Open files by their real path, not this.
+pub fn key(p: &Path) -> String {
+    nfc(&plain(p).display().to_string()).into_owned()
+}
+
+/// OneDrive's folder backup moves Documents and Desktop under `%USERPROFILE%\OneDrive`, which
+/// leaves paths written for the old place pointing at nothing. Such a path is re-rooted onto
+/// where the folder is now, when it (or its parent) exists only there.
+pub fn redirected(p: PathBuf) -> PathBuf {
+    if p.exists() { return p; }
+    let Some(home) = dirs::home_dir() else { return p };
+    for (old, now) in [("Documents", dirs::document_dir()), ("Desktop", dirs::desktop_dir())] {
+        let (old, Some(now)) = (home.join(old), now) else { continue };
+        if old == now { continue; }
+        let Ok(rest) = p.strip_prefix(&old) else { continue };
+        let moved = now.join(rest);
+        let found = |q: &Path| q.exists() || q.parent().is_some_and(Path::exists);
+        if found(&moved) && !found(&p) {
+            info!(target: "overlay", "{} is at {} now (OneDrive folder backup); using that", old.display(), now.display());
+            return moved;
+        }
+    }
+    p
+}
+
+/// Copy everything under `from` into `to` that isn't there yet, leaving existing files alone.
+/// Returns how many files were copied.
+pub fn copy_missing(from: &Path, to: &Path) -> io::Result<usize> {
+    let mut copied = 0;
+    std::fs::create_dir_all(to).map_err(|e| at(e, to))?;
+    // `to` may sit inside `from` (the dev fallback installs `./overlay` into itself)
+    let skip = std::fs::canonicalize(to).ok();
+    let mut stack = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((src, dest)) = stack.pop() {
+        std::fs::create_dir_all(&dest).map_err(|e| at(e, &dest))?;
+        for entry in std::fs::read_dir(&src).map_err(|e| at(e, &src))? {
+            let entry = entry.map_err(|e| at(e, &src))?;
+            let (s, d) = (entry.path(), dest.join(entry.file_name()));
+            if s.is_dir() {
+                if skip.is_none() || std::fs::canonicalize(&s).ok() != skip { stack.push((s, d)); }
+            }
+            else if !d.exists() {
+                std::fs::copy(&s, &d).map_err(|e| at(e, &d))?;
+                copied += 1;
+            }
+        }
+    }
+    Ok(copied)
+}
+
+fn at(e: io::Error, p: &Path) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {e}", p.display()))
+}
@@ -0,0 +1,221 @@
+//! Uncompressed PNG writer (zlib "stored" blocks) for generated images, and a decoder for
+//! non-interlaced PNGs of any colour type.
+use anyhow::{bail, ensure, Context};
+
+use crate::image::Image;
+
+/// PNG of `pixels` (rows of `width` RGB triples, top to bottom).
+pub fn rgb(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in pixels.chunks_exact(stride) {
+        raw.push(0); // filter: none
+        raw.extend_from_slice(row);
+    }
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend(width.to_be_bytes());
+    ihdr.extend(height.to_be_bytes());
+    ihdr.extend([8, 2, 0, 0, 0]); // 8-bit, truecolor, deflate, no filter, no interlace
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &ihdr);
+    chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crate::zip::crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() { out.extend([1, 0, 0, 0xff, 0xff]); }
+    while let Some(b) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8); // BFINAL
+        out.extend((b.len() as u16).to_le_bytes());
+        out.extend((!(b.len() as u16)).to_le_bytes());
+        out.extend(b);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &x in data { a = (a + x as u32) % 65521; b = (b + a) % 65521; }
+    out.extend(((b << 16) | a).to_be_bytes());
+    out
+}
+
+/// Decode to 8-bit RGB; alpha is dropped, 16-bit samples keep their high byte.
+pub fn decode(data: &[u8]) -> anyhow::Result<Image> {
+    ensure!(data.starts_with(b"\x89PNG\r\n\x1a\n"), "not a PNG");
+    let (mut width, mut height, mut depth, mut color) = (0usize, 0usize, 0u8, 0u8);
+    let (mut palette, mut idat) = (Vec::new(), Vec::new());
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data.get(pos + 8..pos + 8 + len).context("truncated PNG chunk")?;
+        match kind {
+            b"IHDR" => {
+                ensure!(body.len() >= 13, "bad IHDR");
+                width = u32::from_be_bytes(body[0..4].try_into()?) as usize;
+                height = u32::from_be_bytes(body[4..8].try_into()?) as usize;
+                (depth, color) = (body[8], body[9]);
+                // the bit depths the spec allows per colour type; anything else would break the sample maths below
+                let depths: &[u8] = match color { 0 => &[1, 2, 4, 8, 16], 3 => &[1, 2, 4, 8], 2 | 4 | 6 => &[8, 16], c => bail!("bad PNG colour type {c}") };
+                ensure!(depths.contains(&depth), "bad PNG bit depth {depth} for colour type {color}");
+                ensure!(body[12] == 0, "interlaced PNGs are not supported");
+            }
+            b"PLTE" => palette = body.to_vec(),
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+    ensure!(width > 0 && height > 0, "PNG without dimensions");
+    let channels = match color { 2 => 3, 4 => 2, 6 => 4, _ => 1 };
+    let bits = channels * depth as usize;
+    let stride = (width * bits).div_ceil(8);
+    let bpp = bits.div_ceil(8);
+    let raw = crate::zip::inflate(idat.get(2..).context("empty IDAT")?).context("corrupt PNG data")?;
+    ensure!(raw.len() >= (stride + 1).checked_mul(height).context("PNG too large")?, "truncated PNG data");
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    let mut prev = vec![0u8; stride];
+    let mut row = vec![0u8; stride];
+    for y in 0..height {
+        let line = &raw[y * (stride + 1)..(y + 1) * (stride + 1)];
+        row.copy_from_slice(&line[1..]);
+        for i in 0..stride {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let (b, c) = (prev[i], if i >= bpp { prev[i - bpp] } else { 0 });
+            row[i] = row[i].wrapping_add(match line[0] {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                f => bail!("bad PNG filter {f}"),
+            });
+        }
+        for x in 0..width {
+            let sample = |ch: usize| -> u8 {
+                if depth >= 8 { return row[(x * channels + ch) * (depth as usize / 8)]; }
+                let bit = (x * channels + ch) * depth as usize;
+                let v = (row[bit / 8] >> (8 - depth as usize - bit % 8)) & ((1 << depth) - 1);
+                if color == 3 { v } else { v * (255 / ((1u16 << depth) - 1) as u8) }
+            };
+            match color {
+                0 | 4 => { let l = sample(0); rgb.extend([l, l, l]); }
+                3 => {
+                    let i = sample(0) as usize * 3;
+                    rgb.extend(palette.get(i..i + 3).unwrap_or(&[0, 0, 0]));
+                }
+                _ => rgb.extend([sample(0), sample(1), sample(2)]),
+            }
+        }
+        std::mem::swap(&mut prev, &mut row);
+    }
+    Ok(Image { width: width as u32, height: height as u32, rgb })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // made by an encoder outside this crate (zlib, every filter type); the pixels follow the
+    // formulas in each test
+    const FILTERS: &[u8] = include_bytes!("testdata/rgb_filters.png");
+    const PALETTE: &[u8] = include_bytes!("testdata/palette4.png");
+    const RGBA16: &[u8] = include_bytes!("testdata/rgba16.png");
+    const GRAY1: &[u8] = include_bytes!("testdata/gray1.png");
+
+    fn px(img: &Image, x: u32, y: u32) -> [u8; 3] {
+        let i = (y * img.width + x) as usize * 3;
+        img.rgb[i..i + 3].try_into().unwrap()
+    }
+
+    #[test]
+    fn rgb_with_every_filter() {
+        // 7x5, filters none / sub / up / average / paeth on rows 0-4, IDAT split in two
+        let img = decode(FILTERS).unwrap();
+        assert_eq!((img.width, img.height), (7, 5));
+        for y in 0..5 {
+            for x in 0..7 { assert_eq!(px(&img, x, y), [(x * 37) as u8, (y * 50) as u8, (x * y * 11 % 256) as u8], "({x}, {y})"); }
+        }
+    }
+
+    #[test]
+    fn four_bit_palette() {
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0], [0, 255, 255], [17, 34, 51]];
+        let img = decode(PALETTE).unwrap();
+        assert_eq!((img.width, img.height), (5, 3));
+        for y in 0..3 {
+            for x in 0..5 { assert_eq!(px(&img, x, y), colors[((x + 2 * y) % 6) as usize], "({x}, {y})"); }
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_keeps_the_high_byte_and_drops_alpha() {
+        let img = decode(RGBA16).unwrap();
+        assert_eq!((img.width, img.height), (3, 2));
+        for y in 0..2 {
+            for x in 0..3 { assert_eq!(px(&img, x, y), [0, 1, 2].map(|ch| (x * 80 + y * 30 + ch * 20) as u8), "({x}, {y})"); }
+        }
+    }
+
+    #[test]
+    fn one_bit_gray_is_black_or_white() {
+        let img = decode(GRAY1).unwrap();
+        assert_eq!((img.width, img.height), (10, 3));
+        for y in 0..3 {
+            for x in 0..10 {
+                let l = if (x + y) % 3 == 0 { 255 } else { 0 };
+                assert_eq!(px(&img, x, y), [l, l, l], "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn bad_bit_depths_are_errors() {
+        // IHDR's bit depth is byte 24 of the file; the CRC isn't checked
+        for (file, depth) in [(FILTERS, 0), (FILTERS, 4), (PALETTE, 16), (GRAY1, 3)] {
+            let mut bad = file.to_vec();
+            bad[24] = depth;
+            assert!(decode(&bad).is_err(), "depth {depth}");
+        }
+    }
+
+    #[test]
+    fn truncated_and_garbage_input_are_errors() {
+        for file in [FILTERS, PALETTE, RGBA16, GRAY1] {
+            // everything up to the end of the image data is needed; CRCs and IEND aren't checked
+            let needed = file.len() - 16;
+            for n in 0..needed { assert!(decode(&file[..n]).is_err(), "{n} of {} bytes", file.len()); }
+        }
+        let mut x = 1u32;
+        let mut noise = || { x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345); (x >> 16) as u8 };
+        for len in [0, 1, 20, 100, 1000] {
+            let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+            data.extend((0..len).map(|_| noise()));
+            assert!(decode(&data).is_err());
+            // a valid header in front of garbage image data
+            let mut data = FILTERS[..33].to_vec();
+            data.extend((len as u32).to_be_bytes());
+            data.extend(b"IDAT");
+            data.extend((0..len).map(|_| noise()));
+            assert!(decode(&data).is_err());
+        }
+        assert!(decode(b"GIF89a").is_err());
+    }
+}
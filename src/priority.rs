@@ -0,0 +1,92 @@
+use std::sync::OnceLock;
+use tracing::*;
+
+use crate::config::Config;
+
+/// `MSD_CPU_AFFINITY`, for threads started later.
+static CORES: OnceLock<Vec<usize>> = OnceLock::new();
+
+/// `MSD_LOW_PRIORITY` / `MSD_CPU_AFFINITY`: run everything below normal priority, and remember
+/// which cores calculator workers may use.
+pub fn init(cfg: &Config) {
+    if cfg.low_priority { lower(); }
+    if !cfg.cpu_affinity.is_empty() {
+        debug!(target: "calc", "calculator workers pinned to cores {:?}", cfg.cpu_affinity);
+        CORES.set(cfg.cpu_affinity.clone()).ok();
+    }
+}
+
+/// `0,2,4-7` → `[0, 2, 4, 5, 6, 7]`.
+pub fn parse_cores(s: &str) -> Option<Vec<usize>> {
+    let mut cores = Vec::new();
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((a, b)) => {
+                let (a, b) = (a.trim().parse::<usize>().ok()?, b.trim().parse::<usize>().ok()?);
+                if a > b { return None; }
+                cores.extend(a..=b);
+            }
+            None => cores.push(part.parse().ok()?),
+        }
+    }
+    cores.sort();
+    cores.dedup();
+    (!cores.is_empty()).then_some(cores)
+}
+
+/// Keep the calculator out of osu!'s way. Threads started afterwards inherit it.
+pub fn lower() {
+    #[cfg(target_os = "linux")]
+    {
+        // the nice value is per thread here, and the runtime's are already running: lower each
+        let tids = std::fs::read_dir("/proc/self/task").into_iter().flatten().flatten()
+            .filter_map(|e| e.file_name().to_str()?.parse::<libc::id_t>().ok());
+        // SAFETY: changes the nice value of this process's own threads only
+        for tid in tids { unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, 10); } }
+    }
+    #[cfg(all(unix, not(target_os = "linux")))]
+    // SAFETY: changes this process's nice value only
+    unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 10); }
+    #[cfg(windows)]
+    {
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn GetCurrentProcess() -> *mut std::ffi::c_void;
+            fn SetPriorityClass(process: *mut std::ffi::c_void, class: u32) -> i32;
+        }
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x4000;
+        // SAFETY: the pseudo-handle needs no cleanup
+        unsafe { SetPriorityClass(GetCurrentProcess(), BELOW_NORMAL_PRIORITY_CLASS); }
+    }
+}
+
+/// Restrict the calling thread to the `MSD_CPU_AFFINITY` cores; call it first thing in a
+/// calculator worker. Does nothing without the setting, or where threads can't be pinned (macOS).
+pub fn pin_worker() {
+    let Some(cores) = CORES.get() else { return };
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: a zeroed cpu_set_t is the empty set; the call only affects this thread
+        let ok = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            for &c in cores.iter().filter(|&&c| c < libc::CPU_SETSIZE as usize) { libc::CPU_SET(c, &mut set); }
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+        };
+        if !ok { warn!(target: "calc", "MSD_CPU_AFFINITY: can't pin to {cores:?}: {}", std::io::Error::last_os_error()); }
+    }
+    #[cfg(windows)]
+    {
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn GetCurrentThread() -> *mut std::ffi::c_void;
+            fn SetThreadAffinityMask(thread: *mut std::ffi::c_void, mask: usize) -> usize;
+        }
+        let mask = cores.iter().filter(|&&c| c < usize::BITS as usize).fold(0usize, |m, &c| m | 1 << c);
+        // SAFETY: the pseudo-handle needs no cleanup; a zero return means the mask was refused
+        if unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+            warn!(target: "calc", "MSD_CPU_AFFINITY: can't pin to {cores:?}: {}", std::io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    let _ = cores;
+}
@@ -0,0 +1,93 @@
+use std::{io::{IsTerminal, Write}, time::{Duration, Instant}};
+use serde_json::json;
+use tracing::*;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode { Bar, Quiet, Json }
+
+/// Progress for the batch commands: a bar with ETA on a terminal, one JSON object per line on
+/// stdout with `--json-progress`, just the final report with `--quiet` (or when stderr isn't a
+/// terminal). Per-item failures are collected and listed in the report instead of interleaving with the bar.
+pub struct Progress {
+    what: &'static str,
+    total: usize,
+    done: usize,
+    started: Instant,
+    drawn: Option<Instant>,
+    mode: Mode,
+    errors: Vec<(String, String)>,
+}
+
+impl Progress {
+    /// `what` names the items (`files`, `maps`, `sets`).
+    pub fn new(what: &'static str, total: usize) -> Progress {
+        let args: Vec<String> = std::env::args().collect();
+        let mode = if args.iter().any(|a| a == "--json-progress") { Mode::Json }
+            else if args.iter().any(|a| a == "--quiet") || !std::io::stderr().is_terminal() { Mode::Quiet }
+            else { Mode::Bar };
+        Progress { what, total, done: 0, started: Instant::now(), drawn: None, mode, errors: Vec::new() }
+    }
+
+    /// One item finished.
+    pub fn tick(&mut self, item: &str) {
+        self.done += 1;
+        match self.mode {
+            Mode::Json => println!("{}", json!({
+                "event": "progress", "what": self.what, "done": self.done, "total": self.total,
+                "item": item, "elapsed_secs": self.started.elapsed().as_secs(), "eta_secs": self.eta().map(|d| d.as_secs()),
+            })),
+            Mode::Bar => {
+                // redrawing more often than this only costs time
+                if self.done < self.total && self.drawn.is_some_and(|t| t.elapsed() < Duration::from_millis(100)) { return; }
+                self.drawn = Some(Instant::now());
+                self.draw();
+            }
+            Mode::Quiet => {}
+        }
+    }
+
+    /// One item failed; it still counts as done.
+    pub fn fail(&mut self, item: &str, err: &anyhow::Error) {
+        self.errors.push((item.to_string(), format!("{err:#}")));
+        if self.mode == Mode::Json { println!("{}", json!({ "event": "error", "item": item, "error": format!("{err:#}") })); }
+        self.tick(item);
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        if self.done == 0 { return None; }
+        let per = self.started.elapsed().as_secs_f64() / self.done as f64;
+        Some(Duration::from_secs_f64(per * (self.total - self.done.min(self.total)) as f64))
+    }
+
+    fn draw(&self) {
+        const WIDTH: usize = 30;
+        let filled = (self.done * WIDTH).checked_div(self.total).unwrap_or(WIDTH).min(WIDTH);
+        let eta = self.eta().map_or_else(|| "--:--".into(), clock);
+        let mut err = std::io::stderr().lock();
+        let _ = write!(err, "\r[{}{}] {}/{} {} · {} elapsed · ETA {} · {} failed ",
+            "#".repeat(filled), " ".repeat(WIDTH - filled), self.done, self.total, self.what,
+            clock(self.started.elapsed()), eta, self.errors.len());
+        let _ = err.flush();
+    }
+
+    /// Final report: `counts` like `[("rated", 12), ("skipped", 3)]`, then every failure.
+    pub fn finish(self, counts: &[(&str, usize)]) {
+        if self.mode == Mode::Bar { eprintln!(); }
+        let elapsed = self.started.elapsed();
+        if self.mode == Mode::Json {
+            let counts: serde_json::Map<String, serde_json::Value> = counts.iter().map(|(k, v)| (k.to_string(), json!(v))).collect();
+            let errors: Vec<_> = self.errors.iter().map(|(i, e)| json!({ "item": i, "error": e })).collect();
+            println!("{}", json!({ "event": "done", "what": self.what, "total": self.total, "elapsed_secs": elapsed.as_secs(), "counts": counts, "errors": errors }));
+            return;
+        }
+        let counts: Vec<String> = counts.iter().map(|(k, v)| format!("{v} {k}")).collect();
+        info!(target: "output", "{} {} in {}: {}, {} failed", self.total, self.what, clock(elapsed), counts.join(", "), self.errors.len());
+        for (item, e) in &self.errors { warn!(target: "calc", "{item}: {e}"); }
+    }
+}
+
+/// `1:02:03` / `02:03`
+fn clock(d: Duration) -> String {
+    let s = d.as_secs();
+    if s >= 3600 { format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60) } else { format!("{:02}:{:02}", s / 60, s % 60) }
+}
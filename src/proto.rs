@@ -0,0 +1,460 @@
+use serde_json::Value;
+
+/// Wire shape of a field, as declared in proto/minacalc.proto.
+pub enum Kind { Str, Strs, F32, U64, I32, Bool, Msg(&'static [Field]), Msgs(&'static [Field]) }
+
+pub struct Field { pub name: &'static str, pub number: u32, pub kind: Kind }
+
+const fn f(name: &'static str, number: u32, kind: Kind) -> Field { Field { name, number, kind } }
+
+/// `message MsdResult`
+pub const MSD_RESULT: &[Field] = &[
+    f("song", 1, Kind::Str),
+    f("diff", 2, Kind::Str),
+    f("overall", 3, Kind::F32),
+    f("stamina", 4, Kind::F32),
+    f("jumpstream", 5, Kind::F32),
+    f("handstream", 6, Kind::F32),
+    f("stream", 7, Kind::F32),
+    f("chordjack", 8, Kind::F32),
+    f("jacks", 9, Kind::F32),
+    f("technical", 10, Kind::F32),
+    f("rate", 11, Kind::Str),
+    f("md5", 12, Kind::Str),
+    f("beatmap_id", 13, Kind::U64),
+    f("set_id", 14, Kind::U64),
+    f("url", 15, Kind::Str),
+    f("timing", 16, Kind::Msg(TIMING)),
+    f("speed_mod", 17, Kind::Str),
+    f("pitched", 18, Kind::Bool),
+    f("mods", 19, Kind::Strs),
+    f("notes", 20, Kind::Strs),
+    f("goal", 21, Kind::F32),
+    f("curve", 22, Kind::Msgs(CURVE_POINT)),
+    f("sections", 23, Kind::Msgs(SECTION)),
+    f("windows", 24, Kind::Msg(WINDOWS)),
+    f("calc_version", 25, Kind::I32),
+    f("overlay_version", 26, Kind::Str),
+    f("compare", 27, Kind::Msg(COMPARISON)),
+    f("previous", 28, Kind::Msg(PREVIOUS)),
+    f("delta", 29, Kind::Msg(DELTA)),
+    f("load", 30, Kind::Msg(LOAD)),
+    f("percentile", 31, Kind::Msg(PERCENTILE)),
+    f("live_rate", 32, Kind::Str),
+    f("unrated", 33, Kind::Msg(UNRATED)),
+    f("ladder", 34, Kind::Msgs(LADDER_STEP)),
+    f("tags", 35, Kind::Strs),
+    f("user_note", 36, Kind::Str),
+    f("rates", 37, Kind::Msgs(RATE_ROW)),
+];
+
+/// `message RateRow`
+pub const RATE_ROW: &[Field] = &[
+    f("rate", 1, Kind::Str),
+    f("overall", 2, Kind::F32),
+    f("stamina", 3, Kind::F32),
+    f("jumpstream", 4, Kind::F32),
+    f("handstream", 5, Kind::F32),
+    f("stream", 6, Kind::F32),
+    f("chordjack", 7, Kind::F32),
+    f("jacks", 8, Kind::F32),
+    f("technical", 9, Kind::F32),
+];
+
+/// `message Unrated`
+pub const UNRATED: &[Field] = &[
+    f("mode", 1, Kind::Str),
+    f("keys", 2, Kind::U64),
+    f("stars", 3, Kind::F32),
+];
+
+/// `message Percentile`
+pub const PERCENTILE: &[Field] = &[
+    f("charts", 1, Kind::U64),
+    f("overall", 2, Kind::F32),
+    f("stamina", 3, Kind::F32),
+    f("jumpstream", 4, Kind::F32),
+    f("handstream", 5, Kind::F32),
+    f("stream", 6, Kind::F32),
+    f("chordjack", 7, Kind::F32),
+    f("jacks", 8, Kind::F32),
+    f("technical", 9, Kind::F32),
+];
+
+/// `message Load`
+pub const LOAD: &[Field] = &[
+    f("columns", 1, Kind::Msgs(SHARE)),
+    f("left", 2, Kind::Msg(SHARE)),
+    f("right", 3, Kind::Msg(SHARE)),
+    f("peak_nps", 4, Kind::F32),
+];
+
+/// `message Share`
+pub const SHARE: &[Field] = &[
+    f("notes", 1, Kind::U64),
+    f("share", 2, Kind::F32),
+    f("peak_nps", 3, Kind::F32),
+];
+
+/// `message Delta`
+pub const DELTA: &[Field] = &[
+    f("overall", 1, Kind::F32),
+    f("stamina", 2, Kind::F32),
+    f("jumpstream", 3, Kind::F32),
+    f("handstream", 4, Kind::F32),
+    f("stream", 5, Kind::F32),
+    f("chordjack", 6, Kind::F32),
+    f("jacks", 7, Kind::F32),
+    f("technical", 8, Kind::F32),
+];
+
+/// `message Previous`
+pub const PREVIOUS: &[Field] = &[
+    f("song", 1, Kind::Str),
+    f("diff", 2, Kind::Str),
+    f("rate", 3, Kind::Str),
+    f("overall", 4, Kind::F32),
+];
+
+/// `message Comparison`
+pub const COMPARISON: &[Field] = &[
+    f("calc_version", 1, Kind::I32),
+    f("overall", 2, Kind::F32),
+    f("stamina", 3, Kind::F32),
+    f("jumpstream", 4, Kind::F32),
+    f("handstream", 5, Kind::F32),
+    f("stream", 6, Kind::F32),
+    f("chordjack", 7, Kind::F32),
+    f("jacks", 8, Kind::F32),
+    f("technical", 9, Kind::F32),
+    f("delta", 10, Kind::F32),
+];
+
+/// `message Windows`
+pub const WINDOWS: &[Field] = &[
+    f("max", 1, Kind::F32),
+    f("great", 2, Kind::F32),
+    f("good", 3, Kind::F32),
+    f("ok", 4, Kind::F32),
+    f("meh", 5, Kind::F32),
+    f("miss", 6, Kind::F32),
+];
+
+/// `message Section`
+pub const SECTION: &[Field] = &[
+    f("label", 1, Kind::Str),
+    f("start_ms", 2, Kind::I32),
+    f("end_ms", 3, Kind::I32),
+    f("notes", 4, Kind::U64),
+    f("overall", 5, Kind::F32),
+    f("skillset", 6, Kind::Str),
+];
+
+/// `message CurvePoint`
+pub const CURVE_POINT: &[Field] = &[
+    f("goal", 1, Kind::F32),
+    f("overall", 2, Kind::F32),
+];
+
+/// `message LadderStep`
+pub const LADDER_STEP: &[Field] = &[
+    f("goal", 1, Kind::F32),
+    f("overall", 2, Kind::F32),
+    f("stamina", 3, Kind::F32),
+    f("jumpstream", 4, Kind::F32),
+    f("handstream", 5, Kind::F32),
+    f("stream", 6, Kind::F32),
+    f("chordjack", 7, Kind::F32),
+    f("jacks", 8, Kind::F32),
+    f("technical", 9, Kind::F32),
+];
+
+/// `message Timing`
+pub const TIMING: &[Field] = &[
+    f("snapshot_unix_ms", 1, Kind::U64),
+    f("calc_unix_ms", 2, Kind::U64),
+    f("emitted_unix_ms", 3, Kind::U64),
+    f("fetch_ms", 4, Kind::U64),
+    f("calc_ms", 5, Kind::U64),
+    f("latency_ms", 6, Kind::U64),
+];
+
+/// `message Update`
+pub const UPDATE: &[Field] = &[
+    f("seq", 1, Kind::U64),
+    f("unix_ms", 2, Kind::U64),
+    f("result", 3, Kind::Msg(MSD_RESULT)),
+    f("source", 4, Kind::Str),
+];
+
+/// `message MapTags`
+pub const MAP_TAGS: &[Field] = &[
+    f("md5", 1, Kind::Str),
+    f("song", 2, Kind::Str),
+    f("diff", 3, Kind::Str),
+    f("tags", 4, Kind::Strs),
+    f("note", 5, Kind::Str),
+];
+
+/// `message Goals`
+pub const GOALS: &[Field] = &[
+    f("goals", 1, Kind::Msgs(GOAL_STATUS)),
+    f("just_completed", 2, Kind::Strs),
+];
+
+/// `message GoalStatus`
+pub const GOAL_STATUS: &[Field] = &[
+    f("goal", 1, Kind::Str),
+    f("completed", 2, Kind::Bool),
+    f("completed_unix_ms", 3, Kind::U64),
+    f("progress", 4, Kind::F32),
+    f("best", 5, Kind::Msg(GOAL_ATTEMPT)),
+];
+
+/// `message GoalAttempt`
+pub const GOAL_ATTEMPT: &[Field] = &[
+    f("song", 1, Kind::Str),
+    f("diff", 2, Kind::Str),
+    f("rate", 3, Kind::Str),
+    f("msd", 4, Kind::F32),
+    f("wife", 5, Kind::F32),
+    f("unix_ms", 6, Kind::U64),
+];
+
+/// `message Recent`
+pub const RECENT: &[Field] = &[
+    f("updates", 1, Kind::Msgs(UPDATE)),
+];
+
+/// `message Status`
+pub const STATUS: &[Field] = &[
+    f("version", 1, Kind::Str),
+    f("calc_version", 2, Kind::I32),
+    f("updates", 3, Kind::U64),
+    f("uptime_secs", 4, Kind::U64),
+    f("tosu_connected", 5, Kind::Bool),
+    f("last", 6, Kind::Msg(MSD_RESULT)),
+    f("unwritten", 7, Kind::U64),
+    f("write_error", 8, Kind::Str),
+];
+
+/// `message ScoreGoal`
+pub const SCORE_GOAL: &[Field] = &[
+    f("goal", 1, Kind::F32),
+];
+
+/// Encode a JSON object against a message schema. Keys the schema doesn't know are skipped and,
+/// as in proto3, default values (0, "", missing) are left off the wire.
+pub fn encode(fields: &[Field], v: &Value) -> Vec<u8> {
+    let mut out = Vec::with_capacity(128);
+    for fd in fields {
+        let Some(x) = v.get(fd.name) else { continue };
+        match fd.kind {
+            Kind::Str => {
+                let s = x.as_str().unwrap_or_default();
+                if s.is_empty() { continue; }
+                put_tag(&mut out, fd.number, 2);
+                put_varint(&mut out, s.len() as u64);
+                out.extend(s.as_bytes());
+            }
+            Kind::Strs => {
+                for s in x.as_array().into_iter().flatten().filter_map(Value::as_str) {
+                    put_tag(&mut out, fd.number, 2);
+                    put_varint(&mut out, s.len() as u64);
+                    out.extend(s.as_bytes());
+                }
+            }
+            Kind::F32 => {
+                let n = x.as_f64().unwrap_or_default() as f32;
+                if n == 0.0 { continue; }
+                put_tag(&mut out, fd.number, 5);
+                out.extend(n.to_le_bytes());
+            }
+            Kind::U64 => {
+                let n = x.as_u64().unwrap_or_default();
+                if n == 0 { continue; }
+                put_tag(&mut out, fd.number, 0);
+                put_varint(&mut out, n);
+            }
+            Kind::I32 => {
+                let n = x.as_i64().unwrap_or_default() as i32;
+                if n == 0 { continue; }
+                put_tag(&mut out, fd.number, 0);
+                put_varint(&mut out, n as i64 as u64); // negatives sign-extend to 10 bytes
+            }
+            Kind::Bool => {
+                if !x.as_bool().unwrap_or_default() { continue; }
+                put_tag(&mut out, fd.number, 0);
+                out.push(1);
+            }
+            Kind::Msg(sub) => {
+                if x.is_null() { continue; }
+                let body = encode(sub, x);
+                put_tag(&mut out, fd.number, 2);
+                put_varint(&mut out, body.len() as u64);
+                out.extend(body);
+            }
+            Kind::Msgs(sub) => {
+                for item in x.as_array().into_iter().flatten() {
+                    let body = encode(sub, item);
+                    put_tag(&mut out, fd.number, 2);
+                    put_varint(&mut out, body.len() as u64);
+                    out.extend(body);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The JSON object `encode` was given, back from the wire: fields the schema doesn't know are
+/// skipped, and defaults that were left off stay missing. `None` if it's malformed.
+pub fn to_value(fields: &[Field], buf: &[u8]) -> Option<Value> {
+    let mut out = serde_json::Map::new();
+    for (number, wire) in decode(buf)? {
+        let Some(fd) = fields.iter().find(|f| f.number == number) else { continue };
+        let v = match (&fd.kind, wire) {
+            (Kind::Str | Kind::Strs, Wire::Bytes(b)) => Value::String(std::str::from_utf8(b).ok()?.to_string()),
+            (Kind::F32, Wire::Fixed32(b)) => {
+                // the f32's shortest spelling, as output::to_value writes floats
+                let n = f32::from_le_bytes(b);
+                Value::from(n.to_string().parse::<f64>().ok()?)
+            }
+            (Kind::U64, Wire::Varint(n)) => Value::from(n),
+            (Kind::I32, Wire::Varint(n)) => Value::from(n as i64 as i32),
+            (Kind::Bool, Wire::Varint(n)) => Value::Bool(n != 0),
+            (Kind::Msg(sub) | Kind::Msgs(sub), Wire::Bytes(b)) => to_value(sub, b)?,
+            _ => return None,
+        };
+        match fd.kind {
+            Kind::Strs | Kind::Msgs(_) => match out.entry(fd.name).or_insert_with(|| Value::Array(Vec::new())) {
+                Value::Array(a) => a.push(v),
+                _ => return None,
+            },
+            _ => { out.insert(fd.name.to_string(), v); }
+        }
+    }
+    Some(Value::Object(out))
+}
+
+fn put_tag(out: &mut Vec<u8>, number: u32, wire: u8) { put_varint(out, ((number as u64) << 3) | wire as u64); }
+
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// A decoded field value; groups (wire types 3/4) are not supported.
+#[allow(dead_code)] // not every request message uses every wire type
+pub enum Wire<'a> { Varint(u64), Fixed64([u8; 8]), Bytes(&'a [u8]), Fixed32([u8; 4]) }
+
+/// Split a message into `(field number, value)` pairs; `None` if it's malformed.
+pub fn decode(mut buf: &[u8]) -> Option<Vec<(u32, Wire<'_>)>> {
+    let mut fields = Vec::new();
+    while !buf.is_empty() {
+        let tag = get_varint(&mut buf)?;
+        let number = (tag >> 3) as u32;
+        let value = match tag & 7 {
+            0 => Wire::Varint(get_varint(&mut buf)?),
+            1 => Wire::Fixed64(take(&mut buf, 8)?.try_into().ok()?),
+            2 => {
+                let len = get_varint(&mut buf)? as usize;
+                Wire::Bytes(take(&mut buf, len)?)
+            }
+            5 => Wire::Fixed32(take(&mut buf, 4)?.try_into().ok()?),
+            _ => return None,
+        };
+        fields.push((number, value));
+    }
+    Some(fields)
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if buf.len() < n { return None; }
+    let (head, rest) = buf.split_at(n);
+    *buf = rest;
+    Some(head)
+}
+
+fn get_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = buf.split_first()?;
+        *buf = rest;
+        n |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 { return Some(n); }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msgpack::tests::sample;
+
+    #[test]
+    fn encodes_a_known_result() {
+        // field order as declared
+        let want = [
+        b"\x0a\x0eArtist - Title".as_slice(), // 1 song
+        b"\x12\x06Insane", // 2 diff
+        b"\x1d\x00\x00\xbc\x41", // 3 overall
+        b"\x25\x00\x00\xaa\x41", // 4 stamina
+        b"\x2d\x00\x00\xb6\x41", // 5 jumpstream
+        b"\x35\x00\x00\xa4\x41", // 6 handstream
+        b"\x3d\x00\x00\x98\x41", // 7 stream
+        b"\x45\x00\x00\x92\x41", // 8 chordjack
+        b"\x4d\x00\x00\x8c\x41", // 9 jacks
+        b"\x55\x00\x00\xb0\x41", // 10 technical
+        b"\x5a\x041.50", // 11 rate
+        ].concat();
+        assert_eq!(encode(MSD_RESULT, &sample()), want);
+        assert_eq!(to_value(MSD_RESULT, &want), Some(sample()));
+    }
+
+    #[test]
+    fn skips_defaults_and_unknown_keys() {
+        let v = serde_json::json!({ "song": "", "overall": 0.0, "rate": "x".repeat(300), "extra": 1 });
+        // only rate is left, its length a two-byte varint
+        assert_eq!(encode(MSD_RESULT, &v), [b"\x5a\xac\x02".as_slice(), "x".repeat(300).as_bytes()].concat());
+    }
+
+    #[test]
+    fn repeated_strings() {
+        let v = serde_json::json!({ "mods": ["HD", "DT"] });
+        let want = b"\x9a\x01\x02HD\x9a\x01\x02DT"; // 19 mods, once per entry
+        assert_eq!(encode(MSD_RESULT, &v), want);
+        assert_eq!(to_value(MSD_RESULT, want), Some(v));
+    }
+
+    #[test]
+    fn varints_and_nested_messages() {
+        let v = serde_json::json!({ "seq": 300, "source": "a", "result": { "calc_version": -1, "timing": { "latency_ms": 1 } } });
+        let want = [
+            b"\x08\xac\x02".as_slice(), // 1 seq: 300 in two groups
+            b"\x1a\x11", // 3 result
+            b"\x82\x01\x02\x30\x01", // 16 timing { 6 latency_ms }
+            b"\xc8\x01\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01", // 25 calc_version, sign-extended
+            b"\x22\x01a", // 4 source
+        ].concat();
+        assert_eq!(encode(UPDATE, &v), want);
+        assert_eq!(to_value(UPDATE, &want), Some(v));
+    }
+
+    #[test]
+    fn rejects_malformed_buffers() {
+        let good = encode(MSD_RESULT, &sample());
+        // a cut only decodes where a field ends: 11 fields, so 12 of the prefixes
+        let whole = (0..=good.len()).filter(|&n| decode(&good[..n]).is_some()).count();
+        assert_eq!(whole, decode(&good).unwrap().len() + 1);
+        assert_eq!(whole, 12);
+        assert!(decode(b"\x0a\x05ab").is_none());
+        assert!(decode(b"\x0b").is_none(), "group start");
+        assert!(decode(&[0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_none(), "varint over 64 bits");
+        // a float where the schema says string
+        assert!(to_value(MSD_RESULT, b"\x0d\x00\x00\x00\x00").is_none());
+    }
+}
@@ -0,0 +1,125 @@
+//! Where the game's state comes from: tosu's `/json/v2`, tosu's older `/json`, or gosumemory's
+//! `/json` (the shape tosu's v1 copied). Everything past here sees tosu v2's shape.
+use std::time::Duration;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// tosu and gosumemory both listen here by default.
+pub const ADDR: &str = "http://127.0.0.1:24050";
+
+/// `MSD_PROVIDER`: which one to poll; unset or `auto` probes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source { TosuV2, TosuV1, Gosumemory }
+
+impl Source {
+    pub fn from_name(s: &str) -> Option<Source> {
+        match s.to_ascii_lowercase().as_str() {
+            "tosu" | "tosu-v2" => Some(Source::TosuV2),
+            "tosu-v1" => Some(Source::TosuV1),
+            "gosumemory" | "gosu" => Some(Source::Gosumemory),
+            _ => None,
+        }
+    }
+
+    pub fn provider(self) -> &'static dyn Provider {
+        match self {
+            Source::TosuV2 => &TosuV2,
+            Source::TosuV1 => &V1 { tosu: true },
+            Source::Gosumemory => &V1 { tosu: false },
+        }
+    }
+}
+
+/// One poll's state: the JSON in tosu v2's shape, and where the current map's files are.
+pub struct Snapshot { pub json: String, pub osu: String, pub background: String }
+
+/// The game's current beatmap, rate (through the mods) and metadata, from one program.
+pub trait Provider: Send + Sync {
+    /// For the log: `tosu v2`, `tosu v1`, `gosumemory`.
+    fn name(&self) -> &'static str;
+    /// Path of the JSON polled every `MSD_POLL_MS`.
+    fn json_path(&self) -> &'static str;
+    /// The polled JSON in tosu v2's shape, plus the URLs of the map's `.osu` and background.
+    fn read(&self, raw: String) -> anyhow::Result<Snapshot>;
+}
+
+struct TosuV2;
+
+impl Provider for TosuV2 {
+    fn name(&self) -> &'static str { "tosu v2" }
+    fn json_path(&self) -> &'static str { "/json/v2" }
+    fn read(&self, raw: String) -> anyhow::Result<Snapshot> {
+        Ok(Snapshot { json: raw, osu: format!("{ADDR}/files/beatmap/file"), background: format!("{ADDR}/files/beatmap/background") })
+    }
+}
+
+/// gosumemory's `/json`, which tosu also serves. tosu has the map's files at `/files/beatmap`;
+/// gosumemory only serves the Songs folder, so they're looked up by the map's path there.
+struct V1 { tosu: bool }
+
+#[derive(Deserialize)]
+struct JsonV1 { menu: MenuV1, #[serde(default)] gameplay: GameplayV1 }
+#[derive(Deserialize)]
+struct MenuV1 { state: Option<u32>, bm: BeatmapV1, mods: Option<ModsV1> }
+#[derive(Deserialize)]
+struct BeatmapV1 { id: Option<u64>, set: Option<u64>, metadata: MetaV1, #[serde(default)] stats: StatsV1, #[serde(default)] path: PathV1 }
+#[derive(Deserialize)]
+struct MetaV1 { artist: Option<String>, title: Option<String>, difficulty: Option<String> }
+#[derive(Deserialize, Default)]
+struct StatsV1 {
+    /// star rating with the current mods
+    #[serde(rename = "fullSR")] full_sr: Option<f32>,
+}
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PathV1 { folder: String, file: String, bg: String }
+/// `"HDDT"`, or `"NM"`.
+#[derive(Deserialize)]
+struct ModsV1 { str: Option<String> }
+#[derive(Deserialize, Default)]
+struct GameplayV1 { hits: Option<Value> }
+
+impl Provider for V1 {
+    fn name(&self) -> &'static str { if self.tosu { "tosu v1" } else { "gosumemory" } }
+    fn json_path(&self) -> &'static str { "/json" }
+    fn read(&self, raw: String) -> anyhow::Result<Snapshot> {
+        let v1: JsonV1 = serde_json::from_str(&raw)?;
+        let (bm, path) = (&v1.menu.bm, &v1.menu.bm.path);
+        // same numbers as v2's (osu!'s own): 2 playing, 5 song select, ...
+        let v2 = json!({
+            "beatmap": { "artist": bm.metadata.artist, "title": bm.metadata.title, "version": bm.metadata.difficulty, "id": bm.id, "set": bm.set,
+                "stats": { "stars": { "total": bm.stats.full_sr } } },
+            "play": { "mods": { "name": v1.menu.mods.as_ref().and_then(|m| m.str.as_deref()) }, "hits": v1.gameplay.hits },
+            "state": { "number": v1.menu.state },
+        });
+        let (osu, background) = if self.tosu {
+            (format!("{ADDR}/files/beatmap/file"), format!("{ADDR}/files/beatmap/background"))
+        } else {
+            (songs_url(&path.folder, &path.file)?, songs_url(&path.folder, &path.bg)?)
+        };
+        Ok(Snapshot { json: v2.to_string(), osu, background })
+    }
+}
+
+/// `Songs/<folder>/<file>` on gosumemory's server, each part escaped.
+fn songs_url(folder: &str, file: &str) -> anyhow::Result<String> {
+    let mut url = url::Url::parse(ADDR)?;
+    url.path_segments_mut().map_err(|()| anyhow::anyhow!("{ADDR} can't have a path"))?.extend(["Songs", folder, file]);
+    Ok(url.into())
+}
+
+/// Which provider is listening: tosu v2 if `/json/v2` answers, else whatever serves `/json`,
+/// tosu if it also has `/files/beatmap/file`. None while nothing's up.
+pub async fn probe(http: &Client, timeout: Duration) -> Option<Source> {
+    let ok = |path: &'static str| async move {
+        http.get(format!("{ADDR}{path}")).timeout(timeout).send().await.ok().map(|r| r.status())
+    };
+    if ok("/json/v2").await.is_some_and(|s| s.is_success()) { return Some(Source::TosuV2); }
+    if !ok("/json").await.is_some_and(|s| s.is_success()) { return None; }
+    // no map loaded yet still isn't a 404 on tosu
+    match ok("/files/beatmap/file").await {
+        Some(StatusCode::NOT_FOUND) | None => Some(Source::Gosumemory),
+        Some(_) => Some(Source::TosuV1),
+    }
+}
@@ -0,0 +1,416 @@
+//! QR code encoder (ISO/IEC 18004): byte mode, error correction level M, versions 1–10
+//! (up to 213 bytes, plenty for a beatmap link).
+use std::path::Path;
+use tracing::*;
+
+/// Per version at level M: EC codewords per block, and (block count, data codewords) groups.
+const BLOCKS: [(usize, [(usize, usize); 2]); 10] = [
+    (10, [(1, 16), (0, 0)]),
+    (16, [(1, 28), (0, 0)]),
+    (26, [(1, 44), (0, 0)]),
+    (18, [(2, 32), (0, 0)]),
+    (24, [(2, 43), (0, 0)]),
+    (16, [(4, 27), (0, 0)]),
+    (18, [(4, 31), (0, 0)]),
+    (22, [(2, 38), (2, 39)]),
+    (22, [(3, 36), (2, 37)]),
+    (26, [(4, 43), (1, 44)]),
+];
+
+/// A square of modules; `true` is dark.
+pub struct Qr { pub size: usize, modules: Vec<bool> }
+
+impl Qr {
+    pub fn get(&self, x: usize, y: usize) -> bool { self.modules[y * self.size + x] }
+
+    /// Smallest version that fits `data`; `None` if it's over 213 bytes.
+    pub fn encode(data: &[u8]) -> Option<Qr> {
+        let version = (1..=10).find(|&v| data.len() <= capacity(v))?;
+        let codewords = codewords(data, version);
+        let mut best: Option<(u32, Qr)> = None;
+        for mask in 0..8 {
+            let mut q = Matrix::new(version);
+            q.draw_function_patterns();
+            q.draw_codewords(&codewords);
+            q.apply_mask(mask);
+            q.draw_format(mask);
+            let p = q.penalty();
+            if best.as_ref().is_none_or(|(b, _)| p < *b) { best = Some((p, Qr { size: q.size, modules: q.dark })); }
+        }
+        best.map(|(_, q)| q)
+    }
+}
+
+impl Qr {
+    /// Black on white, `scale` pixels per module, with the 4-module quiet zone.
+    pub fn to_png(&self, scale: u32) -> Vec<u8> {
+        let scale = scale.max(1) as usize;
+        let px = (self.size + 8) * scale;
+        let mut pixels = vec![0xffu8; px * px * 3];
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if !self.get(x, y) { continue; }
+                for dy in 0..scale {
+                    let row = ((y + 4) * scale + dy) * px;
+                    let from = (row + (x + 4) * scale) * 3;
+                    pixels[from..from + scale * 3].fill(0);
+                }
+            }
+        }
+        crate::png::rgb(px as u32, px as u32, &pixels)
+    }
+}
+
+/// Keeps `qr.png` in the overlay folder pointing at the current map's link.
+#[derive(Default)]
+pub struct Sink { last: Option<String> }
+
+impl Sink {
+    pub async fn update(&mut self, overlay_dir: &Path, url: Option<&str>, scale: u32) {
+        if self.last.as_deref() == url && url.is_some() { return; }
+        let path = overlay_dir.join("qr.png");
+        match url.and_then(|u| Qr::encode(u.as_bytes())) {
+            Some(q) => if let Err(e) = tokio::fs::write(&path, q.to_png(scale)).await { warn!(target: "output", %e, "failed to write qr.png"); },
+            // no link for this map: don't leave the previous map's code up
+            None => { tokio::fs::remove_file(&path).await.ok(); }
+        }
+        self.last = url.map(str::to_string);
+    }
+}
+
+fn data_codewords(v: usize) -> usize { BLOCKS[v - 1].1.iter().map(|(n, d)| n * d).sum() }
+
+/// Byte-mode capacity: 4 mode bits and an 8-bit (16 from version 10) length come first.
+fn capacity(v: usize) -> usize { (data_codewords(v) * 8 - 4 - count_bits(v)) / 8 }
+
+fn count_bits(v: usize) -> usize { if v < 10 { 8 } else { 16 } }
+
+/// Data + error correction codewords, interleaved in transmission order.
+fn codewords(data: &[u8], v: usize) -> Vec<u8> {
+    let mut bits = Bits::default();
+    bits.put(0b0100, 4);
+    bits.put(data.len() as u32, count_bits(v));
+    for &b in data { bits.put(b as u32, 8); }
+    let cap = data_codewords(v) * 8;
+    bits.put(0, (cap - bits.len).min(4));
+    bits.put(0, (8 - bits.len % 8) % 8);
+    let mut dat = bits.bytes;
+    for pad in [0xEC, 0x11].into_iter().cycle() {
+        if dat.len() >= cap / 8 { break; }
+        dat.push(pad);
+    }
+
+    let (ec_len, groups) = BLOCKS[v - 1];
+    let gen = generator(ec_len);
+    let mut blocks: Vec<(&[u8], Vec<u8>)> = Vec::new();
+    let mut at = 0;
+    for &(count, len) in &groups {
+        for _ in 0..count {
+            let block = &dat[at..at + len];
+            blocks.push((block, remainder(block, &gen)));
+            at += len;
+        }
+    }
+    let mut out = Vec::new();
+    let longest = blocks.iter().map(|(d, _)| d.len()).max().unwrap_or(0);
+    for i in 0..longest { for (d, _) in &blocks { if let Some(&b) = d.get(i) { out.push(b); } } }
+    for i in 0..ec_len { for (_, e) in &blocks { out.push(e[i]); } }
+    out
+}
+
+#[derive(Default)]
+struct Bits { bytes: Vec<u8>, len: usize }
+
+impl Bits {
+    fn put(&mut self, value: u32, n: usize) {
+        for i in (0..n).rev() {
+            if self.len.is_multiple_of(8) { self.bytes.push(0); }
+            if let Some(last) = self.bytes.last_mut().filter(|_| (value >> i) & 1 != 0) { *last |= 0x80 >> (self.len % 8); }
+            self.len += 1;
+        }
+    }
+}
+
+// GF(256) with the QR polynomial x^8 + x^4 + x^3 + x^2 + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut r = 0u8;
+    while b != 0 {
+        if b & 1 != 0 { r ^= a; }
+        a = if a & 0x80 != 0 { (a << 1) ^ 0x1D } else { a << 1 };
+        b >>= 1;
+    }
+    r
+}
+
+/// Coefficients of (x - α^0)(x - α^1)...(x - α^(n-1)), leading 1 dropped.
+fn generator(n: usize) -> Vec<u8> {
+    let mut g = vec![0u8; n];
+    g[n - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..n {
+        for j in 0..n {
+            g[j] = gf_mul(g[j], root);
+            if j + 1 < n { g[j] ^= g[j + 1]; }
+        }
+        root = gf_mul(root, 2);
+    }
+    g
+}
+
+fn remainder(data: &[u8], gen: &[u8]) -> Vec<u8> {
+    let mut r = vec![0u8; gen.len()];
+    for &b in data {
+        let factor = b ^ r[0];
+        r.remove(0);
+        r.push(0);
+        for (x, &g) in r.iter_mut().zip(gen) { *x ^= gf_mul(g, factor); }
+    }
+    r
+}
+
+struct Matrix { size: usize, version: usize, dark: Vec<bool>, function: Vec<bool> }
+
+impl Matrix {
+    fn new(version: usize) -> Matrix {
+        let size = 17 + 4 * version;
+        Matrix { size, version, dark: vec![false; size * size], function: vec![false; size * size] }
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        let i = y * self.size + x;
+        self.dark[i] = dark;
+        self.function[i] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let s = self.size;
+        for i in 0..s {
+            self.set(6, i, i % 2 == 0);
+            self.set(i, 6, i % 2 == 0);
+        }
+        for (cx, cy) in [(3, 3), (s - 4, 3), (3, s - 4)] { self.finder(cx, cy); }
+        let pos = alignment_positions(self.version);
+        for &ax in &pos {
+            for &ay in &pos {
+                let corner = (ax == 6 && (ay == 6 || ay == s - 7)) || (ax == s - 7 && ay == 6);
+                if corner { continue; }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let d = dx.abs().max(dy.abs());
+                        self.set((ax as i32 + dx) as usize, (ay as i32 + dy) as usize, d != 1);
+                    }
+                }
+            }
+        }
+        self.draw_format(0); // reserve; the real mask is drawn last
+        if self.version >= 7 {
+            let mut rem = self.version as u32;
+            for _ in 0..12 { rem = (rem << 1) ^ ((rem >> 11) * 0x1F25); }
+            let bits = (self.version as u32) << 12 | rem;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let (a, b) = (s - 11 + i % 3, i / 3);
+                self.set(a, b, dark);
+                self.set(b, a, dark);
+            }
+        }
+    }
+
+    /// Finder pattern centred on (cx, cy), with its white separator.
+    fn finder(&mut self, cx: usize, cy: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                if x < 0 || y < 0 || x >= self.size as i32 || y >= self.size as i32 { continue; }
+                let d = dx.abs().max(dy.abs());
+                self.set(x as usize, y as usize, d != 2 && d != 4);
+            }
+        }
+    }
+
+    /// Format bits (level M = 00, then the mask), both copies, plus the dark module.
+    fn draw_format(&mut self, mask: u32) {
+        let data = mask; // (00 << 3) | mask
+        let mut rem = data;
+        for _ in 0..10 { rem = (rem << 1) ^ ((rem >> 9) * 0x537); }
+        let bits = ((data << 10) | rem) ^ 0x5412;
+        let bit = |i: u32| (bits >> i) & 1 != 0;
+        let s = self.size;
+        for i in 0..6 { self.set(8, i, bit(i as u32)); }
+        self.set(8, 7, bit(6));
+        self.set(8, 8, bit(7));
+        self.set(7, 8, bit(8));
+        for i in 9..15 { self.set(14 - i, 8, bit(i as u32)); }
+        for i in 0..8 { self.set(s - 1 - i, 8, bit(i as u32)); }
+        for i in 8..15 { self.set(8, s - 15 + i, bit(i as u32)); }
+        self.set(8, s - 8, true);
+    }
+
+    /// Zigzag up and down two-column strips from the right, skipping the timing column.
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let s = self.size;
+        let mut i = 0;
+        let mut right = s as i32 - 1;
+        while right >= 1 {
+            if right == 6 { right = 5; }
+            for vert in 0..s {
+                for j in 0..2 {
+                    let x = (right - j) as usize;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { s - 1 - vert } else { vert };
+                    if !self.function[y * s + x] && i < data.len() * 8 {
+                        self.dark[y * s + x] = (data[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        let s = self.size;
+        for y in 0..s {
+            for x in 0..s {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if flip && !self.function[y * s + x] { self.dark[y * s + x] ^= true; }
+            }
+        }
+    }
+
+    /// The four penalty rules of the spec; lower is easier to scan.
+    fn penalty(&self) -> u32 {
+        let s = self.size;
+        let at = |x: usize, y: usize| self.dark[y * s + x];
+        let mut p = 0;
+        for horizontal in [true, false] {
+            for a in 0..s {
+                let line: Vec<bool> = (0..s).map(|b| if horizontal { at(b, a) } else { at(a, b) }).collect();
+                let mut run = 1;
+                for b in 1..=s {
+                    if b < s && line[b] == line[b - 1] { run += 1; continue; }
+                    if run >= 5 { p += run - 2; }
+                    run = 1;
+                }
+                // 1:1:3:1:1 finder look-alikes with four light modules on either side
+                for b in 0..s.saturating_sub(10) {
+                    let w = &line[b..b + 11];
+                    let core = [true, false, true, true, true, false, true];
+                    if w[4..] == core && w[..4].iter().all(|d| !d) { p += 40; }
+                    if w[..7] == core && w[7..].iter().all(|d| !d) { p += 40; }
+                }
+            }
+        }
+        for y in 0..s - 1 {
+            for x in 0..s - 1 {
+                let c = at(x, y);
+                if c == at(x + 1, y) && c == at(x, y + 1) && c == at(x + 1, y + 1) { p += 3; }
+            }
+        }
+        let dark = self.dark.iter().filter(|d| **d).count();
+        let percent = dark * 100 / (s * s);
+        p += percent.abs_diff(50) / 5 * 10;
+        p as u32
+    }
+}
+
+fn alignment_positions(v: usize) -> Vec<usize> {
+    match v {
+        1 => vec![],
+        2..=6 => vec![6, 4 * v + 10],
+        7 => vec![6, 22, 38],
+        8 => vec![6, 24, 42],
+        9 => vec![6, 26, 46],
+        _ => vec![6, 28, 50],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // checked with a decoder outside this crate: version 2, EC level M, mask 0
+    const OSU_LINK: [&str; 25] = [
+        "#######..#######..#######",
+        "#.....#.#.####.##.#.....#",
+        "#.###.#..#.##.###.#.###.#",
+        "#.###.#..##....##.#.###.#",
+        "#.###.#.##.#####..#.###.#",
+        "#.....#..###..##..#.....#",
+        "#######.#.#.#.#.#.#######",
+        ".........#...##.#........",
+        "#.#.#.#..##.##.##...#..#.",
+        "##.##..#.#.###....#.....#",
+        ".##...#..###.##...##..###",
+        "..#.##.#..#..#.#..#.#..#.",
+        ".##.####.#.#####.##..#.##",
+        ".##....##..##...#.#..#..#",
+        "#..#.##.#.####..####..###",
+        ".#..##..##..####....#..#.",
+        "#..##.###..#.##.######...",
+        "........####.##.#...##.##",
+        "#######..#...#.##.#.##.##",
+        "#.....#....#.##.#...##..#",
+        "#.###.#.#...###.######.##",
+        "#.###.#...######.#.####..",
+        "#.###.#.#####...#...#...#",
+        "#.....#...#.###.#.#.##.#.",
+        "#######.##.##.###..#...##",
+    ];
+
+    fn rows(qr: &Qr) -> Vec<String> {
+        (0..qr.size).map(|y| (0..qr.size).map(|x| if qr.get(x, y) { '#' } else { '.' }).collect()).collect()
+    }
+
+    /// The 15 format bits beside the top-left finder, unmasked: (EC level, mask).
+    fn format(qr: &Qr) -> (u16, u16) {
+        let cells = (0..6).map(|i| (8, i)).chain([(8, 7), (8, 8), (7, 8)]).chain((9..15).map(|i| (14 - i, 8)));
+        let bits = cells.enumerate().fold(0u16, |f, (i, (x, y))| f | (qr.get(x, y) as u16) << i) ^ 0x5412;
+        (bits >> 13, bits >> 10 & 7)
+    }
+
+    #[test]
+    fn error_correction_matches_the_spec_example() {
+        // "HELLO WORLD" at 1-M, from ISO/IEC 18004's worked example
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        assert_eq!(remainder(&data, &generator(10)), [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+    }
+
+    #[test]
+    fn encodes_a_known_matrix() {
+        let qr = Qr::encode(b"https://osu.ppy.sh/b/75").unwrap();
+        assert_eq!(qr.size, 25);
+        assert_eq!(format(&qr), (0, 0));
+        assert_eq!(rows(&qr), OSU_LINK);
+    }
+
+    #[test]
+    fn picks_the_smallest_version() {
+        assert_eq!(Qr::encode(&[b'a'; 14]).unwrap().size, 21);
+        assert_eq!(Qr::encode(&[b'a'; 15]).unwrap().size, 25);
+        assert!(Qr::encode(&[b'a'; 4096]).is_none());
+    }
+
+    #[test]
+    fn png_decodes_back_to_the_modules() {
+        let qr = Qr::encode(b"https://osu.ppy.sh/b/75").unwrap();
+        let img = crate::png::decode(&qr.to_png(3)).unwrap();
+        assert_eq!((img.width, img.height), (99, 99));
+        for y in 0..img.height as usize {
+            for x in 0..img.width as usize {
+                let dark = (x / 3).checked_sub(4).zip((y / 3).checked_sub(4)).is_some_and(|(mx, my)| mx < qr.size && my < qr.size && qr.get(mx, my));
+                let i = (y * img.width as usize + x) * 3;
+                assert_eq!(img.rgb[i..i + 3], if dark { [0; 3] } else { [255; 3] }, "pixel {x},{y}");
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::{path::Path, sync::Arc};
+use tracing::*;
+
+use crate::{hub::Hub, output};
+
+/// A `recalc` file in the overlay folder (`touch recalc`, or an empty file from a stream deck)
+/// forces one recalculation; it's removed once seen.
+pub fn file_requested(static_root: &Path) -> bool {
+    let path = output::overlay_dir(static_root).join("recalc");
+    if !path.exists() { return false; }
+    if let Err(e) = std::fs::remove_file(&path) { warn!(target: "output", %e, "couldn't remove the recalc file"); }
+    true
+}
+
+/// `Ctrl+Shift+R`, `F8`: Windows virtual-key codes for the modifiers and the key.
+pub fn parse_hotkey(spec: &str) -> Option<(Vec<i32>, i32)> {
+    let mut parts: Vec<String> = spec.split('+').map(|p| p.trim().to_ascii_uppercase()).collect();
+    let key = parts.pop()?;
+    let mods = parts.iter().map(|m| match m.as_str() {
+        "CTRL" | "CONTROL" => Some(0x11),
+        "SHIFT" => Some(0x10),
+        "ALT" => Some(0x12),
+        _ => None,
+    }).collect::<Option<Vec<i32>>>()?;
+    let key = match key.as_bytes() {
+        [c] if c.is_ascii_alphanumeric() => *c as i32,
+        // VK_F1 = 0x70
+        _ => key.strip_prefix('F')?.parse::<i32>().ok().filter(|n| (1..=24).contains(n))? + 0x6f,
+    };
+    Some((mods, key))
+}
+
+/// `MSD_RECALC_HOTKEY`: watch for the combination system-wide (osu! keeps focus) and request a
+/// recalc on each press.
+pub fn spawn_hotkey(spec: &str, hub: Arc<Hub>) {
+    let Some((mods, key)) = parse_hotkey(spec) else {
+        warn!(target: "tosu", "MSD_RECALC_HOTKEY {spec:?} not understood (e.g. F8, Ctrl+Shift+R)");
+        return;
+    };
+    #[cfg(windows)]
+    {
+        #[link(name = "user32")]
+        extern "system" { fn GetAsyncKeyState(key: i32) -> i16; }
+        info!(target: "tosu", "recalc hotkey: {spec}");
+        std::thread::spawn(move || {
+            // SAFETY: reads global key state, no pointers involved
+            let down = |vk: i32| unsafe { GetAsyncKeyState(vk) } < 0;
+            let mut was_down = false;
+            loop {
+                let now_down = down(key) && mods.iter().all(|&m| down(m));
+                if now_down && !was_down { hub.request_recalc(); }
+                was_down = now_down;
+                std::thread::sleep(std::time::Duration::from_millis(30));
+            }
+        });
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (mods, key, hub);
+        warn!(target: "tosu", "MSD_RECALC_HOTKEY only works on Windows; use the recalc file or gRPC Recalc");
+    }
+}
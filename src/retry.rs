@@ -0,0 +1,59 @@
+use std::{future::Future, time::Duration};
+use tracing::*;
+
+/// How often and how patiently a request is retried: `attempts` in total, waiting `base`,
+/// then twice that, and so on, each wait shifted by up to ±`jitter` of itself so that many
+/// clients don't retry in lockstep.
+#[derive(Clone, Copy, Debug)]
+pub struct Policy { pub attempts: u32, pub base: Duration, pub jitter: f32 }
+
+impl Policy {
+    /// tosu on localhost: a couple of quick tries, since the next poll is never far off.
+    pub const POLL: Policy = Policy { attempts: 2, base: Duration::from_millis(50), jitter: 0.2 };
+    /// Mirrors and osu.ppy.sh in batch commands: worth waiting for rather than failing the item.
+    pub const BULK: Policy = Policy { attempts: 4, base: Duration::from_secs(1), jitter: 0.5 };
+
+    /// `attempts[,base ms[,jitter]]`, e.g. `3,200,0.3`; left-out parts keep `default`'s.
+    pub fn parse(s: &str, default: Policy) -> Option<Policy> {
+        let mut parts = s.split(',').map(str::trim);
+        let attempts = parts.next()?.parse::<u32>().ok().filter(|&n| n > 0)?;
+        let base = match parts.next() { Some(ms) => Duration::from_millis(ms.parse().ok()?), None => default.base };
+        let jitter = match parts.next() { Some(j) => j.parse::<f32>().ok().filter(|j| (0.0..=1.0).contains(j))?, None => default.jitter };
+        if parts.next().is_some() { return None; }
+        Some(Policy { attempts, base, jitter })
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        let mut b = [0u8; 4];
+        getrandom::getrandom(&mut b).ok();
+        let r = u32::from_le_bytes(b) as f32 / u32::MAX as f32 * 2.0 - 1.0;
+        self.base.saturating_mul(1 << retry.min(16)).mul_f32((1.0 + r * self.jitter).max(0.0))
+    }
+
+    /// Run `f` until it succeeds, fails with something retrying won't fix, or runs out of attempts.
+    pub async fn run<T, F, Fut>(&self, what: &str, mut f: F) -> anyhow::Result<T>
+    where F: FnMut() -> Fut, Fut: Future<Output = anyhow::Result<T>> {
+        let mut retry = 0;
+        loop {
+            match f().await {
+                Ok(v) => return Ok(v),
+                Err(e) if retry + 1 < self.attempts && transient(&e) => {
+                    let wait = self.delay(retry);
+                    debug!(target: "http", "{what}: {e:#}; retrying in {}ms", wait.as_millis());
+                    tokio::time::sleep(wait).await;
+                    retry += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Timeouts, refused connections, dropped bodies, 5xx and 429; not 404s or bad data.
+fn transient(e: &anyhow::Error) -> bool {
+    let Some(e) = e.chain().find_map(|c| c.downcast_ref::<reqwest::Error>()) else { return false };
+    match e.status() {
+        Some(s) => s.is_server_error() || s == reqwest::StatusCode::TOO_MANY_REQUESTS,
+        None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+    }
+}
@@ -0,0 +1,102 @@
+//! Song and difficulty names as they go into outputs and chat. Beatmap metadata is whatever the
+//! uploader typed: control characters, right-to-left overrides and kilobyte-long titles all
+//! exist, and without this they'd end up in `np.txt`, chat messages and overlay layouts as is.
+use crate::config::Config;
+
+/// `s` with `MSD_META_*` applied: control and invisible formatting characters removed (line
+/// breaks and tabs become spaces), whitespace runs collapsed, optionally folded to ASCII, and
+/// cut to `MSD_META_MAX_LEN` characters with `…`.
+pub fn meta(s: &str, cfg: &Config) -> String {
+    clean(s, cfg.meta_strip_control, cfg.meta_max_len, cfg.meta_ascii)
+}
+
+fn clean(s: &str, strip_control: bool, max_len: usize, ascii: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if strip_control && c.is_whitespace() { out.push(' '); continue; }
+        if strip_control && hidden(c) { continue; }
+        if ascii && !c.is_ascii() { fold(c, &mut out); continue; }
+        out.push(c);
+    }
+    let mut out = if strip_control { out.split_whitespace().collect::<Vec<_>>().join(" ") } else { out };
+    if max_len > 0 && out.chars().count() > max_len {
+        out = out.chars().take(max_len.saturating_sub(1)).collect::<String>().trim_end().to_string();
+        out.push(if ascii { '~' } else { '…' });
+    }
+    out
+}
+
+/// Controls (C0, C1), bidi overrides and isolates, zero-width characters and BOMs.
+fn hidden(c: char) -> bool {
+    c.is_control() || matches!(c, '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2060}'..='\u{2069}' | '\u{061c}' | '\u{feff}' | '\u{fff9}'..='\u{fffb}')
+}
+
+/// The closest ASCII for `c`: marks dropped (`é` → `e`), ligatures spelled out, fullwidth forms
+/// narrowed, typographic punctuation made plain; `?` for the rest (kana, kanji, ...).
+fn fold(c: char, out: &mut String) {
+    let s = match c {
+        'ß' => "ss", 'æ' => "ae", 'Æ' => "AE", 'œ' => "oe", 'Œ' => "OE", 'ø' => "o", 'Ø' => "O",
+        'ł' => "l", 'Ł' => "L", 'đ' => "d", 'Đ' => "D", 'ð' => "d", 'Ð' => "D", 'þ' => "th", 'Þ' => "Th", 'ı' => "i",
+        '‘' | '’' | '‚' | '′' => "'", '“' | '”' | '„' | '″' => "\"", '‐'..='―' | '−' => "-", '…' => "...",
+        '★' | '☆' | '＊' => "*", '♪' | '♫' => "~", '×' => "x", '\u{3000}' => " ",
+        _ => {
+            // fullwidth ASCII (common in Japanese metadata)
+            if let Some(n) = ('\u{ff01}'..='\u{ff5e}').contains(&c).then(|| char::from_u32(c as u32 - 0xfee0)).flatten() { out.push(n); return; }
+            match crate::nfc::base(c).filter(char::is_ascii) {
+                Some(b) => out.push(b),
+                // one `?` for a run of them
+                None => if !out.ends_with('?') { out.push('?') },
+            }
+            return;
+        }
+    };
+    out.push_str(s);
+}
+
+/// `s` usable as a file name on Windows, macOS and Linux: reserved characters and controls
+/// become `_`, and trailing dots and spaces (which Windows drops) go.
+pub fn file_safe(s: &str) -> String {
+    let out: String = s.chars().map(|c| if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') { '_' } else { c }).collect();
+    let out = out.trim_end_matches(['.', ' ']).trim_start();
+    // CON, NUL, COM1 ... can't be file names on Windows, whatever the extension
+    let stem = out.split('.').next().unwrap_or("").to_ascii_uppercase();
+    let reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || (stem.len() == 4 && (stem.starts_with("COM") || stem.starts_with("LPT")) && stem.as_bytes()[3].is_ascii_digit());
+    if out.is_empty() { "_".into() } else if reserved { format!("_{out}") } else { out.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_hidden_characters() {
+        assert_eq!(clean("Song\u{202e}Name\u{200b}", true, 200, false), "SongName");
+        assert_eq!(clean("  two\nlines\t here ", true, 200, false), "two lines here");
+        assert_eq!(clean("a\u{0}b", false, 200, false), "a\u{0}b");
+    }
+
+    #[test]
+    fn cuts_long_names() {
+        assert_eq!(clean("abcdefghij", true, 5, false), "abcd…");
+        assert_eq!(clean("abc defghij", true, 5, true), "abc~");
+        assert_eq!(clean("abcdefghij", true, 0, false), "abcdefghij");
+    }
+
+    #[test]
+    fn folds_to_ascii() {
+        assert_eq!(clean("Café “Ænima” ★", true, 200, true), "Cafe \"AEnima\" *");
+        assert_eq!(clean("ＦＵＬＬ", true, 200, true), "FULL");
+        assert_eq!(clean("夜に駆ける - YOASOBI", true, 200, true), "? - YOASOBI");
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_safe("a/b: c?"), "a_b_ c_");
+        assert_eq!(file_safe("name. . "), "name");
+        assert_eq!(file_safe("con.txt"), "_con.txt");
+        assert_eq!(file_safe("COM1"), "_COM1");
+        assert_eq!(file_safe("COMET"), "COMET");
+        assert_eq!(file_safe(" ..."), "_");
+    }
+}
@@ -0,0 +1,160 @@
+use std::{path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, mpsc}};
+use anyhow::Context;
+use minacalc_rs::Calc;
+use serde::Serialize;
+use tracing::*;
+
+use crate::{calc::{self, MsdOut}, config::{arg_value, Config}, library::Ratings, output::{self, Format}, paths, priority, progress::Progress, sheet::{Cell, Table}, warm};
+
+const USAGE: &str = "usage: minacalc-overlay scan <Songs folder> [--out <index.csv | .json | .xlsx>] [--rate 1.0] [--workers N]";
+const SKILLSETS: [&str; 8] = ["Overall", "Stamina", "Jumpstream", "Handstream", "Stream", "Chordjack", "Jacks", "Technical"];
+
+/// The JSON (YAML, XML) form of the index.
+#[derive(Serialize)]
+struct Index { folder: String, rate: String, goal: f32, calc_version: i32, charts: Vec<Row> }
+
+/// One mania chart; other key counts than 4 have no ratings.
+#[derive(Serialize)]
+struct Row {
+    song: String,
+    diff: String,
+    keys: u32,
+    md5: String,
+    beatmap_id: Option<u64>,
+    set_id: Option<u64>,
+    path: String,
+    #[serde(flatten)]
+    msd: Option<Ratings>,
+}
+
+enum Outcome { Row(Box<Row>), NotMania, Failed(anyhow::Error) }
+
+/// `minacalc-overlay scan <Songs folder> [--out <index.csv | .json | .xlsx>] [--rate 1.0] [--workers N]`:
+/// every osu!mania chart under the folder with its key count and, for 4K, every skillset at
+/// `--rate` and `MSD_SCORE_GOAL`, as one table to sort in a spreadsheet. CSV on stdout without
+/// `--out`. Unlike `warm-cache` it rates every file again and leaves the library alone.
+pub fn run(cfg: &Config) -> anyhow::Result<()> {
+    let root = PathBuf::from(std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(USAGE)?);
+    anyhow::ensure!(root.is_dir(), "{} is not a folder", root.display());
+    let root = paths::plain(&root);
+    let rate: f32 = arg_value("--rate").map(|r| r.trim_end_matches('x').parse().ok().filter(|r| *r > 0.0 && *r <= 3.0).context("--rate must be a number between 0 and 3, like 1.1")).transpose()?.unwrap_or(1.0);
+    let workers = arg_value("--workers").map(|n| n.parse::<usize>().context("--workers must be a number")).transpose()?.unwrap_or(cfg.workers).max(1);
+    let out_path = arg_value("--out").map(PathBuf::from);
+    priority::lower();
+
+    let files = warm::osu_files(&root);
+    debug!(target: "calc", "{} .osu files under {}, {workers} workers", files.len(), root.display());
+    let mut progress = Progress::new("files", files.len());
+    let mut rows: Vec<Option<Row>> = std::iter::repeat_with(|| None).take(files.len()).collect();
+    let (mut rated, mut other_keys, mut skipped) = (0, 0, 0);
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..workers.min(files.len()) {
+            let (tx, files, next) = (tx.clone(), &files, &next);
+            scope.spawn(move || {
+                priority::pin_worker();
+                // Calc isn't Send: one per worker
+                let calc = match Calc::new() {
+                    Ok(c) => c,
+                    Err(e) => { let _ = tx.send((usize::MAX, Outcome::Failed(e.into()))); return; }
+                };
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = files.get(i) else { break };
+                    if tx.send((i, process(&calc, path, rate, cfg))).is_err() { break; }
+                }
+            });
+        }
+        drop(tx);
+        for (i, outcome) in rx {
+            let Some(path) = files.get(i) else {
+                if let Outcome::Failed(e) = outcome { warn!(target: "calc", "worker failed to start: {e:#}"); }
+                continue;
+            };
+            let item = path.display().to_string();
+            match outcome {
+                Outcome::Row(row) => {
+                    if row.msd.is_some() { rated += 1; } else { other_keys += 1; }
+                    rows[i] = Some(*row);
+                    progress.tick(&item);
+                }
+                Outcome::NotMania => { skipped += 1; progress.tick(&item); }
+                Outcome::Failed(e) => progress.fail(&item, &e),
+            }
+        }
+    });
+    let charts: Vec<Row> = rows.into_iter().flatten().collect();
+
+    let ext = out_path.as_deref().and_then(Path::extension).and_then(|e| e.to_str()).unwrap_or("csv").to_ascii_lowercase();
+    let name = root.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let bytes = match ext.as_str() {
+        "csv" => table(&charts).to_csv().into_bytes(),
+        "xlsx" => table(&charts).to_xlsx(&name),
+        e => {
+            let index = Index { folder: root.display().to_string(), rate: calc::rate_label(rate), goal: cfg.score_goal, calc_version: Calc::version(), charts };
+            output::encode(&index, Format::from_name(e).unwrap_or(Format::Json), cfg)?
+        }
+    };
+    progress.finish(&[("rated", rated), ("other key counts", other_keys), ("not mania", skipped)]);
+    match out_path {
+        Some(p) => {
+            std::fs::write(&p, bytes).with_context(|| format!("writing {}", p.display()))?;
+            info!(target: "output", "index written to {}", p.display());
+        }
+        None => { use std::io::Write; std::io::stdout().write_all(&bytes)?; }
+    }
+    Ok(())
+}
+
+fn process(calc: &Calc, path: &Path, rate: f32, cfg: &Config) -> Outcome {
+    let bytes = match std::fs::read(path) {
+        Ok(b) => b,
+        Err(e) => return Outcome::Failed(e.into()),
+    };
+    let (text, _) = calc::decode(&bytes);
+    // cheap check before the full parse: most of a Songs folder isn't mania
+    if text.lines().find_map(|l| l.strip_prefix("Mode")).map(|v| v.trim_start_matches(':').trim()) != Some("3") { return Outcome::NotMania; }
+    let (map, msd, keys) = match calc::notes(&text) {
+        Ok((map, notes)) => match calc::ssr(calc, &notes, rate, cfg.score_goal) {
+            Ok(scores) => (map, Some((&MsdOut::new(String::new(), String::new(), &scores, calc::rate_label(rate))).into()), 4),
+            Err(e) => return Outcome::Failed(e),
+        },
+        Err(e) => match e.downcast::<calc::Unrated>() {
+            Ok(calc::Unrated { keys: Some(k), .. }) => match rosu_map::from_str::<rosu_map::Beatmap>(&text) {
+                Ok(map) => (map, None, k),
+                Err(e) => return Outcome::Failed(anyhow::anyhow!("parse failed: {e}")),
+            },
+            Ok(_) => return Outcome::NotMania,
+            Err(e) => return Outcome::Failed(e),
+        },
+    };
+    let (beatmap_id, set_id) = calc::map_ids(&map);
+    Outcome::Row(Box::new(Row {
+        song: crate::sanitize::meta(&calc::song_label(&map.artist, &map.title), cfg),
+        diff: crate::sanitize::meta(&map.version, cfg),
+        keys,
+        md5: crate::md5::hex(&bytes),
+        beatmap_id,
+        set_id,
+        path: path.display().to_string(),
+        msd,
+    }))
+}
+
+fn table(charts: &[Row]) -> Table {
+    let mut header: Vec<String> = ["Song", "Difficulty", "Keys"].map(String::from).to_vec();
+    header.extend(SKILLSETS.map(String::from));
+    header.extend(["MD5", "Beatmap ID", "Path"].map(String::from));
+    let rows = charts.iter().map(|c| {
+        let mut row = vec![Cell::Text(c.song.clone()), Cell::Text(c.diff.clone()), Cell::Int(c.keys.into())];
+        match &c.msd {
+            Some(r) => row.extend([r.overall, r.stamina, r.jumpstream, r.handstream, r.stream, r.chordjack, r.jacks, r.technical]
+                .map(|v| Cell::Num((v as f64 * 100.0).round() / 100.0))),
+            None => row.extend((0..SKILLSETS.len()).map(|_| Cell::Empty)),
+        }
+        row.extend([Cell::Text(c.md5.clone()), c.beatmap_id.map_or(Cell::Empty, Cell::Int), Cell::Text(c.path.clone())]);
+        row
+    }).collect();
+    Table { header, rows }
+}
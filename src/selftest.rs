@@ -0,0 +1,45 @@
+use anyhow::{bail, Context};
+use minacalc_rs::{Calc, SkillsetScores};
+use tracing::*;
+
+use crate::calc;
+
+/// A made-up 4K chart (a roll, jumps, then chordjacks) that gives every skillset a rating.
+const CHART: &str = include_str!("selftest.osu");
+/// The MinaCalc version the expected scores are from.
+const VERSION: i32 = 505;
+/// `(rate, [overall, stream, jumpstream, handstream, stamina, jackspeed, chordjack, technical])` at 93%.
+const EXPECTED: [(f32, [f32; 8]); 2] = [
+    (1.0, [22.64, 13.79, 16.31, 9.86, 13.48, 22.64, 17.57, 20.42]),
+    (1.5, [32.43, 16.28, 22.89, 14.30, 19.30, 32.43, 25.70, 25.85]),
+];
+/// Float noise between compilers and CPUs stays far below this; a real change doesn't.
+const TOLERANCE: f32 = 0.05;
+
+/// `MSD_SELF_TEST`: rate the bundled chart and compare against the known scores before doing
+/// anything else, so a broken or swapped calc library stops the sidecar with a clear message
+/// instead of putting wrong numbers on stream.
+pub fn run() -> anyhow::Result<()> {
+    let hint = "the MinaCalc library this was built with isn't working as expected; rebuild, or set MSD_SELF_TEST=off to run anyway";
+    if Calc::version() != VERSION {
+        bail!("self-test: MinaCalc version {} but the reference scores are from {VERSION}; {hint}", Calc::version());
+    }
+    let (_, notes) = calc::notes(CHART).context("self-test: parsing the reference chart")?;
+    for (rate, want) in EXPECTED {
+        // the shared calculator: dropping one of its own would tear down MinaCalc's global
+        // state under the poll loop's
+        let got = calc::pooled(|c| calc::ssr(c, &notes, rate, calc::SCORE_GOAL)).with_context(|| format!("self-test at {rate}x: {hint}"))?;
+        let got = values(&got);
+        if let Some(i) = (0..8).find(|&i| (got[i] - want[i]).abs() > TOLERANCE || !got[i].is_finite()) {
+            bail!("self-test at {rate}x: {} is {:.2}, expected {:.2} (all: {got:.2?}); {hint}", NAMES[i], got[i], want[i]);
+        }
+    }
+    debug!(target: "calc", "self-test passed");
+    Ok(())
+}
+
+const NAMES: [&str; 8] = ["overall", "stream", "jumpstream", "handstream", "stamina", "jackspeed", "chordjack", "technical"];
+
+fn values(s: &SkillsetScores) -> [f32; 8] {
+    [s.overall, s.stream, s.jumpstream, s.handstream, s.stamina, s.jackspeed, s.chordjack, s.technical]
+}
@@ -0,0 +1,156 @@
+//! A small static file server for the overlay folder, for `MSD_SERVE` and for when tosu can't
+//! serve it, with every result pushed on `/events` as it's calculated.
+use std::{net::SocketAddr, path::{Component, Path, PathBuf}, sync::Arc, time::Duration};
+use tokio::{io::AsyncWriteExt, net::{TcpListener, TcpStream}, sync::broadcast::error::RecvError};
+use tracing::*;
+
+use crate::{hub::{Hub, Update}, ws::{self, Message, Opcode}};
+
+/// Comment lines sent to idle `/events` streams, so proxies and OBS don't drop them.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Serve `root` read-only over plain HTTP/1.1 on `addr`, one request per connection, and
+/// `hub`'s results on `/events`.
+pub async fn run(addr: SocketAddr, root: PathBuf, hub: Arc<Hub>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(target: "serve", "serving {} on http://{addr}/", root.display());
+    loop {
+        let (mut sock, peer) = match listener.accept().await {
+            Ok(x) => x,
+            Err(e) => { warn!(target: "serve", %e, "accept failed"); continue; }
+        };
+        let (root, hub) = (root.clone(), hub.clone());
+        tokio::spawn(async move {
+            if let Err(e) = handle(&mut sock, &root, &hub).await { debug!(target: "serve", %e, %peer, "request failed"); }
+        });
+    }
+}
+
+async fn handle(sock: &mut TcpStream, root: &Path, hub: &Hub) -> anyhow::Result<()> {
+    let head = ws::read_head(sock).await?;
+    let mut hdrs = [httparse::EMPTY_HEADER; 32];
+    let mut req = httparse::Request::new(&mut hdrs);
+    req.parse(&head)?;
+    let (method, path) = (req.method.unwrap_or(""), req.path.unwrap_or("/"));
+    if method == "GET" && path.split('?').next() == Some("/events") {
+        let key = req.headers.iter().find(|h| h.name.eq_ignore_ascii_case("sec-websocket-key")).map(|h| String::from_utf8_lossy(h.value).trim().to_string());
+        sock.set_nodelay(true).ok();
+        return match key {
+            Some(key) => { ws::accept(sock, &key).await?; websocket(sock, hub).await }
+            None => events(sock, hub).await,
+        };
+    }
+    let (status, kind, body) = match (method, file_path(root, path)) {
+        ("GET" | "HEAD", Some(p)) => match tokio::fs::read(&p).await {
+            Ok(b) => ("200 OK", content_type(&p), b),
+            Err(_) => ("404 Not Found", "text/plain", b"not found".to_vec()),
+        },
+        ("GET" | "HEAD", None) => ("404 Not Found", "text/plain", b"not found".to_vec()),
+        _ => ("405 Method Not Allowed", "text/plain", b"GET only".to_vec()),
+    };
+    // overlays poll msd.json; never let the browser source cache it
+    let head = format!("HTTP/1.1 {status}\r\nContent-Type: {kind}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n", body.len());
+    sock.write_all(head.as_bytes()).await?;
+    if method != "HEAD" { sock.write_all(&body).await?; }
+    sock.shutdown().await.ok();
+    Ok(())
+}
+
+/// The result as JSON, with the fields of `msd.json`.
+fn payload(u: &Update) -> String { u.result.to_string() }
+
+/// Server-Sent Events: the latest result, then each new one as an `msd` event with the
+/// update's number as its ID.
+async fn events(sock: &mut TcpStream, hub: &Hub) -> anyhow::Result<()> {
+    let (latest, mut rx) = hub.subscribe();
+    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nAccess-Control-Allow-Origin: *\r\nConnection: keep-alive\r\n\r\n").await?;
+    let event = |u: &Update| format!("event: msd\nid: {}\ndata: {}\n\n", u.seq, payload(u));
+    if let Some(u) = latest { sock.write_all(event(&u).as_bytes()).await?; }
+    let mut keepalive = tokio::time::interval(KEEPALIVE);
+    keepalive.tick().await;
+    loop {
+        let chunk = tokio::select! {
+            u = rx.recv() => match u {
+                Ok(u) => event(&u),
+                Err(RecvError::Lagged(n)) => { debug!(target: "serve", n, "event stream lagged"); continue; }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = keepalive.tick() => ": keepalive\n\n".to_string(),
+        };
+        sock.write_all(chunk.as_bytes()).await?;
+    }
+}
+
+/// The same over a WebSocket, one text message per result.
+async fn websocket(sock: &mut TcpStream, hub: &Hub) -> anyhow::Result<()> {
+    let (latest, mut rx) = hub.subscribe();
+    let (rd, mut wr) = sock.split();
+    // buffered, so a result going out mid-frame doesn't lose what's been read of it
+    let mut rd = ws::Reader::new(rd);
+    if let Some(u) = latest { ws::write_frame(&mut wr, Opcode::Text, payload(&u).as_bytes(), false).await?; }
+    loop {
+        tokio::select! {
+            u = rx.recv() => match u {
+                Ok(u) => ws::write_frame(&mut wr, Opcode::Text, payload(&u).as_bytes(), false).await?,
+                Err(RecvError::Lagged(n)) => debug!(target: "serve", n, "event socket lagged"),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            msg = rd.next() => match msg? {
+                Message::Ping(p) => ws::write_frame(&mut wr, Opcode::Pong, &p, false).await?,
+                Message::Close => { ws::write_frame(&mut wr, Opcode::Close, &[], false).await.ok(); return Ok(()); }
+                _ => {}
+            },
+        }
+    }
+}
+
+/// The file under `root` a request path names (`/` and folders mean their `index.html`), or
+/// `None` for anything that would step outside it.
+fn file_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let decoded = percent_decode(path)?;
+    let mut out = root.to_path_buf();
+    for c in Path::new(decoded.trim_start_matches('/')).components() {
+        match c {
+            Component::Normal(p) => out.push(p),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if out.is_dir() { out.push("index.html"); }
+    Some(out)
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' {
+            let hex = std::str::from_utf8(b.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(b[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn content_type(p: &Path) -> &'static str {
+    match p.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("yaml" | "yml") => "application/yaml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("svg") => "image/svg+xml",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,103 @@
+//! Tabular exports: CSV and a minimal single-sheet XLSX (bold frozen header row).
+use std::fmt::Write as _;
+
+/// `Num` is shown with two decimals, `Int` as is.
+pub enum Cell { Text(String), Num(f64), Int(u64), Empty }
+
+pub struct Table { pub header: Vec<String>, pub rows: Vec<Vec<Cell>> }
+
+impl Table {
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        let line = |out: &mut String, cells: &mut dyn Iterator<Item = String>| {
+            let row: Vec<String> = cells.map(|c| csv_field(&c)).collect();
+            out.push_str(&row.join(","));
+            out.push_str("\r\n");
+        };
+        line(&mut out, &mut self.header.iter().cloned());
+        for r in &self.rows {
+            line(&mut out, &mut r.iter().map(|c| match c { Cell::Text(s) => s.clone(), Cell::Num(n) => format!("{n:.2}"), Cell::Int(n) => n.to_string(), Cell::Empty => String::new() }));
+        }
+        out
+    }
+
+    pub fn to_xlsx(&self, sheet_name: &str) -> Vec<u8> {
+        let mut zip = crate::zip::Writer::default();
+        zip.add("[Content_Types].xml", CONTENT_TYPES.as_bytes());
+        zip.add("_rels/.rels", ROOT_RELS.as_bytes());
+        zip.add("xl/workbook.xml", format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><workbook xmlns="{NS}" xmlns:r="{NS_R}"><sheets><sheet name="{}" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+            xml_escape(&sheet_name.chars().filter(|c| !"[]:*?/\\".contains(*c)).take(31).collect::<String>())
+        ).as_bytes());
+        zip.add("xl/_rels/workbook.xml.rels", WORKBOOK_RELS.as_bytes());
+        zip.add("xl/styles.xml", STYLES.as_bytes());
+        zip.add("xl/worksheets/sheet1.xml", self.sheet_xml().as_bytes());
+        zip.finish()
+    }
+
+    fn sheet_xml(&self) -> String {
+        let mut x = format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><worksheet xmlns="{NS}">"#);
+        x.push_str(r#"<sheetViews><sheetView workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews>"#);
+        // text columns sized to their longest value, number columns just fit the header
+        x.push_str("<cols>");
+        for (i, h) in self.header.iter().enumerate() {
+            let longest = self.rows.iter().filter_map(|r| match r.get(i) { Some(Cell::Text(s)) => Some(s.chars().count()), _ => None }).max().unwrap_or(0);
+            let width = longest.max(h.chars().count()).clamp(6, 60) + 2;
+            let _ = write!(x, r#"<col min="{n}" max="{n}" width="{width}" customWidth="1"/>"#, n = i + 1);
+        }
+        x.push_str("</cols><sheetData>");
+        let row = |x: &mut String, r: usize, cells: &mut dyn Iterator<Item = (&Cell, u8)>| {
+            let _ = write!(x, r#"<row r="{r}">"#);
+            for (i, (c, style)) in cells.enumerate() {
+                let at = format!("{}{r}", column(i));
+                match c {
+                    Cell::Text(s) => { let _ = write!(x, r#"<c r="{at}" s="{style}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#, xml_escape(s)); }
+                    Cell::Num(n) => { let _ = write!(x, r#"<c r="{at}" s="2"><v>{n}</v></c>"#); }
+                    Cell::Int(n) => { let _ = write!(x, r#"<c r="{at}"><v>{n}</v></c>"#); }
+                    Cell::Empty => {}
+                }
+            }
+            x.push_str("</row>");
+        };
+        let header: Vec<Cell> = self.header.iter().map(|h| Cell::Text(h.clone())).collect();
+        row(&mut x, 1, &mut header.iter().map(|c| (c, 1)));
+        for (n, r) in self.rows.iter().enumerate() { row(&mut x, n + 2, &mut r.iter().map(|c| (c, 0))); }
+        x.push_str("</sheetData></worksheet>");
+        x
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.chars().filter(|c| !c.is_control() || matches!(c, '\t' | '\n')).fold(String::with_capacity(s.len()), |mut o, c| {
+        match c { '&' => o.push_str("&amp;"), '<' => o.push_str("&lt;"), '>' => o.push_str("&gt;"), '"' => o.push_str("&quot;"), c => o.push(c) }
+        o
+    })
+}
+
+/// 0 → A, 25 → Z, 26 → AA.
+fn column(mut i: usize) -> String {
+    let mut s = Vec::new();
+    loop {
+        s.push(b'A' + (i % 26) as u8);
+        if i < 26 { break; }
+        i = i / 26 - 1;
+    }
+    s.reverse();
+    String::from_utf8(s).unwrap_or_default()
+}
+
+const NS: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const NS_R: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/></Types>"#;
+
+const ROOT_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#;
+
+/// Cell styles: 0 plain, 1 bold (header), 2 two decimals (`0.00`).
+const STYLES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts><fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills><borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders><cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs><cellXfs count="3"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/><xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/><xf numFmtId="2" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/></cellXfs></styleSheet>"#;
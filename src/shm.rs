@@ -0,0 +1,114 @@
+//! `MSD_SHARED_MEMORY`: the latest update in a named shared-memory region, like gosumemory's,
+//! for native overlay plugins that want it without touching a file.
+//!
+//! Layout (little endian), `SIZE` bytes:
+//!
+//! | offset | type | |
+//! |---|---|---|
+//! | 0 | `[u8; 4]` | `MSDM` |
+//! | 4 | `u32` | layout version, `1` |
+//! | 8 | `u32` | write counter: odd while an update is being written |
+//! | 12 | `u32` | payload length |
+//! | 16 | bytes | the update (`MSD_SHARED_MEMORY_FORMAT`) |
+//!
+//! Readers read the counter, skip if it's odd, copy the payload, and read the counter again;
+//! if it changed, a write came in between and they read again.
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::*;
+
+use crate::{config::Config, hub::Hub, output};
+
+/// Room for the header and a payload; results with every section and comparison fit well within it.
+const SIZE: usize = 1 << 20;
+const HEADER: usize = 16;
+#[cfg(windows)]
+const VERSION: u32 = 1;
+
+/// Map the region `name` and rewrite it on every update until the hub closes.
+pub async fn run(name: String, hub: Arc<Hub>, cfg: Arc<Config>) -> anyhow::Result<()> {
+    let mut region = Region::open(&name)?;
+    info!(target: "output", "writing updates to shared memory {name:?} ({:?}, {} KiB)", cfg.shared_memory_format, SIZE / 1024);
+    let (latest, mut rx) = hub.subscribe();
+    if let Some(u) = latest { write(&mut region, &u, &cfg); }
+    loop {
+        match rx.recv().await {
+            Ok(u) => write(&mut region, &u, &cfg),
+            Err(RecvError::Lagged(n)) => debug!(target: "output", n, "shared memory lagged"),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+fn write(region: &mut Region, u: &crate::hub::Update, cfg: &Config) {
+    match output::encode_update(u, cfg.shared_memory_format, cfg) {
+        Ok(bytes) if bytes.len() > SIZE - HEADER => warn!(target: "output", code = "output.shared_memory", "update is {} bytes, more than the shared memory holds; skipped", bytes.len()),
+        Ok(bytes) => region.put(&bytes),
+        Err(e) => warn!(target: "output", code = "output.shared_memory", %e, "encode failed"),
+    }
+}
+
+#[cfg(windows)]
+struct Region { view: *mut u8, counter: u32 }
+
+// the view is only touched from the task that owns the region
+#[cfg(windows)]
+unsafe impl Send for Region {}
+
+#[cfg(windows)]
+impl Region {
+    fn open(name: &str) -> anyhow::Result<Region> {
+        use std::ffi::c_void;
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn CreateFileMappingW(file: *mut c_void, attrs: *mut c_void, protect: u32, size_high: u32, size_low: u32, name: *const u16) -> *mut c_void;
+            fn MapViewOfFile(mapping: *mut c_void, access: u32, offset_high: u32, offset_low: u32, bytes: usize) -> *mut c_void;
+        }
+        const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+        const PAGE_READWRITE: u32 = 0x04;
+        const FILE_MAP_ALL_ACCESS: u32 = 0xF001F;
+        let wide: Vec<u16> = name.encode_utf16().chain([0]).collect();
+        // SAFETY: a pagefile-backed mapping of SIZE bytes and a view of all of it; the handle
+        // stays open (and the region alive) for as long as the sidecar runs
+        let view = unsafe {
+            let mapping = CreateFileMappingW(INVALID_HANDLE_VALUE, std::ptr::null_mut(), PAGE_READWRITE, 0, SIZE as u32, wide.as_ptr());
+            if mapping.is_null() { anyhow::bail!("creating shared memory {name:?}: {}", std::io::Error::last_os_error()); }
+            let view = MapViewOfFile(mapping, FILE_MAP_ALL_ACCESS, 0, 0, SIZE) as *mut u8;
+            if view.is_null() { anyhow::bail!("mapping shared memory {name:?}: {}", std::io::Error::last_os_error()); }
+            view
+        };
+        let mut region = Region { view, counter: 0 };
+        region.put(&[]);
+        Ok(region)
+    }
+
+    fn put(&mut self, payload: &[u8]) {
+        use std::sync::atomic::{fence, AtomicU32, Ordering};
+        // SAFETY: `view` is SIZE bytes, the header is 4-aligned (views are page-aligned) and
+        // `write` keeps payloads within SIZE - HEADER
+        unsafe {
+            let counter = &*(self.view.add(8) as *const AtomicU32);
+            self.counter = self.counter.wrapping_add(1);
+            counter.store(self.counter, Ordering::Relaxed);
+            fence(Ordering::Release);
+            std::ptr::copy_nonoverlapping(b"MSDM".as_ptr(), self.view, 4);
+            (self.view.add(4) as *mut u32).write_volatile(VERSION.to_le());
+            (self.view.add(12) as *mut u32).write_volatile((payload.len() as u32).to_le());
+            std::ptr::copy_nonoverlapping(payload.as_ptr(), self.view.add(HEADER), payload.len());
+            self.counter = self.counter.wrapping_add(1);
+            counter.store(self.counter, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(not(windows))]
+struct Region;
+
+#[cfg(not(windows))]
+impl Region {
+    fn open(_: &str) -> anyhow::Result<Region> {
+        anyhow::bail!("MSD_SHARED_MEMORY: named shared memory is only available on Windows")
+    }
+
+    fn put(&mut self, _: &[u8]) {}
+}
@@ -0,0 +1,314 @@
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use minacalc_rs::{Calc, Note, SkillsetScores};
+use serde::Serialize;
+use tracing::*;
+
+use crate::{calc, config::{arg_value, Config}, hub::unix_ms, library::Ratings, output::{self, Format}, priority, progress::Progress};
+
+const USAGE: &str = "usage: minacalc-overlay scan-packs <pack folder | folder of packs> [--rates 1.0,1.1] [--out <dir>]";
+/// Etterna's rows per beat; chart keys are taken per row.
+const ROWS_PER_BEAT: f64 = 48.0;
+
+/// One `dance-single` chart of a simfile.
+struct Chart {
+    difficulty: String,
+    meter: Option<u32>,
+    /// Non-empty rows by beat, each with Etterna's tap-note type per column.
+    rows: Vec<(f64, [u8; 4])>,
+    timing: Timing,
+}
+
+/// `#OFFSET`, `#BPMS` and `#STOPS`, from the song or (`.ssc` split timing) the chart.
+#[derive(Clone, Default)]
+struct Timing {
+    offset: f64,
+    bpms: Vec<(f64, f64)>,
+    stops: Vec<(f64, f64)>,
+}
+
+impl Timing {
+    /// Seconds from the start of the music to `beat`.
+    fn time(&self, beat: f64) -> f64 {
+        let mut t = 0.0;
+        let (mut from, mut bpm) = (0.0, self.bpms.first().map_or(120.0, |b| b.1));
+        for &(b, next) in self.bpms.iter().skip(1) {
+            if b >= beat { break; }
+            t += (b - from) * 60.0 / bpm;
+            (from, bpm) = (b, next);
+        }
+        t += (beat - from) * 60.0 / bpm;
+        // a stop on a row comes after the row's notes
+        t += self.stops.iter().filter(|s| s.0 < beat).map(|s| s.1).sum::<f64>();
+        t - self.offset
+    }
+
+    fn bpm_at(&self, beat: f64) -> f64 {
+        self.bpms.iter().rev().find(|b| b.0 <= beat).or(self.bpms.first()).map_or(0.0, |b| b.1)
+    }
+}
+
+struct Simfile {
+    title: String,
+    artist: String,
+    charts: Vec<Chart>,
+}
+
+/// `#KEY:value;` pairs in file order, keys uppercased, comments gone.
+fn tags(text: &str) -> Vec<(String, String)> {
+    let text: String = text.lines().map(|l| l.split("//").next().unwrap_or("")).collect::<Vec<_>>().join("\n");
+    let mut out = Vec::new();
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find('#') {
+        rest = &rest[start + 1..];
+        let Some(colon) = rest.find(':') else { break };
+        let key = rest[..colon].trim().to_ascii_uppercase();
+        rest = &rest[colon + 1..];
+        let end = rest.find(';').unwrap_or(rest.len());
+        out.push((key, rest[..end].trim().to_string()));
+        rest = &rest[end.min(rest.len())..];
+    }
+    out
+}
+
+/// `0.000=180.000,64.000=90.000` → `[(0, 180), (64, 90)]`, by beat.
+fn pairs(s: &str) -> Vec<(f64, f64)> {
+    let mut v: Vec<(f64, f64)> = s.split(',').filter_map(|p| {
+        let (a, b) = p.split_once('=')?;
+        Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+    }).collect();
+    v.sort_by(|a, b| a.0.total_cmp(&b.0));
+    v
+}
+
+/// The rows of a `#NOTES` block: measures split by `,`, each row a beat fraction of its measure.
+fn rows(notes: &str) -> anyhow::Result<Vec<(f64, [u8; 4])>> {
+    let mut out = Vec::new();
+    for (m, measure) in notes.split(',').enumerate() {
+        let lines: Vec<&str> = measure.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+        for (i, line) in lines.iter().enumerate() {
+            anyhow::ensure!(line.len() == 4, "measure {}: a row of {} columns in a 4-key chart", m + 1, line.len());
+            let mut types = [0u8; 4];
+            for (c, ch) in line.chars().enumerate() {
+                // Etterna's TapNoteType: tap, hold/roll head, mine, lift, fake; tails aren't notes of their own
+                types[c] = match ch { '1' => 1, '2' | '4' => 2, 'M' => 4, 'L' => 5, 'F' => 8, _ => 0 };
+            }
+            if types != [0; 4] { out.push((4.0 * (m as f64 + i as f64 / lines.len() as f64), types)); }
+        }
+    }
+    Ok(out)
+}
+
+/// A `.ssc` chart being read: its tags follow its `#NOTEDATA`.
+#[derive(Default)]
+struct Pending {
+    stepstype: String,
+    difficulty: String,
+    meter: Option<u32>,
+    /// Split timing; starts from the song's where the chart sets any.
+    timing: Option<Timing>,
+}
+
+/// An `.sm` or `.ssc`; charts other than `dance-single` are left out.
+fn parse(text: &str) -> anyhow::Result<Simfile> {
+    let (mut title, mut artist, mut song_timing) = (String::new(), String::new(), Timing::default());
+    let mut charts: Vec<(Chart, bool)> = Vec::new();
+    let mut ssc: Option<Pending> = None;
+    for (key, value) in tags(text) {
+        match (key.as_str(), &mut ssc) {
+            ("TITLE", None) => title = value,
+            ("ARTIST", None) => artist = value,
+            ("OFFSET" | "BPMS" | "STOPS" | "FREEZES", c) => {
+                let t = match c { Some(c) => c.timing.get_or_insert_with(|| song_timing.clone()), None => &mut song_timing };
+                match key.as_str() {
+                    "OFFSET" => t.offset = value.parse().unwrap_or(0.0),
+                    "BPMS" => t.bpms = pairs(&value),
+                    _ => t.stops = pairs(&value),
+                }
+            }
+            ("NOTEDATA", _) => ssc = Some(Pending::default()),
+            ("STEPSTYPE", Some(c)) => c.stepstype = value.to_ascii_lowercase(),
+            ("DIFFICULTY", Some(c)) => c.difficulty = value,
+            ("METER", Some(c)) => c.meter = value.parse().ok(),
+            ("NOTES" | "NOTES2", _) => {
+                let (c, data) = match ssc.take() {
+                    Some(c) => (c, value),
+                    // .sm: type:description:difficulty:meter:radar:notes
+                    None => {
+                        let f: Vec<&str> = value.splitn(6, ':').map(str::trim).collect();
+                        anyhow::ensure!(f.len() == 6, "a #NOTES block without its six fields");
+                        (Pending { stepstype: f[0].to_ascii_lowercase(), difficulty: f[2].to_string(), meter: f[3].parse().ok(), timing: None }, f[5].to_string())
+                    }
+                };
+                if c.stepstype != "dance-single" { continue; }
+                let rows = rows(&data).with_context(|| c.difficulty.clone())?;
+                let own = c.timing.is_some();
+                charts.push((Chart { difficulty: c.difficulty, meter: c.meter, rows, timing: c.timing.unwrap_or_default() }, own));
+            }
+            _ => {}
+        }
+    }
+    // the song's timing can come after an .sm's charts, so it's filled in once it's all read
+    let charts = charts.into_iter().map(|(mut c, own)| { if !own { c.timing = song_timing.clone(); } c }).collect();
+    Ok(Simfile { title, artist, charts })
+}
+
+/// Etterna's chart key: `X` plus the SHA-1 of every non-empty row's tap-note types and the
+/// whole BPM there, as its `GenerateChartKey` does.
+fn chartkey(c: &Chart) -> String {
+    let mut k = String::new();
+    for (beat, types) in &c.rows {
+        let row_beat = (beat * ROWS_PER_BEAT).round() / ROWS_PER_BEAT;
+        for t in types { k.push_str(&t.to_string()); }
+        k.push_str(&(c.timing.bpm_at(row_beat) as i32).to_string());
+    }
+    format!("X{}", sha1_smol::Sha1::from(k.as_bytes()).hexdigest())
+}
+
+/// MinaCalc's rows: the tapped columns (taps and hold heads) at each time.
+fn notes(c: &Chart) -> Vec<Note> {
+    let mut out: Vec<Note> = Vec::new();
+    for (beat, types) in &c.rows {
+        let mask = types.iter().enumerate().filter(|(_, t)| matches!(t, 1 | 2)).fold(0u32, |m, (i, _)| m | 1 << i);
+        if mask == 0 { continue; }
+        let row_time = c.timing.time(*beat) as f32;
+        match out.last_mut() {
+            Some(last) if (last.row_time - row_time).abs() < 0.0005 => last.notes |= mask,
+            _ => out.push(Note { notes: mask, row_time }),
+        }
+    }
+    out
+}
+
+#[derive(Serialize)]
+struct ManifestChart {
+    difficulty: String,
+    meter: Option<u32>,
+    chartkey: String,
+    notes: usize,
+    /// First to last note at 1.0x.
+    length_secs: f32,
+    dominant: &'static str,
+    msd: Vec<Ratings>,
+}
+
+#[derive(Serialize)]
+struct ManifestSong {
+    folder: String,
+    title: String,
+    artist: String,
+    charts: Vec<ManifestChart>,
+}
+
+/// `manifest.json` of one pack.
+#[derive(Serialize)]
+struct Manifest {
+    pack: String,
+    generated_unix_ms: u64,
+    calc_version: i32,
+    overlay_version: &'static str,
+    /// Wife% the MSD is for.
+    goal: f32,
+    songs: Vec<ManifestSong>,
+}
+
+/// The simfile in a song folder; `.ssc` wins over `.sm`, as in StepMania.
+fn simfile_in(dir: &Path) -> Option<PathBuf> {
+    let files: Vec<PathBuf> = std::fs::read_dir(dir).ok()?.flatten().map(|e| e.path()).filter(|p| p.is_file()).collect();
+    let with = |ext: &str| files.iter().find(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case(ext))).cloned();
+    with("ssc").or_else(|| with("sm"))
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut v: Vec<PathBuf> = std::fs::read_dir(dir).into_iter().flatten().flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect();
+    v.sort();
+    v
+}
+
+/// `minacalc-overlay scan-packs <folder> [--rates 1.0,1.1] [--out <dir>]`: rates every 4K chart
+/// of a StepMania pack (a folder of song folders), or of every pack in a folder of them, and
+/// writes each pack's `manifest.json` with chart keys, lengths and MSD, for difficulty listings.
+pub fn run(cfg: &Config) -> anyhow::Result<()> {
+    let root = PathBuf::from(std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(USAGE)?);
+    anyhow::ensure!(root.is_dir(), "{} isn't a folder", root.display());
+    let rates = match arg_value("--rates") {
+        Some(r) => calc::parse_rates(&r).context("--rates: comma separated rates like 1.0,1.1")?,
+        None => vec![1.0],
+    };
+    let out_dir = arg_value("--out").map(PathBuf::from);
+    let is_pack = |d: &Path| subdirs(d).iter().any(|s| simfile_in(s).is_some());
+    let packs: Vec<PathBuf> = if is_pack(&root) { vec![root.clone()] } else { subdirs(&root).into_iter().filter(|d| is_pack(d)).collect() };
+    anyhow::ensure!(!packs.is_empty(), "no packs under {}: a pack is a folder of song folders with .sm or .ssc files", root.display());
+    priority::lower();
+
+    let songs: Vec<(usize, PathBuf, PathBuf)> = packs.iter().enumerate()
+        .flat_map(|(i, p)| subdirs(p).into_iter().filter_map(move |s| Some((i, simfile_in(&s)?, s))))
+        .collect();
+    let calc = Calc::new()?;
+    let mut progress = Progress::new("songs", songs.len());
+    let mut manifests: Vec<Manifest> = packs.iter().map(|p| Manifest {
+        pack: p.file_name().map_or_else(|| p.display().to_string(), |n| n.to_string_lossy().into_owned()),
+        generated_unix_ms: unix_ms(), calc_version: Calc::version(), overlay_version: env!("CARGO_PKG_VERSION"), goal: cfg.score_goal, songs: Vec::new(),
+    }).collect();
+    let mut charts = 0;
+    for (pack, file, dir) in &songs {
+        let item = file.display().to_string();
+        match rate_song(&calc, file, dir, &rates, cfg.score_goal) {
+            Ok(song) => {
+                charts += song.charts.len();
+                if !song.charts.is_empty() { manifests[*pack].songs.push(song); }
+                progress.tick(&item);
+            }
+            Err(e) => progress.fail(&item, &e),
+        }
+    }
+    let mut written = 0;
+    for (m, dir) in manifests.iter().zip(&packs) {
+        if m.songs.is_empty() { debug!(target: "output", "{}: no charts rated, no manifest", m.pack); continue; }
+        let path = match &out_dir {
+            Some(o) => o.join(format!("{}.json", crate::sanitize::file_safe(&m.pack))),
+            None => dir.join("manifest.json"),
+        };
+        if let Some(parent) = path.parent() { std::fs::create_dir_all(parent)?; }
+        std::fs::write(&path, output::encode(m, Format::Json, cfg)?).with_context(|| format!("writing {}", path.display()))?;
+        debug!(target: "output", "{}: {} songs → {}", m.pack, m.songs.len(), path.display());
+        written += 1;
+    }
+    progress.finish(&[("charts", charts), ("manifests", written)]);
+    Ok(())
+}
+
+fn rate_song(calc: &Calc, file: &Path, dir: &Path, rates: &[f32], goal: f32) -> anyhow::Result<ManifestSong> {
+    let bytes = std::fs::read(file)?;
+    let (text, _) = calc::decode(&bytes);
+    let sim = parse(&text)?;
+    let mut charts = Vec::new();
+    for c in &sim.charts {
+        if c.timing.bpms.iter().any(|b| b.1 <= 0.0) {
+            warn!(target: "calc", code = "scan.warps", "{}: skipping {}, which has warps (negative BPMs)", file.display(), c.difficulty);
+            continue;
+        }
+        let rows = notes(c);
+        let (Some(first), Some(last)) = (rows.first(), rows.last()) else { continue };
+        let length_secs = last.row_time - first.row_time;
+        let mut msd = Vec::with_capacity(rates.len());
+        for &rate in rates {
+            let s = calc::ssr(calc, &rows, rate, goal).with_context(|| format!("{} at {rate}x", c.difficulty))?;
+            msd.push(ratings(&s, rate));
+        }
+        let dominant = calc::strongest(&SkillsetScores::from(&msd[0]));
+        let difficulty = if c.difficulty.is_empty() { "Edit".to_string() } else { c.difficulty.clone() };
+        charts.push(ManifestChart { difficulty, meter: c.meter, chartkey: chartkey(c), notes: rows.len(), length_secs, dominant, msd });
+    }
+    Ok(ManifestSong {
+        folder: dir.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned()),
+        title: sim.title, artist: sim.artist, charts,
+    })
+}
+
+fn ratings(s: &SkillsetScores, rate: f32) -> Ratings {
+    Ratings {
+        rate: calc::rate_label(rate), overall: s.overall, stamina: s.stamina, jumpstream: s.jumpstream, handstream: s.handstream,
+        stream: s.stream, chordjack: s.chordjack, jacks: s.jackspeed, technical: s.technical,
+    }
+}
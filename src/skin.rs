@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use serde::Serialize;
+use tracing::*;
+
+use crate::{background::ThemeOut, config::{arg_value, Config}, image::{self, Palette}, output::{self, Format}};
+
+/// The parts of a `skin.ini` the overlay can use.
+#[derive(Default)]
+struct Skin {
+    name: Option<String>,
+    /// `[Colours]` `Combo1`..`Combo8`, in order.
+    combo: Vec<[u8; 3]>,
+    /// `[Mania]` (the section for the key count): column background `ColourN`, one slot per
+    /// column (`None` where the skin leaves it default); empty if none are set.
+    columns: Vec<Option<[u8; 3]>>,
+    /// `[Mania]` key-press lighting `ColourLightN`, laid out like `columns`.
+    lights: Vec<Option<[u8; 3]>>,
+}
+
+#[derive(Serialize)]
+struct SkinTheme {
+    #[serde(flatten)]
+    theme: ThemeOut,
+    skin: Option<String>,
+    keys: u32,
+    combo: Vec<String>,
+    columns: Vec<Option<String>>,
+    lights: Vec<Option<String>>,
+}
+
+/// `minacalc-overlay skin <skin folder | skin.ini> [--keys 4] [--out <theme.json>]`
+pub async fn run(cfg: &Config, static_root: &Path) -> anyhow::Result<()> {
+    let input = std::env::args().nth(2).filter(|a| !a.starts_with("--"))
+        .context("usage: minacalc-overlay skin <skin folder | skin.ini> [--keys 4] [--out <theme.json>]")?;
+    let mut ini = PathBuf::from(input);
+    if ini.is_dir() { ini = ini.join("skin.ini"); }
+    let keys: u32 = arg_value("--keys").map(|k| k.parse().context("--keys must be a number")).transpose()?.unwrap_or(4);
+    let out_path = arg_value("--out").map(PathBuf::from).unwrap_or_else(|| output::overlay_dir(static_root).join("theme.json"));
+
+    let bytes = std::fs::read(&ini).with_context(|| format!("reading {}", ini.display()))?;
+    let skin = parse(&String::from_utf8_lossy(&bytes), keys);
+    // combo colours first; mania-only skins may only colour their key lights
+    let distinct: Vec<[u8; 3]> = skin.combo.iter().chain(skin.lights.iter().flatten()).fold(Vec::new(), |mut v, c| { if !v.contains(c) { v.push(*c); } v });
+    let dominant = *distinct.first().context("skin.ini has no combo or mania light colours")?;
+    let vivid = |c: &[u8; 3]| { let (max, min) = (*c.iter().max().unwrap() as u32, *c.iter().min().unwrap() as u32); max - min };
+    let accent = distinct.iter().copied().max_by_key(vivid).unwrap_or(dominant);
+    let doc = SkinTheme {
+        theme: ThemeOut::from(Palette { dominant, accent, colors: distinct }),
+        skin: skin.name,
+        keys,
+        combo: skin.combo.into_iter().map(image::hex).collect(),
+        columns: skin.columns.into_iter().map(|c| c.map(image::hex)).collect(),
+        lights: skin.lights.into_iter().map(|c| c.map(image::hex)).collect(),
+    };
+    if let Some(dir) = out_path.parent() { std::fs::create_dir_all(dir).ok(); }
+    std::fs::write(&out_path, output::encode(&doc, Format::Json, cfg)?).with_context(|| format!("writing {}", out_path.display()))?;
+    info!(target: "output", "theme from {} written to {}", ini.display(), out_path.display());
+    Ok(())
+}
+
+fn parse(text: &str, keys: u32) -> Skin {
+    let mut skin = Skin::default();
+    // (index from the key name, colour); the ini may list them in any order
+    let (mut combo, mut columns, mut lights) = (Vec::new(), Vec::new(), Vec::new());
+    let mut section = String::new();
+    // `[Mania]` repeats per key count; only the block whose `Keys:` matches counts
+    let mut mania_keys = None;
+    for line in text.lines() {
+        let line = line.trim_start_matches('\u{feff}').split("//").next().unwrap_or("").trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_ascii_lowercase();
+            mania_keys = None;
+            continue;
+        }
+        let Some((k, v)) = line.split_once(':') else { continue };
+        let (k, v) = (k.trim().to_ascii_lowercase(), v.trim());
+        match section.as_str() {
+            "general" if k == "name" => skin.name = Some(v.to_string()),
+            "colours" => if let (Some(n), Some(c)) = (k.strip_prefix("combo").and_then(|n| n.parse::<u32>().ok()), colour(v)) { combo.push((n, c)); },
+            "mania" if k == "keys" => mania_keys = v.parse::<u32>().ok(),
+            "mania" if mania_keys == Some(keys) => {
+                let n = |p: &str| k.strip_prefix(p).and_then(|n| n.parse::<u32>().ok()).filter(|&n| n >= 1 && n <= keys);
+                match (n("colourlight"), n("colour"), colour(v)) {
+                    (Some(i), _, Some(c)) => lights.push((i, c)),
+                    (None, Some(i), Some(c)) => columns.push((i, c)),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    combo.sort_by_key(|(n, _)| *n);
+    combo.dedup_by_key(|(n, _)| *n);
+    skin.combo = combo.into_iter().map(|(_, c)| c).collect();
+    for (list, out) in [(columns, &mut skin.columns), (lights, &mut skin.lights)] {
+        if list.is_empty() { continue; }
+        *out = vec![None; keys as usize];
+        for (i, c) in list { out[i as usize - 1] = Some(c); }
+    }
+    if skin.combo.is_empty() && skin.lights.is_empty() { warn!(target: "output", "no colours found for {keys}K"); }
+    skin
+}
+
+/// `r,g,b` or `r,g,b,a` (alpha ignored).
+fn colour(v: &str) -> Option<[u8; 3]> {
+    let mut it = v.split(',').map(|p| p.trim().parse::<u8>().ok());
+    Some([it.next()??, it.next()??, it.next()??])
+}
@@ -0,0 +1,176 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use serde::Serialize;
+use tracing::*;
+
+use crate::{chat::Chat, config::Config, history::{self, History, Play}, hub::unix_ms, output::{self, Format}};
+
+/// How often the clock is looked at; sleeping straight to the set time would drift past it
+/// when the computer sleeps or the clock is changed.
+const CHECK: Duration = Duration::from_secs(30);
+const DAY_MS: u64 = 86_400_000;
+
+#[derive(Serialize)]
+struct Hardest {
+    song: String,
+    diff: String,
+    rate: String,
+    overall: f32,
+}
+
+#[derive(Serialize)]
+struct SkillsetShare {
+    skillset: &'static str,
+    /// Plays whose chart is strongest in this skillset, and their fraction of all plays.
+    plays: usize,
+    share: f32,
+}
+
+/// `summary_day.json` / `summary_week.json`.
+#[derive(Serialize)]
+struct Summary {
+    /// `day` or `week`.
+    period: &'static str,
+    from_unix_ms: u64,
+    to_unix_ms: u64,
+    plays: usize,
+    /// Different charts played.
+    charts: usize,
+    /// Average overall MSD of the plays, at their rates.
+    average_msd: Option<f32>,
+    hardest: Option<Hardest>,
+    /// Most played first.
+    skillsets: Vec<SkillsetShare>,
+}
+
+fn summarize(period: &'static str, plays: &[Play], to: u64, days: u64) -> Summary {
+    let from = to.saturating_sub(days * DAY_MS);
+    let of: Vec<&Play> = plays.iter().filter(|p| (from..to).contains(&p.ended_unix_ms)).collect();
+    let mut charts: Vec<&str> = of.iter().map(|p| if p.md5.is_empty() { p.song.as_str() } else { p.md5.as_str() }).collect();
+    charts.sort_unstable();
+    charts.dedup();
+    let mut skillsets: Vec<SkillsetShare> = history::SKILLSETS.iter().map(|&s| {
+        let n = of.iter().filter(|p| p.dominant == s).count();
+        SkillsetShare { skillset: s, plays: n, share: if of.is_empty() { 0.0 } else { n as f32 / of.len() as f32 } }
+    }).collect();
+    skillsets.sort_by_key(|k| std::cmp::Reverse(k.plays));
+    Summary {
+        period,
+        from_unix_ms: from,
+        to_unix_ms: to,
+        plays: of.len(),
+        charts: charts.len(),
+        average_msd: (!of.is_empty()).then(|| of.iter().map(|p| p.msd.overall).sum::<f32>() / of.len() as f32),
+        hardest: of.iter().max_by(|a, b| a.msd.overall.total_cmp(&b.msd.overall))
+            .map(|p| Hardest { song: p.song.clone(), diff: p.diff.clone(), rate: p.msd.rate.clone(), overall: p.msd.overall }),
+        skillsets,
+    }
+}
+
+/// `Weekly summary: 120 plays (64 charts), 24.31 MSD on average; hardest A - T [X] 1.10x 28.12; stream 40%, jumpstream 25%, technical 15%`
+fn message(s: &Summary) -> String {
+    let title = if s.period == "day" { "Daily summary" } else { "Weekly summary" };
+    if s.plays == 0 { return format!("{title}: no plays"); }
+    let mut text = format!("{title}: {} play{} ({} chart{})", s.plays, if s.plays == 1 { "" } else { "s" }, s.charts, if s.charts == 1 { "" } else { "s" });
+    if let Some(avg) = s.average_msd { text += &format!(", {avg:.2} MSD on average"); }
+    if let Some(h) = &s.hardest { text += &format!("; hardest {} [{}] {}x {:.2}", h.song, h.diff, h.rate, h.overall); }
+    let top: Vec<String> = s.skillsets.iter().filter(|k| k.plays > 0).take(3).map(|k| format!("{} {:.0}%", k.skillset, k.share * 100.0)).collect();
+    if !top.is_empty() { text += &format!("; {}", top.join(", ")); }
+    text
+}
+
+/// `MSD_SUMMARY_AT`: once a day at that local time, sum up the last 24 hours of the play
+/// history (and on `MSD_SUMMARY_WEEKDAY` the last 7 days) into `summary_day.json` /
+/// `summary_week.json` in the overlay folder and post it to the chat rooms. It runs on the
+/// clock, not on session ends, so a stream that never stops still gets its report; a time
+/// that passes while the sidecar isn't running is skipped.
+pub async fn run(at_min: u32, static_root: PathBuf, chat: Chat, cfg: Arc<Config>) {
+    let today = |now_ms: u64| {
+        let local = (now_ms / 1000) as i64 + utc_offset_secs();
+        (local.div_euclid(86_400), (local.rem_euclid(86_400) / 60) as u32)
+    };
+    // started after today's time: the next one is tomorrow's
+    let (day, min) = today(unix_ms());
+    let mut last = if min >= at_min { Some(day) } else { None };
+    loop {
+        tokio::time::sleep(CHECK).await;
+        let now = unix_ms();
+        let (day, min) = today(now);
+        if min < at_min || last == Some(day) { continue; }
+        last = Some(day);
+        let plays = match history::load(&History::default_path()) {
+            Ok(p) => p,
+            Err(e) => { warn!(target: "output", code = "summary.history", "summary: {e:#}"); continue; }
+        };
+        // 1970-01-01 was a Thursday; 0 = Monday
+        let weekday = (day + 3).rem_euclid(7) as u32;
+        let mut due = vec![summarize("day", &plays, now, 1)];
+        if cfg.summary_weekday == Some(weekday) { due.push(summarize("week", &plays, now, 7)); }
+        for s in due {
+            let name = format!("summary_{}.json", s.period);
+            let res = match output::encode(&s, Format::Json, &cfg) {
+                Ok(b) => tokio::fs::write(output::overlay_dir(&static_root).join(&name), b).await.map_err(anyhow::Error::from),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res { warn!(target: "output", code = "output.write", %e, "failed to write {name}"); }
+            let text = message(&s);
+            info!(target: "output", "{text}");
+            // a day off isn't worth a message
+            if s.plays > 0 { chat.send(text); }
+        }
+    }
+}
+
+/// Seconds the local time zone is ahead of UTC right now; 0 where it can't be told.
+fn utc_offset_secs() -> i64 {
+    #[cfg(unix)]
+    {
+        let t = (unix_ms() / 1000) as libc::time_t;
+        // SAFETY: localtime_r only writes into `tm`
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() { return 0; }
+        tm.tm_gmtoff as i64
+    }
+    #[cfg(windows)]
+    {
+        #[repr(C)]
+        struct SystemTime { fields: [u16; 8] }
+        #[repr(C)]
+        struct TimeZoneInformation {
+            bias: i32, standard_name: [u16; 32], standard_date: SystemTime, standard_bias: i32,
+            daylight_name: [u16; 32], daylight_date: SystemTime, daylight_bias: i32,
+        }
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn GetTimeZoneInformation(tz: *mut TimeZoneInformation) -> u32;
+        }
+        const TIME_ZONE_ID_STANDARD: u32 = 1;
+        const TIME_ZONE_ID_DAYLIGHT: u32 = 2;
+        // SAFETY: the struct is laid out as the API expects and only written to
+        unsafe {
+            let mut tz: TimeZoneInformation = std::mem::zeroed();
+            let bias = match GetTimeZoneInformation(&mut tz) {
+                TIME_ZONE_ID_STANDARD => tz.bias + tz.standard_bias,
+                TIME_ZONE_ID_DAYLIGHT => tz.bias + tz.daylight_bias,
+                u32::MAX => return 0,
+                _ => tz.bias,
+            };
+            -(bias as i64) * 60
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    { 0 }
+}
+
+/// `21:30` → minutes past midnight.
+pub fn parse_time(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+/// `monday` / `mon` → 0 … `sunday` / `sun` → 6.
+pub fn parse_weekday(s: &str) -> Option<u32> {
+    let s = s.trim().to_ascii_lowercase();
+    ["mon", "tue", "wed", "thu", "fri", "sat", "sun"].iter().position(|d| s.len() >= 3 && (s == *d || s.starts_with(d) && s.ends_with("day")))
+        .map(|d| d as u32)
+}
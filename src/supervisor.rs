@@ -0,0 +1,57 @@
+use std::{path::PathBuf, process::{Command, Stdio}, time::{Duration, Instant}};
+use tokio::net::TcpStream;
+use tracing::*;
+
+/// tosu's web server; something answering here means tosu is already up.
+const TOSU_ADDR: &str = "127.0.0.1:24050";
+/// How often to look for tosu while it's run by someone else.
+const CHECK: Duration = Duration::from_secs(5);
+/// Restart delay after a crash, doubling up to `MAX_BACKOFF` while tosu keeps crashing.
+const BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A run this long counts as healthy and resets the backoff.
+const HEALTHY: Duration = Duration::from_secs(60);
+
+/// `MSD_TOSU_PATH`: start tosu from `exe` unless it's already running, and start it again when
+/// it crashes. A tosu someone else started is left alone; if it goes away, ours takes over.
+/// Exiting tosu normally (tray → Exit) ends supervision. It's a program of its own and keeps
+/// running if this one exits, so its output isn't captured (it would lose its pipe).
+pub async fn run(exe: PathBuf) {
+    let mut backoff = BACKOFF;
+    loop {
+        if TcpStream::connect(TOSU_ADDR).await.is_ok() {
+            tokio::time::sleep(CHECK).await;
+            continue;
+        }
+        let mut cmd = Command::new(&exe);
+        // tosu reads tosu.env and writes its static folder relative to where it's started
+        if let Some(dir) = exe.parent().filter(|d| !d.as_os_str().is_empty()) { cmd.current_dir(dir); }
+        cmd.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+        let mut child = match cmd.spawn() {
+            Ok(c) => c,
+            Err(e) => {
+                error!(target: "tosu", code = "tosu.launch", "can't start {}: {e}; retrying in {}s", exe.display(), backoff.as_secs());
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        info!(target: "tosu", "started {} (pid {})", exe.display(), child.id());
+        let started = Instant::now();
+        let status = match tokio::task::spawn_blocking(move || child.wait()).await {
+            Ok(s) => s,
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        if started.elapsed() >= HEALTHY { backoff = BACKOFF; }
+        match status {
+            Ok(s) if s.success() => {
+                info!(target: "tosu", "tosu exited normally; not restarting it");
+                return;
+            }
+            Ok(s) => warn!(target: "tosu", code = "tosu.crashed", "tosu exited ({s}) after {}s; restarting in {}s", started.elapsed().as_secs(), backoff.as_secs()),
+            Err(e) => warn!(target: "tosu", code = "tosu.crashed", "lost track of tosu: {e}; restarting in {}s", backoff.as_secs()),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
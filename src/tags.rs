@@ -0,0 +1,204 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::Mutex, time::SystemTime};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{calc, config::arg_value, hub::unix_ms, library::Library, md5};
+
+const USAGE: &str = "usage: minacalc-overlay tag <md5 | beatmap ID | .osu path> [--add a,b] [--remove a,b] [--note <text> | --clear-note], or tag --list [--tag a,b]";
+
+/// The player's tags and note on one chart.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Entry {
+    /// For reading the file; charts are found by MD5.
+    #[serde(default)]
+    pub song: String,
+    #[serde(default)]
+    pub diff: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub updated_unix_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct File {
+    /// By the `.osu`'s MD5, which survives rescans and is what plays and results carry.
+    maps: BTreeMap<String, Entry>,
+}
+
+/// A change to a chart's tags; `note: Some(None)` clears the note.
+#[derive(Default)]
+pub struct Edit {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+    pub note: Option<Option<String>>,
+}
+
+impl Edit {
+    pub fn is_empty(&self) -> bool { self.add.is_empty() && self.remove.is_empty() && self.note.is_none() }
+}
+
+/// `tags.json` in the app's data folder.
+pub fn default_path() -> PathBuf {
+    dirs::data_local_dir().unwrap_or_else(|| PathBuf::from(".")).join("minacalc-overlay").join("tags.json")
+}
+
+/// `Speed  Practice` → `speed practice`; tags are compared that way. Commas separate tags, so
+/// they can't be in one.
+pub fn normalize(tag: &str) -> Option<String> {
+    let t = tag.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    (!t.is_empty() && !t.contains(',')).then_some(t)
+}
+
+/// `speed practice, stamina` → both, normalized.
+pub fn parse_list(s: &str) -> Vec<String> { s.split(',').filter_map(normalize).collect() }
+
+/// Every tagged chart, by MD5; a missing file is none.
+pub fn load_all(path: &Path) -> anyhow::Result<BTreeMap<String, Entry>> {
+    match std::fs::read(path) {
+        Ok(b) => Ok(serde_json::from_slice::<File>(&b).with_context(|| format!("reading {}", path.display()))?.maps),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+fn save(path: &Path, maps: &BTreeMap<String, Entry>) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(&File { maps: maps.clone() })?)?;
+    std::fs::rename(&tmp, path).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Apply `edit` to the chart `md5`; a chart left with no tags and no note is dropped.
+fn apply(maps: &mut BTreeMap<String, Entry>, md5: &str, song: &str, diff: &str, edit: &Edit) -> Entry {
+    let e = maps.entry(md5.to_string()).or_default();
+    if !song.is_empty() { e.song = song.to_string(); }
+    if !diff.is_empty() { e.diff = diff.to_string(); }
+    for t in &edit.add {
+        if !e.tags.contains(t) { e.tags.push(t.clone()); }
+    }
+    e.tags.retain(|t| !edit.remove.contains(t));
+    if let Some(n) = &edit.note { e.note = n.as_deref().map(str::trim).filter(|n| !n.is_empty()).map(str::to_string); }
+    e.updated_unix_ms = unix_ms();
+    let e = e.clone();
+    if e.tags.is_empty() && e.note.is_none() { maps.remove(md5); }
+    e
+}
+
+/// Fold another machine's `tags.json` into the one at `path`: tags are combined, and a note
+/// only comes over for charts without one here. Returns how many charts gained something.
+pub fn merge_file(path: &Path, other: &[u8]) -> anyhow::Result<usize> {
+    let other: File = serde_json::from_slice(other).context("reading the imported tags")?;
+    let mut maps = load_all(path)?;
+    let mut changed = 0;
+    for (md5, o) in other.maps {
+        let mine = maps.entry(md5).or_insert_with(|| Entry { tags: Vec::new(), note: None, ..o.clone() });
+        let before = (mine.tags.len(), mine.note.is_some());
+        for t in o.tags {
+            if !mine.tags.contains(&t) { mine.tags.push(t); }
+        }
+        if mine.note.is_none() { mine.note = o.note; }
+        if (mine.tags.len(), mine.note.is_some()) != before { changed += 1; }
+    }
+    if changed > 0 { save(path, &maps)?; }
+    Ok(changed)
+}
+
+/// `tags.json`, shared by the poll loop (which puts a chart's tags in its result) and gRPC
+/// (which edits them); read again when the `tag` command changed the file.
+pub struct Store { path: PathBuf, state: Mutex<(Option<SystemTime>, BTreeMap<String, Entry>)> }
+
+impl Store {
+    pub fn load() -> Store {
+        let path = default_path();
+        let maps = load_all(&path).unwrap_or_else(|e| { warn!(target: "output", code = "tags.load", "{e:#}; no tags until it's fixed"); BTreeMap::new() });
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        Store { path, state: Mutex::new((modified, maps)) }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, (Option<SystemTime>, BTreeMap<String, Entry>)> {
+        let mut state = self.state.lock().unwrap_or_else(|p| p.into_inner());
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified != state.0 {
+            match load_all(&self.path) {
+                Ok(maps) => *state = (modified, maps),
+                Err(e) => debug!(target: "output", "{e:#}"),
+            }
+        }
+        state
+    }
+
+    /// The chart's tags and note, if it has any.
+    pub fn get(&self, md5: &str) -> Option<Entry> { self.lock().1.get(md5).cloned() }
+
+    pub fn edit(&self, md5: &str, song: &str, diff: &str, edit: &Edit) -> anyhow::Result<Entry> {
+        let mut state = self.lock();
+        let e = apply(&mut state.1, md5, song, diff, edit);
+        save(&self.path, &state.1)?;
+        state.0 = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        Ok(e)
+    }
+}
+
+/// `minacalc-overlay tag <map> [--add a,b] [--remove a,b] [--note <text> | --clear-note]`: edit
+/// (or without options, show) a chart's tags and note; `tag --list [--tag a,b]` lists the tagged
+/// charts. `<map>` is the `.osu`'s MD5, a local `.osu`, or a beatmap ID or SHA-1 from the library.
+pub fn run() -> anyhow::Result<()> {
+    let path = default_path();
+    if std::env::args().any(|a| a == "--list") {
+        let want = arg_value("--tag").map(|t| parse_list(&t)).unwrap_or_default();
+        let maps = load_all(&path)?;
+        let mut n = 0;
+        for (md5, e) in maps.iter().filter(|(_, e)| want.iter().all(|t| e.tags.contains(t))) {
+            println!("{md5}  {} [{}]  {}{}", e.song, e.diff, e.tags.join(", "), e.note.as_deref().map(|n| format!("  \u{2014} {n}")).unwrap_or_default());
+            n += 1;
+        }
+        if n == 0 { info!(target: "output", "no tagged charts in {}", path.display()); }
+        return Ok(());
+    }
+    let target = std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(USAGE)?;
+    let (md5, song, diff) = resolve(&target)?;
+    let edit = Edit {
+        add: arg_value("--add").map(|t| parse_list(&t)).unwrap_or_default(),
+        remove: arg_value("--remove").map(|t| parse_list(&t)).unwrap_or_default(),
+        note: if std::env::args().any(|a| a == "--clear-note") { Some(None) } else { arg_value("--note").map(Some) },
+    };
+    let e = if edit.is_empty() {
+        load_all(&path)?.remove(&md5).unwrap_or_default()
+    } else {
+        let mut maps = load_all(&path)?;
+        let e = apply(&mut maps, &md5, &song, &diff, &edit);
+        save(&path, &maps)?;
+        e
+    };
+    let tags = if e.tags.is_empty() { "no tags".to_string() } else { e.tags.join(", ") };
+    println!("{song} [{diff}] ({md5}): {tags}{}", e.note.as_deref().map(|n| format!("; note: {n}")).unwrap_or_default());
+    Ok(())
+}
+
+/// A chart's MD5, song and difficulty from what the `tag` command was given.
+fn resolve(target: &str) -> anyhow::Result<(String, String, String)> {
+    let p = Path::new(target);
+    if p.extension().is_some_and(|e| e.eq_ignore_ascii_case("osu")) || p.is_file() {
+        let bytes = std::fs::read(p).with_context(|| format!("reading {}", p.display()))?;
+        let (text, _) = calc::decode(&bytes);
+        let map = rosu_map::from_str::<rosu_map::Beatmap>(&text).map_err(|e| anyhow::anyhow!("parsing {}: {e}", p.display()))?;
+        return Ok((md5::hex(&bytes), calc::song_label(&map.artist, &map.title), map.version));
+    }
+    let library = Library::open(&Library::default_path())?;
+    let t = target.trim().to_ascii_lowercase();
+    let chart = library.charts.values().find(|c| match t.len() {
+        32 => c.md5 == t,
+        40 => c.sha1 == t,
+        _ => t.parse::<u64>().is_ok_and(|id| c.beatmap_id == Some(id)),
+    });
+    match chart {
+        Some(c) if !c.md5.is_empty() => Ok((c.md5.clone(), c.song.clone(), c.diff.clone())),
+        // a chart not in the library can still be tagged by its MD5
+        _ if t.len() == 32 && t.chars().all(|c| c.is_ascii_hexdigit()) => Ok((t, String::new(), String::new())),
+        _ => anyhow::bail!("{target} isn't an MD5, a .osu file, or a beatmap ID or SHA-1 in the library"),
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{calc::{Current, MsdOut}, config::Config, output::{self, Format}, wife::{self, Hits}};
+
+/// tosu's `tourney` object, present when it's attached to a tournament manager client.
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TourneyV2 {
+    #[serde(rename = "bestOF")]
+    pub best_of: u32,
+    pub team: Sides<String>,
+    pub points: Sides<u32>,
+    pub clients: Vec<ClientV2>,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct Sides<T> { pub left: T, pub right: T }
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ClientV2 {
+    pub team: String,
+    pub user: UserV2,
+    pub play: PlayV2,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct UserV2 { pub name: String }
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PlayV2 {
+    pub score: u64,
+    pub accuracy: f64,
+    pub hits: Hits,
+    pub combo: ComboV2,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ComboV2 { pub current: u32, pub max: u32 }
+
+#[derive(Serialize)]
+struct Player<'a> {
+    name: &'a str,
+    score: u64,
+    accuracy: f64,
+    /// Wife% estimated from the judgement counts (see `wife::from_hits`).
+    wife: Option<f32>,
+    /// Score extrapolated to the whole map from the notes judged so far.
+    projected_score: u64,
+    combo: u32,
+    max_combo: u32,
+    misses: u32,
+}
+
+#[derive(Serialize)]
+struct Side<'a> {
+    name: &'a str,
+    points: u32,
+    score: u64,
+    projected_score: u64,
+    players: Vec<Player<'a>>,
+}
+
+#[derive(Serialize)]
+struct H2hOut<'a> {
+    active: bool,
+    best_of: u32,
+    map: Option<&'a MsdOut>,
+    left: Option<Side<'a>>,
+    right: Option<Side<'a>>,
+    /// `"left"`, `"right"` or null when tied, by projected score.
+    leading: Option<&'static str>,
+    score_diff: u64,
+}
+
+/// Writes `h2h.json` while tosu reports tournament clients, for 1v1 / team-vs-team cast overlays.
+#[derive(Default)]
+pub struct HeadToHead { last: Option<Vec<u8>> }
+
+impl HeadToHead {
+    pub async fn update(&mut self, static_root: &Path, tourney: Option<&TourneyV2>, map: Option<&Current>, cfg: &Config) {
+        let t = tourney.filter(|t| !t.clients.is_empty());
+        if t.is_none() && self.last.is_none() { return; }
+        let doc = match t {
+            Some(t) => {
+                let notes = map.map_or(0, |m| m.notes);
+                let windows = map.and_then(|m| m.out.windows).unwrap_or_else(|| wife::Windows::new(8.0, &[], 1.0));
+                let left = side(t, "left", &t.team.left, t.points.left, notes, &windows);
+                let right = side(t, "right", &t.team.right, t.points.right, notes, &windows);
+                let (l, r) = (left.projected_score, right.projected_score);
+                H2hOut {
+                    active: true,
+                    best_of: t.best_of,
+                    map: map.map(|m| &m.out),
+                    leading: match l.cmp(&r) { std::cmp::Ordering::Greater => Some("left"), std::cmp::Ordering::Less => Some("right"), _ => None },
+                    score_diff: l.abs_diff(r),
+                    left: Some(left),
+                    right: Some(right),
+                }
+            }
+            None => H2hOut { active: false, best_of: 0, map: None, left: None, right: None, leading: None, score_diff: 0 },
+        };
+        let bytes = match output::encode(&doc, Format::Json, cfg) {
+            Ok(b) => b,
+            Err(e) => { warn!(target: "output", %e, "encode h2h.json"); return; }
+        };
+        if self.last.as_ref() == Some(&bytes) { return; }
+        let path = output::overlay_dir(static_root).join("h2h.json");
+        match tokio::fs::write(&path, &bytes).await {
+            Ok(()) => self.last = Some(bytes),
+            Err(e) => warn!(target: "output", %e, "failed to write h2h.json"),
+        }
+    }
+}
+
+fn side<'a>(t: &'a TourneyV2, team: &str, name: &'a str, points: u32, notes: usize, windows: &wife::Windows) -> Side<'a> {
+    let players: Vec<Player> = t.clients.iter().filter(|c| c.team == team).map(|c| {
+        let judged = c.play.hits.judged();
+        let projected_score = if judged > 0 && notes > 0 {
+            (c.play.score as f64 * notes as f64 / judged.min(notes as u32) as f64).round() as u64
+        } else { c.play.score };
+        Player {
+            name: &c.user.name,
+            score: c.play.score,
+            accuracy: c.play.accuracy,
+            wife: wife::from_hits(&c.play.hits, windows),
+            projected_score,
+            combo: c.play.combo.current,
+            max_combo: c.play.combo.max,
+            misses: c.play.hits.miss,
+        }
+    }).collect();
+    Side {
+        name,
+        points,
+        score: players.iter().map(|p| p.score).sum(),
+        projected_score: players.iter().map(|p| p.projected_score).sum(),
+        players,
+    }
+}
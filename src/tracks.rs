@@ -0,0 +1,72 @@
+use std::{path::Path, time::{Duration, Instant}};
+use serde::Serialize;
+use tracing::*;
+
+use crate::{calc::MsdOut, config::Config, hub::unix_ms, output::{self, Format}};
+
+#[derive(Serialize)]
+struct LastPlayed<'a> {
+    /// `null` once the retention window has passed.
+    map: Option<&'a MsdOut>,
+    ended_unix_ms: u64,
+    expires_unix_ms: u64,
+}
+
+/// Keeps `selected.json` (song select) and `playing.json` (the map in gameplay) apart, so the
+/// played map stays up while the streamer browses afterwards. With `MSD_LAST_PLAYED_MINS`,
+/// `last_played.json` also pins the finished play for that long.
+#[derive(Default)]
+pub struct Tracks {
+    selected: Option<Vec<u8>>,
+    playing: Option<Vec<u8>>,
+    /// The map of the play in progress, for `last_played.json` when it ends.
+    in_play: Option<MsdOut>,
+    /// When the last play ended, while `last_played.json` still shows it.
+    ended: Option<(Instant, u64)>,
+}
+
+impl Tracks {
+    /// `playing`: tosu reports gameplay (state 2). Runs on every poll, so entering a play
+    /// without a recalc still moves the map over.
+    pub async fn update(&mut self, static_root: &Path, out: Option<&MsdOut>, playing: bool, cfg: &Config) {
+        if let Some(mins) = cfg.last_played_mins { self.retain(static_root, out, playing, Duration::from_secs(mins * 60), cfg).await; }
+        let Some(out) = out else { return };
+        let bytes = match output::encode(out, Format::Json, cfg) {
+            Ok(b) => b,
+            Err(e) => { warn!(target: "output", %e, "encode selected/playing.json"); return; }
+        };
+        let (last, name) = if playing { (&mut self.playing, "playing.json") } else { (&mut self.selected, "selected.json") };
+        if last.as_ref() == Some(&bytes) { return; }
+        match tokio::fs::write(output::overlay_dir(static_root).join(name), &bytes).await {
+            Ok(()) => *last = Some(bytes),
+            Err(e) => warn!(target: "output", %e, "failed to write {name}"),
+        }
+    }
+
+    async fn retain(&mut self, static_root: &Path, out: Option<&MsdOut>, playing: bool, keep: Duration, cfg: &Config) {
+        if playing {
+            self.in_play = out.cloned();
+            return;
+        }
+        let doc = if let Some(map) = self.in_play.take() {
+            // a play just ended
+            let now = unix_ms();
+            self.ended = Some((Instant::now(), now));
+            let doc = LastPlayed { map: Some(&map), ended_unix_ms: now, expires_unix_ms: now + keep.as_millis() as u64 };
+            output::encode(&doc, Format::Json, cfg)
+        } else {
+            match self.ended {
+                Some((at, ended_ms)) if at.elapsed() >= keep => {
+                    self.ended = None;
+                    output::encode(&LastPlayed { map: None, ended_unix_ms: ended_ms, expires_unix_ms: ended_ms + keep.as_millis() as u64 }, Format::Json, cfg)
+                }
+                _ => return,
+            }
+        };
+        let path = output::overlay_dir(static_root).join("last_played.json");
+        match doc {
+            Ok(b) => if let Err(e) = tokio::fs::write(&path, b).await { warn!(target: "output", %e, "failed to write last_played.json"); },
+            Err(e) => warn!(target: "output", %e, "encode last_played.json"),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use std::{net::SocketAddr, sync::Arc};
+use anyhow::Context;
+use tokio::{net::UdpSocket, sync::broadcast::error::RecvError};
+use tracing::*;
+
+use crate::{config::Config, hub::Hub, output::{self, Format}};
+
+/// Send every update as one datagram to `addr`: a broadcast (`255.255.255.255:port`, or the subnet's
+/// broadcast address) or multicast group (`239.x.x.x:port`). Listeners just bind the port.
+pub async fn run(addr: SocketAddr, hub: Arc<Hub>, cfg: Arc<Config>) -> anyhow::Result<()> {
+    let format = cfg.udp_format;
+    let bind: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0".parse()? } else { "[::]:0".parse()? };
+    let sock = UdpSocket::bind(bind).await.context("binding UDP socket")?;
+    if let SocketAddr::V4(v4) = addr {
+        if v4.ip().is_multicast() { sock.set_multicast_ttl_v4(1)?; } // stay on the LAN
+        else { sock.set_broadcast(true)?; }
+    }
+    info!(target: "udp", "sending updates to {addr} ({format:?})");
+    let (latest, mut rx) = hub.subscribe();
+    if let Some(u) = latest { send(&sock, addr, format, &u, &cfg).await; }
+    loop {
+        match rx.recv().await {
+            Ok(u) => send(&sock, addr, format, &u, &cfg).await,
+            Err(RecvError::Lagged(n)) => debug!(target: "udp", n, "lagged"),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn send(sock: &UdpSocket, addr: SocketAddr, format: Format, u: &crate::hub::Update, cfg: &Config) {
+    match output::encode_update(u, format, cfg) {
+        Ok(bytes) => if let Err(e) = sock.send_to(&bytes, addr).await { warn!(target: "udp", %e, "send failed") },
+        Err(e) => warn!(target: "udp", %e, "encode failed"),
+    }
+}
@@ -0,0 +1,66 @@
+use std::{sync::Arc, time::Duration};
+use tokio::{io::WriteHalf, sync::broadcast::error::RecvError};
+use tracing::*;
+use url::Url;
+
+use crate::{config::Config, hub::{Hub, Update}, output::{self, Format}, ws::{self, Io, Message, Opcode}};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Keep a WebSocket to the aggregator open and push every update over it, reconnecting with
+/// backoff. One message per update: `{"source", "seq", "unix_ms", "result"}`.
+pub async fn run(url: Url, hub: Arc<Hub>, cfg: Arc<Config>) {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match session(&url, &hub, &cfg).await {
+            Ok(()) => { info!(target: "uplink", "aggregator closed the connection"); backoff = Duration::from_secs(1); }
+            Err(e) => warn!(target: "uplink", "{e:#}; retrying in {}s", backoff.as_secs()),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn session(url: &Url, hub: &Hub, cfg: &Config) -> anyhow::Result<()> {
+    let mut headers = Vec::new();
+    if let Some(t) = &cfg.aggregator_token { headers.push(("Authorization", format!("Bearer {t}"))); }
+    let io = ws::connect(url, &headers).await?;
+    info!(target: "uplink", "connected to {url} as {:?}", cfg.aggregator_name);
+    let (rd, mut wr) = tokio::io::split(io);
+    let mut rd = ws::Reader::new(rd);
+
+    let (latest, mut rx) = hub.subscribe();
+    if let Some(u) = latest { send(&mut wr, &u, cfg).await?; }
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(u) => send(&mut wr, &u, cfg).await?,
+                Err(RecvError::Lagged(n)) => debug!(target: "uplink", n, "lagged"),
+                Err(RecvError::Closed) => break,
+            },
+            msg = rd.next() => match msg? {
+                Message::Ping(p) => ws::write_frame(&mut wr, Opcode::Pong, &p, true).await?,
+                Message::Close => { ws::write_frame(&mut wr, Opcode::Close, &[], true).await.ok(); return Ok(()); }
+                _ => {} // nothing to receive; acks etc. are ignored
+            },
+        }
+    }
+    ws::write_frame(&mut wr, Opcode::Close, &[], true).await.ok();
+    Ok(())
+}
+
+async fn send(wr: &mut WriteHalf<Box<dyn Io>>, u: &Update, cfg: &Config) -> anyhow::Result<()> {
+    let (op, bytes) = envelope(u, cfg)?;
+    ws::write_frame(wr, op, &bytes, true).await?;
+    Ok(())
+}
+
+fn envelope(u: &Update, cfg: &Config) -> anyhow::Result<(Opcode, Vec<u8>)> {
+    let mut v = serde_json::to_value(u)?;
+    v["source"] = cfg.aggregator_name.clone().into();
+    Ok(match cfg.aggregator_format {
+        Format::Protobuf => (Opcode::Binary, crate::proto::encode(crate::proto::UPDATE, &v)),
+        Format::MsgPack => (Opcode::Binary, output::encode(&v, Format::MsgPack, cfg)?),
+        f => (Opcode::Text, output::encode(&v, f, cfg)?),
+    })
+}
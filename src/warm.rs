@@ -0,0 +1,313 @@
+use std::{collections::HashSet, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, mpsc, Condvar, Mutex}, time::{Duration, Instant, SystemTime}};
+use anyhow::Context;
+use minacalc_rs::Calc;
+use tracing::*;
+
+use crate::{calc::{self, MsdOut}, config::{arg_value, Config}, hub::unix_ms, library::{Chart, Library, Stamp}, priority, paths, progress::Progress};
+
+/// Etterna's rate ladder, 0.7x to 2.0x.
+const LADDER: [f32; 14] = [0.7, 0.8, 0.9, 1.0, 1.1, 1.2, 1.3, 1.4, 1.5, 1.6, 1.7, 1.8, 1.9, 2.0];
+/// Save this often, so an interrupted run resumes close to where it stopped.
+const SAVE_EVERY: Duration = Duration::from_secs(60);
+
+/// `minacalc-overlay warm-cache <Songs folder> [--rates 1.0,1.1 | --ladder] [--workers N] [--io N] [--quiet | --json-progress]`: rate every 4K mania
+/// chart into the library at low priority, so the overlay finds them there instead of calculating
+/// on hover. Files unchanged since the last scan (size and mtime) aren't read again, and charts
+/// already in the library at those rates are skipped, so reruns are incremental and resume.
+pub async fn run(cfg: &Config) -> anyhow::Result<()> {
+    const USAGE: &str = "usage: minacalc-overlay warm-cache <Songs folder> [--rates 1.0,1.1 | --ladder] [--workers N] [--io N] [--quiet | --json-progress]";
+    scan(cfg, songs_arg(USAGE)?, None)
+}
+
+/// `minacalc-overlay rescan <Songs folder> --since <last | unix seconds | YYYY-MM-DD> [warm-cache options]`:
+/// like `warm-cache`, but only files created or modified since then are looked at.
+pub async fn rescan(cfg: &Config) -> anyhow::Result<()> {
+    const USAGE: &str = "usage: minacalc-overlay rescan <Songs folder> --since <last | unix seconds | YYYY-MM-DD> [--rates 1.0,1.1 | --ladder] [--workers N] [--io N]";
+    let root = songs_arg(USAGE)?;
+    let since = arg_value("--since").context(USAGE)?;
+    let since_ms = match since.as_str() {
+        "last" => *Library::open(&Library::default_path())?.scans.get(&paths::key(&root))
+            .with_context(|| format!("no earlier scan of {}; run warm-cache first", root.display()))?,
+        s => parse_since(s).context("--since: last, unix seconds or YYYY-MM-DD")?,
+    };
+    scan(cfg, root, Some(since_ms))
+}
+
+fn songs_arg(usage: &'static str) -> anyhow::Result<PathBuf> {
+    let root = PathBuf::from(std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(usage)?);
+    anyhow::ensure!(root.is_dir(), "{} is not a folder", root.display());
+    Ok(paths::plain(&std::path::absolute(&root).unwrap_or(root)))
+}
+
+/// Unix seconds or a UTC date (`2025-10-01`) → unix ms.
+fn parse_since(s: &str) -> Option<u64> {
+    if let Ok(secs) = s.parse::<u64>() { return Some(secs * 1000); }
+    let mut it = s.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (y, m, d) = (it.next()??, it.next()??, it.next()??);
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) { return None; }
+    // days from civil (Howard Hinnant)
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    u64::try_from((era * 146_097 + doe - 719_468) * 86_400_000).ok()
+}
+
+/// Latest of the file's modification and creation times (unix ms); files unpacked from an
+/// `.osz` can carry old modification times but are created on import.
+fn changed_unix_ms(path: &Path) -> Option<u64> {
+    let m = std::fs::metadata(path).ok()?;
+    let ms = |t: std::io::Result<SystemTime>| t.ok().and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok()).map(|d| d.as_millis() as u64);
+    ms(m.modified()).max(ms(m.created()))
+}
+
+fn scan(cfg: &Config, root: PathBuf, since_ms: Option<u64>) -> anyhow::Result<()> {
+    let started_unix_ms = unix_ms();
+    let rates = match arg_value("--rates") {
+        Some(r) => calc::parse_rates(&r).context("--rates: comma separated rates like 1.0,1.1")?,
+        None if std::env::args().any(|a| a == "--ladder") => LADDER.to_vec(),
+        None => vec![1.0],
+    };
+    let labels: Vec<String> = rates.iter().map(|&r| calc::rate_label(r)).collect();
+    let count = |flag: &str, default: usize| -> anyhow::Result<usize> {
+        Ok(arg_value(flag).map(|n| n.parse::<usize>().with_context(|| format!("{flag} must be a number"))).transpose()?.unwrap_or(default).max(1))
+    };
+    let (workers, io) = (count("--workers", cfg.workers)?, count("--io", cfg.io_concurrency)?);
+    priority::lower();
+
+    let files = osu_files(&root);
+    debug!(target: "calc", "{} .osu files under {}, {workers} workers, {io} reads at a time", files.len(), root.display());
+    let candidates: Vec<&PathBuf> = match since_ms {
+        Some(since) => files.iter().filter(|p| changed_unix_ms(p).is_none_or(|t| t >= since)).collect(),
+        None => files.iter().collect(),
+    };
+    let mut library = Library::open(&Library::default_path())?;
+    let (mut rated, mut cached, mut skipped) = (0, 0, 0);
+    let mut progress = Progress::new("files", candidates.len());
+
+    // unchanged since the last scan: no need to even read them
+    let mut todo = Vec::new();
+    for path in candidates {
+        let key = paths::key(path);
+        let Some(stamp) = Stamp::of(path) else { progress.fail(&key, &anyhow::anyhow!("can't stat")); continue };
+        match library.files.get(&key).filter(|o| o.unchanged(&stamp)).map(|o| &o.sha1) {
+            Some(None) => { skipped += 1; progress.tick(&key); }
+            Some(Some(h)) if library.charts.get(h).is_some_and(|c| has_rates(c, &labels) && !c.notes_hash.is_empty()) => { cached += 1; progress.tick(&key); }
+            _ => todo.push((path.clone(), key, stamp)),
+        }
+    }
+    // workers only need to know what's done already; the library itself stays on this thread
+    let (done, unhashed): (HashSet<String>, HashSet<String>) = library.charts.values().filter(|c| has_rates(c, &labels))
+        .map(|c| c.sha1.clone()).partition(|h| !library.charts[h].notes_hash.is_empty());
+
+    let next = AtomicUsize::new(0);
+    let reads = Semaphore::new(io);
+    let (tx, rx) = mpsc::channel();
+    let mut saved = Instant::now();
+    std::thread::scope(|scope| -> anyhow::Result<()> {
+        for _ in 0..workers.min(todo.len()) {
+            let (tx, todo, next, reads, done, unhashed, rates) = (tx.clone(), &todo, &next, &reads, &done, &unhashed, &rates);
+            scope.spawn(move || {
+                priority::pin_worker();
+                // Calc isn't Send: one per worker
+                let calc = match Calc::new() {
+                    Ok(c) => c,
+                    Err(e) => { let _ = tx.send((usize::MAX, Outcome::Failed(e.into()))); return; }
+                };
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((path, _, _)) = todo.get(i) else { break };
+                    if tx.send((i, process(&calc, path, reads, done, unhashed, rates))).is_err() { break; }
+                }
+            });
+        }
+        drop(tx);
+        for (i, outcome) in rx {
+            let Some((_, key, stamp)) = todo.get(i) else {
+                if let Outcome::Failed(e) = outcome { warn!(target: "calc", "worker failed to start: {e:#}"); }
+                continue;
+            };
+            let (key, mut stamp) = (key.clone(), stamp.clone());
+            let old = library.files.get(&key).and_then(|o| o.sha1.clone());
+            let sha1 = match &outcome { Outcome::Cached(h) | Outcome::Hashed(h, _) => Some(h.clone()), Outcome::Rated(c) => Some(c.sha1.clone()), _ => None };
+            // edited in place: the old version's entry goes
+            if let Some(prev) = old.filter(|h| sha1.as_ref().is_some_and(|s| s != h)) {
+                if library.charts.get(&prev).is_some_and(|c| c.path.as_deref().map(|p| paths::key(Path::new(p))) == Some(key.clone())) { library.charts.remove(&prev); }
+            }
+            match outcome {
+                Outcome::Cached(h) => { stamp.sha1 = Some(h); library.files.insert(key.clone(), stamp); cached += 1; progress.tick(&key); }
+                Outcome::Hashed(h, notes_hash) => {
+                    if let Some(c) = library.charts.get_mut(&h) { c.notes_hash = notes_hash; }
+                    stamp.sha1 = Some(h);
+                    library.files.insert(key.clone(), stamp);
+                    cached += 1;
+                    progress.tick(&key);
+                }
+                Outcome::NotMania => { library.files.insert(key.clone(), stamp); skipped += 1; progress.tick(&key); }
+                Outcome::Rated(mut chart) => {
+                    // keep rates from earlier runs that this one didn't ask for
+                    if let Some(old) = library.charts.get(&chart.sha1).filter(|c| c.calc_version == chart.calc_version) {
+                        chart.msd.extend(old.msd.iter().filter(|r| !labels.contains(&r.rate)).cloned());
+                        chart.added_unix = old.added_unix;
+                    }
+                    debug!(target: "calc", "{} [{}]", chart.song, chart.diff);
+                    stamp.sha1 = Some(chart.sha1.clone());
+                    library.files.insert(key.clone(), stamp);
+                    library.insert(chart);
+                    rated += 1;
+                    progress.tick(&key);
+                }
+                // no stamp: retried next run
+                Outcome::Failed(e) => progress.fail(&key, &e),
+            }
+            if saved.elapsed() >= SAVE_EVERY {
+                library.save()?;
+                saved = Instant::now();
+            }
+        }
+        Ok(())
+    })?;
+    // forget files deleted from this folder since the last scan
+    let prefix = format!("{}{}", paths::key(&root), std::path::MAIN_SEPARATOR);
+    let seen: HashSet<String> = files.iter().map(|p| paths::key(p)).collect();
+    library.files.retain(|k, _| !k.starts_with(&prefix) || seen.contains(k));
+    library.scans.insert(paths::key(&root), started_unix_ms);
+    library.save()?;
+    debug!(target: "output", "library at {}", library.path().display());
+    // reuploads: same notes under another set; find and recommend list them once
+    let copies: usize = library.duplicates().values().map(|g| g.len() - 1).sum();
+    progress.finish(&[("rated", rated), ("up to date", cached), ("not 4K mania", skipped), ("duplicates", copies)]);
+    Ok(())
+}
+
+enum Outcome {
+    /// Already in the library at every rate (same content under another path, or only touched).
+    Cached(String),
+    /// Rated before `notes_hash` existed: only the hash was missing.
+    Hashed(String, String),
+    NotMania,
+    Rated(Chart),
+    Failed(anyhow::Error),
+}
+
+fn process(calc: &Calc, path: &Path, reads: &Semaphore, done: &HashSet<String>, unhashed: &HashSet<String>, rates: &[f32]) -> Outcome {
+    let bytes = {
+        let _permit = reads.acquire();
+        match std::fs::read(path) {
+            Ok(b) => b,
+            Err(e) => return Outcome::Failed(e.into()),
+        }
+    };
+    let sha1 = sha1_smol::Sha1::from(&bytes).hexdigest();
+    if done.contains(&sha1) { return Outcome::Cached(sha1); }
+    // cheap check before the full parse: most of a Songs folder isn't 4K mania
+    let (text, _) = calc::decode(&bytes);
+    if !is_4k_mania(&text) { return Outcome::NotMania; }
+    if unhashed.contains(&sha1) {
+        return match calc::notes(&text) {
+            Ok((_, notes)) => Outcome::Hashed(sha1, calc::notes_hash(&notes)),
+            Err(e) => Outcome::Failed(e),
+        };
+    }
+    match rate(calc, &text, &bytes, sha1, path, rates) {
+        Ok(chart) => Outcome::Rated(chart),
+        Err(e) => Outcome::Failed(e),
+    }
+}
+
+/// Caps how many workers read from disk at once, independent of how many calculate.
+struct Semaphore { free: Mutex<usize>, cv: Condvar }
+
+impl Semaphore {
+    fn new(n: usize) -> Semaphore { Semaphore { free: Mutex::new(n), cv: Condvar::new() } }
+
+    fn acquire(&self) -> Permit<'_> {
+        let mut free = self.cv.wait_while(self.free.lock().unwrap_or_else(|p| p.into_inner()), |n| *n == 0).unwrap_or_else(|p| p.into_inner());
+        *free -= 1;
+        Permit(self)
+    }
+}
+
+struct Permit<'a>(&'a Semaphore);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap_or_else(|p| p.into_inner()) += 1;
+        self.0.cv.notify_one();
+    }
+}
+
+fn has_rates(c: &Chart, labels: &[String]) -> bool { labels.iter().all(|r| c.ratings(r).is_some()) }
+
+fn rate(calc: &Calc, text: &str, bytes: &[u8], sha1: String, path: &Path, rates: &[f32]) -> anyhow::Result<Chart> {
+    let (map, notes) = calc::notes(text)?;
+    let mut msd = Vec::with_capacity(rates.len());
+    for &rate in rates {
+        let scores = calc::ssr(calc, &notes, rate, calc::SCORE_GOAL)?;
+        msd.push((&MsdOut::new(String::new(), String::new(), &scores, calc::rate_label(rate))).into());
+    }
+    let (beatmap_id, set_id) = calc::map_ids(&map);
+    Ok(Chart {
+        sha1,
+        md5: crate::md5::hex(bytes),
+        song: calc::song_label(&map.artist, &map.title),
+        diff: map.version.clone(),
+        beatmap_id,
+        set_id,
+        path: Some(path.display().to_string()),
+        msd,
+        added_unix: unix_ms() / 1000,
+        calc_version: Calc::version(),
+        notes_hash: calc::notes_hash(&notes),
+    })
+}
+
+/// `Mode: 3` in `[General]` and `CircleSize:4` in `[Difficulty]`.
+fn is_4k_mania(text: &str) -> bool {
+    let value = |key: &str| text.lines().find_map(|l| l.strip_prefix(key)).map(|v| v.trim_start_matches(':').trim());
+    value("Mode") == Some("3") && value("CircleSize").and_then(|v| v.parse::<f32>().ok()) == Some(4.0)
+}
+
+/// Every `.osu` below `dir`, sorted so runs visit files in the same order.
+pub fn osu_files(dir: &Path) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(d) = stack.pop() {
+        let entries = match std::fs::read_dir(&d) {
+            Ok(e) => e,
+            Err(e) => { warn!(target: "calc", "skipping {}: {e}", d.display()); continue; }
+        };
+        for e in entries.flatten() {
+            let p = e.path();
+            if p.is_dir() { stack.push(p); }
+            else if p.extension().is_some_and(|x| x.eq_ignore_ascii_case("osu")) { out.push(p); }
+        }
+    }
+    out.sort();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since_dates_and_seconds() {
+        assert_eq!(parse_since("2025-10-01"), Some(1_759_276_800_000));
+        assert_eq!(parse_since("1970-01-01"), Some(0));
+        // across a leap day and the March start of the civil-days year
+        assert_eq!(parse_since("2000-02-29"), Some(951_782_400_000));
+        assert_eq!(parse_since("2024-03-01"), Some(1_709_251_200_000));
+        assert_eq!(parse_since("1759276800"), Some(1_759_276_800_000));
+    }
+
+    #[test]
+    fn since_rejects_non_dates() {
+        assert_eq!(parse_since("2025-13-01"), None);
+        assert_eq!(parse_since("2025-10"), None);
+        assert_eq!(parse_since("yesterday"), None);
+        // before 1970 isn't a unix time
+        assert_eq!(parse_since("1969-12-31"), None);
+    }
+}
@@ -0,0 +1,256 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use anyhow::Context;
+
+use crate::{config::{arg_value, Config}, output::{self, Format}};
+
+/// tosu's `play.hits` for mania: `geki` is MAX (320), `katu` is 200.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct Hits {
+    #[serde(rename = "geki")] pub max: u32,
+    #[serde(rename = "300")] pub great: u32,
+    #[serde(rename = "katu")] pub good: u32,
+    #[serde(rename = "100")] pub ok: u32,
+    #[serde(rename = "50")] pub meh: u32,
+    #[serde(rename = "0")] pub miss: u32,
+}
+
+impl Hits {
+    pub fn judged(&self) -> u32 { self.max + self.great + self.good + self.ok + self.meh + self.miss }
+
+    /// osu! accuracy (0–100). ScoreV1 counts MAX like a 300; ScoreV2 and lazer give it 305.
+    pub fn accuracy(&self, v2: bool) -> Option<f32> {
+        let n = self.judged();
+        if n == 0 { return None; }
+        let max = if v2 { 305.0 } else { 300.0 };
+        let points = self.max as f32 * max + self.great as f32 * 300.0 + self.good as f32 * 200.0 + self.ok as f32 * 100.0 + self.meh as f32 * 50.0;
+        Some(points / (max * n as f32) * 100.0)
+    }
+
+    /// `MAX,300,200,100,50,miss`
+    pub fn parse(s: &str) -> Option<Hits> {
+        let c: Vec<u32> = s.split(',').map(|p| p.trim().parse().ok()).collect::<Option<_>>()?;
+        let [max, great, good, ok, meh, miss] = c[..] else { return None };
+        Some(Hits { max, great, good, ok, meh, miss })
+    }
+}
+
+/// Etterna's Wife3 points (out of 2) for a hit `ms` off, at judge 4 (timescale 1).
+pub fn wife3(ms: f32) -> f32 {
+    const RIDIC: f32 = 5.0;
+    const ZERO: f32 = 65.0;
+    const DEV: f32 = 22.7;
+    const MAX_BOO: f32 = 180.0;
+    const MISS: f32 = -5.5;
+    let x = ms.abs();
+    if x <= RIDIC { 2.0 }
+    else if x <= ZERO { 2.0 * erf((ZERO - x) / DEV) }
+    else if x <= MAX_BOO { (x - ZERO) * MISS / (MAX_BOO - ZERO) }
+    else { MISS }
+}
+
+/// osu!mania hit windows: how far off (±ms, real time) a hit can be for each judgement.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Windows {
+    pub max: f32,
+    pub great: f32,
+    pub good: f32,
+    pub ok: f32,
+    pub meh: f32,
+    /// Later than this the note isn't hittable any more.
+    pub miss: f32,
+}
+
+impl Windows {
+    /// Stable's windows at `od`; HR tightens them by 1.4x, EZ loosens them by 1.4x, and as they're
+    /// in song time a faster `rate` shrinks them in real time.
+    pub fn new(od: f32, mods: &[String], rate: f32) -> Windows {
+        let has = |m: &str| mods.iter().any(|a| a == m);
+        let scale = if has("HR") { 1.0 / 1.4 } else if has("EZ") { 1.4 } else { 1.0 } / rate.max(0.01);
+        let edge = |base: f32| (base - 3.0 * od) * scale;
+        Windows { max: 16.0 * scale, great: edge(64.0), good: edge(97.0), ok: edge(127.0), meh: edge(151.0), miss: edge(188.0) }
+    }
+}
+
+/// Estimated wife% (0–100) from osu!mania judgement counts: osu! only reports which window each
+/// hit landed in, so each counts as the middle of its window, except MAX, which is as good as osu!
+/// can tell and gets full marks (an all-MAX play is 100%).
+pub fn from_hits(h: &Hits, w: &Windows) -> Option<f32> {
+    let n = h.judged();
+    if n == 0 { return None; }
+    let mid = |inner: f32, outer: f32| (inner + outer) / 2.0;
+    let points = h.max as f32 * wife3(0.0)
+        + h.great as f32 * wife3(mid(w.max, w.great))
+        + h.good as f32 * wife3(mid(w.great, w.good))
+        + h.ok as f32 * wife3(mid(w.good, w.ok))
+        + h.meh as f32 * wife3(mid(w.ok, w.meh))
+        + h.miss as f32 * wife3(f32::INFINITY);
+    Some((points / (2.0 * n as f32) * 100.0).max(-100.0))
+}
+
+/// Estimated wife% from an osu! accuracy alone, for when the judgement counts aren't known:
+/// perfect hits are taken as half MAX, half 300 (V1 can't tell them apart) and the rest of the
+/// lost accuracy as 200s, then as misses once even all-200s would be too high.
+pub fn from_accuracy(acc: f32, w: &Windows, v2: bool) -> Option<f32> {
+    if !(0.0..=100.0).contains(&acc) { return None; }
+    const N: f32 = 10_000.0;
+    let (acc, max) = (acc / 100.0, if v2 { 305.0 } else { 300.0 });
+    let perfect = (max + 300.0) / 2.0;
+    let good = ((perfect - max * acc) / (perfect - 200.0)).clamp(0.0, 1.0);
+    let miss = if good >= 1.0 { (1.0 - max * acc / 200.0).max(0.0) } else { 0.0 };
+    let rest = ((1.0 - good) * N / 2.0).round() as u32;
+    let hits = Hits { max: rest, great: rest, good: ((good - miss) * N).round() as u32, miss: (miss * N).round() as u32, ..Hits::default() };
+    from_hits(&hits, w)
+}
+
+#[derive(Serialize)]
+struct ScoreOut {
+    active: bool,
+    accuracy: Option<f32>,
+    /// `from_hits` estimate
+    wife: Option<f32>,
+    score_v2: bool,
+    windows: Option<Windows>,
+    judged: u32,
+}
+
+/// Writes `score.json` (osu! accuracy and the wife% estimate) while a map is being played.
+#[derive(Default)]
+pub struct Score {
+    last: Option<Vec<u8>>,
+}
+
+impl Score {
+    pub async fn update(&mut self, static_root: &Path, hits: Option<&Hits>, windows: Option<Windows>, v2: bool, playing: bool, cfg: &Config) {
+        let hits = hits.copied().filter(|_| playing).unwrap_or_default();
+        if !playing && self.last.is_none() { return; }
+        let doc = ScoreOut {
+            active: playing,
+            accuracy: hits.accuracy(v2),
+            wife: windows.and_then(|w| from_hits(&hits, &w)),
+            score_v2: v2,
+            windows: windows.filter(|_| playing),
+            judged: hits.judged(),
+        };
+        let bytes = match output::encode(&doc, Format::Json, cfg) {
+            Ok(b) => b,
+            Err(e) => { warn!(target: "output", %e, "encode score.json"); return; }
+        };
+        if self.last.as_ref() == Some(&bytes) { return; }
+        let path = output::overlay_dir(static_root).join("score.json");
+        match tokio::fs::write(&path, &bytes).await {
+            Ok(()) => self.last = Some(bytes),
+            Err(e) => warn!(target: "output", %e, "failed to write score.json"),
+        }
+    }
+}
+
+/// `minacalc-overlay convert-acc <accuracy | MAX,300,200,100,50,miss> --od <od> [--v2] [--hr | --ez] [--rate 1.5]`
+pub fn run() -> anyhow::Result<()> {
+    const USAGE: &str = "usage: minacalc-overlay convert-acc <accuracy | MAX,300,200,100,50,miss> --od <od> [--v2] [--hr | --ez] [--rate 1.5]";
+    let input = std::env::args().nth(2).filter(|a| !a.starts_with("--")).context(USAGE)?;
+    let od: f32 = arg_value("--od").context(USAGE)?.parse().context("--od must be a number")?;
+    let flag = |f: &str| std::env::args().any(|a| a == f);
+    let v2 = flag("--v2");
+    let rate: f32 = arg_value("--rate").map(|r| r.trim_end_matches('x').parse().context("--rate must be a number")).transpose()?.unwrap_or(1.0);
+    let mods: Vec<String> = [("--hr", "HR"), ("--ez", "EZ")].into_iter().filter(|(f, _)| flag(f)).map(|(_, m)| m.to_string()).collect();
+    let w = Windows::new(od, &mods, rate);
+    let label = if v2 { "ScoreV2" } else { "ScoreV1" };
+    match Hits::parse(&input) {
+        Some(hits) => {
+            let acc = hits.accuracy(v2).context("no judgements")?;
+            let wife = from_hits(&hits, &w).context("no judgements")?;
+            println!("{acc:.2}% ({label}, OD {od}) ~ {wife:.2}% wife3");
+        }
+        None => {
+            let acc: f32 = input.trim_end_matches('%').parse().context(USAGE)?;
+            let wife = from_accuracy(acc, &w, v2).context("accuracy must be within 0-100")?;
+            println!("{acc:.2}% ({label}, OD {od}) ~ {wife:.2}% wife3 (estimated from accuracy alone)");
+        }
+    }
+    Ok(())
+}
+
+/// Abramowitz–Stegun 7.1.26 (|error| < 1.5e-7), plenty for a percentage.
+fn erf(x: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let y = 1.0 - (((((1.061_405_4 * t - 1.453_152_1) * t) + 1.421_413_7) * t - 0.284_496_72) * t + 0.254_829_6) * t * (-x * x).exp();
+    y.copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool { (a - b).abs() < 0.01 }
+
+    #[test]
+    fn windows_at_od8() {
+        let w = Windows::new(8.0, &[], 1.0);
+        assert_eq!([w.max, w.great, w.good, w.ok, w.meh, w.miss], [16.0, 40.0, 73.0, 103.0, 127.0, 164.0]);
+        // HR is 1.4x tighter, and 1.5x makes everything 1.5x shorter in real time
+        let hr = Windows::new(8.0, &["HR".into()], 1.5);
+        assert!(close(hr.max, 16.0 / 1.4 / 1.5) && close(hr.great, 40.0 / 1.4 / 1.5));
+        assert!(close(Windows::new(8.0, &["EZ".into()], 1.0).meh, 127.0 * 1.4));
+    }
+
+    #[test]
+    fn wife3_curve() {
+        assert_eq!(wife3(0.0), 2.0);
+        assert_eq!(wife3(-5.0), 2.0);
+        assert!(close(wife3(65.0), 0.0));
+        assert!(close(wife3(28.0), 1.9577));
+        assert!(close(wife3(-122.5), -2.75));
+        assert_eq!(wife3(180.0), -5.5);
+        assert_eq!(wife3(500.0), -5.5);
+    }
+
+    #[test]
+    fn all_max_is_100_percent() {
+        let hits = Hits { max: 500, ..Hits::default() };
+        assert_eq!(hits.accuracy(false), Some(100.0));
+        assert_eq!(hits.accuracy(true), Some(100.0));
+        assert_eq!(from_hits(&hits, &Windows::new(8.0, &[], 1.0)), Some(100.0));
+    }
+
+    #[test]
+    fn wife_from_hits() {
+        let w = Windows::new(8.0, &[], 1.0);
+        // 300s count as 28ms, halfway between the MAX and 300 windows
+        assert!(close(from_hits(&Hits { max: 90, great: 10, ..Hits::default() }, &w).unwrap(), 99.788));
+        // a miss costs 5.5 of a hit's 2 points
+        assert!(close(from_hits(&Hits { max: 9, miss: 1, ..Hits::default() }, &w).unwrap(), 62.5));
+        assert_eq!(from_hits(&Hits::default(), &w), None);
+    }
+
+    #[test]
+    fn wife_from_accuracy() {
+        let w = Windows::new(8.0, &[], 1.0);
+        // V1 can't tell MAX from 300, so 100% is half of each
+        assert!(close(from_accuracy(100.0, &w, false).unwrap(), 98.942));
+        assert!(from_accuracy(95.0, &w, false).unwrap() < from_accuracy(98.0, &w, false).unwrap());
+        assert_eq!(from_accuracy(100.5, &w, false), None);
+        assert_eq!(from_accuracy(-1.0, &w, true), None);
+    }
+
+    #[test]
+    fn accuracy_v1_and_v2() {
+        let hits = Hits { max: 50, great: 50, ..Hits::default() };
+        assert_eq!(hits.accuracy(false), Some(100.0));
+        assert!(close(hits.accuracy(true).unwrap(), 30_250.0 / 305.0));
+        assert!(close(Hits { good: 1, ..Hits::default() }.accuracy(false).unwrap(), 66.667));
+        assert_eq!(Hits::default().accuracy(true), None);
+    }
+
+    #[test]
+    fn parse_counts() {
+        let h = Hits::parse("1, 2,3,4,5,6").unwrap();
+        assert_eq!([h.max, h.great, h.good, h.ok, h.meh, h.miss], [1, 2, 3, 4, 5, 6]);
+        assert!(Hits::parse("1,2,3").is_none());
+        assert!(Hits::parse("1,2,3,4,5,6,7").is_none());
+        assert!(Hits::parse("1,2,x,4,5,6").is_none());
+    }
+}
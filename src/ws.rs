@@ -0,0 +1,290 @@
+//! Just enough RFC 6455 for pushing payloads: single-frame sends, reassembled reads, ping/pong, close.
+use std::io;
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Incoming messages bigger than this are refused.
+pub const MAX_MESSAGE: usize = 1 << 20;
+
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+#[derive(Debug, PartialEq)]
+pub enum Message { Text(String), Binary(Vec<u8>), Ping(Vec<u8>), Pong, Close }
+
+#[derive(Clone, Copy)]
+pub enum Opcode { Text = 1, Binary = 2, Close = 8, Pong = 10 }
+
+/// `Sec-WebSocket-Accept` for a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    B64.encode(sha1_smol::Sha1::from(format!("{key}{GUID}")).digest().bytes())
+}
+
+/// Open `ws://` or `wss://` and complete the client handshake. `headers` go on the upgrade request.
+pub async fn connect(url: &Url, headers: &[(&str, String)]) -> anyhow::Result<Box<dyn Io>> {
+    let host = url.host_str().context("URL has no host")?;
+    let port = url.port_or_known_default().context("URL has no port")?;
+    let tcp = TcpStream::connect((host, port)).await.with_context(|| format!("connecting to {host}:{port}"))?;
+    tcp.set_nodelay(true).ok();
+    let mut io: Box<dyn Io> = match url.scheme() {
+        "ws" => Box::new(tcp),
+        "wss" => {
+            let tls = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+            Box::new(tls.connect(host, tcp).await.context("TLS handshake")?)
+        }
+        s => anyhow::bail!("unsupported scheme {s:?} (use ws:// or wss://)"),
+    };
+
+    let mut nonce = [0u8; 16];
+    getrandom::getrandom(&mut nonce)?;
+    let key = B64.encode(nonce);
+    let path = match url.query() { Some(q) => format!("{}?{q}", url.path()), None => url.path().to_string() };
+    let mut req = format!(
+        "GET {path} HTTP/1.1\r\nHost: {host}:{port}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n"
+    );
+    for (k, v) in headers { req.push_str(&format!("{k}: {v}\r\n")); }
+    req.push_str("\r\n");
+    io.write_all(req.as_bytes()).await?;
+
+    let head = read_head(&mut io).await?;
+    let mut hdrs = [httparse::EMPTY_HEADER; 32];
+    let mut rsp = httparse::Response::new(&mut hdrs);
+    rsp.parse(&head).context("malformed handshake response")?;
+    anyhow::ensure!(rsp.code == Some(101), "server refused upgrade: {} {}", rsp.code.unwrap_or(0), rsp.reason.unwrap_or(""));
+    let accept = rsp.headers.iter().find(|h| h.name.eq_ignore_ascii_case("sec-websocket-accept")).map(|h| h.value);
+    anyhow::ensure!(accept == Some(accept_key(&key).as_bytes()), "bad Sec-WebSocket-Accept");
+    Ok(io)
+}
+
+/// Finish the server side of a handshake whose request carried `Sec-WebSocket-Key: key`.
+pub async fn accept<W: AsyncWrite + Unpin>(w: &mut W, key: &str) -> io::Result<()> {
+    let rsp = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    w.write_all(rsp.as_bytes()).await?;
+    w.flush().await
+}
+
+/// HTTP head up to and including the blank line (byte-wise so nothing past it is consumed).
+pub async fn read_head<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(512);
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > 16 * 1024 { return Err(io::Error::new(io::ErrorKind::InvalidData, "header too large")); }
+        head.push(r.read_u8().await?);
+    }
+    Ok(head)
+}
+
+/// One unfragmented frame. Clients must mask (`mask = true`), servers must not.
+pub async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, op: Opcode, payload: &[u8], mask: bool) -> io::Result<()> {
+    let mut out = Vec::with_capacity(payload.len() + 14);
+    out.push(0x80 | op as u8);
+    let m = if mask { 0x80 } else { 0 };
+    match payload.len() {
+        n @ 0..=125 => out.push(m | n as u8),
+        n @ 126..=0xffff => { out.push(m | 126); out.extend((n as u16).to_be_bytes()); }
+        n => { out.push(m | 127); out.extend((n as u64).to_be_bytes()); }
+    }
+    if mask {
+        let mut key = [0u8; 4];
+        getrandom::getrandom(&mut key).map_err(io::Error::other)?;
+        out.extend(key);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+    } else {
+        out.extend(payload);
+    }
+    w.write_all(&out).await?;
+    w.flush().await
+}
+
+/// Reads messages off one side of a socket, keeping whatever has arrived of an unfinished frame
+/// or message between calls, so `next` can sit in a `select!` arm: another arm winning loses
+/// no bytes.
+pub struct Reader<R> { r: R, buf: Vec<u8>, message: Vec<u8>, first_op: Option<u8> }
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    pub fn new(r: R) -> Self { Reader { r, buf: Vec::new(), message: Vec::new(), first_op: None } }
+
+    /// Next complete message. Cancel-safe: the only await is one `read_buf` into the buffer.
+    pub async fn next(&mut self) -> io::Result<Message> {
+        loop {
+            if let Some(m) = self.parse()? { return Ok(m); }
+            self.buf.reserve(4096);
+            if self.r.read_buf(&mut self.buf).await? == 0 { return Err(io::ErrorKind::UnexpectedEof.into()); }
+        }
+    }
+
+    /// A message out of the frames already buffered, or None until more arrive.
+    fn parse(&mut self) -> io::Result<Option<Message>> {
+        while let Some(Frame { fin, op, payload, used }) = frame(&self.buf, self.message.len())? {
+            self.buf.drain(..used);
+            if op >= 8 && (!fin || payload.len() > 125) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "fragmented or oversized control frame"));
+            }
+            match op {
+                8 => return Ok(Some(Message::Close)),
+                // control frames may sit between fragments, and don't end the message
+                9 => return Ok(Some(Message::Ping(payload))),
+                10 => return Ok(Some(Message::Pong)),
+                0..=2 => {
+                    match (op, self.first_op) {
+                        (0, None) => return Err(io::Error::new(io::ErrorKind::InvalidData, "continuation frame outside a message")),
+                        (1 | 2, Some(_)) => return Err(io::Error::new(io::ErrorKind::InvalidData, "new message inside a fragmented one")),
+                        (1 | 2, None) => self.first_op = Some(op),
+                        _ => {}
+                    }
+                    self.message.extend(payload);
+                    if !fin { continue; }
+                    let buf = std::mem::take(&mut self.message);
+                    return Ok(Some(match self.first_op.take() {
+                        Some(1) => Message::Text(String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
+                        _ => Message::Binary(buf),
+                    }));
+                }
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown opcode")),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// One frame, unmasked; `used` is how many bytes of the buffer it took.
+struct Frame { fin: bool, op: u8, payload: Vec<u8>, used: usize }
+
+/// The frame at the start of `buf`, or None if it hasn't all arrived. `so_far` is the size of
+/// the message it continues.
+fn frame(buf: &[u8], so_far: usize) -> io::Result<Option<Frame>> {
+    let [b0, b1, ..] = *buf else { return Ok(None) };
+    let (fin, op, masked) = (b0 & 0x80 != 0, b0 & 0x0f, b1 & 0x80 != 0);
+    let (len, mut at) = match b1 & 0x7f {
+        126 => match buf.get(2..4) { Some(b) => (u16::from_be_bytes([b[0], b[1]]) as u64, 4), None => return Ok(None) },
+        127 => match buf.get(2..10) { Some(b) => (u64::from_be_bytes(b.try_into().unwrap_or_default()), 10), None => return Ok(None) },
+        n => (n as u64, 2),
+    };
+    // before waiting for the payload, so a huge length can't make the buffer grow to it
+    if len > MAX_MESSAGE as u64 || so_far + len as usize > MAX_MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too large"));
+    }
+    let key = if masked {
+        let Some(k) = buf.get(at..at + 4) else { return Ok(None) };
+        at += 4;
+        Some([k[0], k[1], k[2], k[3]])
+    } else { None };
+    let Some(payload) = buf.get(at..at + len as usize) else { return Ok(None) };
+    let mut payload = payload.to_vec();
+    if let Some(k) = key { payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= k[i % 4]); }
+    Ok(Some(Frame { fin, op, payload, used: at + len as usize }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_all(bytes: &[u8]) -> Vec<io::Result<Message>> {
+        let mut rd = Reader::new(bytes);
+        let mut out = Vec::new();
+        loop {
+            match rd.next().await {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return out,
+                m => { let stop = m.is_err(); out.push(m); if stop { return out; } }
+            }
+        }
+    }
+
+    async fn messages(bytes: &[u8]) -> Vec<Message> {
+        read_all(bytes).await.into_iter().map(Result::unwrap).collect()
+    }
+
+    async fn error(bytes: &[u8]) -> String {
+        read_all(bytes).await.pop().unwrap().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn accept_key_matches_the_rfc() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[tokio::test]
+    async fn reads_the_rfc_examples() {
+        // RFC 6455 5.7
+        let hello = || Message::Text("Hello".into());
+        assert_eq!(messages(b"\x81\x05Hello").await, [hello()]);
+        assert_eq!(messages(b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58").await, [hello()]);
+        assert_eq!(messages(b"\x01\x03Hel\x80\x02lo").await, [hello()]);
+        assert_eq!(messages(b"\x89\x05Hello\x8a\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58").await, [Message::Ping(b"Hello".to_vec()), Message::Pong]);
+        let long = [b"\x82\x7e\x01\x00".as_slice(), &[7; 256]].concat();
+        assert_eq!(messages(&long).await, [Message::Binary(vec![7; 256])]);
+        let longer = [b"\x82\x7f\x00\x00\x00\x00\x00\x01\x00\x00".as_slice(), &[9; 65536]].concat();
+        assert_eq!(messages(&longer).await, [Message::Binary(vec![9; 65536])]);
+    }
+
+    #[tokio::test]
+    async fn control_frames_between_fragments() {
+        let frames = b"\x01\x03Hel\x89\x01p\x00\x01l\x88\x02\x03\xe8\x80\x01o";
+        assert_eq!(messages(frames).await, [Message::Ping(b"p".to_vec()), Message::Close, Message::Text("Hello".into())]);
+    }
+
+    #[tokio::test]
+    async fn writes_length_forms_and_masks() {
+        for (n, head) in [(125, &[0x82, 125][..]), (126, &[0x82, 126, 0, 126]), (65535, &[0x82, 126, 0xff, 0xff]), (65536, &[0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0])] {
+            let payload: Vec<u8> = (0..n).map(|i| i as u8).collect();
+            let mut out = Vec::new();
+            write_frame(&mut out, Opcode::Binary, &payload, false).await.unwrap();
+            assert_eq!(out[..head.len()], *head, "{n} bytes");
+            assert_eq!(out[head.len()..], payload);
+
+            let mut masked = Vec::new();
+            write_frame(&mut masked, Opcode::Binary, &payload, true).await.unwrap();
+            assert_eq!(masked[1] & 0x80, 0x80);
+            assert_eq!(masked.len(), out.len() + 4);
+            assert_eq!(messages(&masked).await, [Message::Binary(payload)]);
+        }
+        let mut close = Vec::new();
+        write_frame(&mut close, Opcode::Close, &[], false).await.unwrap();
+        assert_eq!(close, [0x88, 0]);
+    }
+
+    #[tokio::test]
+    async fn survives_being_cancelled_mid_frame() {
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let mut frames = Vec::new();
+        write_frame(&mut frames, Opcode::Text, "x".repeat(300).as_bytes(), true).await.unwrap();
+        frames.extend(b"\x01\x02ab\x80\x02cd");
+        let mut rd = Reader::new(rx);
+        let sent = tokio::spawn(async move {
+            for b in frames { tx.write_all(&[b]).await.unwrap(); tokio::task::yield_now().await; }
+        });
+        let mut got = Vec::new();
+        while got.len() < 2 {
+            // the other arm wins whenever a message isn't complete yet, dropping `next` mid-frame
+            tokio::select! {
+                biased;
+                m = rd.next() => got.push(m.unwrap()),
+                _ = tokio::task::yield_now() => {}
+            }
+        }
+        sent.await.unwrap();
+        assert_eq!(got, [Message::Text("x".repeat(300)), Message::Text("abcd".into())]);
+    }
+
+    #[tokio::test]
+    async fn refuses_bad_frames() {
+        assert_eq!(error(b"\x82\x7f\x00\x00\x01\x00\x00\x00\x00\x00").await, "message too large");
+        // fragments adding up past the limit: refused at the second header
+        let half = [b"\x02\x7f\x00\x00\x00\x00\x00\x08\x00\x00".as_slice(), &vec![0; 1 << 19]].concat();
+        assert_eq!(error(&[half.as_slice(), b"\x00\x7f\x00\x00\x00\x00\x00\x08\x00\x01"].concat()).await, "message too large");
+        assert_eq!(error(b"\x83\x00").await, "unknown opcode");
+        assert_eq!(error(b"\x81\x02\xc3\x28").await, "invalid utf-8 sequence of 1 bytes from index 0");
+        assert_eq!(error(b"\x80\x01a").await, "continuation frame outside a message");
+        assert_eq!(error(b"\x01\x01a\x81\x01b").await, "new message inside a fragmented one");
+        assert_eq!(error(b"\x09\x00").await, "fragmented or oversized control frame");
+        assert_eq!(error(&[b"\x89\x7e\x00\x7e".as_slice(), &[0; 126]].concat()).await, "fragmented or oversized control frame");
+        // cut off mid-frame: the caller sees the end of the stream
+        assert!(read_all(b"\x81\x05Hel").await.is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+//! Just enough zip: writing stored archives (XLSX) and reading stored / deflated entries (.osz).
+use anyhow::Context;
+
+/// Uncompressed ("stored") zip archive; plenty for a few KB of XML.
+#[derive(Default)]
+pub struct Writer { data: Vec<u8>, central: Vec<u8>, count: u16 }
+
+impl Writer {
+    pub fn add(&mut self, name: &str, body: &[u8]) {
+        let (crc, offset) = (crc32(body), self.data.len() as u32);
+        let size = body.len() as u32;
+        // version 20, UTF-8 names, stored, 1980-01-01 00:00
+        let common = |v: &mut Vec<u8>| {
+            v.extend(20u16.to_le_bytes());
+            v.extend(0x0800u16.to_le_bytes());
+            v.extend(0u16.to_le_bytes());
+            v.extend(0u16.to_le_bytes());
+            v.extend(0x21u16.to_le_bytes());
+            v.extend(crc.to_le_bytes());
+            v.extend(size.to_le_bytes());
+            v.extend(size.to_le_bytes());
+            v.extend((name.len() as u16).to_le_bytes());
+            v.extend(0u16.to_le_bytes());
+        };
+        self.data.extend(0x0403_4b50u32.to_le_bytes());
+        common(&mut self.data);
+        self.data.extend(name.as_bytes());
+        self.data.extend(body);
+
+        self.central.extend(0x0201_4b50u32.to_le_bytes());
+        self.central.extend(20u16.to_le_bytes()); // made by
+        common(&mut self.central);
+        self.central.extend([0u8; 6]); // comment len, disk, internal attrs
+        self.central.extend(0u32.to_le_bytes()); // external attrs
+        self.central.extend(offset.to_le_bytes());
+        self.central.extend(name.as_bytes());
+        self.count += 1;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let (cd_offset, cd_size) = (self.data.len() as u32, self.central.len() as u32);
+        self.data.append(&mut self.central);
+        self.data.extend(0x0605_4b50u32.to_le_bytes());
+        self.data.extend([0u8; 4]); // disk numbers
+        self.data.extend(self.count.to_le_bytes());
+        self.data.extend(self.count.to_le_bytes());
+        self.data.extend(cd_size.to_le_bytes());
+        self.data.extend(cd_offset.to_le_bytes());
+        self.data.extend(0u16.to_le_bytes());
+        self.data
+    }
+}
+
+/// Entries whose name passes `want`, decompressed, in archive order.
+pub fn read(zip: &[u8], want: impl Fn(&str) -> bool) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let u16_at = |i: usize| zip.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize);
+    let u32_at = |i: usize| zip.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    // end of central directory: last signature within the max comment length
+    let eocd = (0..zip.len().saturating_sub(21)).rev().take(0xffff + 22)
+        .find(|&i| zip[i..i + 4] == [0x50, 0x4b, 0x05, 0x06]).context("not a zip archive")?;
+    let count = u16_at(eocd + 10).context("truncated zip")?;
+    let mut at = u32_at(eocd + 16).context("truncated zip")?;
+    let mut out = Vec::new();
+    for _ in 0..count {
+        anyhow::ensure!(u32_at(at) == Some(0x0201_4b50), "corrupt central directory");
+        let method = u16_at(at + 10).context("truncated zip")?;
+        let crc = u32_at(at + 16).context("truncated zip")?;
+        let size = u32_at(at + 20).context("truncated zip")?;
+        let (name_len, extra_len, comment_len) = (u16_at(at + 28).unwrap_or(0), u16_at(at + 30).unwrap_or(0), u16_at(at + 32).unwrap_or(0));
+        let local = u32_at(at + 42).context("truncated zip")?;
+        let name = String::from_utf8_lossy(zip.get(at + 46..at + 46 + name_len).context("truncated zip")?).into_owned();
+        at += 46 + name_len + extra_len + comment_len;
+        if name.ends_with('/') || !want(&name) { continue; }
+
+        let start = local + 30 + u16_at(local + 26).context("truncated zip")? + u16_at(local + 28).context("truncated zip")?;
+        let raw = zip.get(start..start + size).with_context(|| format!("{name}: truncated"))?;
+        let data = match method {
+            0 => raw.to_vec(),
+            8 => inflate(raw).with_context(|| format!("{name}: corrupt deflate data"))?,
+            m => anyhow::bail!("{name}: unsupported compression method {m}"),
+        };
+        anyhow::ensure!(crc32(&data) as usize == crc, "{name}: CRC mismatch");
+        out.push((name, data));
+    }
+    Ok(out)
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 { crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 }; }
+    }
+    !crc
+}
+
+// --- raw DEFLATE (RFC 1951) decoder ---
+
+struct Bits<'a> { data: &'a [u8], pos: usize, buf: u32, cnt: u32 }
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Option<u32> {
+        while self.cnt < n {
+            self.buf |= (*self.data.get(self.pos)? as u32) << self.cnt;
+            self.pos += 1;
+            self.cnt += 8;
+        }
+        let v = self.buf & ((1u64 << n) - 1) as u32;
+        self.buf = if n == 32 { 0 } else { self.buf >> n };
+        self.cnt -= n;
+        Some(v)
+    }
+}
+
+/// Canonical Huffman code: symbol counts per length, symbols in code order.
+struct Huffman { counts: [u16; 16], symbols: Vec<u16> }
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &l in lengths { counts[l as usize] += 1; }
+        counts[0] = 0;
+        let mut offs = [0u16; 16];
+        for i in 1..16 { offs[i] = offs[i - 1] + counts[i - 1]; }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (s, &l) in lengths.iter().enumerate() {
+            if l != 0 { symbols[offs[l as usize] as usize] = s as u16; offs[l as usize] += 1; }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first { return self.symbols.get((index + code - first) as usize).copied(); }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+const LEN_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LEN_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+pub fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut bits = Bits { data, pos: 0, buf: 0, cnt: 0 };
+    let mut out = Vec::with_capacity(data.len() * 3);
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                // stored: skip to a byte boundary, LEN and NLEN follow
+                bits.buf = 0;
+                bits.cnt = 0;
+                let p = bits.pos;
+                let len = u16::from_le_bytes([*data.get(p)?, *data.get(p + 1)?]) as usize;
+                let nlen = u16::from_le_bytes([*data.get(p + 2)?, *data.get(p + 3)?]) as usize;
+                if len ^ nlen != 0xffff { return None; }
+                out.extend_from_slice(data.get(p + 4..p + 4 + len)?);
+                bits.pos = p + 4 + len;
+            }
+            1 => {
+                let mut l = [8u8; 288];
+                l[144..256].fill(9);
+                l[256..280].fill(7);
+                codes(&mut bits, &mut out, &Huffman::new(&l), &Huffman::new(&[5u8; 30]))?;
+            }
+            2 => {
+                let (hlit, hdist, hclen) = (bits.take(5)? as usize + 257, bits.take(5)? as usize + 1, bits.take(4)? as usize + 4);
+                const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+                let mut cl = [0u8; 19];
+                for &i in &ORDER[..hclen] { cl[i] = bits.take(3)? as u8; }
+                let clh = Huffman::new(&cl);
+                let mut lengths = Vec::with_capacity(hlit + hdist);
+                while lengths.len() < hlit + hdist {
+                    match clh.decode(&mut bits)? {
+                        s @ 0..=15 => lengths.push(s as u8),
+                        16 => { let prev = *lengths.last()?; let n = 3 + bits.take(2)? as usize; lengths.resize(lengths.len() + n, prev); }
+                        17 => { let n = 3 + bits.take(3)? as usize; lengths.resize(lengths.len() + n, 0); }
+                        _ => { let n = 11 + bits.take(7)? as usize; lengths.resize(lengths.len() + n, 0); }
+                    }
+                }
+                if lengths.len() > hlit + hdist { return None; }
+                codes(&mut bits, &mut out, &Huffman::new(&lengths[..hlit]), &Huffman::new(&lengths[hlit..]))?;
+            }
+            _ => return None,
+        }
+        if last { return Some(out); }
+    }
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> Option<()> {
+    loop {
+        match lit.decode(bits)? {
+            s @ 0..=255 => out.push(s as u8),
+            256 => return Some(()),
+            s => {
+                let i = (s - 257) as usize;
+                let len = *LEN_BASE.get(i)? as usize + bits.take(*LEN_EXTRA.get(i)? as u32)? as usize;
+                let d = dist.decode(bits)? as usize;
+                let back = *DIST_BASE.get(d)? as usize + bits.take(*DIST_EXTRA.get(d)? as u32)? as usize;
+                let from = out.len().checked_sub(back)?;
+                for k in 0..len { out.push(out[from + k]); }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // made outside this crate (zlib): a folder entry, then one entry per block type and a
+    // stored (method 0) one
+    const ENTRIES: &[u8] = include_bytes!("testdata/entries.zip");
+    // "abcabcabcabc", fixed Huffman with one back-reference
+    const FIXED: [u8; 7] = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+
+    fn hits() -> Vec<u8> {
+        (0..400).map(|i| format!("{},192,{},1,0,0:0:0:0:\n", 64 + 128 * (i % 4), 1000 + 125 * i)).collect::<String>().into_bytes()
+    }
+
+    fn noise(n: usize) -> Vec<u8> {
+        let mut x = 1u32;
+        (0..n).map(|_| { x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fff_ffff; (x >> 16) as u8 }).collect()
+    }
+
+    /// The compressed bytes of the entry named `name`, found through its local header.
+    fn body(name: &str) -> &'static [u8] {
+        let at = ENTRIES.windows(name.len()).position(|w| w == name.as_bytes()).unwrap();
+        let size = u32::from_le_bytes(ENTRIES[at - 12..at - 8].try_into().unwrap()) as usize;
+        &ENTRIES[at + name.len()..at + name.len() + size]
+    }
+
+    #[test]
+    fn reads_every_block_type() {
+        let metadata = b"[Metadata]\nTitle:test\nTitle:test\nTitle:test\n".to_vec();
+        let want = [("stored.osu", metadata.clone()), ("fixed.osu", metadata), ("dynamic.osu", hits()), ("random.bin", noise(300))];
+        let got = read(ENTRIES, |_| true).unwrap();
+        assert_eq!(got, want.map(|(n, d)| (n.to_string(), d)));
+        assert_eq!(body("fixed.osu")[0] >> 1 & 3, 1);
+        assert_eq!(body("dynamic.osu")[0] >> 1 & 3, 2);
+        assert_eq!(body("random.bin")[0] >> 1 & 3, 0);
+        assert_eq!(inflate(&FIXED).unwrap(), b"abcabcabcabc");
+    }
+
+    #[test]
+    fn reads_only_wanted_entries() {
+        let got = read(ENTRIES, |n| n.ends_with(".osu")).unwrap();
+        assert_eq!(got.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(), ["stored.osu", "fixed.osu", "dynamic.osu"]);
+    }
+
+    #[test]
+    fn truncated_archives_and_streams_fail() {
+        for n in 0..ENTRIES.len() { assert!(read(&ENTRIES[..n], |_| true).is_err(), "prefix of {n}"); }
+        for name in ["fixed.osu", "dynamic.osu", "random.bin"] {
+            let stream = body(name);
+            for n in 0..stream.len() { assert_eq!(inflate(&stream[..n]), None, "{name} cut to {n}"); }
+        }
+    }
+
+    #[test]
+    fn corrupt_data_fails() {
+        let mut zip = ENTRIES.to_vec();
+        let at = zip.windows(10).position(|w| w == b"stored.osu").unwrap() + 10;
+        zip[at] ^= 1;
+        assert!(read(&zip, |_| true).unwrap_err().to_string().contains("CRC mismatch"));
+        // stored block whose NLEN isn't LEN's complement
+        assert_eq!(inflate(&[0x01, 0x01, 0x00, 0xff, 0xff, b'a']), None);
+        assert_eq!(inflate(&[0x01, 0x01, 0x00, 0xfe, 0xff, b'a']).unwrap(), b"a");
+        // block type 3; a length-3 match one byte back, with nothing written yet
+        assert_eq!(inflate(&[0x07]), None);
+        assert_eq!(inflate(&[0x03, 0x02, 0x00]), None);
+        for seed in 1..200 { let _ = inflate(&noise(seed * 7)); }
+    }
+
+    #[test]
+    fn writer_round_trip() {
+        let mut w = Writer::default();
+        w.add("xl/workbook.xml", b"<workbook/>");
+        w.add("empty", b"");
+        let zip = w.finish();
+        assert_eq!(read(&zip, |_| true).unwrap(), [("xl/workbook.xml".to_string(), b"<workbook/>".to_vec()), ("empty".to_string(), Vec::new())]);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}